@@ -0,0 +1,75 @@
+//! Compares streaming glob expansion with the old count-then-collect approach
+//! on a large flat directory. Run with `cargo bench --bench expand_paths`;
+//! `TAP_BENCH_ENTRIES` sets the number of files (default 200000).
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use glob::glob;
+
+/// What `expand_paths` used to do: walk every pattern once to count the
+/// matches, then again to collect them.
+fn count_then_collect(pattern: &str) -> Vec<PathBuf> {
+    if glob(pattern).unwrap().count() == 0 {
+        return vec![PathBuf::from(pattern)];
+    }
+    glob(pattern).unwrap().filter_map(Result::ok).collect()
+}
+
+/// The soonest the old approach could yield a path: only once the counting
+/// walk is over.
+fn count_then_first(pattern: &str) -> Option<PathBuf> {
+    if glob(pattern).unwrap().count() == 0 {
+        return Some(PathBuf::from(pattern));
+    }
+    glob(pattern).unwrap().find_map(Result::ok)
+}
+
+/// Best of three runs, so the first one warming the directory cache doesn't count.
+fn time<T>(f: impl Fn() -> T) -> (T, Duration) {
+    let mut best = Duration::MAX;
+    let mut result = None;
+    for _ in 0..3 {
+        let start = Instant::now();
+        result = Some(f());
+        best = best.min(start.elapsed());
+    }
+    (result.unwrap(), best)
+}
+
+fn main() -> anyhow::Result<()> {
+    let entries = std::env::var("TAP_BENCH_ENTRIES")
+        .ok()
+        .and_then(|entries| entries.parse().ok())
+        .unwrap_or(200_000);
+    let dir = tempfile::tempdir()?;
+    for i in 0..entries {
+        fs::File::create(dir.path().join(format!("file-{:07}.dat", i)))?;
+    }
+    let pattern = dir.path().join("*.dat").to_string_lossy().into_owned();
+    let patterns = [pattern.clone()];
+    println!("{} entries", entries);
+
+    let (old_first, old_latency) = time(|| count_then_first(&pattern));
+    let (old, old_total) = time(|| count_then_collect(&pattern));
+    let (first, first_latency) = time(|| tap::glob_paths(&patterns).unwrap().next());
+    let (streamed, streamed_total) = time(|| tap::glob_paths(&patterns).unwrap().count());
+    assert_eq!(old.len(), streamed);
+    assert!(old_first.is_some() && first.is_some());
+
+    println!(
+        "count then collect: {:>10.1?} before the first path",
+        old_latency
+    );
+    println!(
+        "streaming:          {:>10.1?} before the first path",
+        first_latency
+    );
+    println!("count then collect: {:>10.1?} for all paths", old_total);
+    println!(
+        "streaming:          {:>10.1?} for all paths",
+        streamed_total
+    );
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! Per-invocation overhead of `tap FILE`, the way shell loops call it,
+//! next to coreutils `touch`. Run with `cargo bench --bench startup`;
+//! `TAP_BENCH_RUNS` sets the number of invocations (default 500).
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+fn per_run(runs: u32, program: &Path, args: &[&str], dir: &Path) -> Duration {
+    let start = Instant::now();
+    for i in 0..runs {
+        let status = Command::new(program)
+            .args(args)
+            .arg(format!("file-{}", i % 50))
+            .current_dir(dir)
+            .stdout(Stdio::null())
+            .status()
+            .expect("Failed to run benchmark command");
+        assert!(status.success());
+    }
+    start.elapsed() / runs
+}
+
+fn main() -> anyhow::Result<()> {
+    let runs = std::env::var("TAP_BENCH_RUNS")
+        .ok()
+        .and_then(|runs| runs.parse().ok())
+        .unwrap_or(500);
+    let dir = tempfile::tempdir()?;
+    let tap = Path::new(env!("CARGO_BIN_EXE_tap"));
+
+    let cases: [(&str, &Path, &[&str]); 4] = [
+        ("touch FILE", Path::new("touch"), &[]),
+        ("tap FILE", tap, &[]),
+        ("tap -v FILE", tap, &["-v"]),
+        ("tap --chmod 644 FILE", tap, &["--chmod", "644"]),
+    ];
+    println!("{} runs each", runs);
+    for (name, program, args) in cases {
+        println!(
+            "{:<22} {:>10.1?}",
+            name,
+            per_run(runs, program, args, dir.path())
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,349 @@
+//! The `when` conditions of manifest entries and presets, such as
+//! `os == 'linux'` or `env.CI == 'true' && !(arch == 'aarch64')`, checked
+//! against the machine tap runs on.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use anyhow::Result;
+
+use crate::tr;
+
+/// What a condition can ask about: `os`, `arch` and `family` as Rust names
+/// them (`linux`, `x86_64`, `unix`), and `env.NAME`, which is empty for a
+/// variable that isn't set.
+#[derive(Clone, Debug, Default)]
+pub struct Facts {
+    pub os: String,
+    pub arch: String,
+    pub family: String,
+    pub env: HashMap<String, String>,
+}
+
+impl Facts {
+    /// The facts of this machine and process, read once.
+    pub fn current() -> &'static Self {
+        static CURRENT: OnceLock<Facts> = OnceLock::new();
+        CURRENT.get_or_init(|| Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            family: std::env::consts::FAMILY.to_string(),
+            env: std::env::vars().collect(),
+        })
+    }
+
+    fn value(&self, name: &Name) -> &str {
+        match name {
+            Name::Os => &self.os,
+            Name::Arch => &self.arch,
+            Name::Family => &self.family,
+            Name::Env(var) => self.env.get(var).map_or("", String::as_str),
+        }
+    }
+}
+
+/// A parsed condition. Values are compared as strings with `==` and `!=`,
+/// combined with `&&`, `||` and `!` (or `and`, `or` and `not`) and grouped
+/// with parentheses; a value on its own holds when it isn't empty.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition(Expr);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Value(Operand),
+    Equals(Operand, Operand, bool),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Literal(String),
+    Name(Name),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Name {
+    Os,
+    Arch,
+    Family,
+    Env(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Literal(String),
+    Word(String),
+    Equals,
+    NotEquals,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            text,
+            tokens: &tokens,
+            next: 0,
+        };
+        let expr = parser.or()?;
+        if let Some((column, _)) = parser.tokens.get(parser.next) {
+            return Err(parser.error(*column, &tr!("expected && or ||")));
+        }
+        Ok(Self(expr))
+    }
+
+    pub fn holds(&self, facts: &Facts) -> bool {
+        self.0.holds(facts)
+    }
+}
+
+/// Whether `when`, if there is one, holds on this machine.
+pub fn holds(when: Option<&str>) -> Result<bool> {
+    match when {
+        Some(when) => Ok(Condition::parse(when)?.holds(Facts::current())),
+        None => Ok(true),
+    }
+}
+
+impl Expr {
+    fn holds(&self, facts: &Facts) -> bool {
+        match self {
+            Expr::Value(operand) => !operand.value(facts).is_empty(),
+            Expr::Equals(left, right, equal) => (left.value(facts) == right.value(facts)) == *equal,
+            Expr::Not(expr) => !expr.holds(facts),
+            Expr::And(left, right) => left.holds(facts) && right.holds(facts),
+            Expr::Or(left, right) => left.holds(facts) || right.holds(facts),
+        }
+    }
+}
+
+impl Operand {
+    fn value<'a>(&'a self, facts: &'a Facts) -> &'a str {
+        match self {
+            Operand::Literal(value) => value,
+            Operand::Name(name) => facts.value(name),
+        }
+    }
+}
+
+/// The tokens of `text`, each with the column (from 1) it starts at.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>> {
+    let error = |column: usize, what: String| {
+        anyhow::anyhow!(tr!(
+            "Invalid condition {}: {} at column {}",
+            text,
+            what,
+            column
+        ))
+    };
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let column = text[..i].chars().count() + 1;
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '\'' | '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, end)) if end == c => break,
+                        Some((_, c)) => value.push(c),
+                        None => return Err(error(column, tr!("unterminated string"))),
+                    }
+                }
+                Token::Literal(value)
+            }
+            '=' | '!' | '&' | '|' => {
+                let token = match (c, chars.peek().map(|&(_, next)| next)) {
+                    ('=', Some('=')) => Token::Equals,
+                    ('!', Some('=')) => Token::NotEquals,
+                    ('&', Some('&')) => Token::And,
+                    ('|', Some('|')) => Token::Or,
+                    ('!', _) => {
+                        tokens.push((column, Token::Not));
+                        continue;
+                    }
+                    _ => return Err(error(column, tr!("unexpected {}", c))),
+                };
+                // Past the operator's second character
+                chars.next();
+                token
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                }
+            }
+            c => return Err(error(column, tr!("unexpected {}", c))),
+        };
+        tokens.push((column, token));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: &'a [(usize, Token)],
+    next: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, column: usize, what: &str) -> anyhow::Error {
+        anyhow::anyhow!(tr!(
+            "Invalid condition {}: {} at column {}",
+            self.text,
+            what,
+            column
+        ))
+    }
+
+    /// The column just past the end, for errors about what is missing.
+    fn end(&self) -> usize {
+        self.text.chars().count() + 1
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.eat(&Token::Open) {
+            let expr = self.or()?;
+            if !self.eat(&Token::Close) {
+                let column = self.tokens.get(self.next).map_or(self.end(), |(c, _)| *c);
+                return Err(self.error(column, &tr!("expected )")));
+            }
+            return Ok(expr);
+        }
+        let left = self.operand()?;
+        let equal = match self.peek() {
+            Some(Token::Equals) => true,
+            Some(Token::NotEquals) => false,
+            _ => return Ok(Expr::Value(left)),
+        };
+        self.next += 1;
+        Ok(Expr::Equals(left, self.operand()?, equal))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        let Some((column, token)) = self.tokens.get(self.next) else {
+            return Err(self.error(self.end(), &tr!("expected a value")));
+        };
+        self.next += 1;
+        match token {
+            Token::Literal(value) => Ok(Operand::Literal(value.clone())),
+            Token::Word(word) => match word.as_str() {
+                "os" => Ok(Operand::Name(Name::Os)),
+                "arch" => Ok(Operand::Name(Name::Arch)),
+                "family" => Ok(Operand::Name(Name::Family)),
+                _ => match word.strip_prefix("env.") {
+                    Some(var) if !var.is_empty() => Ok(Operand::Name(Name::Env(var.to_string()))),
+                    _ => Err(self.error(
+                        *column,
+                        &tr!(
+                            "unknown name {} (expected os, arch, family or env.NAME)",
+                            word
+                        ),
+                    )),
+                },
+            },
+            _ => Err(self.error(*column, &tr!("expected a value"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        Facts {
+            os: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            family: "unix".to_string(),
+            env: HashMap::from([("CI".to_string(), "true".to_string())]),
+        }
+    }
+
+    fn holds(text: &str) -> bool {
+        Condition::parse(text).unwrap().holds(&facts())
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert!(holds("os == 'linux'"));
+        assert!(!holds("os == \"windows\""));
+        assert!(holds("os != 'windows'"));
+        assert!(holds("env.CI == 'true' && family == 'unix'"));
+        assert!(holds("env.CI"));
+        assert!(!holds("env.MISSING"));
+        assert!(holds("env.MISSING == ''"));
+        assert!(holds("os == 'macos' || arch == 'x86_64'"));
+        assert!(!holds("!(os == 'linux')"));
+        assert!(holds("not os == 'windows' and (env.CI or env.MISSING)"));
+        // && binds tighter than ||
+        assert!(holds("os == 'linux' || os == 'x' && os == 'y'"));
+    }
+
+    #[test]
+    fn test_invalid_conditions() {
+        for (text, error) in [
+            ("os = 'linux'", "unexpected = at column 4"),
+            ("os == 'linux", "unterminated string at column 7"),
+            ("platform == 'linux'", "unknown name platform"),
+            ("(os == 'linux'", "expected ) at column 15"),
+            ("os ==", "expected a value at column 6"),
+            ("os 'linux'", "expected && or || at column 4"),
+        ] {
+            let message = Condition::parse(text).unwrap_err().to_string();
+            assert!(message.contains(error), "{text}: {message}");
+        }
+    }
+}
@@ -0,0 +1,596 @@
+//! The config file, `~/.config/tap/config.toml`: defaults, presets and
+//! the rules that give new files a preset.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use glob::Pattern;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::manifest::{from_toml, IncludeStack};
+use crate::{
+    condition, default_templates_dir, parent_dir, platform, tr, xdg_base_dir, Condition, FileOp,
+    Mode, Sandbox,
+};
+
+/// Defaults from `~/.config/tap/config.toml` (or the file named by
+/// `TAP_CONFIG`), applied wherever the command line leaves an option unset.
+///
+/// `include` names config files, relative to the one naming them, to layer
+/// this one over: its own settings win, then those of later includes over
+/// earlier ones. Presets are merged by name the same way, and rules are
+/// tried in the same order, the including file's first.
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Config files to layer this one over, relative to this one
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Always print what is being done
+    #[serde(default)]
+    pub verbose: bool,
+    /// Permissions for files tap creates (octal)
+    pub file_mode: Option<String>,
+    /// Permissions for directories tap creates (octal)
+    pub dir_mode: Option<String>,
+    /// Where `--template NAME` looks when NAME isn't a path to a file
+    /// (default `~/.config/tap/templates`)
+    pub templates: Option<PathBuf>,
+    /// Record every run for `tap undo` (default true); when false, only
+    /// with `--undo`
+    pub undo: Option<bool>,
+    /// Public minisign keys that may sign templates fetched from URLs,
+    /// besides those `tap key add` stored
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// Create missing parent directories (default true); when false, only
+    /// with `--parents`, and otherwise suggest a close existing directory
+    pub parents: Option<bool>,
+    /// Named content and modes for `--preset NAME`, from `[presets.NAME]`
+    #[serde(default)]
+    pub presets: HashMap<String, Preset>,
+    /// Presets new files get by name, from `[[rules]]`
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+/// A `[[rules]]` entry: new files whose name matches `match` get a preset,
+/// named or given inline, without `--preset`. `match = "*.sh"` looks at the
+/// file name; a pattern with a `/` looks at the whole path.
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(from = "RawRule")]
+pub struct Rule {
+    pub pattern: String,
+    /// The named preset, or the one given inline, with its content and mode
+    pub preset: Preset,
+    /// A condition such as `env.CI == 'true'` the rule only applies under
+    pub when: Option<String>,
+    /// The name of the preset, resolved when the config is loaded
+    preset_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    /// Pattern for the file name, or the whole path if it has a `/`
+    #[serde(rename = "match")]
+    pattern: String,
+    /// A preset from `[presets.NAME]`, instead of content and a mode
+    preset: Option<String>,
+    /// Template content
+    content: Option<String>,
+    /// Template file, or the name of one in the templates directory
+    template: Option<String>,
+    /// Permissions, as `--chmod` takes them
+    mode: Option<String>,
+    /// A condition such as `env.CI == 'true'` the rule only applies under
+    when: Option<String>,
+    /// Limits on the commands its content runs, as a preset's
+    sandbox: Option<Sandbox>,
+}
+
+impl From<RawRule> for Rule {
+    fn from(raw: RawRule) -> Self {
+        Self {
+            pattern: raw.pattern,
+            preset: Preset {
+                content: raw.content,
+                template: raw.template,
+                mode: raw.mode,
+                when: None,
+                sandbox: raw.sandbox,
+            },
+            when: raw.when,
+            preset_name: raw.preset,
+        }
+    }
+}
+
+impl Rule {
+    pub fn new(pattern: impl Into<String>, preset: Preset) -> Self {
+        Self {
+            pattern: pattern.into(),
+            preset,
+            when: None,
+            preset_name: None,
+        }
+    }
+
+    /// Whether `path` matches, and the rule's condition and its preset's
+    /// hold here.
+    pub(crate) fn matches(&self, path: &Path) -> bool {
+        let holds = |when: &Option<String>| matches!(condition::holds(when.as_deref()), Ok(true));
+        if !holds(&self.when) || !holds(&self.preset.when) {
+            return false;
+        }
+        let Ok(pattern) = Pattern::new(&self.pattern) else {
+            return false;
+        };
+        if self.pattern.contains('/') {
+            return pattern.matches_path(path);
+        }
+        path.file_name()
+            .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+    }
+}
+
+/// What `--preset NAME` gives a file: content written inline in the config
+/// and rendered like a template, or a template file, and a mode. Simple
+/// boilerplate such as a script header needs no template file this way.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    /// Template content, usually a multi-line `"""` string
+    pub content: Option<String>,
+    /// Template file, or the name of one in the templates directory
+    pub template: Option<String>,
+    /// Permissions, as `--chmod` takes them
+    pub mode: Option<String>,
+    /// A condition such as `os == 'macos'` the preset only applies under
+    pub when: Option<String>,
+    /// Limits on the commands its content runs with `shell(...)`, unless
+    /// `--sandbox` sets them
+    pub sandbox: Option<Sandbox>,
+}
+
+impl Preset {
+    /// Fills in the content and mode `op` leaves unset.
+    pub fn apply_to(&self, op: &mut FileOp) {
+        let has_content = op.template.is_some()
+            || op.template_text.is_some()
+            || op.write.is_some()
+            || op.stdin
+            || op.copy_from.is_some();
+        if !has_content {
+            op.template = self.template.clone();
+            op.template_text = self.content.clone();
+        }
+        if op.chmod.is_none() {
+            op.chmod = self.mode.clone();
+        }
+        if op.sandbox.is_none() {
+            op.sandbox = self.sandbox.clone();
+        }
+    }
+}
+
+pub const CONFIG_ENV: &str = "TAP_CONFIG";
+
+impl Config {
+    /// Loads the user's config. A missing default config file is not an
+    /// error, but a missing `TAP_CONFIG` file is.
+    pub fn load() -> Result<Option<Self>> {
+        if let Some(path) = std::env::var_os(CONFIG_ENV) {
+            return Self::load_from(Path::new(&path)).map(Some);
+        }
+        let path = xdg_base_dir("XDG_CONFIG_HOME", ".config", |var| std::env::var_os(var))?
+            .join("tap/config.toml");
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::load_from(&path).map(Some)
+    }
+
+    /// Loads the config in `path`, with the files it includes.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let mut config = Self::load_included(path, &mut IncludeStack::default())?;
+        for rule in &mut config.rules {
+            let Some(name) = rule.preset_name.take() else {
+                continue;
+            };
+            rule.preset = config
+                .presets
+                .get(&name)
+                .cloned()
+                .with_context(|| tr!("Unknown preset: {}", name))?;
+        }
+        Ok(config)
+    }
+
+    fn load_included(path: &Path, stack: &mut IncludeStack) -> Result<Self> {
+        stack.push(path)?;
+        let mut config = Self::parse(path)?;
+        let mut under = Self::default();
+        for include in std::mem::take(&mut config.include) {
+            let mut included = Self::load_included(&IncludeStack::resolve(path, &include), stack)
+                .with_context(|| tr!("Included from {}", path.display()))?;
+            included.layer_over(under);
+            under = included;
+        }
+        config.layer_over(under);
+        stack.pop();
+        Ok(config)
+    }
+
+    /// Fills in what this config leaves unset from `under`.
+    fn layer_over(&mut self, under: Self) {
+        self.verbose |= under.verbose;
+        self.undo = self.undo.or(under.undo);
+        self.parents = self.parents.or(under.parents);
+        self.file_mode = self.file_mode.take().or(under.file_mode);
+        self.dir_mode = self.dir_mode.take().or(under.dir_mode);
+        self.templates = self.templates.take().or(under.templates);
+        for (name, preset) in under.presets {
+            self.presets.entry(name).or_insert(preset);
+        }
+        self.rules.extend(under.rules);
+        self.trusted_keys.extend(under.trusted_keys);
+    }
+
+    /// Reads and checks one config file, leaving its includes and the
+    /// presets its rules name to be resolved.
+    fn parse(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| tr!("Failed to read config {}", path.display()))?;
+        let mut config: Self =
+            from_toml(&text).with_context(|| tr!("Invalid config {}", path.display()))?;
+        for mode in [&config.file_mode, &config.dir_mode].into_iter().flatten() {
+            u32::from_str_radix(mode, 8)
+                .with_context(|| tr!("Invalid mode {} in {}", mode, path.display()))?;
+        }
+        for (name, preset) in &config.presets {
+            if preset.content.is_some() && preset.template.is_some() {
+                anyhow::bail!(tr!("Preset {} has both content and a template", name));
+            }
+            if let Some(mode) = &preset.mode {
+                Mode::parse(mode)
+                    .with_context(|| tr!("Invalid mode {} in {}", mode, path.display()))?;
+            }
+            if let Some(when) = &preset.when {
+                Condition::parse(when).with_context(|| tr!("Invalid config {}", path.display()))?;
+            }
+        }
+        for rule in &config.rules {
+            Pattern::new(&rule.pattern)
+                .with_context(|| tr!("Invalid pattern {} in {}", rule.pattern, path.display()))?;
+            if let Some(when) = &rule.when {
+                Condition::parse(when).with_context(|| tr!("Invalid config {}", path.display()))?;
+            }
+            if rule.preset_name.is_none() {
+                if let Some(mode) = &rule.preset.mode {
+                    Mode::parse(mode)
+                        .with_context(|| tr!("Invalid mode {} in {}", mode, path.display()))?;
+                }
+                continue;
+            }
+            let inline = &rule.preset;
+            if inline.content.is_some() || inline.template.is_some() || inline.mode.is_some() {
+                anyhow::bail!(tr!(
+                    "Rule {} names a preset and also sets its own content or mode",
+                    rule.pattern
+                ));
+            }
+        }
+        // `~/` means the home directory; other relative paths are relative
+        // to the config file
+        config.templates = config.templates.map(|templates| {
+            let home = platform::HOME_VARS.iter().find_map(std::env::var_os);
+            match (templates.strip_prefix("~"), home) {
+                (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+                _ => parent_dir(path).join(templates),
+            }
+        });
+        Ok(config)
+    }
+
+    /// The preset `--preset NAME` asks for, which must apply here.
+    pub fn preset(&self, name: &str) -> Result<&Preset> {
+        let preset = self
+            .presets
+            .get(name)
+            .with_context(|| tr!("Unknown preset: {}", name))?;
+        if !condition::holds(preset.when.as_deref())? {
+            anyhow::bail!(tr!(
+                "Preset {} doesn't apply here (when {})",
+                name,
+                preset.when.as_deref().unwrap_or("")
+            ));
+        }
+        Ok(preset)
+    }
+
+    /// Fills in the options `op` leaves unset.
+    pub fn apply_to(&self, op: &mut FileOp) {
+        op.verbose |= self.verbose;
+        if op.new_file_mode.is_none() {
+            op.new_file_mode = self.file_mode.clone();
+        }
+        if op.new_dir_mode.is_none() {
+            op.new_dir_mode = self.dir_mode.clone();
+        }
+        if op.parents.is_none() {
+            op.parents = self.parents;
+        }
+        if op.templates_dir.is_none() {
+            op.templates_dir = self
+                .templates
+                .clone()
+                .or_else(|| default_templates_dir().ok());
+        }
+        if op.rules.is_empty() {
+            op.rules = self.rules.clone();
+        }
+        op.trusted_keys.extend(self.trusted_keys.iter().cloned());
+    }
+}
+
+#[cfg(all(test, feature = "manifest"))]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    use crate::TapBuilder;
+
+    #[test]
+    fn test_config_includes() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join("org.toml"),
+            r##"
+verbose = true
+undo = false
+file_mode = "600"
+dir_mode = "700"
+
+[presets.script]
+content = "#!/bin/sh\n"
+
+[presets.notes]
+content = "org\n"
+
+[[rules]]
+match = "*.md"
+preset = "notes"
+"##,
+        )?;
+        let config_path = dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            r##"
+include = ["org.toml"]
+file_mode = "640"
+
+[presets.notes]
+content = "mine\n"
+
+[[rules]]
+match = "*.sh"
+preset = "script"
+"##,
+        )?;
+        let config = Config::load_from(&config_path)?;
+        assert!(config.verbose);
+        assert_eq!(config.undo, Some(false));
+        assert_eq!(config.file_mode.as_deref(), Some("640"));
+        assert_eq!(config.dir_mode.as_deref(), Some("700"));
+        assert_eq!(
+            config
+                .rules
+                .iter()
+                .map(|r| r.pattern.as_str())
+                .collect::<Vec<_>>(),
+            ["*.sh", "*.md"]
+        );
+        // Rules from an included file use the presets as merged
+        assert_eq!(config.rules[1].preset.content.as_deref(), Some("mine\n"));
+
+        fs::write(dir.path().join("org.toml"), "include = [\"config.toml\"]\n")?;
+        let error = format!("{:#}", Config::load_from(&config_path).unwrap_err());
+        assert!(error.contains("Include cycle"), "{error}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_defaults() -> Result<()> {
+        let dir = tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            "file_mode = \"600\"\ndir_mode = \"700\"\ntemplates = \"templates\"\n",
+        )?;
+        fs::create_dir(dir.path().join("templates"))?;
+        fs::write(dir.path().join("templates/license"), "MIT\n")?;
+        let config = Config::load_from(&config_path)?;
+
+        let mut op = FileOp {
+            template: Some("license".to_string()),
+            ..Default::default()
+        };
+        config.apply_to(&mut op);
+        let file = dir.path().join("LICENSE");
+        op.apply(&file)?;
+        assert_eq!(fs::read_to_string(&file)?, "MIT\n");
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&file)?.permissions().mode() & 0o777, 0o600);
+
+        // Existing files keep their modes; explicit flags win
+        #[cfg(unix)]
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644))?;
+        op.apply(&file)?;
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&file)?.permissions().mode() & 0o777, 0o644);
+        let mut op = FileOp {
+            dir: true,
+            chmod: Some("750".to_string()),
+            ..Default::default()
+        };
+        config.apply_to(&mut op);
+        op.apply(&dir.path().join("shared"))?;
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(dir.path().join("shared"))?
+                .permissions()
+                .mode()
+                & 0o777,
+            0o750
+        );
+
+        fs::write(&config_path, "parents = false\n")?;
+        let mut op = FileOp::default();
+        Config::load_from(&config_path)?.apply_to(&mut op);
+        assert!(op.apply(&dir.path().join("missing/file")).is_err());
+        let mut op = TapBuilder::new().parents(true).build();
+        Config::load_from(&config_path)?.apply_to(&mut op);
+        op.apply(&dir.path().join("missing/file"))?;
+
+        fs::write(&config_path, "file_mode = \"rw\"\n")?;
+        assert!(Config::load_from(&config_path).is_err());
+        fs::write(&config_path, "verbos = true\n")?;
+        assert!(Config::load_from(&config_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_presets() -> Result<()> {
+        let dir = tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            r#"
+[presets.script]
+mode = "755"
+content = """
+#!/usr/bin/env bash
+set -euo pipefail
+"""
+"#,
+        )?;
+        let config = Config::load_from(&config_path)?;
+        assert!(config.preset("missing").is_err());
+
+        let mut op = FileOp::default();
+        config.preset("script")?.apply_to(&mut op);
+        let script = dir.path().join("deploy.sh");
+        op.apply(&script)?;
+        assert_eq!(
+            fs::read_to_string(&script)?,
+            "#!/usr/bin/env bash\nset -euo pipefail\n"
+        );
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&script)?.permissions().mode() & 0o777, 0o755);
+
+        // Content given on the command line wins
+        let mut op = FileOp {
+            write: Some("echo hi".to_string()),
+            ..Default::default()
+        };
+        config.preset("script")?.apply_to(&mut op);
+        assert_eq!(op.template_text, None);
+
+        // A preset's sandbox, unless --sandbox gives one
+        fs::write(
+            &config_path,
+            "[presets.stamp]\ncontent = \"x\"\nsandbox = { keep_env = [\"TERM\"] }\n",
+        )?;
+        let config = Config::load_from(&config_path)?;
+        let mut op = FileOp::default();
+        config.preset("stamp")?.apply_to(&mut op);
+        let sandbox = op.sandbox.clone().unwrap();
+        assert_eq!(
+            (sandbox.keep_env, sandbox.network),
+            (vec!["TERM".to_string()], false)
+        );
+        let given = Sandbox {
+            network: true,
+            ..Sandbox::default()
+        };
+        let mut op = TapBuilder::new().sandbox(given.clone()).build();
+        config.preset("stamp")?.apply_to(&mut op);
+        assert_eq!(op.sandbox, Some(given));
+
+        fs::write(
+            &config_path,
+            "[presets.both]\ncontent = \"x\"\ntemplate = \"t\"\n",
+        )?;
+        assert!(Config::load_from(&config_path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rules_pick_presets_for_new_files() -> Result<()> {
+        let dir = tempdir()?;
+        let config_path = dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            r##"
+[presets.script]
+mode = "755"
+content = "#!/bin/sh\n"
+
+[[rules]]
+match = "*.sh"
+preset = "script"
+
+[[rules]]
+match = "*/tools/*.py"
+content = "#!/usr/bin/env python3\n"
+"##,
+        )?;
+        let config = Config::load_from(&config_path)?;
+        let mut op = FileOp::default();
+        config.apply_to(&mut op);
+
+        let script = dir.path().join("deploy.sh");
+        let tool = dir.path().join("tools/lint.py");
+        let notes = dir.path().join("notes.txt");
+        for path in [&script, &tool, &notes] {
+            op.apply(path)?;
+        }
+        assert_eq!(fs::read_to_string(&script)?, "#!/bin/sh\n");
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&script)?.permissions().mode() & 0o777, 0o755);
+        assert_eq!(fs::read_to_string(&tool)?, "#!/usr/bin/env python3\n");
+        assert_eq!(fs::read_to_string(&notes)?, "");
+
+        // Existing files and explicit content are left to the command line
+        fs::write(&script, "echo mine\n")?;
+        op.apply(&script)?;
+        assert_eq!(fs::read_to_string(&script)?, "echo mine\n");
+        let written = FileOp {
+            write: Some("echo hi\n".to_string()),
+            ..op.clone()
+        };
+        written.apply(&dir.path().join("other.sh"))?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("other.sh"))?,
+            "echo hi\n"
+        );
+
+        fs::write(
+            &config_path,
+            "[[rules]]\nmatch = \"*.sh\"\npreset = \"nope\"\n",
+        )?;
+        assert!(Config::load_from(&config_path).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! `tap conventions`: starter `.gitignore`, `.editorconfig` and
+//! `.gitattributes` bundles, kept in managed blocks.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use crate::{replace_content, say, tr, FileOp};
+
+/// Languages with a convention bundle for `tap conventions`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Language {
+    Rust,
+    Go,
+    Python,
+    Node,
+}
+
+impl Language {
+    /// Guesses the project's language from the build files in `dir`.
+    pub fn detect(dir: &Path) -> Option<Self> {
+        let markers: [(&str, Self); 6] = [
+            ("Cargo.toml", Self::Rust),
+            ("go.mod", Self::Go),
+            ("pyproject.toml", Self::Python),
+            ("setup.py", Self::Python),
+            ("requirements.txt", Self::Python),
+            ("package.json", Self::Node),
+        ];
+        markers
+            .iter()
+            .find(|(marker, _)| dir.join(marker).exists())
+            .map(|(_, language)| *language)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Rust => "rust",
+            Self::Go => "go",
+            Self::Python => "python",
+            Self::Node => "node",
+        }
+    }
+
+    fn gitignore(self) -> &'static str {
+        match self {
+            Self::Rust => "/target/\n**/*.rs.bk\n",
+            Self::Go => "/bin/\n*.exe\n*.test\n*.out\n",
+            Self::Python => {
+                "__pycache__/\n*.py[cod]\n.venv/\n*.egg-info/\n/build/\n/dist/\n.pytest_cache/\n"
+            }
+            Self::Node => "node_modules/\n/dist/\nnpm-debug.log*\n",
+        }
+    }
+
+    fn editorconfig(self) -> String {
+        let (indent, extra) = match self {
+            Self::Rust | Self::Python => ("indent_style = space\nindent_size = 4\n", ""),
+            Self::Go => (
+                "indent_style = space\nindent_size = 4\n",
+                "\n[*.go]\nindent_style = tab\n",
+            ),
+            Self::Node => ("indent_style = space\nindent_size = 2\n", ""),
+        };
+        format!(
+            "[*]\ncharset = utf-8\nend_of_line = lf\ninsert_final_newline = true\n\
+             trim_trailing_whitespace = true\n{}{}\n[Makefile]\nindent_style = tab\n",
+            indent, extra
+        )
+    }
+
+    fn gitattributes(self) -> &'static str {
+        match self {
+            Self::Rust => "* text=auto eol=lf\n*.rs diff=rust\n",
+            Self::Go => "* text=auto eol=lf\n*.go diff=golang\n",
+            Self::Python => "* text=auto eol=lf\n*.py diff=python\n",
+            Self::Node => "* text=auto eol=lf\npackage-lock.json -diff linguist-generated\n",
+        }
+    }
+}
+
+const CONVENTIONS_BLOCK: &str = "tap conventions";
+
+/// Writes the starter `.gitignore`, `.editorconfig` and `.gitattributes` for
+/// `language` into `dir`. Each bundle lives in a managed block, so existing
+/// entries are kept and re-running updates only tap's part. Returns the
+/// files that changed.
+pub fn write_conventions(dir: &Path, language: Language, verbose: bool) -> Result<Vec<PathBuf>> {
+    let files = [
+        (".gitignore", language.gitignore().to_string(), ""),
+        (".editorconfig", language.editorconfig(), "root = true\n"),
+        (".gitattributes", language.gitattributes().to_string(), ""),
+    ];
+    let mut changed = Vec::new();
+    for (name, content, header) in files {
+        let path = dir.join(name);
+        let content = format!("# {} bundle\n{}", language.name(), content);
+        if write_managed_block(&path, CONVENTIONS_BLOCK, &content, header)? {
+            if verbose {
+                say!("Conventions written to: {}", path.display());
+            }
+            changed.push(path);
+        }
+    }
+    Ok(changed)
+}
+
+/// Puts `content` between `# >>> id >>>` and `# <<< id <<<` marker lines,
+/// replacing what an earlier run left there or appending the block. A new
+/// file starts with `header`. Returns whether the file changed.
+pub fn write_managed_block(path: &Path, id: &str, content: &str, header: &str) -> Result<bool> {
+    let start = format!("# >>> {} >>>", id);
+    let end = format!("# <<< {} <<<", id);
+    let existing = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => header.to_string(),
+        Err(e) => return Err(e).with_context(|| tr!("Failed to read {}", path.display())),
+    };
+    let block = format!("{}\n{}{}\n", start, content, end);
+
+    let lines = existing.lines().collect::<Vec<_>>();
+    let begin = lines.iter().position(|line| line.trim_end() == start);
+    let finish = begin.and_then(|begin| {
+        lines[begin..]
+            .iter()
+            .position(|line| line.trim_end() == end)
+            .map(|offset| begin + offset)
+    });
+    let updated = match (begin, finish) {
+        (Some(begin), Some(finish)) => {
+            let before = lines[..begin].iter().map(|line| format!("{}\n", line));
+            let after = lines[finish + 1..].iter().map(|line| format!("{}\n", line));
+            before
+                .chain(std::iter::once(block))
+                .chain(after)
+                .collect::<String>()
+        }
+        (Some(_), None) => {
+            anyhow::bail!(tr!("Unterminated '{}' block in {}", start, path.display()))
+        }
+        _ => {
+            let mut updated = existing.clone();
+            if !updated.is_empty() {
+                if !updated.ends_with('\n') {
+                    updated.push('\n');
+                }
+                if !updated.ends_with("\n\n") {
+                    updated.push('\n');
+                }
+            }
+            updated.push_str(&block);
+            updated
+        }
+    };
+    if updated == existing {
+        return Ok(false);
+    }
+    replace_content(path, updated.as_bytes(), &FileOp::default())
+        .with_context(|| tr!("Failed to write {}", path.display()))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_conventions() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("Cargo.toml"), "")?;
+        fs::write(dir.path().join(".gitignore"), "/secrets")?;
+        assert_eq!(Language::detect(dir.path()), Some(Language::Rust));
+
+        let changed = write_conventions(dir.path(), Language::Rust, false)?;
+        assert_eq!(changed.len(), 3);
+        let gitignore = fs::read_to_string(dir.path().join(".gitignore"))?;
+        assert!(gitignore.starts_with("/secrets\n\n# >>> tap conventions >>>\n"));
+        assert!(gitignore.contains("/target/\n"));
+        let editorconfig = fs::read_to_string(dir.path().join(".editorconfig"))?;
+        assert!(editorconfig.starts_with("root = true\n\n# >>> tap conventions >>>\n"));
+
+        // Running again changes nothing; switching languages replaces the block
+        assert!(write_conventions(dir.path(), Language::Rust, false)?.is_empty());
+        write_conventions(dir.path(), Language::Node, false)?;
+        let gitignore = fs::read_to_string(dir.path().join(".gitignore"))?;
+        assert!(gitignore.contains("node_modules/"));
+        assert!(!gitignore.contains("/target/"));
+        assert_eq!(gitignore.matches(">>> tap conventions >>>").count(), 1);
+        assert!(gitignore.starts_with("/secrets\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! `tap env`: creating and updating `.env` files in place.
+
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::platform::ModeExt;
+use crate::{say, tr};
+
+/// A `.env` file, kept line by line so comments, blank lines and the order of
+/// keys survive edits.
+pub struct DotEnv {
+    lines: Vec<String>,
+}
+
+impl DotEnv {
+    pub fn parse(text: &str) -> Self {
+        Self {
+            lines: text.lines().map(str::to_string).collect(),
+        }
+    }
+
+    /// The key a line assigns, if it is an assignment (`KEY=value` or
+    /// `export KEY=value`).
+    fn line_key(line: &str) -> Option<&str> {
+        let line = line.trim_start();
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, _) = line.split_once('=')?;
+        let key = key.trim_end();
+        valid_env_key(key).then_some(key)
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|line| Self::line_key(line) == Some(key))
+    }
+
+    /// The raw value assigned to `key`, quotes included.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let line = &self.lines[self.position(key)?];
+        Some(line.split_once('=')?.1.trim())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter_map(|line| Self::line_key(line))
+    }
+
+    /// Sets `key`, replacing an existing assignment in place (keeping an
+    /// `export` prefix) only when `overwrite` is set. Returns whether
+    /// anything changed.
+    pub fn set(&mut self, key: &str, value: &str, overwrite: bool) -> bool {
+        let value = quote_env_value(value);
+        match self.position(key) {
+            Some(index) => {
+                let line = &self.lines[index];
+                let (assignment, current) = line.split_once('=').unwrap_or((line, ""));
+                if !overwrite || current.trim() == value {
+                    return false;
+                }
+                self.lines[index] = format!("{}={}", assignment, value);
+            }
+            None => self.lines.push(format!("{}={}", key, value)),
+        }
+        true
+    }
+
+    pub fn render(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+fn valid_env_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Double-quotes values that dotenv parsers would otherwise split or expand.
+fn quote_env_value(value: &str) -> String {
+    let plain = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-./:,@+%".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!(
+            "\"{}\"",
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('$', "\\$")
+                .replace('\n', "\\n")
+        )
+    }
+}
+
+/// Adds or updates keys in a `.env` file, creating it with mode 600.
+///
+/// With `example`, keys from that file which are missing get the example's
+/// value, or one asked for with `prompt` when the example leaves it blank.
+/// `--set` values take precedence over the example; with `unless_exists`
+/// neither replaces a key the file already has.
+pub fn update_dotenv<F>(
+    path: &Path,
+    sets: &[(String, String)],
+    unless_exists: bool,
+    example: Option<&Path>,
+    mut prompt: F,
+    verbose: bool,
+) -> Result<()>
+where
+    F: FnMut(&str) -> Result<String>,
+{
+    for (key, _) in sets {
+        if !valid_env_key(key) {
+            anyhow::bail!(tr!("Invalid variable name: {}", key));
+        }
+    }
+    let existing = match fs::read_to_string(path) {
+        Ok(text) => Some(text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| tr!("Failed to read {}", path.display())),
+    };
+    let mut env = DotEnv::parse(existing.as_deref().unwrap_or_default());
+    let mut changed = false;
+
+    if let Some(example) = example {
+        let text = fs::read_to_string(example)
+            .with_context(|| tr!("Failed to read {}", example.display()))?;
+        let example = DotEnv::parse(&text);
+        for key in example.keys() {
+            if env.get(key).is_some() || sets.iter().any(|(set, _)| set == key) {
+                continue;
+            }
+            match example.get(key) {
+                Some(value) if !value.is_empty() => {
+                    env.lines.push(format!("{}={}", key, value));
+                }
+                _ => {
+                    let value = prompt(key)?;
+                    env.set(key, &value, false);
+                }
+            }
+            changed = true;
+        }
+    }
+    for (key, value) in sets {
+        changed |= env.set(key, value, !unless_exists);
+    }
+
+    if existing.is_none() {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .with_mode(0o600)
+            .open(path)
+            .with_context(|| tr!("Failed to create {}", path.display()))?;
+    } else if !changed {
+        return Ok(());
+    }
+    fs::write(path, env.render()).with_context(|| tr!("Failed to write {}", path.display()))?;
+    if verbose {
+        say!("Environment file updated: {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_update_dotenv() -> Result<()> {
+        let dir = tempdir()?;
+        let env = dir.path().join(".env");
+        let example = dir.path().join(".env.example");
+        fs::write(
+            &example,
+            "# Database\nDATABASE_URL=postgres://localhost/app\nSECRET_KEY=\nPORT=\n",
+        )?;
+
+        let sets = [("PORT".to_string(), "8080".to_string())];
+        let mut asked = Vec::new();
+        update_dotenv(
+            &env,
+            &sets,
+            false,
+            Some(&example),
+            |key| {
+                asked.push(key.to_string());
+                Ok("s3cret value".to_string())
+            },
+            false,
+        )?;
+        assert_eq!(asked, ["SECRET_KEY"]);
+        assert_eq!(
+            fs::read_to_string(&env)?,
+            "DATABASE_URL=postgres://localhost/app\nSECRET_KEY=\"s3cret value\"\nPORT=8080\n"
+        );
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&env)?.permissions().mode() & 0o777, 0o600);
+
+        fs::write(&env, "# local\nexport PORT=3000\nDEBUG=1\n")?;
+        let no_prompt = |_: &str| -> Result<String> { anyhow::bail!("unexpected prompt") };
+        update_dotenv(&env, &sets, true, None, no_prompt, false)?;
+        assert_eq!(
+            fs::read_to_string(&env)?,
+            "# local\nexport PORT=3000\nDEBUG=1\n"
+        );
+        update_dotenv(&env, &sets, false, None, no_prompt, false)?;
+        assert_eq!(
+            fs::read_to_string(&env)?,
+            "# local\nexport PORT=8080\nDEBUG=1\n"
+        );
+
+        let bad = [("1BAD".to_string(), "x".to_string())];
+        assert!(update_dotenv(&env, &bad, false, None, no_prompt, false).is_err());
+        Ok(())
+    }
+}
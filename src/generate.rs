@@ -0,0 +1,595 @@
+//! Generated content for test fixtures: `--fill` payloads of random,
+//! zero or repeated bytes, `--lorem` placeholder prose and the `{uuid}`
+//! style tokens of `--write` content, from a small seedable generator so
+//! that a `--seed` gives the same content on every run and every machine.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, SecondsFormat};
+
+use crate::tr;
+use crate::units::{parse_size, show_size};
+
+/// SplitMix64: fast, statistically sound for fixtures, and fully
+/// determined by its seed. Not for secrets.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// A generator from `seed` and `key`, so that each of many things given
+    /// the same seed, such as the files of a run, gets its own values that
+    /// are still the same every time.
+    pub fn keyed(seed: u64, key: &[u8]) -> Self {
+        // FNV-1a, stable across platforms and releases unlike `Hash`
+        let key = key.iter().fold(0xCBF2_9CE4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01B3)
+        });
+        Self(Self(seed ^ key).next_u64())
+    }
+
+    /// A generator seeded from the randomness the standard library keys its
+    /// hash maps with, so no two differ.
+    pub fn from_entropy() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        hasher.write_u128(now.as_nanos());
+        Self(hasher.finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`, which must not be 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// A parsed `--fill` value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fill {
+    /// `random:SIZE`
+    Random(u64),
+    /// `zeros:SIZE`
+    Zeros(u64),
+    /// `pattern:BYTE:SIZE`, the byte over and over
+    Pattern(u8, u64),
+}
+
+impl Fill {
+    pub fn parse(input: &str) -> Result<Self> {
+        let (kind, rest) = input.split_once(':').unwrap_or((input, ""));
+        let fill = match kind {
+            "random" => Self::Random(parse_size(rest)?),
+            "zeros" => Self::Zeros(parse_size(rest)?),
+            "pattern" => {
+                let (byte, size) = rest
+                    .rsplit_once(':')
+                    .with_context(|| tr!("Expected pattern:BYTE:SIZE: {}", input))?;
+                Self::Pattern(parse_byte(byte)?, parse_size(size)?)
+            }
+            _ => anyhow::bail!(tr!(
+                "Unknown fill '{}' (expected random:SIZE, zeros:SIZE or pattern:BYTE:SIZE)",
+                input
+            )),
+        };
+        if fill.size() > MAX_FILL {
+            anyhow::bail!(tr!(
+                "Fill too large in {} (at most {})",
+                input,
+                show_size(MAX_FILL, false)
+            ));
+        }
+        Ok(fill)
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Random(size) | Self::Zeros(size) | Self::Pattern(_, size) => *size,
+        }
+    }
+
+    /// Writes the payload to `out` a chunk at a time, so a large one never
+    /// has to fit in memory. Only `Random` draws from `rng`.
+    pub fn write_to(&self, out: &mut impl Write, rng: &mut Rng) -> io::Result<()> {
+        const CHUNK: usize = 1 << 16;
+        let mut chunk = vec![
+            match self {
+                Self::Pattern(byte, _) => *byte,
+                _ => 0,
+            };
+            CHUNK
+        ];
+        let mut left = self.size();
+        while left > 0 {
+            let len = left.min(CHUNK as u64) as usize;
+            if let Self::Random(_) = self {
+                rng.fill_bytes(&mut chunk[..len]);
+            }
+            out.write_all(&chunk[..len])?;
+            left -= len as u64;
+        }
+        Ok(())
+    }
+}
+
+/// The start of the classic placeholder text, which every `Lorem` begins
+/// with.
+const LOREM_START: &[&str] = &["lorem", "ipsum", "dolor", "sit", "amet"];
+
+/// The words the rest is made up from.
+const LOREM_WORDS: &[&str] = &[
+    "consectetur",
+    "adipiscing",
+    "elit",
+    "sed",
+    "do",
+    "eiusmod",
+    "tempor",
+    "incididunt",
+    "ut",
+    "labore",
+    "et",
+    "dolore",
+    "magna",
+    "aliqua",
+    "enim",
+    "ad",
+    "minim",
+    "veniam",
+    "quis",
+    "nostrud",
+    "exercitation",
+    "ullamco",
+    "laboris",
+    "nisi",
+    "aliquip",
+    "ex",
+    "ea",
+    "commodo",
+    "consequat",
+    "duis",
+    "aute",
+    "irure",
+    "in",
+    "reprehenderit",
+    "voluptate",
+    "velit",
+    "esse",
+    "cillum",
+    "fugiat",
+    "nulla",
+    "pariatur",
+    "excepteur",
+    "sint",
+    "occaecat",
+    "cupidatat",
+    "non",
+    "proident",
+    "sunt",
+    "culpa",
+    "qui",
+    "officia",
+    "deserunt",
+    "mollit",
+    "anim",
+    "id",
+    "est",
+    "laborum",
+    "perspiciatis",
+    "unde",
+    "omnis",
+    "iste",
+    "natus",
+    "error",
+    "voluptatem",
+    "accusantium",
+    "doloremque",
+    "laudantium",
+    "totam",
+    "rem",
+    "aperiam",
+    "eaque",
+    "ipsa",
+    "quae",
+    "ab",
+    "illo",
+    "inventore",
+    "veritatis",
+    "quasi",
+    "architecto",
+    "beatae",
+    "vitae",
+    "dicta",
+    "explicabo",
+    "nemo",
+    "ipsam",
+    "quia",
+    "voluptas",
+    "aspernatur",
+    "aut",
+    "odit",
+    "fugit",
+    "magnam",
+    "dolores",
+    "eos",
+    "ratione",
+    "sequi",
+    "nesciunt",
+    "neque",
+    "porro",
+    "quisquam",
+    "dolorem",
+    "numquam",
+    "eius",
+    "modi",
+    "tempora",
+    "incidunt",
+];
+
+/// A parsed `--lorem` value: how much placeholder prose to write.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Lorem {
+    /// `words:N`, as one sentence
+    Words(usize),
+    /// `sentences:N`, as one paragraph
+    Sentences(usize),
+    /// `paragraphs:N`, separated by blank lines
+    Paragraphs(usize),
+}
+
+impl Lorem {
+    /// `words:N`, `sentences:N` or `paragraphs:N`; without `:N`, one.
+    pub fn parse(input: &str) -> Result<Self> {
+        let (kind, count) = match input.split_once(':') {
+            Some((kind, count)) => (kind, count.trim().parse().ok().filter(|n| *n > 0)),
+            None => (input, Some(1)),
+        };
+        let lorem: fn(usize) -> Self = match kind {
+            "words" | "word" => Self::Words,
+            "sentences" | "sentence" => Self::Sentences,
+            "paragraphs" | "paragraph" => Self::Paragraphs,
+            _ => anyhow::bail!(tr!(
+                "Unknown placeholder text '{}' (expected words:N, sentences:N or paragraphs:N)",
+                input
+            )),
+        };
+        let count = count.with_context(|| tr!("Expected a count of at least 1: {}", input))?;
+        if count > MAX_COUNT {
+            anyhow::bail!(tr!("Count too large in {} (at most {})", input, MAX_COUNT));
+        }
+        Ok(lorem(count))
+    }
+
+    /// The text, ending in a newline.
+    pub fn text(&self, rng: &mut Rng) -> String {
+        let mut prose = Prose { rng, words: 0 };
+        let paragraphs = match *self {
+            Self::Words(count) => vec![prose.sentence(count)],
+            Self::Sentences(count) => vec![prose.paragraph(count)],
+            Self::Paragraphs(count) => (0..count)
+                .map(|_| {
+                    let sentences = 4 + prose.rng.below(4) as usize;
+                    prose.paragraph(sentences)
+                })
+                .collect(),
+        };
+        paragraphs.join("\n\n") + "\n"
+    }
+}
+
+/// Placeholder prose, written a word at a time.
+struct Prose<'a> {
+    rng: &'a mut Rng,
+    /// How many words have been written, to start with `LOREM_START`
+    words: usize,
+}
+
+impl Prose<'_> {
+    fn word(&mut self) -> &'static str {
+        let word = match LOREM_START.get(self.words) {
+            Some(word) => word,
+            None => LOREM_WORDS[self.rng.below(LOREM_WORDS.len() as u64) as usize],
+        };
+        self.words += 1;
+        word
+    }
+
+    fn sentence(&mut self, len: usize) -> String {
+        let mut sentence = String::new();
+        let mut since_comma = 0;
+        for i in 0..len {
+            let word = self.word();
+            if i == 0 {
+                sentence.push_str(&word[..1].to_ascii_uppercase());
+                sentence.push_str(&word[1..]);
+                continue;
+            }
+            // A comma now and then: a few words apart, not before the last
+            // word, and not in the classic opening
+            since_comma += 1;
+            if since_comma > 3
+                && i + 1 < len
+                && self.words > LOREM_START.len() + 1
+                && self.rng.below(6) == 0
+            {
+                sentence.push(',');
+                since_comma = 0;
+            }
+            sentence.push(' ');
+            sentence.push_str(word);
+        }
+        sentence.push('.');
+        sentence
+    }
+
+    fn paragraph(&mut self, sentences: usize) -> String {
+        (0..sentences)
+            .map(|_| {
+                let len = 6 + self.rng.below(9) as usize;
+                self.sentence(len)
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The most digits a `{hex:N}` token, or words, sentences or paragraphs a
+/// `--lorem`, may ask for: far more than any fixture needs, and little
+/// enough that a typo can't exhaust memory.
+pub const MAX_COUNT: usize = 100_000;
+
+/// The largest payload `--fill` writes, 64 GiB: room for big test files,
+/// while a size with a few digits too many is an error rather than a full
+/// disk.
+pub const MAX_FILL: u64 = 64 << 30;
+
+/// Expands the generator tokens in `content`, with fresh values on every
+/// call: `{uuid}` (a random version 4 UUID), `{hex:N}` (N random hex
+/// digits), and `{now}` or `{now:FORMAT}` (the local time, as RFC 3339 or
+/// strftime FORMAT). Anything else in braces is left as written, so JSON
+/// and the like come through untouched. A token in double braces, such as
+/// `{{uuid}}`, is written as the token itself in single ones.
+pub fn expand_tokens(content: &str, rng: &mut Rng) -> Result<String> {
+    let mut expanded = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(escaped) = escaped_token(after) {
+            expanded.push_str(&after[..escaped.len() + 2]);
+            rest = &after[escaped.len() + 3..];
+            continue;
+        }
+        let token = after
+            .find(['{', '}'])
+            .filter(|&end| after[end..].starts_with('}'));
+        match token.map(|end| (&after[..end], expand_token(&after[..end], rng))) {
+            Some((token, Some(value))) => {
+                expanded.push_str(&value?);
+                rest = &after[token.len() + 1..];
+            }
+            _ => {
+                expanded.push('{');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The token of a `{{token}}` escape at the start of `after`, what follows
+/// its first brace.
+fn escaped_token(after: &str) -> Option<&str> {
+    let inner = after.strip_prefix('{')?;
+    let token = &inner[..inner.find(['{', '}'])?];
+    let is_token = match token.split_once(':') {
+        Some((name, _)) => name == "hex" || name == "now",
+        None => token == "uuid" || token == "now",
+    };
+    (is_token && inner[token.len()..].starts_with("}}")).then_some(token)
+}
+
+/// The value of one token, or `None` if it isn't one.
+fn expand_token(token: &str, rng: &mut Rng) -> Option<Result<String>> {
+    let (name, argument) = match token.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (token, None),
+    };
+    Some(match (name, argument) {
+        ("uuid", None) => Ok(uuid(rng)),
+        ("hex", Some(digits)) => match digits.parse::<usize>() {
+            Ok(digits) if digits > MAX_COUNT => Err(anyhow::anyhow!(tr!(
+                "Count too large in {} (at most {})",
+                format!("{{{}}}", token),
+                MAX_COUNT
+            ))),
+            Ok(digits) if digits > 0 => {
+                let mut bytes = vec![0; digits.div_ceil(2)];
+                rng.fill_bytes(&mut bytes);
+                let mut hex = hex(&bytes);
+                hex.truncate(digits);
+                Ok(hex)
+            }
+            _ => Err(anyhow::anyhow!(tr!(
+                "Invalid length in {}: expected a number of digits",
+                format!("{{{}}}", token)
+            ))),
+        },
+        ("now", None) => Ok(Local::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+        ("now", Some("")) => Err(anyhow::anyhow!(tr!(
+            "Empty time format in {}",
+            format!("{{{}}}", token)
+        ))),
+        ("now", Some(format)) => {
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                Err(anyhow::anyhow!(tr!("Invalid time format: {}", format)))
+            } else {
+                Ok(Local::now().format(format).to_string())
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn uuid(rng: &mut Rng) -> String {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex(&bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A byte as `pattern:` takes it: `171`, `0xAB`, or a character such as `x`.
+fn parse_byte(input: &str) -> Result<u8> {
+    let parsed = match input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None if input.len() > 1 || input.bytes().all(|b| b.is_ascii_digit()) => input.parse().ok(),
+        None => input.bytes().next().filter(u8::is_ascii),
+    };
+    parsed.with_context(|| {
+        tr!(
+            "Invalid fill byte '{}' (expected 0 to 255, 0x00 to 0xFF, or a single character)",
+            input
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fill() -> Result<()> {
+        assert_eq!(Fill::parse("random:1M")?, Fill::Random(1 << 20));
+        assert_eq!(Fill::parse("zeros:512")?, Fill::Zeros(512));
+        assert_eq!(Fill::parse("pattern:0xAB:4K")?, Fill::Pattern(0xAB, 4096));
+        assert_eq!(Fill::parse("pattern:7:1")?, Fill::Pattern(7, 1));
+        assert_eq!(Fill::parse("pattern:x:1")?, Fill::Pattern(b'x', 1));
+        assert!(Fill::parse("pattern:256:1").is_err());
+        assert!(Fill::parse("pattern:1K").is_err());
+        assert!(Fill::parse("noise:1M").is_err());
+        assert!(Fill::parse("random").is_err());
+        assert_eq!(Fill::parse("zeros:64G")?, Fill::Zeros(MAX_FILL));
+        let error = Fill::parse("random:99999999999999999").unwrap_err();
+        assert!(error.to_string().contains("Fill too large"), "{error}");
+        assert!(Fill::parse("pattern:0:65G").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seeded_fill() -> Result<()> {
+        let random = |seed| -> Result<Vec<u8>> {
+            let mut out = Vec::new();
+            Fill::Random(100_000).write_to(&mut out, &mut Rng::seeded(seed))?;
+            Ok(out)
+        };
+        assert_eq!(random(42)?, random(42)?);
+        assert_ne!(random(42)?, random(43)?);
+        assert_eq!(random(42)?.len(), 100_000);
+
+        let mut out = Vec::new();
+        Fill::Pattern(b'-', 3).write_to(&mut out, &mut Rng::from_entropy())?;
+        assert_eq!(out, b"---");
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_tokens() -> Result<()> {
+        let mut rng = Rng::seeded(5);
+        let expanded = expand_tokens("id={uuid} key={hex:5}", &mut rng)?;
+        let (id, key) = expanded
+            .strip_prefix("id=")
+            .and_then(|rest| rest.split_once(" key="))
+            .unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(id.matches('-').count(), 4);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]));
+        assert_eq!(key.len(), 5);
+        assert!(key.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(expand_tokens("{uuid}", &mut rng)?, id);
+
+        let today = Local::now().format("%Y").to_string();
+        assert_eq!(expand_tokens("{now:%Y}", &mut rng)?, today);
+        assert!(expand_tokens("{now}", &mut rng)?.starts_with(&today));
+
+        // Only the tokens themselves are touched
+        let json = r#"{"id": "{uuid}", "tags": {}, "name": "{name}"}"#;
+        let expanded = expand_tokens(json, &mut rng)?;
+        assert!(expanded.starts_with(r#"{"id": ""#));
+        assert!(expanded.ends_with(r#"", "tags": {}, "name": "{name}"}"#));
+        assert!(!expanded.contains("{uuid}"));
+
+        assert!(expand_tokens("{hex:0}", &mut rng).is_err());
+        assert_eq!(expand_tokens("{hex:100000}", &mut rng)?.len(), MAX_COUNT);
+        assert!(expand_tokens("{hex:999999999999999999}", &mut rng).is_err());
+        assert!(expand_tokens("{now:%Q}", &mut rng).is_err());
+        assert!(expand_tokens("{now:}", &mut rng).is_err());
+
+        // Doubled braces write a token as it is
+        assert_eq!(
+            expand_tokens("{{uuid}} {{hex:8}} {{now}} {{now:%Y}}", &mut rng)?,
+            "{uuid} {hex:8} {now} {now:%Y}"
+        );
+        assert_eq!(expand_tokens("{{name}}", &mut rng)?, "{{name}}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_lorem() -> Result<()> {
+        let text =
+            |lorem: &str| Ok::<_, anyhow::Error>(Lorem::parse(lorem)?.text(&mut Rng::seeded(1)));
+        assert_eq!(text("words:5")?, "Lorem ipsum dolor sit amet.\n");
+        assert_eq!(text("words:12")?.split(' ').count(), 12);
+        assert_eq!(text("sentences:3")?.matches(". ").count(), 2);
+        let paragraphs = text("paragraphs:3")?;
+        assert!(paragraphs.starts_with("Lorem ipsum dolor sit amet"));
+        assert_eq!(paragraphs.split("\n\n").count(), 3);
+        assert_eq!(paragraphs, text("paragraphs:3")?);
+        assert_eq!(Lorem::parse("paragraph")?, Lorem::Paragraphs(1));
+        assert!(Lorem::parse("words:0").is_err());
+        assert_eq!(Lorem::parse("words:100000")?, Lorem::Words(MAX_COUNT));
+        assert!(Lorem::parse("words:99999999999999999").is_err());
+        assert!(Lorem::parse("pages:2").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+//! The little HTTP tap needs: posting run summaries and fetching templates.
+//! Plain HTTP is spoken directly; HTTPS goes through `curl`, so tap needs no
+//! TLS stack of its own.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::tr;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest body `get` reads, 16 MiB: more than any template, and little
+/// enough that a runaway server can't exhaust memory.
+const MAX_BODY: u64 = 16 << 20;
+
+/// POSTs `body` to `url` as JSON.
+pub fn post_json(url: &str, body: &[u8]) -> Result<()> {
+    let Some(address) = url.strip_prefix("http://") else {
+        return curl(url, Some(body)).map(drop);
+    };
+    let (mut stream, authority, path) = connect(address)?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: tap/{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        authority,
+        env!("CARGO_PKG_VERSION"),
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    read_status(&mut BufReader::new(stream))
+}
+
+/// The body of `url`. Redirects are not followed.
+pub fn get(url: &str) -> Result<Vec<u8>> {
+    let Some(address) = url.strip_prefix("http://") else {
+        return curl(url, None);
+    };
+    let (mut stream, authority, path) = connect(address)?;
+    // HTTP/1.0, so the body is sent as it is and ends with the connection
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: tap/{}\r\n\r\n",
+        path,
+        authority,
+        env!("CARGO_PKG_VERSION")
+    )?;
+    let mut reader = BufReader::new(stream);
+    read_status(&mut reader)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+    let mut body = Vec::new();
+    reader.take(MAX_BODY + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BODY {
+        anyhow::bail!(tr!("Response larger than {} bytes", MAX_BODY));
+    }
+    Ok(body)
+}
+
+/// A connection to the host of `address`, a URL without its `http://`,
+/// with the authority and path to ask it for.
+fn connect(address: &str) -> Result<(TcpStream, &str, &str)> {
+    let address = address.split('#').next().unwrap_or_default();
+    let (authority, path) = match address.find('/') {
+        Some(slash) => address.split_at(slash),
+        None => (address, "/"),
+    };
+    // A colon inside brackets is part of an IPv6 address, not a port
+    let target = match authority.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') => authority.to_string(),
+        _ => format!("{}:80", authority),
+    };
+    let socket = target
+        .to_socket_addrs()?
+        .next()
+        .with_context(|| tr!("No address for {}", authority))?;
+    let stream = TcpStream::connect_timeout(&socket, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok((stream, authority, path))
+}
+
+/// Reads the status line of a response, failing unless it is a 2xx.
+fn read_status(reader: &mut impl BufRead) -> Result<()> {
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some(_) => anyhow::bail!(tr!("Server answered {}", status.trim_end())),
+        None => anyhow::bail!(tr!("Server sent no HTTP response")),
+    }
+}
+
+/// Has `curl` GET `url`, or POST `body` to it as JSON, returning the body
+/// of the response.
+fn curl(url: &str, body: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut command = Command::new("curl");
+    command
+        .args(["--silent", "--show-error", "--fail", "--max-time", "10"])
+        .args(["--max-filesize", &MAX_BODY.to_string()])
+        .stdout(Stdio::piped());
+    if body.is_some() {
+        command
+            .args(["--header", "Content-Type: application/json"])
+            .args(["--data-binary", "@-"])
+            .stdin(Stdio::piped());
+    }
+    let mut curl = command
+        .arg(url)
+        .spawn()
+        .with_context(|| tr!("Failed to run curl, which tap uses for https:// URLs"))?;
+    if let (Some(mut stdin), Some(body)) = (curl.stdin.take(), body) {
+        stdin.write_all(body)?;
+    }
+    let output = curl.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!(tr!("curl {}", output.status));
+    }
+    Ok(output.stdout)
+}
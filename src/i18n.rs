@@ -0,0 +1,290 @@
+//! The messages tap prints, in the user's language.
+//!
+//! Messages are written in English where they are used, through [`tr!`];
+//! the English text is also the key its translations are looked up by, so
+//! a message without a translation still reads fine. The language comes
+//! from `--lang`, or else the `TAP_LANG` environment variable.
+
+use std::fmt::{Display, Write};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+mod es;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    Es,
+}
+
+impl Lang {
+    /// A language from a locale name such as `es`, `es_MX.UTF-8` or `en-GB`.
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let code = locale.split(['_', '-', '.', '@']).next()?;
+        Self::from_str(code, true).ok()
+    }
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Chooses the language for the rest of the process. Only the first call
+/// counts, and it has to come before the first message to count at all.
+pub fn set_lang(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+pub fn lang() -> Lang {
+    *LANG.get_or_init(|| {
+        std::env::var("TAP_LANG")
+            .ok()
+            .and_then(|locale| Lang::from_locale(&locale))
+            .unwrap_or_default()
+    })
+}
+
+/// The message `english` in the chosen language, with `args` filled in.
+/// Used through [`tr!`].
+pub fn translate(english: &'static str, args: &[&dyn Display]) -> String {
+    fill(lookup(lang(), english), args)
+}
+
+fn lookup(lang: Lang, english: &'static str) -> &'static str {
+    let catalog = match lang {
+        Lang::En => return english,
+        Lang::Es => es::MESSAGES,
+    };
+    catalog
+        .iter()
+        .find(|(key, _)| *key == english)
+        .map_or(english, |(_, translated)| translated)
+}
+
+/// Fills in a message's placeholders: `{}` takes the next argument and
+/// `{N}` the Nth, so translations can reorder them, and `:#` after either
+/// prints it in the alternate form (for errors, with their causes).
+fn fill(message: &str, args: &[&dyn Display]) -> String {
+    let mut filled = String::with_capacity(message.len());
+    let mut next = 0;
+    for piece in pieces(message) {
+        let (index, alternate) = match piece {
+            Piece::Text(text) => {
+                filled.push_str(text);
+                continue;
+            }
+            Piece::Next(alternate) => {
+                next += 1;
+                (next - 1, alternate)
+            }
+            Piece::Arg(index, alternate) => (index, alternate),
+            Piece::Unknown(spec) => {
+                let _ = write!(filled, "{{{}}}", spec);
+                continue;
+            }
+        };
+        let _ = match args.get(index) {
+            Some(arg) if alternate => write!(filled, "{:#}", arg),
+            Some(arg) => write!(filled, "{}", arg),
+            None => write!(filled, "{{{}}}", index),
+        };
+    }
+    filled
+}
+
+enum Piece<'a> {
+    Text(&'a str),
+    /// `{}`, with whether it is `{:#}`
+    Next(bool),
+    /// `{N}` or `{N:#}`
+    Arg(usize, bool),
+    Unknown(&'a str),
+}
+
+fn pieces(message: &str) -> impl Iterator<Item = Piece<'_>> {
+    let mut rest = message;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let Some(start) = rest.find(['{', '}']) else {
+            return Some(Piece::Text(std::mem::take(&mut rest)));
+        };
+        if start > 0 {
+            let (text, after) = rest.split_at(start);
+            rest = after;
+            return Some(Piece::Text(text));
+        }
+        // Doubled braces stand for themselves, as do stray ones
+        let (brace, after) = rest.split_at(1);
+        let end = after
+            .find('}')
+            .filter(|_| brace == "{" && !after.starts_with('{'));
+        let Some(end) = end else {
+            rest = after.strip_prefix(brace).unwrap_or(after);
+            return Some(Piece::Text(brace));
+        };
+        let spec = &after[..end];
+        rest = &after[end + 1..];
+        let (index, alternate) = match spec.strip_suffix(":#") {
+            Some(index) => (index, true),
+            None => (spec, false),
+        };
+        Some(match index {
+            "" => Piece::Next(alternate),
+            index => match index.parse() {
+                Ok(index) => Piece::Arg(index, alternate),
+                Err(_) => Piece::Unknown(spec),
+            },
+        })
+    })
+}
+
+/// `format!` for messages people read: the literal is looked up in the
+/// catalog for the chosen language. Placeholders are `{}`, `{N}` and `{:#}`;
+/// anything fancier is formatted into an argument first.
+#[macro_export]
+macro_rules! tr {
+    ($english:literal $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::translate($english, &[$(&$arg as &dyn ::std::fmt::Display),*])
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The argument indices a message takes, or `None` if it has a
+    /// placeholder `fill` doesn't understand.
+    fn arguments(message: &str) -> Option<Vec<usize>> {
+        let mut next = 0;
+        let mut used = Vec::new();
+        for piece in pieces(message) {
+            match piece {
+                Piece::Text(_) => {}
+                Piece::Next(_) => {
+                    used.push(next);
+                    next += 1;
+                }
+                Piece::Arg(index, _) => used.push(index),
+                Piece::Unknown(_) => return None,
+            }
+        }
+        used.sort_unstable();
+        used.dedup();
+        Some(used)
+    }
+
+    /// The messages given to `tr!`, `say!` and `say_err!` in `source`,
+    /// unescaped.
+    fn messages(source: &str) -> Vec<String> {
+        let source = source.split("\n#[cfg(test)]\nmod tests {").next().unwrap();
+        let mut messages = Vec::new();
+        for (start, _) in source.match_indices("!(") {
+            if !["tr", "say", "say_err"]
+                .iter()
+                .any(|name| source[..start].ends_with(name))
+            {
+                continue;
+            }
+            let Some(literal) = source[start + 2..].trim_start().strip_prefix('"') else {
+                continue;
+            };
+            let mut message = String::new();
+            let mut chars = literal.chars();
+            while let Some(c) = chars.next() {
+                match (c, c == '\\') {
+                    ('"', _) => break,
+                    (_, true) => message.push(match chars.next().unwrap() {
+                        'n' => '\n',
+                        't' => '\t',
+                        escaped => escaped,
+                    }),
+                    _ => message.push(c),
+                }
+            }
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_fill() {
+        let path = "a.txt";
+        let error = anyhow::anyhow!("disk full").context("Failed to write");
+        assert_eq!(fill("Created: {}", &[&path]), "Created: a.txt");
+        assert_eq!(fill("{1} <- {0}", &[&1, &2]), "2 <- 1");
+        assert_eq!(
+            fill("{}: {:#}", &[&path, &error]),
+            "a.txt: Failed to write: disk full"
+        );
+        assert_eq!(fill("{{{}}} }} {", &[&path]), "{a.txt} } {");
+        assert_eq!(fill("{} {} {:?}", &[&path]), "a.txt {1} {:?}");
+    }
+
+    #[test]
+    fn test_spanish_catalog() {
+        let mut keys = Vec::new();
+        for (english, spanish) in es::MESSAGES {
+            assert!(!keys.contains(english), "listed twice: {}", english);
+            keys.push(*english);
+            assert!(arguments(english).is_some(), "{}", english);
+            assert_eq!(arguments(english), arguments(spanish), "{}", english);
+        }
+
+        let sources = [
+            include_str!("condition.rs"),
+            include_str!("config.rs"),
+            include_str!("conventions.rs"),
+            include_str!("dotenv.rs"),
+            include_str!("generate.rs"),
+            include_str!("http.rs"),
+            include_str!("lib.rs"),
+            include_str!("main.rs"),
+            include_str!("manifest.rs"),
+            include_str!("mode.rs"),
+            include_str!("notification.rs"),
+            include_str!("output.rs"),
+            include_str!("platform.rs"),
+            include_str!("scaffold.rs"),
+            include_str!("serve.rs"),
+            include_str!("signature.rs"),
+            include_str!("snapshot.rs"),
+            include_str!("sys/unix.rs"),
+            include_str!("sys/windows.rs"),
+            include_str!("temp_files.rs"),
+            include_str!("template.rs"),
+            include_str!("template/engine.rs"),
+            include_str!("undo.rs"),
+            include_str!("units.rs"),
+            include_str!("users.rs"),
+            include_str!("watch.rs"),
+        ];
+        for message in sources.into_iter().flat_map(messages) {
+            assert!(arguments(&message).is_some(), "{}", message);
+            // Messages of nothing but placeholders read the same in any language
+            if message.chars().any(char::is_alphabetic) {
+                assert!(
+                    keys.contains(&message.as_str()),
+                    "not translated: {}",
+                    message
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_locales() {
+        assert_eq!(Lang::from_locale("es_MX.UTF-8"), Some(Lang::Es));
+        assert_eq!(Lang::from_locale("en-GB"), Some(Lang::En));
+        assert_eq!(Lang::from_locale("fr_FR"), None);
+        assert_eq!(
+            lookup(Lang::Es, "Directory created: {}"),
+            "Directorio creado: {}"
+        );
+        assert_eq!(
+            lookup(Lang::En, "Directory created: {}"),
+            "Directory created: {}"
+        );
+    }
+}
@@ -0,0 +1,488 @@
+//! Spanish, keyed by the English message.
+
+pub const MESSAGES: &[(&str, &str)] = &[
+    // Runs and paths
+    ("--xargs and --stdin can't both read standard input", "--xargs y --stdin no pueden leer ambos la entrada estándar"),
+    ("Warning: {}", "Aviso: {}"),
+    ("Warning: {:#}", "Aviso: {:#}"),
+    ("Error: {}", "Error: {}"),
+    ("Error: {:#}", "Error: {:#}"),
+    ("Error: {}: {:#}", "Error: {}: {:#}"),
+    ("Caused by:", "Causado por:"),
+    ("Changed {} path(s)", "Se cambiaron {} ruta(s)"),
+    ("Failed to write changed-file list", "No se pudo escribir la lista de archivos cambiados"),
+    ("Failed to write output", "No se pudo escribir la salida"),
+    ("Failed to start worker threads", "No se pudieron iniciar los hilos de trabajo"),
+    ("Failed to process {} of {} paths", "No se pudieron procesar {} de {} rutas"),
+    ("Processing: {}", "Procesando: {}"),
+    ("Skipped (does not exist): {}", "Omitido (no existe): {}"),
+    ("No changes: {}", "Sin cambios: {}"),
+    ("Would {}: {}", "Se haría ({}): {}"),
+    ("Failed to create parent directories", "No se pudieron crear los directorios superiores"),
+    ("Does not exist: {}", "No existe: {}"),
+    ("Exists: {}", "Existe: {}"),
+    ("Error: Failed to read paths from stdin: {}", "Error: No se pudieron leer las rutas de la entrada estándar: {}"),
+    ("Invalid glob pattern '{}': {}", "Patrón glob no válido '{}': {}"),
+    ("Invalid glob pattern: {}", "Patrón glob no válido: {}"),
+    // Planned actions, shown by --dry-run
+    ("create parent directory {}", "crear el directorio superior {}"),
+    ("create directory", "crear el directorio"),
+    ("write PID {}", "escribir el PID {}"),
+    ("rotate the log ({})", "rotar el registro ({})"),
+    ("append a log entry", "añadir una entrada al registro"),
+    ("remove trailing whitespace from {} lines", "quitar los espacios finales de {} líneas"),
+    ("create file", "crear el archivo"),
+    ("append {}", "añadir {} al final"),
+    ("prepend {}", "añadir {} al principio"),
+    ("insert {} at line {}", "insertar {} en la línea {}"),
+    ("insert {} after the line containing '{}'", "insertar {} tras la línea que contiene '{}'"),
+    ("back up to {}", "hacer una copia de seguridad en {}"),
+    ("truncate {} of existing content", "truncar {} de contenido existente"),
+    ("write {}", "escribir {}"),
+    ("give {} to {}", "dar {} a {}"),
+    ("change mode {} -> {}", "cambiar el modo {} -> {}"),
+    ("set mode {}", "poner el modo {}"),
+    ("apply mode {}", "aplicar el modo {}"),
+    ("set mode {} on everything inside", "poner el modo {} a todo el contenido"),
+    ("apply mode {} to everything inside", "aplicar el modo {} a todo el contenido"),
+    ("change owner to {}", "cambiar el propietario a {}"),
+    ("change owner of everything inside to {}", "cambiar el propietario de todo el contenido a {}"),
+    ("set {} to {}", "poner {} a {}"),
+    ("set modification time just after {}", "poner la hora de modificación justo después de {}"),
+    ("copy {} from {}", "copiar {} de {}"),
+    ("set {} to now", "poner {} a la hora actual"),
+    ("access and modification times", "las horas de acceso y modificación"),
+    ("access time", "la hora de acceso"),
+    ("modification time", "la hora de modificación"),
+    // Files and content
+    ("Reference file not found: {}", "No se encontró el archivo de referencia: {}"),
+    ("Failed to read file content", "No se pudo leer el contenido del archivo"),
+    ("Failed to create directory", "No se pudo crear el directorio"),
+    ("Directory created: {}", "Directorio creado: {}"),
+    ("Failed to write trimmed content to file", "No se pudo escribir el contenido recortado en el archivo"),
+    ("Trailing whitespace removed from: {}", "Espacios finales eliminados de: {}"),
+    ("Failed to create or open file", "No se pudo crear o abrir el archivo"),
+    ("Failed to write template content to file", "No se pudo escribir el contenido de la plantilla en el archivo"),
+    ("File created/updated with template content: {}", "Archivo creado/actualizado con el contenido de la plantilla: {}"),
+    ("Failed to write content to file", "No se pudo escribir el contenido en el archivo"),
+    ("Content appended to file: {}", "Contenido añadido al final del archivo: {}"),
+    ("File created/updated with content: {}", "Archivo creado/actualizado con contenido: {}"),
+    ("Failed to back up to {}", "No se pudo hacer la copia de seguridad en {}"),
+    ("Backed up {} to {}", "Copia de seguridad de {} en {}"),
+    ("No file name to back up: {}", "No hay nombre de archivo del que hacer copia de seguridad: {}"),
+    ("--backup needs a suffix, or numbered", "--backup necesita un sufijo, o numbered"),
+    ("Failed to lock {}", "No se pudo bloquear {}"),
+    ("Content already present: {}", "El contenido ya está presente: {}"),
+    ("Appended to: {}", "Añadido al final de: {}"),
+    ("Failed to find where to insert into {}", "No se encontró dónde insertar en {}"),
+    ("Prepended to: {}", "Añadido al principio de: {}"),
+    ("Inserted into: {}", "Insertado en: {}"),
+    ("Content prepended to file: {}", "Contenido añadido al principio del archivo: {}"),
+    ("Content inserted into file: {}", "Contenido insertado en el archivo: {}"),
+    ("--at-line counts lines from 1", "--at-line cuenta las líneas desde 1"),
+    ("--insert-after needs text to look for", "--insert-after necesita un texto que buscar"),
+    ("No line contains '{}'", "Ninguna línea contiene '{}'"),
+    ("Content inside a header guard must be UTF-8 text", "El contenido dentro de una guarda de cabecera debe ser texto UTF-8"),
+    ("Failed to read standard input", "No se pudo leer la entrada estándar"),
+    ("File created: {}", "Archivo creado: {}"),
+    ("File timestamp updated: {}", "Marca de tiempo del archivo actualizada: {}"),
+    // Owners and permissions
+    ("Failed to read owner", "No se pudo leer el propietario"),
+    ("Failed to read directory", "No se pudo leer el directorio"),
+    ("Failed to read directory entry", "No se pudo leer la entrada del directorio"),
+    ("Owner set to {} for: {}", "Propietario cambiado a {} para: {}"),
+    ("Warning: Not permitted to change the owner of {} to {} (that needs root)", "Aviso: No se permite cambiar el propietario de {} a {} (hace falta ser root)"),
+    ("Failed to change owner of {}", "No se pudo cambiar el propietario de {}"),
+    ("Invalid group name", "Nombre de grupo no válido"),
+    ("Failed to look up group {}", "No se pudo buscar el grupo {}"),
+    ("Unknown group: {}", "Grupo desconocido: {}"),
+    ("Groups are not supported on Windows: {}", "Los grupos no están disponibles en Windows: {}"),
+    ("Failed to read permissions", "No se pudieron leer los permisos"),
+    ("Failed to set permissions", "No se pudieron cambiar los permisos"),
+    ("Permissions set to {} for: {}", "Permisos cambiados a {} para: {}"),
+    ("Invalid chmod value: {}", "Valor de chmod no válido: {}"),
+    ("Invalid chmod clause '{}' in: {}", "Cláusula de chmod no válida '{}' en: {}"),
+    ("'{}' is not one of u, g, o or a", "'{}' no es ninguno de u, g, o ni a"),
+    ("Expected +, - or = after '{}'", "Se esperaba +, - o = después de '{}'"),
+    ("'{}' is not one of r, w, x, X, s, t (or a single u, g or o)", "'{}' no es ninguno de r, w, x, X, s, t (ni una sola u, g u o)"),
+    ("Invalid umask value: {}", "Valor de umask no válido: {}"),
+    ("Warning: --umask has no effect on Windows", "Aviso: --umask no tiene efecto en Windows"),
+    ("Warning: Windows only has a read-only attribute; modes other than 444 and 644 are approximated by the owner's write bit", "Aviso: Windows solo tiene un atributo de solo lectura; los modos distintos de 444 y 644 se aproximan por el bit de escritura del propietario"),
+    ("file owners are not supported on Windows", "los propietarios de archivos no están disponibles en Windows"),
+    // Templates
+    ("Failed to read template file", "No se pudo leer el archivo de plantilla"),
+    ("Failed to render template {}", "No se pudo generar la plantilla {}"),
+    ("Can't render template {}: tap was built without the template-engine feature", "No se puede generar la plantilla {}: tap se compiló sin la característica template-engine"),
+    ("Failed to render {{{{{}}}}}", "No se pudo generar {{{{{}}}}}"),
+    ("Unknown escape \\{} in string", "Secuencia de escape desconocida \\{} en la cadena"),
+    ("Unterminated string", "Cadena sin terminar"),
+    ("Unexpected '{}'", "'{}' inesperado"),
+    ("Unexpected {} after the expression", "{} inesperado después de la expresión"),
+    ("Expected a filter name after |", "Se esperaba un nombre de filtro después de |"),
+    ("Unknown variable '{}'", "Variable desconocida '{}'"),
+    ("Unexpected {}", "{} inesperado"),
+    ("Empty expression", "Expresión vacía"),
+    ("Expected , or ) in argument list", "Se esperaba , o ) en la lista de argumentos"),
+    ("Unknown function '{}'", "Función desconocida '{}'"),
+    ("Wrong number of arguments for {}: use {}", "Número de argumentos incorrecto para {}: use {}"),
+    ("Invalid width for pad (at most {}): {}", "Ancho no válido para pad (como máximo {}): {}"),
+    ("pad's fill must be a single character", "el relleno de pad debe ser un solo carácter"),
+    ("Environment variable {} is not set", "La variable de entorno {} no está definida"),
+    ("Incomplete % directive in format", "Directiva % incompleta en el formato"),
+    ("Not enough values for format \"{}\"", "Faltan valores para el formato \"{}\""),
+    ("%{} needs a whole number, got '{}'", "%{} necesita un número entero, pero se recibió '{}'"),
+    ("%f needs a number, got '{}'", "%f necesita un número, pero se recibió '{}'"),
+    ("Unsupported directive %{} in format", "Directiva %{} no admitida en el formato"),
+    ("More values than directives in format \"{}\"", "Sobran valores para las directivas del formato \"{}\""),
+    ("shell() is disabled; pass --allow-shell to let templates run commands", "shell() está desactivado; use --allow-shell para que las plantillas puedan ejecutar órdenes"),
+    ("Failed to run: {}", "No se pudo ejecutar: {}"),
+    ("Command failed ({}): {}: {}", "La orden falló ({}): {}: {}"),
+    // Times
+    ("Failed to set timestamp", "No se pudo cambiar la marca de tiempo"),
+    ("Timestamp set to {} for: {}", "Marca de tiempo cambiada a {} para: {}"),
+    ("Timestamps copied from {} to: {}", "Marcas de tiempo copiadas de {} a: {}"),
+    ("Failed to read back timestamp", "No se pudo volver a leer la marca de tiempo"),
+    ("Timestamp set {} after {} for: {}", "Marca de tiempo puesta {} después de {} para: {}"),
+    ("Could not make {} newer than {}", "No se pudo hacer {} más reciente que {}"),
+    ("Invalid timestamp format: {}", "Formato de marca de tiempo no válido: {}"),
+    ("Invalid time format: {}", "Formato de hora no válido: {}"),
+    ("Empty time format in {}", "Formato de hora vacío en {}"),
+    ("Invalid throttle rate: {}", "Ritmo de --throttle no válido: {}"),
+    ("Invalid throttle unit '{}' (expected s, m or h)", "Unidad de --throttle no válida '{}' (se esperaba s, m o h)"),
+    ("--batch needs --throttle", "--batch necesita --throttle"),
+    ("Invalid duration: {}", "Duración no válida: {}"),
+    ("Invalid duration unit '{}' in: {}", "Unidad de duración no válida '{}' en: {}"),
+    ("Invalid size unit '{}' in: {}", "Unidad de tamaño no válida '{}' en: {}"),
+    ("Invalid size: {}", "Tamaño no válido: {}"),
+    ("Size too large: {}", "Tamaño demasiado grande: {}"),
+    // Waiting, flags and PID files
+    ("Waiting for directory: {}", "Esperando al directorio: {}"),
+    ("Timed out waiting for directory: {}", "Se agotó el tiempo esperando al directorio: {}"),
+    ("Failed to create flag file", "No se pudo crear el archivo de señal"),
+    ("Flag set: {}", "Señal puesta: {}"),
+    ("Flag cleared: {}", "Señal quitada: {}"),
+    ("Failed to clear flag file", "No se pudo quitar el archivo de señal"),
+    ("Failed to start file watcher", "No se pudo iniciar la vigilancia de archivos"),
+    ("Failed to watch flag directory", "No se pudo vigilar el directorio de la señal"),
+    ("Waiting for flag: {}", "Esperando la señal: {}"),
+    ("Timed out waiting for flag: {}", "Se agotó el tiempo esperando la señal: {}"),
+    ("File watcher stopped while waiting for: {}", "La vigilancia de archivos se detuvo mientras se esperaba: {}"),
+    ("Failed to set PID file permissions", "No se pudieron cambiar los permisos del archivo PID"),
+    ("Failed to write PID file", "No se pudo escribir el archivo PID"),
+    ("PID {} written to: {}", "PID {} escrito en: {}"),
+    ("PID file {} is held by running process {}", "El archivo PID {} pertenece al proceso en ejecución {}"),
+    ("Removing stale PID file: {}", "Eliminando el archivo PID obsoleto: {}"),
+    ("Failed to remove stale PID file", "No se pudo eliminar el archivo PID obsoleto"),
+    ("Failed to create PID file", "No se pudo crear el archivo PID"),
+    ("--pidfile needs --pid on this platform", "--pidfile necesita --pid en esta plataforma"),
+    // Workspaces and filesystems
+    ("Failed to read .tap-workspace", "No se pudo leer .tap-workspace"),
+    ("Failed to read Cargo.toml", "No se pudo leer Cargo.toml"),
+    ("Invalid TOML in {}", "TOML no válido en {}"),
+    ("No workspace found above {}", "No se encontró ningún espacio de trabajo por encima de {}"),
+    ("Unknown workspace member '{}' in {} (known members: {})", "Miembro del espacio de trabajo desconocido '{}' en {} (miembros conocidos: {})"),
+    ("Invalid workspace member pattern: {}", "Patrón de miembro del espacio de trabajo no válido: {}"),
+    ("Failed to resolve directory", "No se pudo resolver el directorio"),
+    ("Failed to write cd target", "No se pudo escribir el destino de cd"),
+    ("Path contains a NUL byte", "La ruta contiene un byte NUL"),
+    ("Failed to stat filesystem of {}", "No se pudo consultar el sistema de archivos de {}"),
+    ("Filesystem checks are not supported on Windows: {}", "Las comprobaciones del sistema de archivos no están disponibles en Windows: {}"),
+    ("{} is on a network filesystem ({}); consider --throttle", "{} está en un sistema de archivos de red ({}); considere usar --throttle"),
+    ("{} is on a read-only filesystem", "{} está en un sistema de archivos de solo lectura"),
+    ("{} is on a nearly full filesystem ({}% free)", "{} está en un sistema de archivos casi lleno ({}% libre)"),
+    // Duplicates and watching
+    ("Failed to open {}", "No se pudo abrir {}"),
+    ("Failed to read {}", "No se pudo leer {}"),
+    ("Failed to resolve original file", "No se pudo resolver el archivo original"),
+    ("Failed to link {}", "No se pudo enlazar {}"),
+    ("Failed to replace {}", "No se pudo reemplazar {}"),
+    ("Linked {} -> {}", "Enlazado {} -> {}"),
+    ("Duplicate group ({} files, {} each, sha256 {}):", "Grupo de duplicados ({} archivos, {} cada uno, sha256 {}):"),
+    ("No duplicates found", "No se encontraron duplicados"),
+    ("Failed to get current directory", "No se pudo obtener el directorio actual"),
+    ("Failed to watch {}", "No se pudo vigilar {}"),
+    ("Watching: {}", "Vigilando: {}"),
+    ("File watcher stopped", "La vigilancia de archivos se detuvo"),
+    ("Running: {}", "Ejecutando: {}"),
+    ("Command exited with {}", "La orden terminó con {}"),
+    ("Failed to run {}: {}", "No se pudo ejecutar {}: {}"),
+    // Locks, logs and reports
+    ("Cannot determine {}: HOME is not set", "No se puede determinar {}: HOME no está definida"),
+    ("Invalid lock name: {}", "Nombre de bloqueo no válido: {}"),
+    ("Failed to create lock directory", "No se pudo crear el directorio de bloqueos"),
+    ("Failed to open lock file", "No se pudo abrir el archivo de bloqueo"),
+    ("Failed to lock", "No se pudo bloquear"),
+    ("Lock '{}' is held by another tap run (pid {})", "El bloqueo '{}' lo tiene otra ejecución de tap (pid {})"),
+    ("Timed out waiting for lock '{}' (held by pid {})", "Se agotó el tiempo esperando el bloqueo '{}' (lo tiene el pid {})"),
+    ("Waiting for lock '{}' (held by pid {})", "Esperando el bloqueo '{}' (lo tiene el pid {})"),
+    ("Failed to write lock file", "No se pudo escribir el archivo de bloqueo"),
+    ("Acquired lock: {}", "Bloqueo obtenido: {}"),
+    ("Invalid log format: {}", "Formato de registro no válido: {}"),
+    ("Log rotated: {}", "Registro rotado: {}"),
+    ("Failed to open log file", "No se pudo abrir el archivo de registro"),
+    ("Failed to write log entry", "No se pudo escribir la entrada de registro"),
+    ("Log entry appended to: {}", "Entrada de registro añadida a: {}"),
+    ("Failed to rotate log", "No se pudo rotar el registro"),
+    ("Warning: failed to write to {} log: {:#}", "Aviso: no se pudo escribir en el registro {}: {:#}"),
+    ("syslog is not available on Windows", "syslog no está disponible en Windows"),
+    ("Failed to create socket", "No se pudo crear el socket"),
+    ("Failed to send to journald", "No se pudo enviar a journald"),
+    ("journald is not available on Windows", "journald no está disponible en Windows"),
+    ("Failed to serialize audit report", "No se pudo serializar el informe de auditoría"),
+    ("Failed to write audit report", "No se pudo escribir el informe de auditoría"),
+    // Users
+    ("Invalid user name", "Nombre de usuario no válido"),
+    ("Failed to look up user {}", "No se pudo buscar el usuario {}"),
+    ("Unknown user: {}", "Usuario desconocido: {}"),
+    ("User accounts are not supported on Windows: {}", "Las cuentas de usuario no están disponibles en Windows: {}"),
+    ("--as-user {} requires running as root", "--as-user {} requiere ejecutarse como root"),
+    ("--as-user {} is not supported on Windows", "--as-user {} no está disponible en Windows"),
+    ("--each-user paths must be relative to the home directory: {}", "Las rutas de --each-user deben ser relativas al directorio personal: {}"),
+    ("Error for user {}: {:#}", "Error para el usuario {}: {:#}"),
+    ("Failed for {} of {} user(s)", "Falló para {} de {} usuario(s)"),
+    ("Failed to read directory {}", "No se pudo leer el directorio {}"),
+    ("{}: would create {}", "{}: se crearía {}"),
+    ("Failed to create {}", "No se pudo crear {}"),
+    ("Failed to copy {}", "No se pudo copiar {}"),
+    ("Failed to set permissions on {}", "No se pudieron cambiar los permisos de {}"),
+    ("{}: created {}", "{}: creado {}"),
+    ("{}: created {} entries", "{}: {} entradas creadas"),
+    ("Invalid application name: {}", "Nombre de aplicación no válido: {}"),
+    ("Failed to serialize directories", "No se pudieron serializar los directorios"),
+    // FIFOs and server clocks
+    ("{} exists and is not a FIFO", "{} existe y no es una FIFO"),
+    ("Failed to create FIFO", "No se pudo crear la FIFO"),
+    ("FIFO created: {}", "FIFO creada: {}"),
+    ("FIFOs are not supported on Windows: {}", "Las FIFO no están disponibles en Windows: {}"),
+    ("Waiting for a reader on: {}", "Esperando a un lector en: {}"),
+    ("Reader attached: {}", "Lector conectado: {}"),
+    ("Timed out waiting for a reader on {}", "Se agotó el tiempo esperando a un lector en {}"),
+    ("Failed to open FIFO", "No se pudo abrir la FIFO"),
+    ("Failed to create clock probe file", "No se pudo crear el archivo de sondeo del reloj"),
+    ("Failed to read clock probe file", "No se pudo leer el archivo de sondeo del reloj"),
+    // Project scaffolding
+    ("Failed to resolve {}", "No se pudo resolver {}"),
+    ("Not a source file: {}", "No es un archivo fuente: {}"),
+    ("No Cargo.toml above {}", "No hay Cargo.toml por encima de {}"),
+    ("Already a test file: {}", "Ya es un archivo de pruebas: {}"),
+    ("Unsupported language: {}", "Lenguaje no admitido: {}"),
+    ("Failed to write test module", "No se pudo escribir el módulo de pruebas"),
+    ("Failed to write test file", "No se pudo escribir el archivo de pruebas"),
+    ("Test file created: {}", "Archivo de pruebas creado: {}"),
+    ("Tests already exist in: {}", "Ya existen pruebas en: {}"),
+    ("Not a Makefile or justfile: {}", "No es un Makefile ni un justfile: {}"),
+    ("Invalid target name: {}", "Nombre de objetivo no válido: {}"),
+    ("Failed to write {}", "No se pudo escribir {}"),
+    ("Target {} added to: {}", "Objetivo {} añadido a: {}"),
+    ("Target {} already exists in: {}", "El objetivo {} ya existe en: {}"),
+    ("Invalid manifest {}", "Manifiesto no válido {}"),
+    ("Unknown manifest format (expected .toml or .yaml): {}", "Formato de manifiesto desconocido (se esperaba .toml o .yaml): {}"),
+    ("Directory entry {} can't have content, a template or a source", "La entrada de directorio {} no puede tener contenido, plantilla ni origen"),
+    ("Entry {} has more than one of content, template and source", "La entrada {} tiene más de uno entre contenido, plantilla y origen"),
+    ("Manifest entry {} is outside {} (use --allow-outside-root to allow it)", "La entrada del manifiesto {} está fuera de {} (use --allow-outside-root para permitirlo)"),
+    ("Failed to apply {}", "No se pudo aplicar {}"),
+    ("Invalid variable name: {}", "Nombre de variable no válido: {}"),
+    ("No value given for {}", "No se dio ningún valor para {}"),
+    ("Environment file updated: {}", "Archivo de entorno actualizado: {}"),
+    ("Updated {}", "Actualizado {}"),
+    ("Conventions written to: {}", "Convenciones escritas en: {}"),
+    ("Couldn't detect the language of {} (use --language)", "No se pudo detectar el lenguaje de {} (use --language)"),
+    ("Unterminated '{}' block in {}", "Bloque '{}' sin terminar en {}"),
+    // Snapshots and config
+    ("Invalid snapshot name: {}", "Nombre de instantánea no válido: {}"),
+    ("Failed to create snapshot directory", "No se pudo crear el directorio de instantáneas"),
+    ("Failed to serialize snapshot", "No se pudo serializar la instantánea"),
+    ("Failed to write snapshot", "No se pudo escribir la instantánea"),
+    ("Failed to read snapshot {}", "No se pudo leer la instantánea {}"),
+    ("Invalid snapshot {}", "Instantánea no válida {}"),
+    ("Recorded {} paths in snapshot {}", "Se registraron {} rutas en la instantánea {}"),
+    ("No changes since snapshot {}", "Sin cambios desde la instantánea {}"),
+    ("{} path(s) changed since snapshot {}", "{} ruta(s) cambiaron desde la instantánea {}"),
+    ("Failed to read config {}", "No se pudo leer la configuración {}"),
+    ("Invalid config {}", "Configuración no válida {}"),
+    ("Invalid mode {} in {}", "Modo no válido {} en {}"),
+    // Notifications
+    ("Processed {} path(s), {} would change, in {}s", "{} ruta(s) procesadas, {} cambiarían, en {} s"),
+    ("Processed {} path(s), {} changed, in {}s", "{} ruta(s) procesadas, {} cambiadas, en {} s"),
+    ("{}; failed: {}", "{}; falló: {}"),
+    ("tap failed", "tap falló"),
+    ("tap finished", "tap terminó"),
+    ("Failed to show desktop notification", "No se pudo mostrar la notificación de escritorio"),
+    ("--notify-url must be an http:// or https:// URL: {}", "--notify-url debe ser una URL http:// o https://: {}"),
+    ("--notify-url needs tap built with the net feature", "--notify-url necesita un tap compilado con la característica net"),
+    ("Failed to serialize run summary", "No se pudo serializar el resumen de la ejecución"),
+    ("Failed to post run summary to {}", "No se pudo enviar el resumen de la ejecución a {}"),
+    ("No address for {}", "No hay dirección para {}"),
+    ("Server answered {}", "El servidor respondió {}"),
+    ("Server sent no HTTP response", "El servidor no envió ninguna respuesta HTTP"),
+    ("Failed to run curl, which tap uses for https:// URLs", "No se pudo ejecutar curl, que tap usa para las URL https://"),
+    ("curl {}", "curl {}"),
+    ("{} not found", "no se encontró {}"),
+    ("desktop notifications are not supported on Windows", "las notificaciones de escritorio no están disponibles en Windows"),
+    // Temporary files
+    ("Failed to remove {}", "No se pudo eliminar {}"),
+    ("Removed orphaned temporary file: {}", "Se eliminó el archivo temporal huérfano: {}"),
+    ("Orphaned temporary file: {}", "Archivo temporal huérfano: {}"),
+    ("Run tap doctor --cleanup to remove them", "Ejecute tap doctor --cleanup para eliminarlos"),
+    ("No orphaned temporary files", "No hay archivos temporales huérfanos"),
+    // Undo
+    ("Failed to save {} for undo", "No se pudo guardar {} para deshacer"),
+    ("Warning: {} can't be undone: {:#}", "Advertencia: {} no se podrá deshacer: {:#}"),
+    ("Failed to write the undo journal", "No se pudo escribir el registro para deshacer"),
+    ("Nothing to undo", "No hay nada que deshacer"),
+    ("Failed to read the undo journal {}", "No se pudo leer el registro para deshacer {}"),
+    ("Warning: {} changed after the run, so it was left alone (undo with --force to revert it anyway)", "Advertencia: {} cambió después de la ejecución, así que no se tocó (deshaga con --force para revertirlo de todos modos)"),
+    ("Would restore: {}", "Se restauraría: {}"),
+    ("Would remove: {}", "Se eliminaría: {}"),
+    ("Removed: {}", "Eliminado: {}"),
+    ("Restored: {}", "Restaurado: {}"),
+    ("Undid {} path(s)", "Se deshicieron {} ruta(s)"),
+    ("{} path(s) were not undone", "{} ruta(s) no se deshicieron"),
+    ("Failed to restore the content of {}", "No se pudo restaurar el contenido de {}"),
+    // Watch mode
+    ("--watch can't read the paths from standard input", "--watch no puede leer las rutas de la entrada estándar"),
+    ("--watch can't add --log-entry lines, as every pass would add one", "--watch no puede añadir líneas de --log-entry, ya que cada pasada añadiría una"),
+    ("--watch needs --once to add content, or every pass would add it again", "--watch necesita --once para añadir contenido, o cada pasada lo añadiría de nuevo"),
+    ("Failed to catch interrupts", "No se pudieron capturar las interrupciones"),
+    ("Stopped watching", "Se dejó de vigilar"),
+    // Platform checks
+    ("Warning: {:#}; checking for changes every {}s instead", "Advertencia: {:#}; se buscarán cambios cada {} s en su lugar"),
+    ("no syslog daemon is listening on {}", "ningún demonio de syslog escucha en {}"),
+    // Symlinks
+    ("create symlink to {}", "crear un enlace simbólico a {}"),
+    ("replace with a symlink to {}", "reemplazar por un enlace simbólico a {}"),
+    ("Failed to create symlink {}", "No se pudo crear el enlace simbólico {}"),
+    ("Symlink created: {} -> {}", "Enlace simbólico creado: {} -> {}"),
+    ("Symlink unchanged: {} -> {}", "Enlace simbólico sin cambios: {} -> {}"),
+    ("Symlink replaced: {} -> {}", "Enlace simbólico reemplazado: {} -> {}"),
+    ("{} is a directory, which is never replaced by a link", "{} es un directorio, que nunca se reemplaza por un enlace"),
+    ("{} already exists (use --force to replace it)", "{} ya existe (use --force para reemplazarlo)"),
+    // Hard links
+    ("create hard link to {}", "crear un enlace duro a {}"),
+    ("replace with a hard link to {}", "reemplazar por un enlace duro a {}"),
+    ("Failed to create hard link {}", "No se pudo crear el enlace duro {}"),
+    ("Hard link created: {} -> {}", "Enlace duro creado: {} -> {}"),
+    ("Hard link unchanged: {} -> {}", "Enlace duro sin cambios: {} -> {}"),
+    ("Hard link replaced: {} -> {}", "Enlace duro reemplazado: {} -> {}"),
+    ("Link target not found: {}", "No se encontró el destino del enlace: {}"),
+    ("{} is a directory, which can't be hard linked", "{} es un directorio, que no admite enlaces duros"),
+    ("{} is on another filesystem than {}, and hard links can't cross filesystems (use --link-to for a symlink)", "{} está en otro sistema de archivos que {}, y los enlaces duros no pueden cruzar sistemas de archivos (use --link-to para un enlace simbólico)"),
+    // FIFOs and sockets
+    ("create FIFO", "crear una FIFO"),
+    ("create socket", "crear un socket"),
+    ("{} exists and is not a socket", "{} existe y no es un socket"),
+    ("Socket created: {}", "Socket creado: {}"),
+    ("Unix sockets are not supported on Windows: {}", "Los sockets de Unix no se admiten en Windows: {}"),
+    // Shebangs
+    ("make executable", "hacer ejecutable"),
+    ("--shebang needs an interpreter, such as bash or /bin/sh", "--shebang necesita un intérprete, como bash o /bin/sh"),
+    ("Made executable: {}", "Hecho ejecutable: {}"),
+    // Sizes
+    ("cut to {}", "recortar a {}"),
+    ("extend to {} with a hole", "ampliar a {} con un hueco"),
+    ("extend to {} with zeros", "ampliar a {} con ceros"),
+    ("Failed to resize {}", "No se pudo cambiar el tamaño de {}"),
+    ("Size set to {}: {}", "Tamaño fijado en {}: {}"),
+    // Holes
+    ("punching holes is only supported on Linux", "solo se pueden abrir huecos en Linux"),
+    ("punch a hole of {} at {}", "abrir un hueco de {} en {}"),
+    ("Failed to punch a hole in {}", "No se pudo abrir un hueco en {}"),
+    ("Hole punched at {} ({}): {}", "Hueco abierto en {} ({}): {}"),
+    ("Expected OFFSET,LEN: {}", "Se esperaba DESPLAZAMIENTO,LONGITUD: {}"),
+    ("A hole needs a length: {}", "Un hueco necesita una longitud: {}"),
+    // Generated content
+    ("Expected pattern:BYTE:SIZE: {}", "Se esperaba pattern:BYTE:TAMAÑO: {}"),
+    ("Unknown fill '{}' (expected random:SIZE, zeros:SIZE or pattern:BYTE:SIZE)", "Relleno desconocido '{}' (se esperaba random:TAMAÑO, zeros:TAMAÑO o pattern:BYTE:TAMAÑO)"),
+    ("Invalid fill byte '{}' (expected 0 to 255, 0x00 to 0xFF, or a single character)", "Byte de relleno no válido '{}' (se esperaba de 0 a 255, de 0x00 a 0xFF o un solo carácter)"),
+    ("File filled with {}: {}", "Archivo rellenado con {}: {}"),
+    ("Unknown placeholder text '{}' (expected words:N, sentences:N or paragraphs:N)", "Texto de relleno desconocido '{}' (se esperaba words:N, sentences:N o paragraphs:N)"),
+    ("Expected a count of at least 1: {}", "Se esperaba una cantidad de al menos 1: {}"),
+    ("Invalid length in {}: expected a number of digits", "Longitud no válida en {}: se esperaba un número de dígitos"),
+    ("Count too large in {} (at most {})", "Cantidad demasiado grande en {} (como máximo {})"),
+    ("{} expands to more than {} paths", "{} se expande a más de {} rutas"),
+    ("Width in format \"{}\" is over {}", "El ancho en el formato \"{}\" pasa de {}"),
+    ("--at-line {} is past the end of a file of {} lines", "--at-line {} está más allá del final de un archivo de {} líneas"),
+    ("PID file {} exists but doesn't hold a PID; remove it if it is stale", "El archivo PID {} existe pero no contiene un PID; elimínelo si está obsoleto"),
+    ("Failed to read PID file", "No se pudo leer el archivo PID"),
+    ("Failed to switch to user {}", "No se pudo cambiar al usuario {}"),
+    ("Failed to render inline template", "No se pudo procesar la plantilla en línea"),
+    ("Can't render an inline template: tap was built without the template-engine feature", "No se puede procesar una plantilla en línea: tap se compiló sin la función template-engine"),
+    ("Preset {} has both content and a template", "El preset {} tiene contenido y plantilla a la vez"),
+    ("Unknown preset: {}", "Preset desconocido: {}"),
+    ("Invalid pattern {} in {}", "Patrón no válido {} en {}"),
+    ("Rule {} names a preset and also sets its own content or mode", "La regla {} nombra un preset y además define su propio contenido o modo"),
+    ("{} names a device on Windows; start the full path with \\\\?\\ to create it as a file: {}", "{} es un dispositivo en Windows; empieza la ruta completa con \\\\?\\ para crearlo como archivo: {}"),
+    ("Failed to list data streams of {}", "No se pudieron listar los flujos de datos de {}"),
+    ("Warning: Unix only has the read-only attribute; hidden, system and archive are ignored", "Aviso: Unix solo tiene el atributo de solo lectura; oculto, sistema y archivo se ignoran"),
+    ("Invalid attribute change {} (expected + or - and some of r, h, s and a, e.g. +h,-a)", "Cambio de atributos no válido {} (se esperaba + o - y algunos de r, h, s y a, p. ej. +h,-a)"),
+    ("Failed to set attributes", "No se pudieron establecer los atributos"),
+    ("Attributes set to {} for: {}", "Atributos establecidos a {} para: {}"),
+    ("change attributes {}", "cambiar los atributos {}"),
+    ("set attributes {}", "establecer los atributos {}"),
+    ("Invalid state file {}", "Archivo de estado no válido {}"),
+    ("Orphaned (no longer in the manifest): {}", "Huérfano (ya no está en el manifiesto): {}"),
+    ("Warning: Kept orphan {}, which is outside {}", "Aviso: Se conserva el huérfano {}, que está fuera de {}"),
+    ("Would remove orphan: {}", "Se eliminaría el huérfano: {}"),
+    ("Removed orphan: {}", "Huérfano eliminado: {}"),
+    ("Warning: Kept orphaned directory {}, which isn't empty", "Aviso: Se conserva el directorio huérfano {}, que no está vacío"),
+    ("Failed to remove orphan {}", "No se pudo eliminar el huérfano {}"),
+    ("Warning: Skipped {}, which is not a file or directory", "Aviso: Se omitió {}, que no es un archivo ni un directorio"),
+    ("Failed to write the manifest", "No se pudo escribir el manifiesto"),
+    ("Invalid condition {}: {} at column {}", "Condición no válida {}: {} en la columna {}"),
+    ("expected && or ||", "se esperaba && o ||"),
+    ("unterminated string", "cadena sin terminar"),
+    ("unexpected {}", "{} inesperado"),
+    ("expected )", "se esperaba )"),
+    ("expected a value", "se esperaba un valor"),
+    ("unknown name {} (expected os, arch, family or env.NAME)", "nombre desconocido {} (se esperaba os, arch, family o env.NOMBRE)"),
+    ("Invalid entry {}", "Entrada no válida {}"),
+    ("Skipped {} (when {})", "Omitido {} (when {})"),
+    ("Preset {} doesn't apply here (when {})", "El preajuste {} no se aplica aquí (when {})"),
+    ("Include cycle: {}", "Ciclo de inclusiones: {}"),
+    ("Included from {}", "Incluido desde {}"),
+    ("did you mean '{}'?", "¿quería decir '{}'?"),
+    ("no keys are allowed here", "aquí no se admiten claves"),
+    ("expected one of {}", "se esperaba una de {}"),
+    ("unknown key '{}'", "clave desconocida '{}'"),
+    ("{} at line {}, column {}", "{} en la línea {}, columna {}"),
+    ("Failed to write the schema", "No se pudo escribir el esquema"),
+    ("Failed to read a request", "No se pudo leer una petición"),
+    ("Failed to write a response", "No se pudo escribir una respuesta"),
+    ("Only JSON-RPC 2.0 is supported", "Solo se admite JSON-RPC 2.0"),
+    ("Unknown method: {}", "Método desconocido: {}"),
+    ("Give a template or content, not both", "Indique una plantilla o un contenido, no ambos"),
+    ("Give a template or content", "Indique una plantilla o un contenido"),
+    ("{} already exists", "{} ya existe"),
+    ("{} can't be used with tap {} (run-wide options can come before the subcommand, other options only without one)", "{} no se puede usar con tap {} (las opciones generales pueden ir antes del subcomando, las demás solo sin él)"),
+    ("Fill too large in {} (at most {})", "Relleno demasiado grande en {} (como mucho {})"),
+    ("Not enough space for {} in {} ({} free)", "No hay espacio para {} en {} ({} libres)"),
+    ("Parent directory does not exist: {}", "El directorio padre no existe: {}"),
+    ("Parent directory does not exist: {}; use '{}' instead? [y/N]", "El directorio padre no existe: {}; ¿usar '{}' en su lugar? [s/N]"),
+    ("y", "s"),
+    ("yes", "sí"),
+    ("--jobs can't be more than 1: tap was built without the parallel feature", "--jobs no puede ser mayor que 1: tap se compiló sin la característica parallel"),
+    ("Can't hash {}: tap was built without the hash feature", "No se puede calcular el hash de {}: tap se compiló sin la característica hash"),
+    ("Can't watch for changes: tap was built without the watch feature", "No se pueden vigilar los cambios: tap se compiló sin la característica watch"),
+    ("--watch needs --every: tap was built without the watch feature", "--watch necesita --every: tap se compiló sin la característica watch"),
+    ("Can't read TOML: tap was built without the manifest feature", "No se puede leer TOML: tap se compiló sin la característica manifest"),
+    ("Can't read YAML: tap was built without the manifest feature", "No se puede leer YAML: tap se compiló sin la característica manifest"),
+    ("Can't write the manifest: tap was built without the manifest feature", "No se puede escribir el manifiesto: tap se compiló sin la característica manifest"),
+    ("Can't describe the schema: tap was built without the schema feature", "No se puede describir el esquema: tap se compiló sin la característica schema"),
+    ("Invalid minisign public key {}: {}", "Clave pública de minisign no válida {}: {}"),
+    ("Can't check signatures: tap was built without the net feature", "No se pueden comprobar firmas: tap se compiló sin la característica net"),
+    ("Invalid signature: {}", "Firma no válida: {}"),
+    ("Not signed by a trusted key", "No está firmado con una clave de confianza"),
+    ("Bad signature: {}", "Firma incorrecta: {}"),
+    ("Failed to fetch {}", "No se pudo descargar {}"),
+    ("Warning: Using {} without checking its signature", "Advertencia: Se usa {} sin comprobar su firma"),
+    ("No trusted keys to check {} with (add one with tap key add, or pass --insecure)", "No hay claves de confianza con las que comprobar {} (añade una con tap key add, o pasa --insecure)"),
+    ("Failed to fetch the signature {}", "No se pudo descargar la firma {}"),
+    ("Refused {}", "Rechazado {}"),
+    ("Can't fetch {}: tap was built without the net feature", "No se puede descargar {}: tap se compiló sin la característica net"),
+    ("Response larger than {} bytes", "Respuesta de más de {} bytes"),
+    ("Trusted key: {}", "Clave de confianza: {}"),
+    ("Already trusted: {}", "Ya es de confianza: {}"),
+    ("Not a trusted key: {}", "No es una clave de confianza: {}"),
+    ("Removed key: {}", "Clave eliminada: {}"),
+    ("Warning: Saved {} of replaced content for undo; tap undo won't restore the rest of this run's writes", "Advertencia: Se guardaron {} de contenido reemplazado para deshacer; tap undo no restaurará el resto de las escrituras de esta ejecución"),
+];
@@ -0,0 +1,7845 @@
+//! The file operations behind the `tap` command, for tools that want tap's
+//! behavior without shelling out.
+//!
+//! Describe what to do with a [`FileOp`], usually built with [`TapBuilder`],
+//! then run it over paths or glob patterns:
+//!
+//! ```no_run
+//! let op = tap::TapBuilder::new()
+//!     .write("Hello, World!")
+//!     .chmod("644")
+//!     .build();
+//! op.run(&["greeting.txt".to_string()])?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "parallel", feature = "watch"))]
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, Utc};
+use clap::ValueEnum;
+use glob::{glob, Pattern};
+#[cfg(feature = "watch")]
+use notify::{RecursiveMode, Watcher};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "hash")]
+use sha2::{Digest, Sha256};
+
+pub mod condition;
+mod config;
+mod conventions;
+mod dotenv;
+pub mod generate;
+#[cfg(feature = "net")]
+mod http;
+pub mod i18n;
+mod manifest;
+pub mod mode;
+mod notification;
+mod output;
+mod platform;
+pub mod sandbox;
+mod scaffold;
+pub mod serve;
+pub mod signature;
+mod snapshot;
+pub mod temp_files;
+pub mod template;
+pub mod undo;
+pub mod units;
+mod users;
+mod watch;
+
+pub use config::{Config, Preset, Rule, CONFIG_ENV};
+pub use conventions::{write_conventions, write_managed_block, Language};
+pub use dotenv::{update_dotenv, DotEnv};
+pub use manifest::{
+    apply_manifest, json_schema, Manifest, ManifestEntry, ManifestState, Produced, SchemaKind,
+};
+pub use output::{
+    render, render_header, render_row, CheckRecord, OutputFormat, PathRecord, Record, StatRecord,
+    StreamRecord, TimeDisplay, TimeStyle, DEFAULT_TIME_FORMAT,
+};
+pub use scaffold::{add_build_target, BuildFile, TestPairing};
+pub use snapshot::{ComparePolicy, Drift, Snapshot};
+pub use users::{skel_sync, UserAccount, XdgDirs};
+pub use watch::run_on_change;
+
+use condition::Condition;
+use generate::{expand_tokens, Fill, Lorem, Rng};
+use mode::Mode;
+use notification::RunSummary;
+use platform::ModeExt;
+use sandbox::Sandbox;
+use undo::Journal;
+use units::{parse_size, show_size};
+use users::{give_to_user, user_targets};
+use watch::watch;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum LogTarget {
+    Syslog,
+    Journald,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DedupeMode {
+    Hardlink,
+    Symlink,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// What a run's exit status reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum StatusFrom {
+    /// Fail if any path fails
+    #[default]
+    AnyError,
+    /// Also fail if any path changed (or with --dry-run, would change), to
+    /// check that nothing needed doing
+    Changes,
+    /// Report failures but always succeed
+    None,
+}
+
+/// Where to announce that a run finished.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum NotifyTarget {
+    /// A desktop notification (Notification Center on macOS, notify-send
+    /// elsewhere)
+    Desktop,
+}
+
+thread_local! {
+    /// Set while a worker thread processes a path, so that its messages can be
+    /// printed in path order once every path is done
+    static CAPTURED_OUTPUT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// `println!` for progress messages, which `capture_output` can hold back.
+/// The message is translated like `tr!`'s, and printed as [`plain_text`].
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::say_line(format_args!("{}", $crate::tr!($($arg)*)))
+    };
+}
+
+#[doc(hidden)]
+pub fn say_line(line: fmt::Arguments) {
+    let line = line.to_string();
+    let line = plain_text(&line);
+    CAPTURED_OUTPUT.with_borrow_mut(|captured| match captured {
+        Some(buffer) => buffer.push_str(&format!("{}\n", line)),
+        None => println!("{}", line),
+    });
+}
+
+/// `eprintln!` for warnings and errors, translated and printed like `say!`'s
+/// messages.
+#[macro_export]
+macro_rules! say_err {
+    ($($arg:tt)*) => {
+        $crate::say_err_line(format_args!("{}", $crate::tr!($($arg)*)))
+    };
+}
+
+#[doc(hidden)]
+pub fn say_err_line(line: fmt::Arguments) {
+    eprintln!("{}", plain_text(&line.to_string()));
+}
+
+/// Runs `f`, returning the `say!` output it produced instead of printing it.
+fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let outer = CAPTURED_OUTPUT.replace(Some(String::new()));
+    let result = f();
+    (result, CAPTURED_OUTPUT.replace(outer).unwrap_or_default())
+}
+
+static PLAIN: OnceLock<bool> = OnceLock::new();
+
+/// Switches to plain output (`--plain`) for the rest of the process. Only
+/// the first call counts, and it has to come before the first message.
+pub fn set_plain(plain: bool) {
+    let _ = PLAIN.set(plain);
+}
+
+/// Whether output is kept to plain lines of text, for screen readers and
+/// dumb terminals. Without `set_plain`, it is when `TAP_PLAIN` is set to
+/// anything but an empty string or `TERM` is `dumb`.
+pub fn plain() -> bool {
+    *PLAIN.get_or_init(|| {
+        std::env::var_os("TAP_PLAIN").is_some_and(|value| !value.is_empty())
+            || std::env::var_os("TERM").is_some_and(|term| term == "dumb")
+    })
+}
+
+/// `text` as tap prints it: in plain output, control characters other than
+/// newlines and tabs are escaped, so that nothing in a path or a message can
+/// colour the terminal, move the cursor or overwrite a line.
+pub fn plain_text(text: &str) -> Cow<'_, str> {
+    if plain() {
+        escape_controls(text)
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn escape_controls(text: &str) -> Cow<'_, str> {
+    if !text.contains(|c: char| c.is_control() && c != '\n' && c != '\t') {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(
+        text.chars()
+            .map(|c| match c {
+                '\n' | '\t' => c.to_string(),
+                c if c.is_control() => c.escape_unicode().to_string(),
+                c => c.to_string(),
+            })
+            .collect(),
+    )
+}
+
+pub const DEFAULT_LOG_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+pub const DEFAULT_LOG_KEEP: u32 = 5;
+
+/// What tap does to each target. Each field mirrors the command-line flag of
+/// the same name; values such as modes, durations and sizes use the same
+/// syntax as on the command line.
+#[derive(Clone, Debug)]
+pub struct FileOp {
+    /// Create a directory instead of a file
+    pub dir: bool,
+    /// Create a symlink to this target instead of a file. The target is
+    /// stored as given, so a relative one is relative to the link's
+    /// directory; content, mode and timestamp options don't apply
+    pub link_to: Option<PathBuf>,
+    /// Create a hard link to this existing file instead of a file. A
+    /// relative target is relative to the current directory, and it has to
+    /// be on the same filesystem as the link
+    pub hard_link_to: Option<PathBuf>,
+    /// Create a FIFO (named pipe) instead of a file
+    pub fifo: bool,
+    /// Create a Unix socket instead of a file, with nothing listening on it
+    pub socket: bool,
+    /// With `link_to` or `hard_link_to`, replace whatever else is at the
+    /// path, except a directory
+    pub force: bool,
+    /// Permissions to set (octal, e.g. `644`)
+    pub chmod: Option<String>,
+    /// File attributes to set or clear (`+h,+r,-a`), as `Attributes::parse`
+    /// reads them
+    pub attrib: Option<String>,
+    /// Content to write to the file
+    pub write: Option<String>,
+    /// Write the content read from standard input instead, which can be
+    /// binary. It is read once, the first time a target needs it
+    pub stdin: bool,
+    /// Copy the content of this file instead, byte for byte, as a manifest
+    /// entry's `source` gives it
+    pub copy_from: Option<PathBuf>,
+    /// Access and modification times (`YYYY-MM-DD HH:MM:SS[.fraction]`)
+    pub timestamp: Option<String>,
+    /// Append content instead of overwriting
+    pub append: bool,
+    /// Insert content at the top of the file instead of overwriting
+    pub prepend: bool,
+    /// Insert content so it starts at this line (from 1) instead of overwriting
+    pub at_line: Option<usize>,
+    /// Insert content after the first line containing this text instead of
+    /// overwriting
+    pub insert_after: Option<String>,
+    /// With `append`, skip files that already contain the content
+    pub once: bool,
+    /// Before overwriting a non-empty file, copy it to its name plus this
+    /// suffix, or with `numbered` to the next free `NAME.~N~`
+    pub backup: Option<String>,
+    /// Keep a journal of what the run changes under this directory, so that
+    /// [`undo::undo`] can revert it (the command line uses
+    /// [`undo::journal_dir`] unless `--no-undo` or the config's `undo`
+    /// turn it off)
+    pub journal: Option<PathBuf>,
+    /// Print what is being done
+    pub verbose: bool,
+    /// Apply `chmod` recursively to directories
+    pub recursive: bool,
+    /// Template file to take the content from
+    pub template: Option<String>,
+    /// Template content given inline, as config presets give it, used when
+    /// there is no template file
+    pub template_text: Option<String>,
+    /// Variables templates can use, as `{{ name }}`
+    pub template_vars: Vec<(String, String)>,
+    /// Let templates run commands with `shell(...)`
+    pub allow_shell: bool,
+    /// Limits on the commands `shell(...)` runs
+    pub sandbox: Option<Sandbox>,
+    /// Public minisign keys that may sign a template fetched from a URL,
+    /// besides those `tap key add` stored
+    pub trusted_keys: Vec<String>,
+    /// Use a template fetched from a URL without checking its signature
+    pub insecure: bool,
+    /// Wrap what is written to a header in an include guard or `#pragma once`
+    pub header_guard: Option<HeaderGuard>,
+    /// Start what is written with a `#!` line for this interpreter (a name
+    /// run through `/usr/bin/env`, or a full path) and make the file
+    /// executable
+    pub shebang: Option<String>,
+    /// Make the file exactly this size (e.g. `10M`), after any content is
+    /// written: cut short, or extended with zeros
+    pub size: Option<String>,
+    /// With `size`, extend the file with a hole rather than allocated zeros,
+    /// which takes no disk space until something is written there
+    pub sparse: bool,
+    /// Free the disk space behind a range of the file, `OFFSET,LEN` (e.g.
+    /// `4K,1M`), which reads as zeros afterwards
+    pub punch_hole: Option<String>,
+    /// Write generated bytes instead of content: `random:SIZE`,
+    /// `zeros:SIZE` or `pattern:BYTE:SIZE`
+    pub fill: Option<String>,
+    /// Write placeholder prose as content: `words:N`, `sentences:N` or
+    /// `paragraphs:N`
+    pub lorem: Option<String>,
+    /// Seed for generated content, which makes it the same on every run.
+    /// `fill` and `lorem` write the same into every file, while the tokens
+    /// of `write` content still differ from file to file.
+    pub seed: Option<u64>,
+    /// Remove trailing whitespace from each line
+    pub trim: bool,
+    /// Only report whether targets exist
+    pub check: bool,
+    /// Maximum paths per second or minute (e.g. `200/s`)
+    pub throttle: Option<String>,
+    /// With `throttle`, process paths in groups of this many, one group
+    /// after another at the same average rate, so a network filesystem gets
+    /// bursts of writes with idle spells in between
+    pub batch: Option<usize>,
+    /// Print what processing each path would change instead of changing it
+    pub dry_run: bool,
+    /// Process a path once per pattern that matches it, instead of once
+    pub keep_duplicates: bool,
+    /// Read the paths from stdin, as xargs does, instead of from the arguments
+    pub xargs: bool,
+    /// With `xargs`, split stdin on NUL bytes instead of whitespace
+    pub null: bool,
+    /// Number of paths to process at once; by default one per CPU for runs of
+    /// more than `LARGE_RUN_THRESHOLD` paths and one otherwise
+    pub jobs: Option<usize>,
+    /// Wait for parent directories and busy targets, up to a timeout or `forever`
+    pub wait: Option<String>,
+    /// Whether missing parent directories are created; `None` leaves it to
+    /// the config, which creates them unless it has `parents = false`
+    pub parents: Option<bool>,
+    /// When a target's parent directory is missing and isn't created, ask on
+    /// the terminal whether to use the close existing one suggested instead
+    pub interactive: bool,
+    /// Keep applying the operation until interrupted: again after each
+    /// change to a matching path, or with `every` on an interval
+    pub watch: bool,
+    /// With `watch`, how long to wait between passes (e.g. `30s`) instead of
+    /// waiting for changes
+    pub every: Option<String>,
+    /// Write a PID file
+    pub pidfile: bool,
+    /// PID to record with `pidfile` (defaults to the calling process)
+    pub pid: Option<u32>,
+    /// Resolve `@member/...` targets against the enclosing workspace
+    pub workspace: bool,
+    /// Report the last directory created for the shell to change into
+    pub print_cd: bool,
+    /// Skip the filesystem warnings shown before large or recursive runs
+    pub no_fs_checks: bool,
+    /// Umask to use while running (octal)
+    pub umask: Option<String>,
+    /// Only change permissions
+    pub no_touch: bool,
+    /// Only update timestamps of existing paths
+    pub times_only: bool,
+    /// Skip paths that don't exist instead of creating them, like `touch -c`
+    pub no_create: bool,
+    /// Name of a cross-process lock to hold for the whole run
+    pub exclusive: Option<String>,
+    /// With `exclusive`, wait for the lock, up to a timeout or `forever`
+    pub exclusive_wait: Option<String>,
+    /// Message to append as a timestamped log line
+    pub log_entry: Option<String>,
+    /// strftime format for `log_entry` timestamps (UTC)
+    pub log_format: String,
+    /// Size at which to rotate the log before appending (e.g. `10M`)
+    pub log_rotate: Option<String>,
+    /// Number of rotated logs to keep
+    pub log_keep: u32,
+    /// System log to record every change in
+    pub log_target: Option<LogTarget>,
+    /// Where to write a JSON report of each path's state before and after
+    pub audit_report: Option<PathBuf>,
+    /// Where to list the paths the run changed, one per line; `-` is stdout
+    pub changed_list: Option<PathBuf>,
+    /// Announce the run's summary when it finishes
+    pub notify: Option<NotifyTarget>,
+    /// URL to POST the run's summary to as JSON when it finishes
+    pub notify_url: Option<String>,
+    /// User (name or UID) to hand everything created to
+    pub as_user: Option<String>,
+    /// User (name or UID) to give targets to, created or not
+    pub owner: Option<String>,
+    /// Group (name or GID) to give targets to, created or not
+    pub group: Option<String>,
+    /// Apply relative paths under every regular user's home
+    pub each_user: bool,
+    /// With `each_user`, only these users
+    pub users: Vec<String>,
+    /// File whose modification time the targets should come just after
+    pub after: Option<PathBuf>,
+    /// File whose access and modification times the targets should get
+    pub reference: Option<PathBuf>,
+    /// Which times a timestamp, a reference or a plain touch changes
+    pub times: TimeSelection,
+    /// On network filesystems, take "now" from the file server's clock
+    pub server_time: bool,
+    /// Permissions for files created without an explicit `chmod`
+    pub new_file_mode: Option<String>,
+    /// Permissions for directories created without an explicit `chmod`
+    pub new_dir_mode: Option<String>,
+    /// Directory to look up templates in when `template` isn't a file
+    pub templates_dir: Option<PathBuf>,
+    /// How results are printed: messages, or a record per path
+    pub output: OutputFormat,
+    /// strftime format for timestamps in output (see `TimeDisplay`)
+    pub time_format: Option<String>,
+    /// How timestamps in output are shown when no --time-format is given
+    pub time_style: TimeStyle,
+    /// Print sizes as exact byte counts instead of human-readable ones
+    pub bytes: bool,
+    /// What the exit status of `run` reports
+    pub status_from: StatusFrom,
+    /// Content and modes new files get by name (the config's `[[rules]]`),
+    /// when nothing else says what they hold
+    pub rules: Vec<Rule>,
+    /// Templates already read and rendered; clones share it
+    pub template_cache: TemplateCache,
+}
+
+impl Default for FileOp {
+    fn default() -> Self {
+        Self {
+            dir: false,
+            link_to: None,
+            hard_link_to: None,
+            fifo: false,
+            socket: false,
+            force: false,
+            chmod: None,
+            attrib: None,
+            write: None,
+            stdin: false,
+            copy_from: None,
+            timestamp: None,
+            append: false,
+            prepend: false,
+            at_line: None,
+            insert_after: None,
+            once: false,
+            backup: None,
+            journal: None,
+            verbose: false,
+            recursive: false,
+            template: None,
+            template_text: None,
+            template_vars: Vec::new(),
+            allow_shell: false,
+            sandbox: None,
+            trusted_keys: Vec::new(),
+            insecure: false,
+            header_guard: None,
+            shebang: None,
+            size: None,
+            sparse: false,
+            punch_hole: None,
+            fill: None,
+            lorem: None,
+            seed: None,
+            trim: false,
+            check: false,
+            throttle: None,
+            batch: None,
+            dry_run: false,
+            keep_duplicates: false,
+            xargs: false,
+            null: false,
+            jobs: None,
+            wait: None,
+            parents: None,
+            interactive: false,
+            watch: false,
+            every: None,
+            pidfile: false,
+            pid: None,
+            workspace: false,
+            print_cd: false,
+            no_fs_checks: false,
+            umask: None,
+            no_touch: false,
+            times_only: false,
+            no_create: false,
+            exclusive: None,
+            exclusive_wait: None,
+            log_entry: None,
+            log_format: DEFAULT_LOG_FORMAT.to_string(),
+            log_rotate: None,
+            log_keep: DEFAULT_LOG_KEEP,
+            log_target: None,
+            audit_report: None,
+            changed_list: None,
+            notify: None,
+            notify_url: None,
+            as_user: None,
+            owner: None,
+            group: None,
+            each_user: false,
+            users: Vec::new(),
+            after: None,
+            reference: None,
+            times: TimeSelection::Both,
+            server_time: false,
+            new_file_mode: None,
+            new_dir_mode: None,
+            templates_dir: None,
+            output: OutputFormat::Text,
+            time_format: None,
+            time_style: TimeStyle::Absolute,
+            bytes: false,
+            status_from: StatusFrom::AnyError,
+            rules: Vec::new(),
+            template_cache: TemplateCache::default(),
+        }
+    }
+}
+
+impl FileOp {
+    /// Expands `paths` (glob patterns, and `@member/...` targets with
+    /// `workspace`) and applies the operation to every match.
+    pub fn run(&self, paths: &[String]) -> Result<()> {
+        run(self, paths)
+    }
+
+    /// Applies the operation to a single path, creating missing parents.
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        process_path(path, self)
+    }
+
+    /// How `time_format` and `time_style` show timestamps.
+    pub fn time_display(&self) -> Result<TimeDisplay> {
+        TimeDisplay::new(self.time_format.as_deref(), self.time_style)
+    }
+
+    /// How `set_timestamp` shows the time it sets: not at all unless verbose.
+    fn timestamp_shown(&self) -> Result<Option<TimeDisplay>> {
+        self.verbose.then(|| self.time_display()).transpose()
+    }
+
+    /// `self` with the preset of the first rule matching `path` applied, if
+    /// `path` is a plain file yet to be created with nothing else to say
+    /// what goes in it.
+    fn with_rule_for(&self, path: &Path) -> Option<Self> {
+        let plain = !self.writes_content()
+            && !self.dir
+            && !self.fifo
+            && !self.socket
+            && self.link_to.is_none()
+            && self.hard_link_to.is_none()
+            && self.fill.is_none()
+            && !self.pidfile
+            && self.log_entry.is_none()
+            && !self.trim;
+        if self.rules.is_empty() || !plain || fs::symlink_metadata(path).is_ok() {
+            return None;
+        }
+        let rule = self.rules.iter().find(|rule| rule.matches(path))?;
+        let mut op = Self {
+            rules: Vec::new(),
+            ..self.clone()
+        };
+        rule.preset.apply_to(&mut op);
+        Some(op)
+    }
+
+    /// Whether content goes into the middle of the file rather than
+    /// replacing it or going at the end.
+    fn inserts(&self) -> bool {
+        self.prepend || self.at_line.is_some() || self.insert_after.is_some()
+    }
+
+    /// Whether running the operation on a path again, after an attempt that
+    /// failed part-way, gives the same result: not when each run adds
+    /// content, a log line or a backup.
+    fn repeatable(&self) -> bool {
+        let adds_content = (self.append || self.inserts()) && !self.once;
+        !adds_content && self.log_entry.is_none() && self.backup.is_none()
+    }
+
+    /// Whether files get content from `write`, `stdin`, `copy_from`,
+    /// `template`, `lorem` or `shebang`, rather than only being touched.
+    fn writes_content(&self) -> bool {
+        self.write.is_some()
+            || self.stdin
+            || self.copy_from.is_some()
+            || self.template.is_some()
+            || self.template_text.is_some()
+            || self.lorem.is_some()
+            || self.shebang.is_some()
+    }
+
+    /// The generator for `fill` and `lorem`: from `seed`, or different every
+    /// time without one.
+    fn generator(&self) -> Rng {
+        self.seed.map_or_else(Rng::from_entropy, Rng::seeded)
+    }
+
+    /// The generator for the tokens of `write` content in `path`, which
+    /// with a `seed` also depends on the path, so each file gets its own
+    /// values.
+    fn token_generator(&self, path: &Path) -> Rng {
+        match self.seed {
+            Some(seed) => Rng::keyed(seed, path.as_os_str().as_encoded_bytes()),
+            None => Rng::from_entropy(),
+        }
+    }
+
+    /// Whether the run needs to know which paths it changed.
+    fn tracks_changes(&self) -> bool {
+        self.status_from == StatusFrom::Changes
+            || self.changed_list.is_some()
+            || self.notify.is_some()
+            || self.notify_url.is_some()
+    }
+}
+
+/// Builds a [`FileOp`] one option at a time.
+#[derive(Default)]
+pub struct TapBuilder {
+    op: FileOp,
+}
+
+impl TapBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn build(self) -> FileOp {
+        self.op
+    }
+
+    pub fn dir(mut self) -> Self {
+        self.op.dir = true;
+        self
+    }
+
+    pub fn link_to(mut self, target: impl Into<PathBuf>) -> Self {
+        self.op.link_to = Some(target.into());
+        self
+    }
+
+    pub fn hard_link_to(mut self, target: impl Into<PathBuf>) -> Self {
+        self.op.hard_link_to = Some(target.into());
+        self
+    }
+
+    pub fn fifo(mut self) -> Self {
+        self.op.fifo = true;
+        self
+    }
+
+    pub fn socket(mut self) -> Self {
+        self.op.socket = true;
+        self
+    }
+
+    pub fn force(mut self) -> Self {
+        self.op.force = true;
+        self
+    }
+
+    pub fn chmod(mut self, mode: impl Into<String>) -> Self {
+        self.op.chmod = Some(mode.into());
+        self
+    }
+
+    pub fn attrib(mut self, changes: impl Into<String>) -> Self {
+        self.op.attrib = Some(changes.into());
+        self
+    }
+
+    pub fn recursive(mut self) -> Self {
+        self.op.recursive = true;
+        self
+    }
+
+    pub fn write(mut self, content: impl Into<String>) -> Self {
+        self.op.write = Some(content.into());
+        self
+    }
+
+    pub fn stdin(mut self) -> Self {
+        self.op.stdin = true;
+        self
+    }
+
+    pub fn append(mut self) -> Self {
+        self.op.append = true;
+        self
+    }
+
+    pub fn prepend(mut self) -> Self {
+        self.op.prepend = true;
+        self
+    }
+
+    pub fn at_line(mut self, line: usize) -> Self {
+        self.op.at_line = Some(line);
+        self
+    }
+
+    pub fn insert_after(mut self, marker: impl Into<String>) -> Self {
+        self.op.insert_after = Some(marker.into());
+        self
+    }
+
+    pub fn once(mut self) -> Self {
+        self.op.once = true;
+        self
+    }
+
+    pub fn backup(mut self, suffix: impl Into<String>) -> Self {
+        self.op.backup = Some(suffix.into());
+        self
+    }
+
+    pub fn journal(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.op.journal = Some(dir.into());
+        self
+    }
+
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.op.template = Some(template.into());
+        self
+    }
+
+    pub fn template_text(mut self, text: impl Into<String>) -> Self {
+        self.op.template_text = Some(text.into());
+        self
+    }
+
+    pub fn template_var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.op.template_vars.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn allow_shell(mut self) -> Self {
+        self.op.allow_shell = true;
+        self
+    }
+
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.op.sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn trusted_key(mut self, key: impl Into<String>) -> Self {
+        self.op.trusted_keys.push(key.into());
+        self
+    }
+
+    pub fn insecure(mut self) -> Self {
+        self.op.insecure = true;
+        self
+    }
+
+    pub fn header_guard(mut self, guard: HeaderGuard) -> Self {
+        self.op.header_guard = Some(guard);
+        self
+    }
+
+    pub fn shebang(mut self, interpreter: impl Into<String>) -> Self {
+        self.op.shebang = Some(interpreter.into());
+        self
+    }
+
+    pub fn size(mut self, size: impl Into<String>) -> Self {
+        self.op.size = Some(size.into());
+        self
+    }
+
+    pub fn sparse(mut self) -> Self {
+        self.op.sparse = true;
+        self
+    }
+
+    pub fn punch_hole(mut self, range: impl Into<String>) -> Self {
+        self.op.punch_hole = Some(range.into());
+        self
+    }
+
+    pub fn fill(mut self, generator: impl Into<String>) -> Self {
+        self.op.fill = Some(generator.into());
+        self
+    }
+
+    pub fn lorem(mut self, amount: impl Into<String>) -> Self {
+        self.op.lorem = Some(amount.into());
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.op.seed = Some(seed);
+        self
+    }
+
+    pub fn trim(mut self) -> Self {
+        self.op.trim = true;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: impl Into<String>) -> Self {
+        self.op.timestamp = Some(timestamp.into());
+        self
+    }
+
+    pub fn after(mut self, reference: impl Into<PathBuf>) -> Self {
+        self.op.after = Some(reference.into());
+        self
+    }
+
+    pub fn reference(mut self, reference: impl Into<PathBuf>) -> Self {
+        self.op.reference = Some(reference.into());
+        self
+    }
+
+    pub fn times(mut self, times: TimeSelection) -> Self {
+        self.op.times = times;
+        self
+    }
+
+    pub fn check(mut self) -> Self {
+        self.op.check = true;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.op.verbose = verbose;
+        self
+    }
+
+    pub fn no_touch(mut self) -> Self {
+        self.op.no_touch = true;
+        self
+    }
+
+    pub fn times_only(mut self) -> Self {
+        self.op.times_only = true;
+        self
+    }
+
+    pub fn no_create(mut self) -> Self {
+        self.op.no_create = true;
+        self
+    }
+
+    pub fn umask(mut self, mask: impl Into<String>) -> Self {
+        self.op.umask = Some(mask.into());
+        self
+    }
+
+    pub fn throttle(mut self, rate: impl Into<String>) -> Self {
+        self.op.throttle = Some(rate.into());
+        self
+    }
+
+    pub fn batch(mut self, size: usize) -> Self {
+        self.op.batch = Some(size);
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.op.jobs = Some(jobs);
+        self
+    }
+
+    pub fn wait(mut self, timeout: Option<Duration>) -> Self {
+        self.op.wait = Some(match timeout {
+            Some(timeout) => format!("{}ms", timeout.as_millis()),
+            None => "forever".to_string(),
+        });
+        self
+    }
+
+    /// Whether to create missing parent directories, or to fail with a
+    /// suggestion of a close existing one.
+    pub fn parents(mut self, parents: bool) -> Self {
+        self.op.parents = Some(parents);
+        self
+    }
+
+    /// Asks on the terminal whether to use a close existing directory instead
+    /// of a missing parent directory that `parents(false)` doesn't create.
+    pub fn interactive(mut self) -> Self {
+        self.op.interactive = true;
+        self
+    }
+
+    /// Keeps applying the operation until interrupted, every `interval` or,
+    /// without one, whenever a matching path changes.
+    pub fn watch(mut self, interval: Option<Duration>) -> Self {
+        self.op.watch = true;
+        self.op.every = interval.map(|interval| format!("{}ms", interval.as_millis()));
+        self
+    }
+
+    pub fn as_user(mut self, user: impl Into<String>) -> Self {
+        self.op.as_user = Some(user.into());
+        self
+    }
+
+    pub fn log_target(mut self, target: LogTarget) -> Self {
+        self.op.log_target = Some(target);
+        self
+    }
+
+    pub fn owner(mut self, owner: impl Into<String>) -> Self {
+        self.op.owner = Some(owner.into());
+        self
+    }
+
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.op.group = Some(group.into());
+        self
+    }
+
+    pub fn audit_report(mut self, destination: impl Into<PathBuf>) -> Self {
+        self.op.audit_report = Some(destination.into());
+        self
+    }
+
+    pub fn changed_list(mut self, destination: impl Into<PathBuf>) -> Self {
+        self.op.changed_list = Some(destination.into());
+        self
+    }
+
+    pub fn notify(mut self, target: NotifyTarget) -> Self {
+        self.op.notify = Some(target);
+        self
+    }
+
+    pub fn notify_url(mut self, url: impl Into<String>) -> Self {
+        self.op.notify_url = Some(url.into());
+        self
+    }
+
+    pub fn status_from(mut self, status_from: StatusFrom) -> Self {
+        self.op.status_from = status_from;
+        self
+    }
+}
+
+fn run(op: &FileOp, paths: &[String]) -> Result<()> {
+    let users = if !op.each_user {
+        Vec::new()
+    } else if op.users.is_empty() {
+        UserAccount::regular_users()
+    } else {
+        op.users
+            .iter()
+            .map(|user| UserAccount::lookup(user))
+            .collect::<Result<Vec<_>>>()?
+    };
+    run_for(op, paths, &users)
+}
+
+/// `run`, with `users` to apply the paths for under `each_user`.
+fn run_for(op: &FileOp, paths: &[String], users: &[UserAccount]) -> Result<()> {
+    if op.watch {
+        return watch(op, paths);
+    }
+    if op.xargs && op.stdin {
+        anyhow::bail!(tr!("--xargs and --stdin can't both read standard input"));
+    }
+    if let Some(url) = &op.notify_url {
+        notification::check_url(url)?;
+    }
+    if !op.dry_run {
+        // Best effort: a run shouldn't fail over another run's leftovers
+        let _ = temp_files::remove_orphans(op.verbose);
+    }
+    let started = Instant::now();
+    let _umask = op.umask.as_deref().map(UmaskGuard::set).transpose()?;
+    let _lock = match &op.exclusive {
+        Some(name) => {
+            let wait = op
+                .exclusive_wait
+                .as_deref()
+                .map(|wait| {
+                    parse_wait_timeout(wait).map(|timeout| timeout.and_then(deadline_after))
+                })
+                .transpose()?;
+            Some(ExclusiveLock::acquire(
+                &state_dir()?.join("locks"),
+                name,
+                wait,
+                op.verbose,
+            )?)
+        }
+        None => None,
+    };
+
+    let owner = op.as_user.as_deref().map(UserAccount::lookup).transpose()?;
+    // Users some of whose paths failed, which doesn't stop the others
+    let mut failed_users = BTreeSet::new();
+    let mut matches: Box<dyn Iterator<Item = (PathBuf, Option<&UserAccount>)> + Send> =
+        if op.each_user {
+            let targets = user_targets(paths, users, &mut failed_users)?;
+            Box::new(targets.into_iter())
+        } else {
+            let patterns = if op.workspace {
+                let workspace = Workspace::discover(&std::env::current_dir()?)?;
+                paths
+                    .iter()
+                    .map(|path| workspace.resolve(path))
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                paths.to_vec()
+            };
+            let found: Box<dyn Iterator<Item = PathBuf> + Send> = if op.xargs {
+                Box::new(read_paths(BufReader::new(io::stdin()), op.null))
+            } else {
+                Box::new(glob_paths(&patterns)?)
+            };
+            // Standard input can only answer questions when nothing else is read from it
+            let ask = op.interactive && !op.xargs && !op.stdin && io::stdin().is_terminal();
+            let found: Box<dyn Iterator<Item = PathBuf> + Send> = if ask {
+                Box::new(found.map(|path| corrected_path(path, op, confirm)))
+            } else {
+                found
+            };
+            let found: Box<dyn Iterator<Item = PathBuf> + Send> = if op.keep_duplicates {
+                found
+            } else {
+                Box::new(unique_paths(found))
+            };
+            let owner = owner.as_ref();
+            Box::new(found.map(move |path| (path, owner)))
+        };
+    // Only look far enough ahead to tell a large run from a small one; the
+    // rest of the matches are processed as the glob walk finds them. Paths
+    // piped in are processed as they arrive, since the writer may be slow
+    let lookahead = if op.xargs { 0 } else { LARGE_RUN_THRESHOLD + 1 };
+    let head = matches.by_ref().take(lookahead).collect::<Vec<_>>();
+    let large = head.len() > LARGE_RUN_THRESHOLD;
+
+    // Without changes to make, a table of results is all --check has to give
+    if op.check && op.output != OutputFormat::Text {
+        let records = head
+            .into_iter()
+            .chain(matches)
+            .map(|(path, _)| CheckRecord {
+                exists: path.exists(),
+                path,
+            })
+            .collect::<Vec<_>>();
+        print!("{}", render(&records, op.output));
+        return Ok(());
+    }
+    let throttle = op.throttle.as_deref().map(Throttle::parse).transpose()?;
+    let throttle = match (throttle, op.batch) {
+        (Some(throttle), Some(size)) => Some(throttle.batch(size)),
+        (None, Some(_)) => anyhow::bail!(tr!("--batch needs --throttle")),
+        (throttle, None) => throttle,
+    };
+    // A bad --time-format fails before anything is changed
+    op.time_display()?;
+
+    if !op.no_fs_checks && (op.recursive || head.len() >= LARGE_RUN_THRESHOLD) {
+        let paths: Vec<_> = head.iter().map(|(path, _)| path.clone()).collect();
+        for warning in filesystem_warnings(&paths) {
+            say_err!("Warning: {}", warning);
+        }
+    }
+
+    let jobs = match op.jobs {
+        Some(jobs) => jobs.max(1),
+        None if large && cfg!(feature = "parallel") => {
+            thread::available_parallelism().map_or(1, |cpus| cpus.get())
+        }
+        None => 1,
+    };
+    if jobs > 1 && !cfg!(feature = "parallel") {
+        anyhow::bail!(tr!(
+            "--jobs can't be more than 1: tap was built without the parallel feature"
+        ));
+    }
+    let throttle = throttle.map(|throttle| throttle.burst(jobs));
+
+    let mut attempted = 0;
+    let paths = head.into_iter().chain(matches).inspect(|_| attempted += 1);
+    print!("{}", render_header::<PathRecord>(op.output));
+    let mut report = op.audit_report.as_ref().map(|_| AuditReport::default());
+    let journal = match &op.journal {
+        Some(dir) if !op.dry_run && !op.check => Some(Journal::start(dir)),
+        _ => None,
+    };
+    let mut changed = Vec::new();
+    let outcome = match jobs {
+        #[cfg(feature = "parallel")]
+        2.. => process_in_parallel(
+            paths,
+            jobs,
+            |path, owner| process_throttled(path, op, owner, throttle.as_ref(), journal.as_ref()),
+            report.as_mut(),
+            &mut changed,
+            &mut io::stdout(),
+        ),
+        _ => {
+            let mut outcome = Ok(None);
+            for (path, owner) in paths {
+                let before = report.as_ref().and_then(|_| PathState::capture(&path));
+                let result =
+                    process_throttled(&path, op, owner, throttle.as_ref(), journal.as_ref());
+                let result = result.map(|path_changed| {
+                    if path_changed {
+                        changed.push(path.clone());
+                    }
+                });
+                if let Some(report) = report.as_mut() {
+                    report.record(&path, before, &result);
+                }
+                match (result, owner) {
+                    (Err(error), Some(user)) if op.each_user => {
+                        say_err!("Error for user {}: {:#}", user.name, error);
+                        failed_users.insert(user.name.as_str());
+                    }
+                    (Err(error), _) => {
+                        outcome = Err(error);
+                        break;
+                    }
+                    (Ok(()), _) => outcome = Ok(Some(path)),
+                }
+            }
+            outcome
+        }
+    };
+    let outcome = match outcome {
+        Ok(_) if !failed_users.is_empty() => Err(anyhow::anyhow!(tr!(
+            "Failed for {} of {} user(s)",
+            failed_users.len(),
+            users.len()
+        ))),
+        outcome => outcome,
+    };
+    // The journal, report and list are written even when a path failed, so
+    // they cover what did change
+    if let Some(journal) = journal {
+        journal.finish();
+    }
+    if let (Some(destination), Some(report)) = (&op.audit_report, &report) {
+        report.write(destination)?;
+    }
+    if let Some(destination) = &op.changed_list {
+        write_changed_list(destination, &changed)?;
+    }
+    if op.notify.is_some() || op.notify_url.is_some() {
+        send_notifications(
+            op,
+            &RunSummary {
+                paths: attempted,
+                changed: changed.len(),
+                dry_run: op.dry_run,
+                seconds: started.elapsed().as_secs_f64(),
+                error: outcome.as_ref().err().map(|error| format!("{:#}", error)),
+            },
+        );
+    }
+    let last = match outcome {
+        Err(error) if op.status_from == StatusFrom::None => {
+            say_err!("Error: {:#}", error);
+            None
+        }
+        outcome => outcome?,
+    };
+
+    if op.print_cd {
+        if let Some(path) = &last {
+            let cd_file = std::env::var_os(CD_FILE_ENV).map(PathBuf::from);
+            emit_cd_target(path, cd_file.as_deref())?;
+        }
+    }
+
+    if op.status_from == StatusFrom::Changes && !changed.is_empty() {
+        anyhow::bail!(tr!("Changed {} path(s)", changed.len()));
+    }
+    Ok(())
+}
+
+fn write_changed_list(destination: &Path, changed: &[PathBuf]) -> Result<()> {
+    let list = changed
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect::<String>();
+    if destination == Path::new("-") {
+        print!("{}", list);
+        return Ok(());
+    }
+    fs::write(destination, list).with_context(|| tr!("Failed to write changed-file list"))
+}
+
+/// Announces the end of a run as `--notify` and `--notify-url` ask, warning
+/// rather than failing when the summary can't be delivered.
+fn send_notifications(op: &FileOp, summary: &RunSummary) {
+    if op.notify == Some(NotifyTarget::Desktop) {
+        if let Err(error) = notification::desktop(summary) {
+            say_err!("Warning: {:#}", error);
+        }
+    }
+    if let Some(url) = &op.notify_url {
+        if let Err(error) = notification::post(url, summary) {
+            say_err!("Warning: {:#}", error);
+        }
+    }
+}
+
+/// How far ahead of the next path to print the workers may get, so a slow
+/// path holds back at most this many finished ones.
+#[cfg(feature = "parallel")]
+const REORDER_WINDOW: usize = 256;
+
+/// Processes paths on `jobs` threads as they arrive, returning the last one.
+/// Unlike the sequential loop, every path is attempted; messages come out in
+/// path order on `out` as soon as the paths before them are done, and
+/// failures are reported together at the end. If `out` can't be written to,
+/// such as a pipe whose reader has quit, the paths not yet started are
+/// skipped.
+#[cfg(feature = "parallel")]
+fn process_in_parallel<'a>(
+    paths: impl Iterator<Item = (PathBuf, Option<&'a UserAccount>)> + Send,
+    jobs: usize,
+    process: impl Fn(&Path, Option<&UserAccount>) -> Result<bool> + Sync,
+    mut report: Option<&mut AuditReport>,
+    changed: &mut Vec<PathBuf>,
+    out: &mut dyn Write,
+) -> Result<Option<PathBuf>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .with_context(|| tr!("Failed to start worker threads"))?;
+    let auditing = report.is_some();
+    let window = REORDER_WINDOW.max(jobs);
+    // How many paths have been printed, which workers wait on to stay
+    // within the window, or `usize::MAX` once printing has stopped
+    let printed = (Mutex::new(0usize), std::sync::Condvar::new());
+    let (sender, receiver) = mpsc::channel();
+    let process = &process;
+
+    let mut total = 0;
+    let mut failures = Vec::new();
+    let mut last = None;
+    let mut written = Ok(());
+    thread::scope(|scope| {
+        let printed = &printed;
+        // Dropped however the printing below ends, so no worker is left
+        // waiting for it
+        let _stop = StopWaiting(printed);
+        scope.spawn(move || {
+            pool.install(|| {
+                paths
+                    .enumerate()
+                    .par_bridge()
+                    .for_each(|(index, (path, owner))| {
+                        let (count, advanced) = printed;
+                        let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+                        while index >= count.saturating_add(window) {
+                            count = advanced.wait(count).unwrap_or_else(|e| e.into_inner());
+                        }
+                        let stopped = *count == usize::MAX;
+                        drop(count);
+                        if stopped {
+                            return;
+                        }
+                        let before = auditing.then(|| PathState::capture(&path)).flatten();
+                        let (result, output) = capture_output(|| process(&path, owner));
+                        // The receiver only goes away once every path is in
+                        let _ = sender.send((index, path, before, result, output));
+                    });
+            })
+        });
+
+        let mut pending = std::collections::BTreeMap::new();
+        'receive: for (index, path, before, result, output) in receiver {
+            pending.insert(index, (path, before, result, output));
+            while let Some((path, before, result, output)) = pending.remove(&total) {
+                if let Err(error) = out.write_all(output.as_bytes()) {
+                    written = Err(error);
+                    break 'receive;
+                }
+                let result = result.map(|path_changed| {
+                    if path_changed {
+                        changed.push(path.clone());
+                    }
+                });
+                if let Some(report) = report.as_deref_mut() {
+                    report.record(&path, before, &result);
+                }
+                if let Err(error) = result {
+                    failures.push((path.clone(), error));
+                }
+                last = Some(path);
+                total += 1;
+                let (count, advanced) = printed;
+                *count.lock().unwrap_or_else(|e| e.into_inner()) = total;
+                advanced.notify_all();
+            }
+        }
+    });
+
+    written.with_context(|| tr!("Failed to write output"))?;
+    if failures.len() == 1 {
+        return Err(failures.remove(0).1);
+    }
+    for (path, error) in &failures {
+        say_err!("Error: {}: {:#}", path.display(), error);
+    }
+    if !failures.is_empty() {
+        anyhow::bail!(tr!(
+            "Failed to process {} of {} paths",
+            failures.len(),
+            total
+        ));
+    }
+    Ok(last)
+}
+
+/// Marks the printing of `process_in_parallel` as stopped when dropped, and
+/// wakes the workers waiting for it.
+#[cfg(feature = "parallel")]
+struct StopWaiting<'a>(&'a (Mutex<usize>, std::sync::Condvar));
+
+#[cfg(feature = "parallel")]
+impl Drop for StopWaiting<'_> {
+    fn drop(&mut self) {
+        let (count, advanced) = self.0;
+        *count.lock().unwrap_or_else(|e| e.into_inner()) = usize::MAX;
+        advanced.notify_all();
+    }
+}
+
+/// `process_and_report` at the pace `throttle` sets, recording what it
+/// changes in `journal` for `tap undo`. A change that failed because the
+/// filesystem was overloaded is tried again only if the operation is
+/// repeatable, and it is journaled once however many attempts it takes.
+fn process_throttled(
+    path: &Path,
+    op: &FileOp,
+    owner: Option<&UserAccount>,
+    throttle: Option<&Throttle>,
+    journal: Option<&Journal>,
+) -> Result<bool> {
+    let change = || match throttle {
+        Some(throttle) => throttle.run(op.repeatable(), || process_and_report(path, op, owner)),
+        None => process_and_report(path, op, owner),
+    };
+    let Some(journal) = journal else {
+        return change();
+    };
+    // The change goes ahead even when it can't be journaled
+    let before = journal.before(path, op).unwrap_or_else(|error| {
+        say_err!("Warning: {} can't be undone: {:#}", path.display(), error);
+        None
+    });
+    let result = change();
+    if let Some(before) = before {
+        journal.record(before, op);
+    }
+    result
+}
+
+/// Processes a path for `owner`, printing a `PathRecord` in place of its
+/// messages when `op.output` is a structured format. When the run tracks
+/// changes, tells whether the path changed, or under `--dry-run` whether it
+/// would have.
+fn process_and_report(path: &Path, op: &FileOp, owner: Option<&UserAccount>) -> Result<bool> {
+    let counting = op.tracks_changes();
+    let before = (counting && !op.dry_run).then(|| Fingerprint::capture(path, op));
+    if op.output == OutputFormat::Text {
+        process_path_as(path, op, owner)?;
+    } else {
+        let (record, result) = PathRecord::process(path, op, owner);
+        say!("{}", render_row(&record, op.output).trim_end_matches('\n'));
+        result?;
+    }
+    match before {
+        Some(before) => Ok(Fingerprint::capture(path, op) != before),
+        None if counting => has_planned_actions(path, op, owner),
+        None => Ok(false),
+    }
+}
+
+/// Whether `--dry-run` has anything to report for `path`.
+fn has_planned_actions(path: &Path, op: &FileOp, owner: Option<&UserAccount>) -> Result<bool> {
+    if op.check || (op.no_create && fs::symlink_metadata(path).is_err()) {
+        return Ok(false);
+    }
+    Ok(!plan_actions(path, op, owner)?.is_empty())
+}
+
+/// What processing can change about a path, compared before and after to
+/// tell whether it did. A run that writes content changes a file when the
+/// content differs, so writing what is already there is no change even
+/// though it moves the modification time; the times only count for plain
+/// touches and for runs that set them explicitly.
+#[derive(PartialEq)]
+struct Fingerprint {
+    file_type: fs::FileType,
+    mode: u32,
+    owner: (u32, u32),
+    len: u64,
+    content: Option<String>,
+    modified: Option<SystemTime>,
+    accessed: Option<SystemTime>,
+}
+
+impl Fingerprint {
+    fn capture(path: &Path, op: &FileOp) -> Option<Self> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let writes = op.writes_content() || op.fill.is_some() || op.trim;
+        let times =
+            !writes || op.timestamp.is_some() || op.after.is_some() || op.reference.is_some();
+        Some(Self {
+            file_type: metadata.file_type(),
+            mode: platform::mode(&metadata),
+            owner: platform::owner(&metadata),
+            len: metadata.len(),
+            content: (writes && metadata.is_file())
+                .then(|| content_digest(path))
+                .flatten(),
+            modified: times.then(|| metadata.modified().ok()).flatten(),
+            // Merely reading a file can move its access time, so it only
+            // counts when it is the one timestamp being set
+            accessed: (times && op.times == TimeSelection::Access)
+                .then(|| metadata.accessed().ok())
+                .flatten(),
+        })
+    }
+}
+
+fn process_path(path: &Path, op: &FileOp) -> Result<()> {
+    let owner = op.as_user.as_deref().map(UserAccount::lookup).transpose()?;
+    process_path_as(path, op, owner.as_ref())
+}
+
+/// Processes a single path; anything created along the way is given to
+/// `owner` when set.
+fn process_path_as(path: &Path, op: &FileOp, owner: Option<&UserAccount>) -> Result<()> {
+    if op.verbose {
+        say!("Processing: {}", path.display());
+    }
+
+    if op.check {
+        return check_existence(path, op.verbose);
+    }
+
+    // The file whose times are taken must be there before anything is
+    // created, so that a mistyped reference leaves nothing behind
+    if let (None, Some(reference), _) | (None, None, Some(reference)) =
+        (&op.timestamp, &op.after, &op.reference)
+    {
+        if fs::metadata(reference).is_err() {
+            anyhow::bail!(tr!("Reference file not found: {}", reference.display()));
+        }
+    }
+
+    // Nothing to do for a missing path, and not an error either
+    if op.no_create && fs::symlink_metadata(path).is_err() {
+        if op.verbose {
+            say!("Skipped (does not exist): {}", path.display());
+        }
+        return Ok(());
+    }
+
+    if cfg!(windows) {
+        if let Some(name) = platform::reserved_component(path) {
+            anyhow::bail!(tr!(
+                "{} names a device on Windows; start the full path with \\\\?\\ to create it as a file: {}",
+                name.to_string_lossy(),
+                path.display()
+            ));
+        }
+    }
+
+    if op.dry_run {
+        let actions = plan_actions(path, op, owner)?;
+        if actions.is_empty() && op.verbose {
+            say!("No changes: {}", path.display());
+        }
+        for action in actions {
+            say!("Would {}: {}", action, path.display());
+        }
+        return Ok(());
+    }
+
+    if op.no_touch {
+        return change_permissions_only(path, op);
+    }
+
+    if op.times_only {
+        return update_times_only(path, op);
+    }
+
+    let Some(owner) = owner else {
+        return create_path(path, op);
+    };
+    // Refused before anything exists, so nothing is left behind
+    if !platform::can_give_to(owner.uid) {
+        anyhow::bail!(tr!("--as-user {} requires running as root", owner.name));
+    }
+    platform::as_user(owner.uid, owner.gid, || {
+        let created = missing_ancestors(path);
+        create_path(path, op)?;
+        give_to_user(&created, owner, op.verbose)
+    })
+    .with_context(|| tr!("Failed to switch to user {}", owner.name))?
+}
+
+fn create_path(path: &Path, op: &FileOp) -> Result<()> {
+    if let Some(wait) = &op.wait {
+        let deadline = parse_wait_timeout(wait)?.and_then(deadline_after);
+        if let Some(parent) = path.parent() {
+            wait_for_directory(parent, deadline, op.verbose)?;
+        }
+        retry_while_busy(deadline, || apply_operations(path, op))
+    } else {
+        check_parent(path, op)?;
+        // Ensure parent directories exist
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| tr!("Failed to create parent directories"))?;
+        }
+        apply_operations(path, op)
+    }
+}
+
+/// Fails when `path`'s parent directory is missing and `op` doesn't create
+/// missing parents.
+fn check_parent(path: &Path, op: &FileOp) -> Result<()> {
+    let parent = parent_dir(path);
+    if op.parents != Some(false) || parent.is_dir() {
+        return Ok(());
+    }
+    let suggestion = suggest_path(parent).filter(|suggestion| suggestion.is_dir());
+    anyhow::bail!(did_you_mean(
+        tr!("Parent directory does not exist: {}", parent.display()),
+        suggestion
+    ))
+}
+
+/// `path`, or when its parent directory is missing and won't be created,
+/// the same name in the close existing directory `check_parent` would
+/// suggest, if `ask` says to use that instead.
+fn corrected_path(path: PathBuf, op: &FileOp, ask: impl FnOnce(&str) -> bool) -> PathBuf {
+    let parent = parent_dir(&path);
+    if op.parents != Some(false) || parent.is_dir() {
+        return path;
+    }
+    let (Some(suggestion), Some(name)) = (
+        suggest_path(parent).filter(|suggestion| suggestion.is_dir()),
+        path.file_name(),
+    ) else {
+        return path;
+    };
+    let corrected = suggestion.join(name);
+    let question = tr!(
+        "Parent directory does not exist: {}; use '{}' instead? [y/N]",
+        parent.display(),
+        corrected.display()
+    );
+    if ask(&question) {
+        corrected
+    } else {
+        path
+    }
+}
+
+/// Asks `question` on the terminal, taking `y` or `yes` for an answer.
+fn confirm(question: &str) -> bool {
+    eprint!("{} ", plain_text(question));
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    let answer = answer.trim().to_lowercase();
+    ["y", "yes"].contains(&answer.as_str()) || answer == tr!("y") || answer == tr!("yes")
+}
+
+/// A path close to `path`, which doesn't exist, that does: the first
+/// missing component swapped for the most similar entry beside it, such as
+/// `src/main.rs` for `srd/main.rs`.
+#[cfg(feature = "suggest")]
+fn suggest_path(path: &Path) -> Option<PathBuf> {
+    let missing = missing_ancestors(path).into_iter().next()?;
+    let name = missing.file_name()?.to_str()?;
+    // One typo in a short name, or one in every three characters, where
+    // swapping two letters is one
+    let allowed = (name.chars().count() / 3).max(1);
+    let (_, closest) = fs::read_dir(parent_dir(&missing))
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .map(|candidate| (strsim::osa_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= allowed)
+        .min()?;
+    let mut suggestion = missing.with_file_name(closest);
+    let rest = path.strip_prefix(&missing).ok()?;
+    if !rest.as_os_str().is_empty() {
+        suggestion.push(rest);
+    }
+    fs::symlink_metadata(&suggestion)
+        .is_ok()
+        .then_some(suggestion)
+}
+
+#[cfg(not(feature = "suggest"))]
+fn suggest_path(_path: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// `message`, followed by the path that was likely meant, if there is one.
+fn did_you_mean(message: String, suggestion: Option<PathBuf>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(
+            "{}, {}",
+            message,
+            tr!("did you mean '{}'?", suggestion.display())
+        ),
+        None => message,
+    }
+}
+
+/// The error for a `path` that has to exist already.
+fn does_not_exist(path: &Path) -> anyhow::Error {
+    anyhow::anyhow!(did_you_mean(
+        tr!("Does not exist: {}", path.display()),
+        suggest_path(path)
+    ))
+}
+
+/// Describes, in order, what processing `path` would change ("create file",
+/// "change mode 0644 -> 0600", ...) by following the same steps as
+/// `process_path_as` but only reading from the filesystem.
+fn plan_actions(path: &Path, op: &FileOp, owner: Option<&UserAccount>) -> Result<Vec<String>> {
+    if let Some(op) = op.with_rule_for(path) {
+        return plan_actions(path, &op, owner);
+    }
+    let metadata = fs::symlink_metadata(path).ok();
+    let mut actions = Vec::new();
+
+    if op.no_touch || op.times_only {
+        if metadata.is_none() {
+            return Err(does_not_exist(path));
+        }
+    } else {
+        if op.wait.is_none() {
+            check_parent(path, op)?;
+        }
+        let mut created = path.parent().map(missing_ancestors).unwrap_or_default();
+        actions.extend(
+            created
+                .iter()
+                .map(|dir| tr!("create parent directory {}", dir.display())),
+        );
+        let size = metadata.as_ref().map_or(0, |metadata| metadata.len());
+        if let Some(target) = &op.link_to {
+            match &metadata {
+                None => actions.push(tr!("create symlink to {}", target.display())),
+                Some(_) if links_to(path, target) => {}
+                Some(metadata) => {
+                    check_replaceable(path, metadata, op.force)?;
+                    actions.push(tr!("replace with a symlink to {}", target.display()));
+                }
+            }
+        } else if let Some(target) = &op.hard_link_to {
+            let target_metadata = check_hard_link(path, target)?;
+            match &metadata {
+                None => actions.push(tr!("create hard link to {}", target.display())),
+                Some(metadata) if same_file(metadata, &target_metadata) => {}
+                Some(metadata) => {
+                    check_replaceable(path, metadata, op.force)?;
+                    actions.push(tr!("replace with a hard link to {}", target.display()));
+                }
+            }
+        } else if op.dir {
+            if metadata.is_none() {
+                actions.push(tr!("create directory"));
+            }
+        } else if op.fifo {
+            match &metadata {
+                None => actions.push(tr!("create FIFO")),
+                Some(metadata) if platform::is_fifo(metadata.file_type()) => {}
+                Some(_) => anyhow::bail!(tr!("{} exists and is not a FIFO", path.display())),
+            }
+        } else if op.socket {
+            match &metadata {
+                None => actions.push(tr!("create socket")),
+                Some(metadata) if platform::is_socket(metadata.file_type()) => {}
+                Some(_) => anyhow::bail!(tr!("{} exists and is not a socket", path.display())),
+            }
+        } else if let Some(fill) = &op.fill {
+            let fill = Fill::parse(fill)?;
+            check_space_for(path, fill.size())?;
+            if metadata.is_none() {
+                actions.push(tr!("create file"));
+            }
+            if let Some(backup) = op.backup.as_deref().filter(|_| size > 0) {
+                actions.push(tr!("back up to {}", backup_path(path, backup)?.display()));
+            }
+            if size > 0 {
+                actions.push(tr!(
+                    "truncate {} of existing content",
+                    show_size(size, op.bytes)
+                ));
+            }
+            actions.push(tr!("write {}", show_size(fill.size(), op.bytes)));
+        } else if op.pidfile {
+            actions.push(tr!("write PID {}", pidfile_pid(op)?));
+        } else if op.log_entry.is_some() {
+            if let Some(limit) = &op.log_rotate {
+                if metadata.is_some() && size >= parse_size(limit)? {
+                    actions.push(tr!("rotate the log ({})", show_size(size, false)));
+                }
+            }
+            actions.push(tr!("append a log entry"));
+        } else if op.trim {
+            let content =
+                fs::read_to_string(path).with_context(|| tr!("Failed to read file content"))?;
+            let lines = content
+                .lines()
+                .filter(|line| line.len() != line.trim_end().len())
+                .count();
+            if let Some(backup) = op.backup.as_deref().filter(|_| lines > 0) {
+                actions.push(tr!("back up to {}", backup_path(path, backup)?.display()));
+            }
+            if lines > 0 {
+                actions.push(tr!("remove trailing whitespace from {} lines", lines));
+            }
+        } else {
+            if metadata.is_none() {
+                actions.push(tr!("create file"));
+            }
+            let content = file_content(path, op)?;
+            let length = content
+                .as_ref()
+                .map_or(size, |content| content.len() as u64);
+            let present = |content: &[u8]| {
+                op.once && fs::read(path).is_ok_and(|existing| contains_lines(&existing, content))
+            };
+            let adding = op.append || op.inserts();
+            if let Some(content) = content.filter(|content| !(adding && present(content))) {
+                let amount = show_size(content.len() as u64, op.bytes);
+                if let Some(backup) = op.backup.as_deref().filter(|_| op.inserts() && size > 0) {
+                    actions.push(tr!("back up to {}", backup_path(path, backup)?.display()));
+                }
+                if op.append {
+                    actions.push(tr!("append {}", amount));
+                } else if op.prepend {
+                    actions.push(tr!("prepend {}", amount));
+                } else if let Some(line) = op.at_line {
+                    actions.push(tr!("insert {} at line {}", amount, line));
+                } else if let Some(marker) = &op.insert_after {
+                    actions.push(tr!(
+                        "insert {} after the line containing '{}'",
+                        amount,
+                        marker
+                    ));
+                } else {
+                    if let Some(backup) = op.backup.as_deref().filter(|_| size > 0) {
+                        actions.push(tr!("back up to {}", backup_path(path, backup)?.display()));
+                    }
+                    if size > 0 {
+                        actions.push(tr!(
+                            "truncate {} of existing content",
+                            show_size(size, op.bytes)
+                        ));
+                    }
+                    actions.push(tr!("write {}", amount));
+                }
+            }
+            if let Some(target) = &op.size {
+                let target = parse_size(target)?;
+                let amount = show_size(target, op.bytes);
+                if target < length {
+                    if let Some(backup) = op.backup.as_deref().filter(|_| !op.writes_content()) {
+                        actions.push(tr!("back up to {}", backup_path(path, backup)?.display()));
+                    }
+                    actions.push(tr!("cut to {}", amount));
+                } else if target > length && op.sparse {
+                    actions.push(tr!("extend to {} with a hole", amount));
+                } else if target > length {
+                    actions.push(tr!("extend to {} with zeros", amount));
+                }
+            }
+            if let Some(range) = &op.punch_hole {
+                let (offset, len) = parse_range(range)?;
+                actions.push(tr!(
+                    "punch a hole of {} at {}",
+                    show_size(len, op.bytes),
+                    offset
+                ));
+            }
+        }
+        if metadata.is_none() {
+            created.push(path.to_path_buf());
+        }
+        if let Some(owner) = owner {
+            actions.extend(
+                created
+                    .iter()
+                    .map(|created| tr!("give {} to {}", created.display(), owner.name)),
+            );
+        }
+    }
+
+    let current_mode = metadata.as_ref().map(platform::mode);
+    let new_mode = match &op.chmod {
+        Some(chmod) => Some(chmod),
+        None if metadata.is_none() && op.dir => op.new_dir_mode.as_ref(),
+        None if metadata.is_none()
+            && !op.pidfile
+            && op.log_entry.is_none()
+            && op.shebang.is_none()
+            && op.link_to.is_none()
+            && op.hard_link_to.is_none() =>
+        {
+            op.new_file_mode.as_ref()
+        }
+        None => None,
+    };
+    if let Some(chmod) = new_mode {
+        let mode = Mode::parse(chmod)?;
+        let is_dir = metadata
+            .as_ref()
+            .map_or(op.dir, |metadata| metadata.is_dir());
+        match (current_mode, &mode) {
+            (Some(current), _) if current != mode.apply(current, is_dir) => actions.push(tr!(
+                "change mode {} -> {}",
+                format!("{:04o}", current),
+                format!("{:04o}", mode.apply(current, is_dir))
+            )),
+            (Some(_), _) => {}
+            (None, Mode::Octal(mode)) => actions.push(tr!("set mode {}", format!("{:04o}", mode))),
+            // Relative to a mode that depends on the umask at creation
+            (None, Mode::Symbolic(_)) => actions.push(tr!("apply mode {}", chmod)),
+        }
+        if op.recursive && is_dir && metadata.is_some() {
+            actions.push(match mode {
+                Mode::Octal(mode) => {
+                    tr!("set mode {} on everything inside", format!("{:04o}", mode))
+                }
+                Mode::Symbolic(_) => tr!("apply mode {} to everything inside", chmod),
+            });
+        }
+    }
+
+    if let Some(attrib) = &op.attrib {
+        let attributes = Attributes::parse(attrib)?;
+        match metadata.as_ref().map(platform::attributes) {
+            Some(current) if attributes.apply(current) == current => {}
+            Some(_) => actions.push(tr!("change attributes {}", attrib)),
+            None => actions.push(tr!("set attributes {}", attrib)),
+        }
+    }
+
+    if op.shebang.is_some() && op.chmod.is_none() {
+        match current_mode {
+            Some(current) if executable(current) == current => {}
+            Some(current) => actions.push(tr!(
+                "change mode {} -> {}",
+                format!("{:04o}", current),
+                format!("{:04o}", executable(current))
+            )),
+            None => actions.push(tr!("make executable")),
+        }
+    }
+
+    if let Some((uid, gid)) = ownership(op)?.filter(|_| op.hard_link_to.is_none()) {
+        let changes = match &metadata {
+            Some(metadata) => changes_owner(metadata, uid, gid),
+            None => true,
+        };
+        if changes {
+            actions.push(tr!("change owner to {}", owner_label(op)));
+        }
+        if op.recursive && metadata.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+            actions.push(tr!(
+                "change owner of everything inside to {}",
+                owner_label(op)
+            ));
+        }
+    }
+
+    match (&op.timestamp, &op.after, &op.reference) {
+        (Some(timestamp), _, _) => {
+            parse_timestamp(timestamp)?;
+            actions.push(tr!("set {} to {}", op.times.describe(), timestamp));
+        }
+        (None, Some(reference), _) => actions.push(tr!(
+            "set modification time just after {}",
+            reference.display()
+        )),
+        (None, None, Some(reference)) => {
+            if fs::metadata(reference).is_err() {
+                anyhow::bail!(tr!("Reference file not found: {}", reference.display()));
+            }
+            actions.push(tr!(
+                "copy {} from {}",
+                op.times.describe(),
+                reference.display()
+            ));
+        }
+        (None, None, None) if op.times_only => {
+            actions.push(tr!("set {} to now", op.times.describe()))
+        }
+        (None, None, None) => {}
+    }
+    Ok(actions)
+}
+
+fn apply_operations(path: &Path, op: &FileOp) -> Result<()> {
+    if let Some(op) = op.with_rule_for(path) {
+        return apply_operations(path, &op);
+    }
+    let existed = fs::symlink_metadata(path).is_ok();
+    if let Some(target) = &op.link_to {
+        if create_symlink(path, target, op.force, op.verbose)? {
+            let action = if existed {
+                "replaced with symlink"
+            } else {
+                "created symlink"
+            };
+            audit(op, action, path);
+        }
+        // Modes and times would go to the link's target; owners are the
+        // link's own
+        return apply_ownership(path, op);
+    }
+    if let Some(target) = &op.hard_link_to {
+        if create_hard_link(path, target, op.force, op.verbose)? {
+            let action = if existed {
+                "replaced with hard link"
+            } else {
+                "created hard link"
+            };
+            audit(op, action, path);
+        }
+        // A hard link is its target, so its mode, owner and times are left
+        // as they are
+        return Ok(());
+    }
+    if op.dir {
+        create_directory(path, op.verbose)?;
+    } else if op.fifo {
+        create_fifo(path, op.verbose)?;
+    } else if op.socket {
+        create_socket(path, op.verbose)?;
+    } else if let Some(fill) = &op.fill {
+        fill_file(path, &Fill::parse(fill)?, &mut op.generator(), op)?;
+    } else if op.pidfile {
+        write_pidfile(path, pidfile_pid(op)?, op.verbose)?;
+    } else if let Some(entry) = &op.log_entry {
+        append_log_entry(path, entry, op)?;
+    } else {
+        let size = op.size.as_deref().map(parse_size).transpose()?;
+        let hole = op.punch_hole.as_deref().map(parse_range).transpose()?;
+        // Cutting a file short loses its end, which is backed up here unless
+        // writing new content backs up the whole file first
+        if let (Some(size), Some(backup)) = (size, &op.backup) {
+            if !op.writes_content()
+                && fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > size)
+            {
+                back_up(path, backup, op.verbose)?;
+            }
+        }
+        create_or_update_file(path, op)?;
+        if let Some(size) = size {
+            if resize_file(path, size, op.sparse, op.verbose)? {
+                audit(op, &format!("resized to {}", size), path);
+            }
+        }
+        if let Some((offset, len)) = hole {
+            punch_hole(path, offset, len, op.verbose)?;
+            audit(
+                op,
+                &format!("punched a hole of {} at {}", len, offset),
+                path,
+            );
+        }
+    }
+
+    if !existed {
+        audit(
+            op,
+            if op.dir {
+                "created directory"
+            } else if op.fifo {
+                "created FIFO"
+            } else if op.socket {
+                "created socket"
+            } else {
+                "created file"
+            },
+            path,
+        );
+    } else if op.writes_content()
+        || op.fill.is_some()
+        || op.trim
+        || op.pidfile
+        || op.log_entry.is_some()
+    {
+        audit(op, "updated content", path);
+    }
+
+    if let Some(chmod) = &op.chmod {
+        set_permissions(path, chmod, op.recursive, op.verbose)?;
+        audit(op, &format!("set mode {}", chmod), path);
+    } else if op.shebang.is_some() {
+        if make_executable(path, op.verbose)? {
+            audit(op, "made executable", path);
+        }
+    } else if !existed {
+        // PID files and logs are created with their own deliberate modes
+        let default_mode = if op.dir {
+            op.new_dir_mode.as_ref()
+        } else if op.pidfile || op.log_entry.is_some() {
+            None
+        } else {
+            op.new_file_mode.as_ref()
+        };
+        if let Some(mode) = default_mode {
+            set_permissions(path, mode, false, op.verbose)?;
+            audit(op, &format!("set mode {}", mode), path);
+        }
+    }
+
+    if let Some(attrib) = &op.attrib {
+        set_attributes(path, attrib, op.recursive, op.verbose)?;
+        audit(op, &format!("set attributes {}", attrib), path);
+    }
+
+    apply_ownership(path, op)?;
+
+    if let Some(timestamp) = &op.timestamp {
+        set_timestamp(path, timestamp, op.times, op.timestamp_shown()?.as_ref())?;
+        audit(op, &format!("set timestamp {}", timestamp), path);
+    }
+
+    if let Some(reference) = &op.after {
+        set_mtime_after(path, reference, op.verbose)?;
+        audit(op, "set timestamp after reference", path);
+    }
+
+    if let Some(reference) = &op.reference {
+        copy_times(path, reference, op.times, op.verbose)?;
+        audit(op, "copied timestamps from reference", path);
+    }
+
+    Ok(())
+}
+
+pub fn expand_paths(paths: &[String]) -> Result<Vec<PathBuf>> {
+    Ok(unique_paths(glob_paths(paths)?).collect())
+}
+
+/// Drops paths that refer to the same file as an earlier one, such as
+/// `a.txt` matched by both `a.txt` and `*.txt`, or `./a.txt` next to
+/// `a.txt`. The first occurrence keeps its place, so the order stays that of
+/// the patterns and, within a glob, sorted.
+pub fn unique_paths(
+    paths: impl Iterator<Item = PathBuf> + Send,
+) -> impl Iterator<Item = PathBuf> + Send {
+    let mut seen = HashSet::new();
+    // The first path can't be a repeat, so it is only resolved once a second
+    // one comes along; a single-path run never pays for canonicalizing
+    let mut first = None;
+    paths.filter(move |path| {
+        if seen.is_empty() && first.is_none() {
+            first = Some(path.clone());
+            return true;
+        }
+        if let Some(first) = first.take() {
+            seen.insert(identity(&first));
+        }
+        seen.insert(identity(path))
+    })
+}
+
+/// A path's canonical form or, for one that doesn't exist yet, its
+/// canonical parent joined with the file name. A Windows data stream is its
+/// file's identity plus the stream's name.
+fn identity(path: &Path) -> PathBuf {
+    if let Some(stream) = platform::data_stream(path).filter(|_| cfg!(windows)) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let file = name.split_once(':').map_or(&*name, |(file, _)| file);
+        let mut identity = identity(&path.with_file_name(file)).into_os_string();
+        identity.push(format!(":{stream}"));
+        return identity.into();
+    }
+    if let Ok(canonical) = fs::canonicalize(path) {
+        return canonical;
+    }
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => fs::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or(absolute),
+        _ => absolute,
+    }
+}
+
+/// Reads paths the way xargs does, split on whitespace or, with `null`, on
+/// NUL bytes only, yielding each one as soon as it is complete. Paths are
+/// taken literally, without expanding braces or globs.
+pub fn read_paths(input: impl BufRead + Send, null: bool) -> impl Iterator<Item = PathBuf> + Send {
+    let delimiter = if null { b'\0' } else { b'\n' };
+    input
+        .split(delimiter)
+        .map_while(|chunk| {
+            chunk
+                .map_err(|e| say_err!("Error: Failed to read paths from stdin: {}", e))
+                .ok()
+        })
+        .flat_map(move |chunk| {
+            let names = if null {
+                vec![chunk]
+            } else {
+                chunk
+                    .split(u8::is_ascii_whitespace)
+                    .map(<[u8]>::to_vec)
+                    .collect()
+            };
+            names
+                .into_iter()
+                .filter(|name| !name.is_empty())
+                .map(platform::path_from_bytes)
+        })
+}
+
+/// Expands brace groups and globs lazily, yielding each match as the
+/// directory walk finds it. A pattern without matches yields itself, as a
+/// new file or directory to create. Brace groups are expanded up front, so
+/// one that makes too many paths fails before anything is done.
+pub fn glob_paths(paths: &[String]) -> Result<impl Iterator<Item = PathBuf> + Send> {
+    let expanded = paths
+        .iter()
+        .map(|path| expand_braces(path))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(expanded.into_iter().flatten().flat_map(
+        |path| -> Box<dyn Iterator<Item = PathBuf> + Send> {
+            let form = if cfg!(windows) {
+                GlobForm::for_windows(&path)
+            } else {
+                GlobForm::as_written(&path)
+            };
+            // A literal path globs to itself whether or not it exists
+            if !form.pattern.contains(['*', '?', '[']) {
+                return Box::new(std::iter::once(PathBuf::from(path)));
+            }
+            match glob(&form.pattern) {
+                Ok(entries) => {
+                    let mut entries = entries.peekable();
+                    if entries.peek().is_none() {
+                        return Box::new(std::iter::once(PathBuf::from(path)));
+                    }
+                    Box::new(entries.filter_map(move |entry| match entry {
+                        Ok(path) => Some(form.respell(path)),
+                        Err(e) => {
+                            say_err!("Error: {}", format!("{:?}", e));
+                            None
+                        }
+                    }))
+                }
+                Err(e) => {
+                    say_err!("Invalid glob pattern '{}': {}", path, format!("{:?}", e));
+                    Box::new(std::iter::empty())
+                }
+            }
+        },
+    ))
+}
+
+/// A pattern the way glob is given it, for the Windows spellings it can't
+/// search as written, and how to spell its matches the way they were asked
+/// for. glob finds nothing under a `\\?\` prefix, so those are searched
+/// without it, and a drive-relative `C:*.txt` is searched from that drive's
+/// current directory.
+struct GlobForm {
+    pattern: String,
+    /// How the matches start, replaced by `shown`
+    found: String,
+    shown: String,
+}
+
+impl GlobForm {
+    fn as_written(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            found: String::new(),
+            shown: String::new(),
+        }
+    }
+
+    fn for_windows(pattern: &str) -> Self {
+        let respelled = |pattern: String, found: &str, shown: &str| Self {
+            pattern,
+            found: found.to_string(),
+            shown: shown.to_string(),
+        };
+        if let Some(rest) = pattern.strip_prefix(r"\\?\UNC\") {
+            return respelled(format!(r"\\{rest}"), r"\\", r"\\?\UNC\");
+        }
+        if platform::is_verbatim(pattern) {
+            return respelled(pattern[4..].to_string(), "", r"\\?\");
+        }
+        let bytes = pattern.as_bytes();
+        let drive_relative = bytes.len() > 2
+            && bytes[0].is_ascii_alphabetic()
+            && bytes[1] == b':'
+            && !matches!(bytes[2], b'\\' | b'/');
+        if drive_relative {
+            if let Ok(current) = std::path::absolute(&pattern[..2]) {
+                let current = current.to_string_lossy();
+                let current = current.trim_end_matches('\\');
+                return respelled(
+                    format!(r"{}\{}", Pattern::escape(current), &pattern[2..]),
+                    &format!(r"{current}\"),
+                    &pattern[..2],
+                );
+            }
+        }
+        Self::as_written(pattern)
+    }
+
+    fn respell(&self, path: PathBuf) -> PathBuf {
+        match path
+            .to_str()
+            .and_then(|path| path.strip_prefix(&self.found))
+        {
+            Some(rest) if !self.found.is_empty() || !self.shown.is_empty() => {
+                PathBuf::from(format!("{}{rest}", self.shown))
+            }
+            _ => path,
+        }
+    }
+}
+
+/// The most paths one pattern's brace groups may expand to, so that a typo
+/// like `{1..100000000}` fails instead of filling memory.
+pub const MAX_BRACE_EXPANSION: usize = 1_000_000;
+
+/// Expands brace groups the way bash does, so `src/{lib,main}.rs` becomes
+/// `src/lib.rs` and `src/main.rs`, also in shells that don't do it
+/// themselves. Groups nest, and sequences like `log-{001..365}.txt` or
+/// `part-{a..z}.dat` count up or down, zero-padded when an endpoint is.
+/// A group without a comma that isn't a sequence is kept literally. Fails
+/// past `MAX_BRACE_EXPANSION` paths.
+pub fn expand_braces(pattern: &str) -> Result<Vec<String>> {
+    let mut search_from = 0;
+    while let Some(open) = pattern[search_from..].find('{').map(|i| i + search_from) {
+        let mut depth = 0;
+        let mut bounds = vec![open];
+        let mut close = None;
+        for (i, c) in pattern[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(open + i);
+                        break;
+                    }
+                }
+                ',' if depth == 1 => bounds.push(open + i),
+                _ => {}
+            }
+        }
+        let Some(close) = close else {
+            search_from = open + 1;
+            continue;
+        };
+        let alternatives = if bounds.len() > 1 {
+            bounds.push(close);
+            bounds
+                .windows(2)
+                .map(|alternative| pattern[alternative[0] + 1..alternative[1]].to_string())
+                .collect()
+        } else if let Some(sequence) = expand_sequence(&pattern[open + 1..close])? {
+            sequence
+        } else {
+            search_from = open + 1;
+            continue;
+        };
+        let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+        let mut expanded = Vec::new();
+        for choice in &alternatives {
+            expanded.extend(expand_braces(&format!("{}{}{}", prefix, choice, suffix))?);
+            if expanded.len() > MAX_BRACE_EXPANSION {
+                anyhow::bail!(too_many_paths(pattern));
+            }
+        }
+        return Ok(expanded);
+    }
+    Ok(vec![pattern.to_string()])
+}
+
+fn too_many_paths(pattern: &str) -> String {
+    tr!(
+        "{} expands to more than {} paths",
+        pattern,
+        MAX_BRACE_EXPANSION
+    )
+}
+
+/// Expands the inside of a `{1..10}`, `{001..365}`, `{a..z}` or `{0..100..5}`
+/// group, or returns `None` if it isn't a sequence.
+fn expand_sequence(body: &str) -> Result<Option<Vec<String>>> {
+    let step = |step: &str| {
+        step.parse::<i64>()
+            .ok()
+            .map(|step| step.unsigned_abs().max(1))
+    };
+    let (start, end, step) = match body.split("..").collect::<Vec<_>>()[..] {
+        [start, end] => (start, end, 1),
+        [start, end, by] => match step(by) {
+            Some(step) => (start, end, step),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    // In i128, so that neither the count nor any step past the end can
+    // overflow at the i64 extremes
+    let count_from = |first: i64, last: i64| {
+        let count = first.abs_diff(last) / step;
+        if count >= MAX_BRACE_EXPANSION as u64 {
+            anyhow::bail!(too_many_paths(&format!("{{{}}}", body)));
+        }
+        let step = if last < first {
+            -i128::from(step)
+        } else {
+            i128::from(step)
+        };
+        Ok((0..=count).map(move |i| (i128::from(first) + i128::from(i) * step) as i64))
+    };
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |end: &str| {
+            let digits = end.trim_start_matches(['-', '+']);
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if padded(start) || padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        return Ok(Some(
+            count_from(first, last)?
+                .map(|n| format!("{:0width$}", n, width = width))
+                .collect(),
+        ));
+    }
+
+    let letter = |end: &str| match end.as_bytes() {
+        [c] if c.is_ascii_alphabetic() => Some(*c as i64),
+        _ => None,
+    };
+    let (Some(first), Some(last)) = (letter(start), letter(end)) else {
+        return Ok(None);
+    };
+    Ok(Some(
+        count_from(first, last)?
+            .map(|c| char::from(c as u8).to_string())
+            .collect(),
+    ))
+}
+
+pub fn check_existence(path: &Path, verbose: bool) -> Result<()> {
+    if path.exists() {
+        if verbose {
+            say!("Exists: {}", path.display());
+        }
+    } else {
+        say!("Does not exist: {}", path.display());
+    }
+    Ok(())
+}
+
+pub fn create_directory(path: &Path, verbose: bool) -> Result<()> {
+    fs::create_dir_all(path).with_context(|| tr!("Failed to create directory"))?;
+    if verbose {
+        say!("Directory created: {}", path.display());
+    }
+    Ok(())
+}
+
+/// Makes `path` a symlink to `target`, returning whether it had to change.
+/// A symlink to `target` already there is left alone; anything else is an
+/// error unless `force` is given, in which case it is replaced in one step
+/// (but never a directory).
+pub fn create_symlink(path: &Path, target: &Path, force: bool, verbose: bool) -> Result<bool> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        platform::symlink(target, path)
+            .with_context(|| tr!("Failed to create symlink {}", path.display()))?;
+        if verbose {
+            say!(
+                "Symlink created: {} -> {}",
+                path.display(),
+                target.display()
+            );
+        }
+        return Ok(true);
+    };
+    if links_to(path, target) {
+        if verbose {
+            say!(
+                "Symlink unchanged: {} -> {}",
+                path.display(),
+                target.display()
+            );
+        }
+        return Ok(false);
+    }
+    check_replaceable(path, &metadata, force)?;
+    replace_with_link(path, |temp| {
+        platform::symlink(target, temp)
+            .with_context(|| tr!("Failed to create symlink {}", path.display()))
+    })?;
+    if verbose {
+        say!(
+            "Symlink replaced: {} -> {}",
+            path.display(),
+            target.display()
+        );
+    }
+    Ok(true)
+}
+
+/// Makes `path` a hard link to `target`, on the same terms as
+/// `create_symlink`. `target` has to exist and be on the same filesystem.
+pub fn create_hard_link(path: &Path, target: &Path, force: bool, verbose: bool) -> Result<bool> {
+    let target_metadata = check_hard_link(path, target)?;
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        fs::hard_link(target, path)
+            .with_context(|| tr!("Failed to create hard link {}", path.display()))?;
+        if verbose {
+            say!(
+                "Hard link created: {} -> {}",
+                path.display(),
+                target.display()
+            );
+        }
+        return Ok(true);
+    };
+    if same_file(&metadata, &target_metadata) {
+        if verbose {
+            say!(
+                "Hard link unchanged: {} -> {}",
+                path.display(),
+                target.display()
+            );
+        }
+        return Ok(false);
+    }
+    check_replaceable(path, &metadata, force)?;
+    replace_with_link(path, |temp| {
+        fs::hard_link(target, temp)
+            .with_context(|| tr!("Failed to create hard link {}", path.display()))
+    })?;
+    if verbose {
+        say!(
+            "Hard link replaced: {} -> {}",
+            path.display(),
+            target.display()
+        );
+    }
+    Ok(true)
+}
+
+/// Puts the link `make` creates in place of `path` in one step, by making
+/// it beside `path` and renaming it over.
+fn replace_with_link(path: &Path, make: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let temp = parent_dir(path).join(format!(
+        ".{}.tap-link",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let _registered = temp_files::register(&temp);
+    let _ = fs::remove_file(&temp);
+    make(&temp)?;
+    if let Err(e) = fs::rename(&temp, path) {
+        let _ = fs::remove_file(&temp);
+        return Err(e).with_context(|| tr!("Failed to replace {}", path.display()));
+    }
+    Ok(())
+}
+
+fn links_to(path: &Path, target: &Path) -> bool {
+    fs::read_link(path).is_ok_and(|current| current == target)
+}
+
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    platform::file_id(a).is_some_and(|id| platform::file_id(b) == Some(id))
+}
+
+/// The metadata of `target`, once it is known a hard link to it can be made
+/// at `path`.
+fn check_hard_link(path: &Path, target: &Path) -> Result<fs::Metadata> {
+    let Ok(metadata) = fs::symlink_metadata(target) else {
+        anyhow::bail!(tr!("Link target not found: {}", target.display()));
+    };
+    if metadata.is_dir() {
+        anyhow::bail!(tr!(
+            "{} is a directory, which can't be hard linked",
+            target.display()
+        ));
+    }
+    // The nearest directory that exists, as the link's parents may be yet
+    // to be created
+    let dir = std::path::absolute(parent_dir(path))
+        .with_context(|| tr!("Failed to resolve {}", path.display()))?;
+    let dir = dir.ancestors().find_map(|dir| fs::metadata(dir).ok());
+    if dir.is_some_and(|dir| platform::device(&dir) != platform::device(&metadata)) {
+        anyhow::bail!(tr!(
+            "{} is on another filesystem than {}, and hard links can't cross filesystems (use --link-to for a symlink)",
+            target.display(),
+            path.display()
+        ));
+    }
+    Ok(metadata)
+}
+
+/// Whether what is at `path` may be replaced by a link.
+fn check_replaceable(path: &Path, metadata: &fs::Metadata, force: bool) -> Result<()> {
+    if metadata.is_dir() {
+        anyhow::bail!(tr!(
+            "{} is a directory, which is never replaced by a link",
+            path.display()
+        ));
+    }
+    if !force {
+        anyhow::bail!(tr!(
+            "{} already exists (use --force to replace it)",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+pub fn create_or_update_file(path: &Path, op: &FileOp) -> Result<()> {
+    if op.trim {
+        let content =
+            fs::read_to_string(path).with_context(|| tr!("Failed to read file content"))?;
+        let trimmed_content = content
+            .lines()
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if trimmed_content != content {
+            replace_content(path, trimmed_content.as_bytes(), op)
+                .with_context(|| tr!("Failed to write trimmed content to file"))?;
+        }
+        if op.verbose {
+            say!("Trailing whitespace removed from: {}", path.display());
+        }
+        return Ok(());
+    }
+
+    // Worked out before opening, so a template error leaves the file as it was
+    let Some(content) = file_content(path, op)? else {
+        return touch_file(path, op);
+    };
+    if op.append && op.once {
+        return append_once(path, &content, op.verbose);
+    }
+    if op.inserts() {
+        return insert_content(path, &content, op);
+    }
+    if let Some(backup) = &op.backup {
+        if !op.append && fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > 0) {
+            back_up(path, backup, op.verbose)?;
+        }
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true);
+
+    if op.append {
+        options.append(true);
+    } else {
+        options.truncate(true);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| tr!("Failed to create or open file"))?;
+
+    if op.template.is_some() || op.template_text.is_some() {
+        file.write_all(&content)
+            .with_context(|| tr!("Failed to write template content to file"))?;
+        if op.verbose {
+            say!(
+                "File created/updated with template content: {}",
+                path.display()
+            );
+        }
+    } else {
+        file.write_all(&content)
+            .with_context(|| tr!("Failed to write content to file"))?;
+        if op.verbose {
+            if op.append {
+                say!("Content appended to file: {}", path.display());
+            } else {
+                say!("File created/updated with content: {}", path.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes `path` exactly `size` bytes long, returning whether it changed. What
+/// is added is zeros: allocated (or written out, where the filesystem can't
+/// allocate), or with `sparse` left as a hole that takes no space.
+pub fn resize_file(path: &Path, size: u64, sparse: bool, verbose: bool) -> Result<bool> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| tr!("Failed to create or open file"))?;
+    let current = file
+        .metadata()
+        .with_context(|| tr!("Failed to read {}", path.display()))?
+        .len();
+    if current == size {
+        return Ok(false);
+    }
+    if size < current || sparse {
+        file.set_len(size)
+            .with_context(|| tr!("Failed to resize {}", path.display()))?;
+    } else {
+        match platform::allocate(&file, current, size - current) {
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                write_zeros(&mut file, current, size)
+            }
+            allocated => allocated,
+        }
+        .with_context(|| tr!("Failed to resize {}", path.display()))?;
+    }
+    if verbose {
+        say!("Size set to {}: {}", show_size(size, false), path.display());
+    }
+    Ok(true)
+}
+
+/// Turns `len` bytes of `path` from `offset` into a hole: they read as zeros
+/// and take no disk space. The file keeps its size.
+pub fn punch_hole(path: &Path, offset: u64, len: u64, verbose: bool) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| tr!("Failed to create or open file"))?;
+    platform::punch_hole(&file, offset, len)
+        .with_context(|| tr!("Failed to punch a hole in {}", path.display()))?;
+    if verbose {
+        say!(
+            "Hole punched at {} ({}): {}",
+            offset,
+            show_size(len, false),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// An `OFFSET,LEN` range for `--punch-hole`, both sizes as `parse_size`
+/// takes them.
+fn parse_range(range: &str) -> Result<(u64, u64)> {
+    let (offset, len) = range
+        .split_once(',')
+        .with_context(|| tr!("Expected OFFSET,LEN: {}", range))?;
+    let len = parse_size(len)?;
+    if len == 0 {
+        anyhow::bail!(tr!("A hole needs a length: {}", range));
+    }
+    Ok((parse_size(offset)?, len))
+}
+
+fn write_zeros(file: &mut fs::File, from: u64, to: u64) -> io::Result<()> {
+    const CHUNK: usize = 1 << 16;
+    let zeros = [0u8; CHUNK];
+    file.seek(SeekFrom::Start(from))?;
+    let mut left = to - from;
+    while left > 0 {
+        let chunk = left.min(CHUNK as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        left -= chunk as u64;
+    }
+    Ok(())
+}
+
+/// Replaces the content of `path` with the bytes `fill` generates.
+pub fn fill_file(path: &Path, fill: &Fill, rng: &mut Rng, op: &FileOp) -> Result<()> {
+    check_space_for(path, fill.size())?;
+    if let Some(backup) = &op.backup {
+        if fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > 0) {
+            back_up(path, backup, op.verbose)?;
+        }
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .with_context(|| tr!("Failed to create or open file"))?;
+    let mut out = io::BufWriter::new(file);
+    fill.write_to(&mut out, rng)
+        .and_then(|()| out.flush())
+        .with_context(|| tr!("Failed to write content to file"))?;
+    if op.verbose {
+        say!(
+            "File filled with {}: {}",
+            show_size(fill.size(), false),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Replaces what `path` holds with `content` in one step, so neither a
+/// reader nor a crash ever sees it half-written: the new content is written
+/// beside it with the same mode and owner, and renamed over it. A symlink
+/// is followed, a file with other hard links is written in place so they
+/// keep sharing it, as is a Windows data stream, which can't be renamed
+/// over, and the file is backed up first with `--backup`.
+pub(crate) fn replace_content(path: &Path, content: &[u8], op: &FileOp) -> Result<()> {
+    let given = path;
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).with_context(|| tr!("Failed to resolve {}", path.display()))?
+        }
+        _ => path.to_path_buf(),
+    };
+    // A new file has nothing to lose, and gets the usual mode
+    let Ok(metadata) = fs::metadata(&path) else {
+        return fs::write(&path, content).with_context(|| tr!("Failed to write content to file"));
+    };
+    if let Some(backup) = op.backup.as_deref().filter(|_| metadata.len() > 0) {
+        back_up(given, backup, op.verbose)?;
+    }
+    if platform::link_count(&metadata) > 1
+        || cfg!(windows) && platform::data_stream(&path).is_some()
+    {
+        return fs::write(&path, content).with_context(|| tr!("Failed to write content to file"));
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut temp = tempfile::Builder::new()
+        .prefix(&format!(".{}.", name))
+        .suffix(".tap-write")
+        .make_in(parent_dir(&path), create_registered)
+        .with_context(|| tr!("Failed to write content to file"))?;
+    let file = &mut temp.as_file_mut().0;
+    file.write_all(content)
+        .with_context(|| tr!("Failed to write content to file"))?;
+    let written = file.metadata()?;
+    if platform::mode(&written) != platform::mode(&metadata) {
+        platform::set_mode(temp.path(), platform::mode(&metadata))
+            .with_context(|| tr!("Failed to set permissions"))?;
+    }
+    let (uid, gid) = platform::owner(&metadata);
+    if platform::owner(&written) != (uid, gid) {
+        platform::lchown(temp.path(), Some(uid), Some(gid))
+            .with_context(|| tr!("Failed to change owner of {}", path.display()))?;
+    }
+    temp.persist(&path)
+        .with_context(|| tr!("Failed to write content to file"))?;
+    Ok(())
+}
+
+/// Copies `path` to where `backup_path` says, before it gets overwritten.
+fn back_up(path: &Path, backup: &str, verbose: bool) -> Result<()> {
+    let destination = backup_path(path, backup)?;
+    fs::copy(path, &destination)
+        .with_context(|| tr!("Failed to back up to {}", destination.display()))?;
+    if verbose {
+        say!("Backed up {} to {}", path.display(), destination.display());
+    }
+    Ok(())
+}
+
+/// Where `--backup` copies `path`: its name plus `backup`, or when `backup`
+/// is `numbered`, the next `NAME.~N~` after the ones already there, as GNU
+/// `cp --backup=numbered` names them.
+fn backup_path(path: &Path, backup: &str) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| tr!("No file name to back up: {}", path.display()))?
+        .to_string_lossy()
+        .into_owned();
+    if backup.is_empty() {
+        anyhow::bail!(tr!("--backup needs a suffix, or numbered"));
+    }
+    if backup != "numbered" {
+        return Ok(path.with_file_name(name + backup));
+    }
+    let prefix = format!("{}.~", name);
+    let mut last = 0;
+    if let Ok(entries) = fs::read_dir(parent_dir(path)) {
+        for entry in entries.flatten() {
+            let entry = entry.file_name();
+            let number = entry
+                .to_str()
+                .and_then(|entry| entry.strip_prefix(&prefix))
+                .and_then(|rest| rest.strip_suffix('~'))
+                .and_then(|number| number.parse::<u64>().ok());
+            if let Some(number) = number {
+                last = last.max(number);
+            }
+        }
+    }
+    Ok(path.with_file_name(format!("{}{}~", prefix, last + 1)))
+}
+
+/// Appends `content` as lines of their own unless the file already has
+/// them, holding an flock on the file from the check to the write, so tap
+/// runs racing on the same file still append it once. Prints the files it
+/// changes.
+fn append_once(path: &Path, content: &[u8], verbose: bool) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| tr!("Failed to create or open file"))?;
+    platform::lock(&file, true).with_context(|| tr!("Failed to lock {}", path.display()))?;
+    let mut existing = Vec::new();
+    file.read_to_end(&mut existing)
+        .with_context(|| tr!("Failed to read file content"))?;
+    if content.is_empty() || contains_lines(&existing, content) {
+        if verbose {
+            say!("Content already present: {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let mut addition = Vec::new();
+    if !existing.is_empty() && !existing.ends_with(b"\n") {
+        addition.push(b'\n');
+    }
+    addition.extend_from_slice(content);
+    if !content.ends_with(b"\n") {
+        addition.push(b'\n');
+    }
+    file.write_all(&addition)
+        .with_context(|| tr!("Failed to write content to file"))?;
+    say!("Appended to: {}", path.display());
+    Ok(())
+}
+
+/// Puts `content` on lines of its own in the middle of the file: at the
+/// top for `prepend` (below the shebang line when `content` isn't one
+/// itself, so scripts keep running), at line `at_line`, or after the first
+/// line containing `insert_after`. With `once`, files that already contain
+/// it are left alone, and the ones changed are printed.
+fn insert_content(path: &Path, content: &[u8], op: &FileOp) -> Result<()> {
+    let existing = match fs::read(path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| tr!("Failed to read file content")),
+    };
+    if op.once && (content.is_empty() || contains_lines(&existing, content)) {
+        if op.verbose {
+            say!("Content already present: {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let split = insertion_point(&existing, content, op)
+        .with_context(|| tr!("Failed to find where to insert into {}", path.display()))?;
+    let (head, rest) = existing.split_at(split);
+    let mut updated = Vec::with_capacity(existing.len() + content.len() + 2);
+    updated.extend_from_slice(head);
+    if !head.is_empty() && !head.ends_with(b"\n") {
+        updated.push(b'\n');
+    }
+    updated.extend_from_slice(content);
+    if !content.ends_with(b"\n") {
+        updated.push(b'\n');
+    }
+    updated.extend_from_slice(rest);
+    replace_content(path, &updated, op)?;
+    match (op.once, op.prepend) {
+        (true, true) => say!("Prepended to: {}", path.display()),
+        (true, false) => say!("Inserted into: {}", path.display()),
+        (false, true) if op.verbose => say!("Content prepended to file: {}", path.display()),
+        (false, false) if op.verbose => say!("Content inserted into file: {}", path.display()),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Where in `existing` `insert_content` puts `content`, always at the start
+/// of a line (or the end of the file).
+fn insertion_point(existing: &[u8], content: &[u8], op: &FileOp) -> Result<usize> {
+    let mut line_ends = existing
+        .iter()
+        .enumerate()
+        .filter(|(_, &byte)| byte == b'\n')
+        .map(|(index, _)| index + 1);
+    match (op.at_line, &op.insert_after) {
+        (Some(0), _) => anyhow::bail!(tr!("--at-line counts lines from 1")),
+        (Some(1), _) => Ok(0),
+        // Just past the last line, the content goes at the end
+        (Some(line), _) => {
+            let lines = line_ends.clone().count()
+                + usize::from(!existing.is_empty() && !existing.ends_with(b"\n"));
+            if line > lines + 1 {
+                anyhow::bail!(tr!(
+                    "--at-line {} is past the end of a file of {} lines",
+                    line,
+                    lines
+                ));
+            }
+            Ok(line_ends.nth(line - 2).unwrap_or(existing.len()))
+        }
+        (None, Some(marker)) if marker.is_empty() => {
+            anyhow::bail!(tr!("--insert-after needs text to look for"))
+        }
+        (None, Some(marker)) => {
+            let mut start = 0;
+            for end in line_ends.chain([existing.len()]) {
+                if existing[start..end]
+                    .windows(marker.len())
+                    .any(|window| window == marker.as_bytes())
+                {
+                    return Ok(end);
+                }
+                start = end;
+            }
+            anyhow::bail!(tr!("No line contains '{}'", marker))
+        }
+        (None, None) if existing.starts_with(b"#!") && !content.starts_with(b"#!") => {
+            Ok(line_ends.next().unwrap_or(existing.len()))
+        }
+        (None, None) => Ok(0),
+    }
+}
+
+/// Whether `content`'s lines appear together, as whole lines, in `existing`.
+fn contains_lines(existing: &[u8], mut content: &[u8]) -> bool {
+    while let Some(rest) = content.strip_suffix(b"\n") {
+        content = rest;
+    }
+    let mut needle = Vec::with_capacity(content.len() + 2);
+    needle.push(b'\n');
+    needle.extend_from_slice(content);
+    needle.push(b'\n');
+    let mut haystack = Vec::with_capacity(existing.len() + 2);
+    haystack.push(b'\n');
+    haystack.extend_from_slice(existing);
+    if !existing.ends_with(b"\n") {
+        haystack.push(b'\n');
+    }
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// What `create_or_update_file` writes to `path`: the rendered template,
+/// the `write` content, standard input, the `copy_from` file or placeholder
+/// prose, wrapped in the header guard if there is one. A new header gets
+/// its guard even without content, and appended or inserted content never
+/// does; the same goes for the shebang line, except that it is also put
+/// above what an existing file already has. `None` means a plain touch.
+fn file_content(path: &Path, op: &FileOp) -> Result<Option<Vec<u8>>> {
+    let content = match (&op.template, &op.template_text, &op.write) {
+        (Some(template), ..) => {
+            Some(render_template(&resolve_template(op, template), path, op)?.into_bytes())
+        }
+        (None, Some(text), _) => Some(render_inline(text, path, op)?.into_bytes()),
+        (None, None, Some(content)) => {
+            Some(expand_tokens(content, &mut op.token_generator(path))?.into_bytes())
+        }
+        (None, None, None) if op.stdin => Some(stdin_content()?.to_vec()),
+        (None, None, None) => match (&op.copy_from, &op.lorem) {
+            (Some(source), _) => {
+                Some(fs::read(source).with_context(|| tr!("Failed to read {}", source.display()))?)
+            }
+            (None, Some(lorem)) => {
+                Some(Lorem::parse(lorem)?.text(&mut op.generator()).into_bytes())
+            }
+            (None, None) => None,
+        },
+    };
+    let content = match &op.header_guard {
+        Some(guard)
+            if !op.append
+                && !op.inserts()
+                && (content.is_some() || fs::symlink_metadata(path).is_err()) =>
+        {
+            let content = std::str::from_utf8(content.as_deref().unwrap_or_default())
+                .with_context(|| tr!("Content inside a header guard must be UTF-8 text"))?;
+            Some(guard.wrap(content, path).into_bytes())
+        }
+        _ => content,
+    };
+    let Some(interpreter) = op.shebang.as_ref().filter(|_| !op.append && !op.inserts()) else {
+        return Ok(content);
+    };
+    // Without content of its own, the file keeps what it has below the line
+    let existing = match &content {
+        Some(_) => None,
+        None => match fs::read(path) {
+            Ok(existing) => Some(existing),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| tr!("Failed to read file content")),
+        },
+    };
+    let mut script = shebang_line(interpreter)?.into_bytes();
+    let body = content
+        .as_ref()
+        .or(existing.as_ref())
+        .map_or(&[][..], Vec::as_slice);
+    // One the content brings along gives way to the one asked for
+    let body = match body.strip_prefix(b"#!") {
+        Some(line) => line
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(&[][..], |end| &line[end + 1..]),
+        None => body,
+    };
+    script.extend_from_slice(body);
+    Ok(Some(script).filter(|script| existing.as_ref() != Some(script)))
+}
+
+/// `#!/usr/bin/env bash` for `bash`, or `#!/bin/sh` for a path like `/bin/sh`.
+fn shebang_line(interpreter: &str) -> Result<String> {
+    let interpreter = interpreter.trim();
+    if interpreter.is_empty() {
+        anyhow::bail!(tr!(
+            "--shebang needs an interpreter, such as bash or /bin/sh"
+        ));
+    }
+    Ok(if interpreter.contains('/') {
+        format!("#!{}\n", interpreter)
+    } else {
+        format!("#!/usr/bin/env {}\n", interpreter)
+    })
+}
+
+/// `mode` with execute permission wherever it has read permission, which
+/// the umask gave it, so a new script is as executable as the umask allows.
+#[cfg(unix)]
+fn executable(mode: u32) -> u32 {
+    mode | (mode & 0o444) >> 2
+}
+
+/// What runs a file on Windows is its extension, not its mode.
+#[cfg(windows)]
+fn executable(mode: u32) -> u32 {
+    mode
+}
+
+/// Makes `path` executable the way `executable` says, returning whether its
+/// mode changed.
+fn make_executable(path: &Path, verbose: bool) -> Result<bool> {
+    let metadata = fs::metadata(path).with_context(|| tr!("Failed to read permissions"))?;
+    let current = platform::mode(&metadata);
+    if executable(current) == current {
+        return Ok(false);
+    }
+    platform::set_mode(path, executable(current))
+        .with_context(|| tr!("Failed to set permissions"))?;
+    if verbose {
+        say!("Made executable: {}", path.display());
+    }
+    Ok(true)
+}
+
+/// Standard input, read to the end the first time a target needs it. Every
+/// target gets the same content.
+static STDIN_CONTENT: Mutex<Option<Arc<[u8]>>> = Mutex::new(None);
+
+fn stdin_content() -> Result<Arc<[u8]>> {
+    let mut cached = STDIN_CONTENT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(content) = cached.as_ref() {
+        return Ok(Arc::clone(content));
+    }
+    let mut content = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut content)
+        .with_context(|| tr!("Failed to read standard input"))?;
+    let content: Arc<[u8]> = content.into();
+    *cached = Some(Arc::clone(&content));
+    Ok(content)
+}
+
+/// How a C/C++ header keeps from being included twice.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeaderGuard {
+    /// `#ifndef`/`#define`/`#endif` around the content, with a macro derived
+    /// from the path and this prefix: `MYPROJ_SRC_FOO_H_`
+    Macro(Option<String>),
+    /// `#pragma once` above the content
+    PragmaOnce,
+}
+
+impl HeaderGuard {
+    pub fn wrap(&self, content: &str, path: &Path) -> String {
+        let content = content.trim_matches('\n');
+        match self {
+            Self::Macro(prefix) => {
+                let body = if content.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}\n\n", content)
+                };
+                let relpath = template::relative_path(path);
+                let guard = template::include_guard(&relpath.to_string_lossy(), prefix.as_deref());
+                format!(
+                    "#ifndef {0}\n#define {0}\n\n{1}#endif  // {0}\n",
+                    guard, body
+                )
+            }
+            Self::PragmaOnce if content.is_empty() => "#pragma once\n".to_string(),
+            Self::PragmaOnce => format!("#pragma once\n\n{}\n", content),
+        }
+    }
+}
+
+/// Plain touch: creates a missing file, and otherwise only sets the access
+/// and modification times with utimensat. Existing files are never opened,
+/// which keeps large sweeps to one syscall per file and works on read-only
+/// files we own.
+fn touch_file(path: &Path, op: &FileOp) -> Result<()> {
+    if fs::metadata(path).is_err() {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| tr!("Failed to create or open file"))?;
+        if op.verbose {
+            say!("File created: {}", path.display());
+        }
+        return Ok(());
+    }
+    let now = filetime::FileTime::from_system_time(current_time_for(path, op.server_time));
+    op.times.set(path, now, now)?;
+    if op.verbose {
+        say!("File timestamp updated: {}", path.display());
+    }
+    Ok(())
+}
+
+/// Sets only the mode, attributes and owner of an existing path.
+fn change_permissions_only(path: &Path, op: &FileOp) -> Result<()> {
+    if fs::symlink_metadata(path).is_err() {
+        return Err(does_not_exist(path));
+    }
+    if let Some(chmod) = &op.chmod {
+        set_permissions(path, chmod, op.recursive, op.verbose)?;
+        audit(op, &format!("set mode {}", chmod), path);
+    }
+    if let Some(attrib) = &op.attrib {
+        set_attributes(path, attrib, op.recursive, op.verbose)?;
+        audit(op, &format!("set attributes {}", attrib), path);
+    }
+    apply_ownership(path, op)
+}
+
+/// Applies `--owner` and `--group`, recursively with `--recursive`.
+fn apply_ownership(path: &Path, op: &FileOp) -> Result<()> {
+    let Some((uid, gid)) = ownership(op)? else {
+        return Ok(());
+    };
+    let label = owner_label(op);
+    change_owner(path, uid, gid, &label, op.recursive, op.verbose)?;
+    audit(op, &format!("set owner {}", label), path);
+    Ok(())
+}
+
+/// `--owner` and `--group` the way chown writes them: `alice:staff`, `:staff`.
+fn owner_label(op: &FileOp) -> String {
+    match (&op.owner, &op.group) {
+        (Some(owner), Some(group)) => format!("{}:{}", owner, group),
+        (Some(owner), None) => owner.clone(),
+        (None, group) => format!(":{}", group.as_deref().unwrap_or_default()),
+    }
+}
+
+fn changes_owner(metadata: &fs::Metadata, uid: Option<u32>, gid: Option<u32>) -> bool {
+    let (current_uid, current_gid) = platform::owner(metadata);
+    uid.unwrap_or(current_uid) != current_uid || gid.unwrap_or(current_gid) != current_gid
+}
+
+/// The user and group IDs `--owner` and `--group` ask for, if either is given.
+fn ownership(op: &FileOp) -> Result<Option<(Option<u32>, Option<u32>)>> {
+    if op.owner.is_none() && op.group.is_none() {
+        return Ok(None);
+    }
+    // Bare IDs need no entry in the user or group database, as with chown
+    let uid = op
+        .owner
+        .as_deref()
+        .map(|owner| match owner.parse() {
+            Ok(uid) => Ok(uid),
+            Err(_) => UserAccount::lookup(owner).map(|user| user.uid),
+        })
+        .transpose()?;
+    let gid = op.group.as_deref().map(group_id).transpose()?;
+    Ok(Some((uid, gid)))
+}
+
+/// Chowns `path`, and with `recursive` everything inside it, without
+/// following symlinks. Entries that already have the owner are left alone.
+/// Without root, changes the system refuses (giving a file away, or to a
+/// group we aren't in) are warned about and skipped rather than failing.
+fn change_owner(
+    path: &Path,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    label: &str,
+    recursive: bool,
+    verbose: bool,
+) -> Result<()> {
+    let metadata = fs::symlink_metadata(path).with_context(|| tr!("Failed to read owner"))?;
+    if recursive && metadata.is_dir() {
+        for entry in fs::read_dir(path).with_context(|| tr!("Failed to read directory"))? {
+            let entry = entry.with_context(|| tr!("Failed to read directory entry"))?;
+            change_owner(&entry.path(), uid, gid, label, recursive, verbose)?;
+        }
+    }
+    if !changes_owner(&metadata, uid, gid) {
+        return Ok(());
+    }
+    match platform::lchown(path, uid, gid) {
+        Ok(()) => {
+            if verbose {
+                say!("Owner set to {} for: {}", label, path.display());
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => say_err!(
+            "Warning: Not permitted to change the owner of {} to {} (that needs root)",
+            path.display(),
+            label
+        ),
+        Err(e) => {
+            return Err(e).with_context(|| tr!("Failed to change owner of {}", path.display()))
+        }
+    }
+    Ok(())
+}
+
+/// Looks up a group by name, or takes a GID as it is.
+pub fn group_id(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    platform::group_by_name(group)
+        .with_context(|| tr!("Failed to look up group {}", group))?
+        .with_context(|| tr!("Unknown group: {}", group))
+}
+
+/// Templates read and rendered during a run, shared by the clones of the
+/// `FileOp` the run started from. A glob can expand to thousands of targets
+/// sharing one template, so it is read once, and rendered once too when its
+/// output doesn't depend on the target. Entries are keyed on the template's
+/// modification time and size, so a template edited between `--watch`
+/// rounds is read again.
+#[derive(Clone, Debug, Default)]
+pub struct TemplateCache(Arc<Mutex<HashMap<PathBuf, CachedTemplate>>>);
+
+#[derive(Debug)]
+struct CachedTemplate {
+    version: (Option<SystemTime>, u64),
+    source: Arc<str>,
+    /// The output, when it came out the same for every target.
+    #[cfg(feature = "template-engine")]
+    rendered: Option<Rendered>,
+}
+
+/// A template's output and the variables and shell setting it was rendered
+/// with.
+#[cfg(feature = "template-engine")]
+#[derive(Debug)]
+struct Rendered {
+    vars: Vec<(String, String)>,
+    allow_shell: bool,
+    output: Arc<str>,
+}
+
+impl TemplateCache {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<PathBuf, CachedTemplate>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A template's content. One given as a URL is fetched once per run,
+    /// and only used with a good signature (see `signature`).
+    fn source(&self, template: &Path, op: &FileOp) -> Result<Arc<str>> {
+        let url = template.to_str().filter(|template| is_url(template));
+        let version = match url {
+            Some(_) => (None, 0),
+            None => {
+                let metadata =
+                    fs::metadata(template).with_context(|| tr!("Failed to read template file"))?;
+                (metadata.modified().ok(), metadata.len())
+            }
+        };
+        if let Some(cached) = self.lock().get(template).filter(|c| c.version == version) {
+            return Ok(Arc::clone(&cached.source));
+        }
+        let source: Arc<str> = match url {
+            Some(url) => {
+                let content = signature::fetch_signed(url, &op.trusted_keys, op.insecure)?;
+                String::from_utf8(content).with_context(|| tr!("Failed to read template file"))?
+            }
+            None => {
+                fs::read_to_string(template).with_context(|| tr!("Failed to read template file"))?
+            }
+        }
+        .into();
+        self.lock().insert(
+            template.to_path_buf(),
+            CachedTemplate {
+                version,
+                source: Arc::clone(&source),
+                #[cfg(feature = "template-engine")]
+                rendered: None,
+            },
+        );
+        Ok(source)
+    }
+
+    #[cfg(feature = "template-engine")]
+    fn rendered(&self, template: &Path, source: &Arc<str>, op: &FileOp) -> Option<Arc<str>> {
+        let cache = self.lock();
+        let cached = cache
+            .get(template)
+            .filter(|c| Arc::ptr_eq(&c.source, source))?;
+        let rendered = cached.rendered.as_ref()?;
+        (rendered.vars == op.template_vars && rendered.allow_shell == op.allow_shell)
+            .then(|| Arc::clone(&rendered.output))
+    }
+
+    #[cfg(feature = "template-engine")]
+    fn keep_rendered(&self, template: &Path, source: &Arc<str>, op: &FileOp, output: &str) {
+        let mut cache = self.lock();
+        if let Some(cached) = cache
+            .get_mut(template)
+            .filter(|c| Arc::ptr_eq(&c.source, source))
+        {
+            cached.rendered = Some(Rendered {
+                vars: op.template_vars.clone(),
+                allow_shell: op.allow_shell,
+                output: output.into(),
+            });
+        }
+    }
+}
+
+/// Whether `text` is an http:// or https:// URL.
+pub(crate) fn is_url(text: &str) -> bool {
+    text.starts_with("http://") || text.starts_with("https://")
+}
+
+/// A template given by name is looked up in the templates directory when
+/// there is no such file, either by file name or without its extension
+/// (`rust-module` finds `rust-module.rs`).
+fn resolve_template(op: &FileOp, template: &str) -> PathBuf {
+    let path = PathBuf::from(template);
+    let Some(dir) = op
+        .templates_dir
+        .as_ref()
+        .filter(|_| !path.exists() && !is_url(template))
+    else {
+        return path;
+    };
+    if dir.join(template).is_file() {
+        return dir.join(template);
+    }
+    list_templates(dir)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|name| name.with_extension("") == Path::new(template))
+        .map_or(path, |name| dir.join(name))
+}
+
+/// The templates directory used when the config file doesn't name one.
+pub fn default_templates_dir() -> Result<PathBuf> {
+    Ok(
+        xdg_base_dir("XDG_CONFIG_HOME", ".config", |var| std::env::var_os(var))?
+            .join("tap/templates"),
+    )
+}
+
+/// The names of the templates in `dir`, including ones in subdirectories
+/// (`rust/module.rs`), sorted.
+pub fn list_templates(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    Ok(walk_tree(dir)?
+        .into_iter()
+        .filter(|name| dir.join(name).is_file())
+        .collect())
+}
+
+/// A template's content with its `{{ ... }}` expressions filled in for
+/// writing to `target`.
+#[cfg(feature = "template-engine")]
+fn render_template(template: &Path, target: &Path, op: &FileOp) -> Result<String> {
+    let source = op.template_cache.source(template, op)?;
+    if let Some(output) = op.template_cache.rendered(template, &source, op) {
+        return Ok(output.to_string());
+    }
+    let (output, used_target) = render_source(&source, target, op)
+        .with_context(|| tr!("Failed to render template {}", template.display()))?;
+    if !used_target {
+        op.template_cache
+            .keep_rendered(template, &source, op, &output);
+    }
+    Ok(output)
+}
+
+/// Inline template content, such as a preset's, filled in for `target`.
+#[cfg(feature = "template-engine")]
+fn render_inline(text: &str, target: &Path, op: &FileOp) -> Result<String> {
+    let (output, _) =
+        render_source(text, target, op).with_context(|| tr!("Failed to render inline template"))?;
+    Ok(output)
+}
+
+/// `source` filled in for `target`, and whether that took anything from
+/// the target, so other targets would get something else.
+#[cfg(feature = "template-engine")]
+fn render_source(source: &str, target: &Path, op: &FileOp) -> Result<(String, bool)> {
+    let context = template::Context::new()
+        .target(target)
+        .vars(op.template_vars.iter().cloned())
+        .allow_shell(op.allow_shell)
+        .sandbox(op.sandbox.clone());
+    let output = template::render(source, &context)?;
+    Ok((output, context.used_target()))
+}
+
+/// Without the template engine, templates are copied as they are, as long
+/// as they have no expressions that would need filling in.
+#[cfg(not(feature = "template-engine"))]
+fn render_template(template: &Path, _target: &Path, op: &FileOp) -> Result<String> {
+    let content = op.template_cache.source(template, op)?;
+    if content.contains("{{") {
+        anyhow::bail!(tr!(
+            "Can't render template {}: tap was built without the template-engine feature",
+            template.display()
+        ));
+    }
+    Ok(content.to_string())
+}
+
+#[cfg(not(feature = "template-engine"))]
+fn render_inline(text: &str, _target: &Path, _op: &FileOp) -> Result<String> {
+    if text.contains("{{") {
+        anyhow::bail!(tr!(
+            "Can't render an inline template: tap was built without the template-engine feature"
+        ));
+    }
+    Ok(text.to_string())
+}
+
+/// Sets `path`'s permissions to an octal `chmod`, or changes them by a
+/// symbolic one (`u+x,go-w`) relative to each file's current mode.
+pub fn set_permissions(path: &Path, chmod: &str, recursive: bool, verbose: bool) -> Result<()> {
+    apply_mode(path, &Mode::parse(chmod)?, chmod, recursive, verbose)
+}
+
+fn apply_mode(path: &Path, mode: &Mode, chmod: &str, recursive: bool, verbose: bool) -> Result<()> {
+    if recursive && path.is_dir() {
+        for entry in fs::read_dir(path).with_context(|| tr!("Failed to read directory"))? {
+            let entry = entry.with_context(|| tr!("Failed to read directory entry"))?;
+            apply_mode(&entry.path(), mode, chmod, recursive, verbose)?;
+        }
+    }
+
+    let mode = match mode {
+        Mode::Octal(mode) => *mode,
+        Mode::Symbolic(_) => {
+            let metadata = fs::metadata(path).with_context(|| tr!("Failed to read permissions"))?;
+            mode.apply(platform::mode(&metadata), metadata.is_dir())
+        }
+    };
+    platform::set_mode(path, mode).with_context(|| tr!("Failed to set permissions"))?;
+    if verbose {
+        say!("Permissions set to {} for: {}", chmod, path.display());
+    }
+    Ok(())
+}
+
+/// Changes to file attributes, as `--attrib` gives them: `+h,+r,-a` sets
+/// hidden and read-only and clears archive, and `+hs` sets two at once. On
+/// Windows these are the file's attributes; elsewhere only read-only has an
+/// equivalent, and the rest are ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attributes {
+    pub set: u32,
+    pub clear: u32,
+}
+
+impl Attributes {
+    pub fn parse(changes: &str) -> Result<Self> {
+        let invalid = || {
+            anyhow::anyhow!(tr!(
+                "Invalid attribute change {} (expected + or - and some of r, h, s and a, e.g. +h,-a)",
+                changes
+            ))
+        };
+        let mut attributes = Self { set: 0, clear: 0 };
+        for change in changes.split(',') {
+            let mut letters = change.chars();
+            let add = match letters.next() {
+                Some('+') => true,
+                Some('-') => false,
+                _ => return Err(invalid()),
+            };
+            if letters.as_str().is_empty() {
+                return Err(invalid());
+            }
+            for letter in letters {
+                let attribute = match letter.to_ascii_lowercase() {
+                    'r' => platform::READ_ONLY,
+                    'h' => platform::HIDDEN,
+                    's' => platform::SYSTEM,
+                    'a' => platform::ARCHIVE,
+                    _ => return Err(invalid()),
+                };
+                // A later change to the same attribute wins
+                if add {
+                    attributes.set |= attribute;
+                    attributes.clear &= !attribute;
+                } else {
+                    attributes.clear |= attribute;
+                    attributes.set &= !attribute;
+                }
+            }
+        }
+        Ok(attributes)
+    }
+
+    pub fn apply(&self, current: u32) -> u32 {
+        (current | self.set) & !self.clear
+    }
+}
+
+/// Sets and clears the file attributes `changes` names on `path`, and with
+/// `recursive` on everything inside it.
+pub fn set_attributes(path: &Path, changes: &str, recursive: bool, verbose: bool) -> Result<()> {
+    apply_attributes(
+        path,
+        &Attributes::parse(changes)?,
+        changes,
+        recursive,
+        verbose,
+    )
+}
+
+fn apply_attributes(
+    path: &Path,
+    attributes: &Attributes,
+    changes: &str,
+    recursive: bool,
+    verbose: bool,
+) -> Result<()> {
+    if recursive && path.is_dir() {
+        for entry in fs::read_dir(path).with_context(|| tr!("Failed to read directory"))? {
+            let entry = entry.with_context(|| tr!("Failed to read directory entry"))?;
+            apply_attributes(&entry.path(), attributes, changes, recursive, verbose)?;
+        }
+    }
+    platform::set_attributes(path, attributes.set, attributes.clear)
+        .with_context(|| tr!("Failed to set attributes"))?;
+    if verbose {
+        say!("Attributes set to {} for: {}", changes, path.display());
+    }
+    Ok(())
+}
+
+/// Sets only the times of an existing path, never creating it.
+fn update_times_only(path: &Path, op: &FileOp) -> Result<()> {
+    if fs::symlink_metadata(path).is_err() {
+        return Err(does_not_exist(path));
+    }
+    match (&op.timestamp, &op.after, &op.reference) {
+        (Some(timestamp), _, _) => {
+            set_timestamp(path, timestamp, op.times, op.timestamp_shown()?.as_ref())?;
+            audit(op, &format!("set timestamp {}", timestamp), path);
+        }
+        (None, Some(reference), _) => {
+            set_mtime_after(path, reference, op.verbose)?;
+            audit(op, "set timestamp after reference", path);
+        }
+        (None, None, Some(reference)) => {
+            copy_times(path, reference, op.times, op.verbose)?;
+            audit(op, "copied timestamps from reference", path);
+        }
+        (None, None, None) => {
+            // utimensat on the path itself, so read-only files work as long as we own them
+            let now = filetime::FileTime::from_system_time(current_time_for(path, op.server_time));
+            op.times.set(path, now, now)?;
+            if op.verbose {
+                say!("File timestamp updated: {}", path.display());
+            }
+            audit(op, "set timestamp to now", path);
+        }
+    }
+    Ok(())
+}
+
+/// Which of a file's timestamps to change, like touch's `-a` and `-m`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimeSelection {
+    #[default]
+    Both,
+    Access,
+    Modification,
+}
+
+impl TimeSelection {
+    /// `--atime` and `--mtime` together, or neither, select both.
+    pub fn from_flags(atime: bool, mtime: bool) -> Self {
+        match (atime, mtime) {
+            (true, false) => Self::Access,
+            (false, true) => Self::Modification,
+            _ => Self::Both,
+        }
+    }
+
+    /// Sets the selected times, leaving the other one as it is.
+    pub fn set(
+        self,
+        path: &Path,
+        atime: filetime::FileTime,
+        mtime: filetime::FileTime,
+    ) -> Result<()> {
+        match self {
+            Self::Both => filetime::set_file_times(path, atime, mtime),
+            Self::Access => filetime::set_file_atime(path, atime),
+            Self::Modification => filetime::set_file_mtime(path, mtime),
+        }
+        .with_context(|| tr!("Failed to set timestamp"))
+    }
+
+    fn describe(self) -> String {
+        match self {
+            Self::Both => tr!("access and modification times"),
+            Self::Access => tr!("access time"),
+            Self::Modification => tr!("modification time"),
+        }
+    }
+}
+
+/// Sets the times `times` selects to `time_str`, printing the time it
+/// resolves to as `shown` displays it, if given.
+pub fn set_timestamp(
+    path: &Path,
+    time_str: &str,
+    times: TimeSelection,
+    shown: Option<&TimeDisplay>,
+) -> Result<()> {
+    let timestamp = parse_timestamp(time_str)?;
+    let file_time = filetime::FileTime::from_system_time(timestamp);
+    times.set(path, file_time, file_time)?;
+    if let Some(display) = shown {
+        say!(
+            "Timestamp set to {} for: {}",
+            display.show(timestamp),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Increments tried in turn by --after, from nanosecond-precision filesystems
+/// down to FAT's two-second granularity.
+const MTIME_INCREMENTS: [Duration; 6] = [
+    Duration::from_nanos(1),
+    Duration::from_micros(1),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+];
+
+/// Gives `path` the selected times of `reference`, like `touch -r`.
+pub fn copy_times(
+    path: &Path,
+    reference: &Path,
+    times: TimeSelection,
+    verbose: bool,
+) -> Result<()> {
+    let metadata = fs::metadata(reference)
+        .with_context(|| tr!("Reference file not found: {}", reference.display()))?;
+    times.set(
+        path,
+        filetime::FileTime::from_last_access_time(&metadata),
+        filetime::FileTime::from_last_modification_time(&metadata),
+    )?;
+    if verbose {
+        say!(
+            "Timestamps copied from {} to: {}",
+            reference.display(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Sets `path`'s mtime to the smallest value the filesystem keeps as strictly
+/// newer than `reference`'s, reading it back after each attempt since
+/// filesystems silently truncate to their own granularity.
+pub fn set_mtime_after(path: &Path, reference: &Path, verbose: bool) -> Result<()> {
+    let reference_time = fs::metadata(reference)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| tr!("Reference file not found: {}", reference.display()))?;
+
+    for increment in MTIME_INCREMENTS {
+        let target = reference_time + increment;
+        filetime::set_file_mtime(path, filetime::FileTime::from_system_time(target))
+            .with_context(|| tr!("Failed to set timestamp"))?;
+        let stored = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| tr!("Failed to read back timestamp"))?;
+        if stored > reference_time {
+            if verbose {
+                say!(
+                    "Timestamp set {} after {} for: {}",
+                    format!("{:?}", increment),
+                    reference.display(),
+                    path.display()
+                );
+            }
+            return Ok(());
+        }
+    }
+    anyhow::bail!(tr!(
+        "Could not make {} newer than {}",
+        path.display(),
+        reference.display()
+    ))
+}
+
+/// Parses `YYYY-MM-DD HH:MM:SS` (UTC) with an optional fraction of up to
+/// nine digits (e.g., `2023-05-01 12:00:00.123456789`), keeping full
+/// precision, as well as times relative to now: `now`, `+30m`, `-2h`,
+/// `2 hours ago`, `in 3 days`, and `today`, `yesterday` or `tomorrow` with
+/// an optional local `HH:MM[:SS]`.
+pub fn parse_timestamp(time_str: &str) -> Result<SystemTime> {
+    if let Ok(dt) = NaiveDateTime::parse_from_str(time_str, "%Y-%m-%d %H:%M:%S%.f") {
+        return Ok(SystemTime::from(dt.and_utc()));
+    }
+    relative_timestamp(time_str, Local::now())
+        .with_context(|| tr!("Invalid timestamp format: {}", time_str))
+}
+
+fn relative_timestamp(input: &str, now: DateTime<Local>) -> Option<SystemTime> {
+    let input = input.trim().to_ascii_lowercase();
+    let words = input.split_whitespace().collect::<Vec<_>>();
+    let from_now = |offset: Duration, later: bool| {
+        let now = SystemTime::from(now);
+        if later {
+            now.checked_add(offset)
+        } else {
+            now.checked_sub(offset)
+        }
+    };
+    match words[..] {
+        ["now"] | ["today"] => Some(now.into()),
+        [offset] if offset.starts_with(['+', '-']) => {
+            from_now(parse_duration(&offset[1..]).ok()?, offset.starts_with('+'))
+        }
+        [count, unit, "ago"] => from_now(time_span(count, unit)?, false),
+        ["in", count, unit] => from_now(time_span(count, unit)?, true),
+        ["yesterday"] => from_now(Duration::from_secs(86400), false),
+        ["tomorrow"] => from_now(Duration::from_secs(86400), true),
+        [day @ ("today" | "yesterday" | "tomorrow"), time] => {
+            let date = match day {
+                "yesterday" => now.date_naive().pred_opt()?,
+                "tomorrow" => now.date_naive().succ_opt()?,
+                _ => now.date_naive(),
+            };
+            let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+                .ok()?;
+            let local = date.and_time(time).and_local_timezone(Local).earliest()?;
+            Some(local.into())
+        }
+        _ => None,
+    }
+}
+
+/// `2` `hours`, `1` `week` and the like, as in "2 hours ago".
+fn time_span(count: &str, unit: &str) -> Option<Duration> {
+    let count: u64 = count.parse().ok()?;
+    let unit_seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" | "hr" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(count.checked_mul(unit_seconds)?))
+}
+
+/// Limits operations to a fixed rate, backing off when the filesystem
+/// reports that it is overloaded (EAGAIN/ETIMEDOUT, common on NFS and SMB
+/// mounts). A token bucket shared by every worker: each slot lets `batch`
+/// operations through at once, the slots are `batch` intervals apart, and up
+/// to `burst` slots left unused while idle can be taken at once, so parallel
+/// workers don't wait on each other after a pause.
+struct Throttle {
+    base_interval: Duration,
+    burst: u32,
+    batch: u32,
+    pace: Mutex<Pace>,
+}
+
+struct Pace {
+    interval: Duration,
+    /// `None` until the first operation, which may take the whole burst
+    next_slot: Option<Instant>,
+    /// When the slot the current batch was given comes
+    batch_slot: Instant,
+    /// Operations the current batch still lets through
+    left_in_batch: u32,
+}
+
+impl Throttle {
+    const MAX_RETRIES: u32 = 5;
+    const MAX_INTERVAL: Duration = Duration::from_secs(5);
+
+    fn parse(rate: &str) -> Result<Self> {
+        let (count, unit) = rate.split_once('/').unwrap_or((rate, "s"));
+        let count: u32 = count
+            .trim()
+            .parse()
+            .ok()
+            .filter(|count| *count > 0)
+            .with_context(|| tr!("Invalid throttle rate: {}", rate))?;
+        let period = match unit.trim() {
+            "s" | "sec" => Duration::from_secs(1),
+            "m" | "min" => Duration::from_secs(60),
+            "h" | "hour" => Duration::from_secs(3600),
+            other => anyhow::bail!(tr!(
+                "Invalid throttle unit '{}' (expected s, m or h)",
+                other
+            )),
+        };
+        let interval = period / count;
+        Ok(Self {
+            base_interval: interval,
+            burst: 1,
+            batch: 1,
+            pace: Mutex::new(Pace {
+                interval,
+                next_slot: None,
+                batch_slot: Instant::now(),
+                left_in_batch: 0,
+            }),
+        })
+    }
+
+    /// Lets `burst` operations start at once after an idle spell, one per
+    /// worker.
+    fn burst(self, burst: usize) -> Self {
+        Self {
+            burst: u32::try_from(burst).unwrap_or(u32::MAX).max(1),
+            ..self
+        }
+    }
+
+    /// Lets operations through `size` at a time, keeping the same average
+    /// rate.
+    fn batch(self, size: usize) -> Self {
+        Self {
+            batch: u32::try_from(size).unwrap_or(u32::MAX).max(1),
+            ..self
+        }
+    }
+
+    fn pace(&self) -> std::sync::MutexGuard<'_, Pace> {
+        self.pace.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs `op` in its turn. When it fails because the filesystem is
+    /// overloaded, the pace slows down, and with `retry` it runs again in a
+    /// later turn, up to `MAX_RETRIES` times; without, the error is returned,
+    /// since an operation that got part-way would not do the same again.
+    fn run<T, F>(&self, retry: bool, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            self.wait_for_slot();
+            match op() {
+                Ok(value) => {
+                    // Recover gradually so a single hiccup doesn't pin us at the slow rate
+                    let mut pace = self.pace();
+                    pace.interval = (pace.interval * 3 / 4).max(self.base_interval);
+                    return Ok(value);
+                }
+                Err(e) if is_transient(&e) => {
+                    {
+                        let mut pace = self.pace();
+                        pace.interval = (pace.interval * 2).min(Self::MAX_INTERVAL);
+                    }
+                    if !retry || attempt == Self::MAX_RETRIES {
+                        return Err(e);
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Takes a place in the current batch, or the next slot for a new one,
+    /// and sleeps until the slot comes, without holding the bucket, so other
+    /// workers can take the places and slots after it meanwhile.
+    fn wait_for_slot(&self) {
+        let slot = {
+            let mut pace = self.pace();
+            if pace.left_in_batch == 0 {
+                let spacing = pace.interval.saturating_mul(self.batch);
+                let now = Instant::now();
+                let unused = spacing.saturating_mul(self.burst - 1);
+                let earliest = now.checked_sub(unused).unwrap_or(now);
+                let slot = pace.next_slot.map_or(earliest, |next| next.max(earliest));
+                pace.next_slot = Some(slot + spacing);
+                pace.batch_slot = slot;
+                pace.left_in_batch = self.batch;
+            }
+            pace.left_in_batch -= 1;
+            pace.batch_slot
+        };
+        let now = Instant::now();
+        if slot > now {
+            thread::sleep(slot - now);
+        }
+    }
+}
+
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )
+        })
+}
+
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn parse_wait_timeout(wait: &str) -> Result<Option<Duration>> {
+    if wait == "forever" {
+        return Ok(None);
+    }
+    parse_duration(wait).map(Some)
+}
+
+/// Parses durations such as `500ms`, `30s`, `5m`, `2h` or `1d`; a bare number is seconds.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (value, unit) = input.split_at(split);
+    let value: f64 = value
+        .parse()
+        .with_context(|| tr!("Invalid duration: {}", input))?;
+    let seconds = match unit.trim() {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        "d" => value * 86400.0,
+        other => anyhow::bail!(tr!("Invalid duration unit '{}' in: {}", other, input)),
+    };
+    Duration::try_from_secs_f64(seconds).with_context(|| tr!("Invalid duration: {}", input))
+}
+
+/// The instant `timeout` from now, or `None` for a timeout so long it never
+/// comes, which waits forever.
+pub fn deadline_after(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+fn wait_for_directory(dir: &Path, deadline: Option<Instant>, verbose: bool) -> Result<()> {
+    if dir.as_os_str().is_empty() || dir.is_dir() {
+        return Ok(());
+    }
+    if verbose {
+        say!("Waiting for directory: {}", dir.display());
+    }
+    while !dir.is_dir() {
+        if deadline_passed(deadline) {
+            let suggestion = suggest_path(dir).filter(|suggestion| suggestion.is_dir());
+            anyhow::bail!(did_you_mean(
+                tr!("Timed out waiting for directory: {}", dir.display()),
+                suggestion
+            ));
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+fn retry_while_busy<F>(deadline: Option<Instant>, mut op: F) -> Result<()>
+where
+    F: FnMut() -> Result<()>,
+{
+    loop {
+        match op() {
+            Err(e) if is_busy(&e) && !deadline_passed(deadline) => {
+                thread::sleep(WAIT_POLL_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_busy(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|e| e.kind() == io::ErrorKind::WouldBlock || platform::is_busy(e))
+}
+
+/// Creates a file for `tempfile::Builder::make_in`, registered in case tap is
+/// killed before the file is renamed or removed.
+fn create_registered(path: &Path) -> io::Result<(fs::File, temp_files::Registered)> {
+    let registered = temp_files::register(path);
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    // Private, like the files tempfile creates itself
+    options.with_mode(0o600);
+    Ok((options.open(path)?, registered))
+}
+
+pub fn set_flag(path: &Path, verbose: bool) -> Result<()> {
+    let parent = parent_dir(path);
+    fs::create_dir_all(parent).with_context(|| tr!("Failed to create parent directories"))?;
+    // Create under a temporary name and rename into place so waiters never
+    // observe a half-created flag
+    let temp = tempfile::Builder::new()
+        .make_in(parent, create_registered)
+        .with_context(|| tr!("Failed to create flag file"))?;
+    temp.persist(path)
+        .with_context(|| tr!("Failed to create flag file"))?;
+    if verbose {
+        say!("Flag set: {}", path.display());
+    }
+    Ok(())
+}
+
+pub fn clear_flag(path: &Path, verbose: bool) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => {
+            if verbose {
+                say!("Flag cleared: {}", path.display());
+            }
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| tr!("Failed to clear flag file")),
+    }
+}
+
+pub fn wait_for_flag(path: &Path, timeout: Option<Duration>, verbose: bool) -> Result<()> {
+    let deadline = timeout.and_then(deadline_after);
+    wait_for_directory(parent_dir(path), deadline, verbose)?;
+    wait_for_file(path, deadline, verbose)
+}
+
+/// Waits for `path`, in a directory that exists, to appear, woken by a
+/// watcher on the directory.
+#[cfg(feature = "watch")]
+fn wait_for_file(path: &Path, deadline: Option<Instant>, verbose: bool) -> Result<()> {
+    let parent = parent_dir(path);
+    let (tx, rx) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(tx).with_context(|| tr!("Failed to start file watcher"))?;
+    watcher
+        .watch(parent, RecursiveMode::NonRecursive)
+        .with_context(|| tr!("Failed to watch flag directory"))?;
+
+    if verbose && !path.exists() {
+        say!("Waiting for flag: {}", path.display());
+    }
+    // Checked only after the watch is registered so a flag set in between isn't missed
+    while !path.exists() {
+        let event = match deadline {
+            Some(deadline) => {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        anyhow::bail!(tr!("Timed out waiting for flag: {}", path.display()))
+                    }
+                    other => other.ok(),
+                }
+            }
+            None => rx.recv().ok(),
+        };
+        if event.is_none() {
+            anyhow::bail!(tr!(
+                "File watcher stopped while waiting for: {}",
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Without the watch feature, checks for `path` every `WAIT_POLL_INTERVAL`.
+#[cfg(not(feature = "watch"))]
+fn wait_for_file(path: &Path, deadline: Option<Instant>, verbose: bool) -> Result<()> {
+    if verbose && !path.exists() {
+        say!("Waiting for flag: {}", path.display());
+    }
+    while !path.exists() {
+        if deadline_passed(deadline) {
+            anyhow::bail!(tr!("Timed out waiting for flag: {}", path.display()));
+        }
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// The directory containing `path`, `.` for a bare file name.
+pub fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Records `pid` in `path` unless a running process already holds it. The
+/// PID is written to a temporary file that is then linked into place
+/// without replacing anything, so a reader never sees a half-written PID
+/// file. A stale one is only removed while holding `<path>.lock`, so two
+/// starters can't both remove it and each take over. The lock file is
+/// removed again once the takeover is decided.
+pub fn write_pidfile(path: &Path, pid: u32, verbose: bool) -> Result<()> {
+    let mut temp = tempfile::Builder::new()
+        .make_in(parent_dir(path), create_registered)
+        .with_context(|| tr!("Failed to create PID file"))?;
+    writeln!(temp.as_file_mut().0, "{}", pid).with_context(|| tr!("Failed to write PID file"))?;
+    platform::set_mode(temp.path(), 0o644)
+        .with_context(|| tr!("Failed to set PID file permissions"))?;
+    let mut stale_lock = None;
+    loop {
+        match temp.persist_noclobber(path) {
+            Ok(_) => {
+                if verbose {
+                    say!("PID {} written to: {}", pid, path.display());
+                }
+                return Ok(());
+            }
+            Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => temp = e.file,
+            Err(e) => return Err(e.error).with_context(|| tr!("Failed to create PID file")),
+        }
+        let existing = match fs::read_to_string(path) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| tr!("Failed to read PID file")),
+        };
+        // Anything else may be another starter's file, or not a PID file
+        let Ok(existing) = existing.trim().parse::<u32>() else {
+            anyhow::bail!(tr!(
+                "PID file {} exists but doesn't hold a PID; remove it if it is stale",
+                path.display()
+            ));
+        };
+        if platform::process_alive(existing) {
+            anyhow::bail!(tr!(
+                "PID file {} is held by running process {}",
+                path.display(),
+                existing
+            ));
+        }
+        if stale_lock.is_none() {
+            // Look again once no other starter can be replacing it
+            stale_lock = Some(lock_stale_pidfile(path)?);
+            continue;
+        }
+        if verbose {
+            say!("Removing stale PID file: {}", path.display());
+        }
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| tr!("Failed to remove stale PID file"));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The lock held while a stale PID file is replaced. Its file is removed
+/// before the lock is released.
+struct StalePidfileLock {
+    path: PathBuf,
+    _file: fs::File,
+}
+
+/// Waits for the lock held while a stale PID file at `path` is replaced.
+fn lock_stale_pidfile(path: &Path) -> Result<StalePidfileLock> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| tr!("Failed to open lock file"))?;
+        platform::lock(&file, true).with_context(|| tr!("Failed to lock"))?;
+        // The previous holder may have removed the file while this one
+        // waited, and a starter opening it now would get a lock of its own
+        let locked = file.metadata().ok().and_then(|m| platform::file_id(&m));
+        let current = fs::metadata(&lock_path).map(|m| platform::file_id(&m));
+        if current.is_ok_and(|current| current == locked) {
+            return Ok(StalePidfileLock {
+                path: lock_path,
+                _file: file,
+            });
+        }
+    }
+}
+
+impl Drop for StalePidfileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The PID `--pidfile` records: `--pid`, or else the process that invoked
+/// tap, since tap itself exits immediately.
+fn pidfile_pid(op: &FileOp) -> Result<u32> {
+    op.pid
+        .or_else(platform::parent_pid)
+        .with_context(|| tr!("--pidfile needs --pid on this platform"))
+}
+
+/// Members of a multi-root workspace, addressable as `@member/relative/path`.
+struct Workspace {
+    root: PathBuf,
+    members: Vec<(String, PathBuf)>,
+}
+
+impl Workspace {
+    const TAP_WORKSPACE_FILE: &'static str = ".tap-workspace";
+
+    /// Walks up from `start` to the nearest `.tap-workspace` file or Cargo
+    /// manifest with a `[workspace]` table. Cargo manifests need the
+    /// manifest feature to be read.
+    fn discover(start: &Path) -> Result<Self> {
+        for dir in start.ancestors() {
+            let tap_file = dir.join(Self::TAP_WORKSPACE_FILE);
+            if tap_file.is_file() {
+                let list = fs::read_to_string(&tap_file)
+                    .with_context(|| tr!("Failed to read .tap-workspace"))?;
+                return Self::from_tap_list(dir, &list);
+            }
+
+            #[cfg(feature = "manifest")]
+            if let Some(workspace) = Self::from_cargo_manifest(dir)? {
+                return Ok(workspace);
+            }
+        }
+        anyhow::bail!(tr!("No workspace found above {}", start.display()))
+    }
+
+    /// The workspace of the Cargo manifest in `dir`, if there is one with a
+    /// `[workspace]` table.
+    #[cfg(feature = "manifest")]
+    fn from_cargo_manifest(dir: &Path) -> Result<Option<Self>> {
+        let cargo_file = dir.join("Cargo.toml");
+        if !cargo_file.is_file() {
+            return Ok(None);
+        }
+        let manifest =
+            fs::read_to_string(&cargo_file).with_context(|| tr!("Failed to read Cargo.toml"))?;
+        let manifest: toml::Table = manifest
+            .parse()
+            .with_context(|| tr!("Invalid TOML in {}", cargo_file.display()))?;
+        match manifest.get("workspace").and_then(|w| w.as_table()) {
+            Some(workspace) => Self::from_cargo_workspace(dir, workspace).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses a `.tap-workspace` list: one member per line, either `path` or
+    /// `name = path`, where paths may be glob patterns.
+    fn from_tap_list(root: &Path, list: &str) -> Result<Self> {
+        let mut members = Vec::new();
+        for line in list.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((name, path)) => {
+                    members.push((name.trim().to_string(), root.join(path.trim())));
+                }
+                None => {
+                    for dir in expand_member_pattern(root, line)? {
+                        members.push((dir_name(&dir), dir));
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+            members,
+        })
+    }
+
+    #[cfg(feature = "manifest")]
+    fn from_cargo_workspace(root: &Path, workspace: &toml::Table) -> Result<Self> {
+        let patterns = workspace
+            .get("members")
+            .and_then(|members| members.as_array())
+            .map(|members| {
+                members
+                    .iter()
+                    .filter_map(|m| m.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let mut members = Vec::new();
+        for pattern in patterns {
+            for dir in expand_member_pattern(root, pattern)? {
+                // Members are addressable by package name as well as by directory name
+                if let Some(package) = cargo_package_name(&dir) {
+                    if package != dir_name(&dir) {
+                        members.push((package, dir.clone()));
+                    }
+                }
+                members.push((dir_name(&dir), dir));
+            }
+        }
+        Ok(Self {
+            root: root.to_path_buf(),
+            members,
+        })
+    }
+
+    /// Rewrites `@member/rest` to a path under that member; other targets are
+    /// returned unchanged.
+    fn resolve(&self, target: &str) -> Result<String> {
+        let Some(reference) = target.strip_prefix('@') else {
+            return Ok(target.to_string());
+        };
+        let (name, rest) = reference.split_once('/').unwrap_or((reference, ""));
+        let Some((_, dir)) = self.members.iter().find(|(member, _)| member == name) else {
+            let known = self
+                .members
+                .iter()
+                .map(|(member, _)| member.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::bail!(tr!(
+                "Unknown workspace member '{}' in {} (known members: {})",
+                name,
+                self.root.display(),
+                known
+            ));
+        };
+        Ok(dir.join(rest).to_string_lossy().into_owned())
+    }
+}
+
+fn expand_member_pattern(root: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let full = root.join(pattern);
+    let entries = glob(&full.to_string_lossy())
+        .with_context(|| tr!("Invalid workspace member pattern: {}", pattern))?;
+    Ok(entries
+        .filter_map(Result::ok)
+        .filter(|dir| dir.is_dir())
+        .collect())
+}
+
+#[cfg(feature = "manifest")]
+fn cargo_package_name(dir: &Path) -> Option<String> {
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let manifest: toml::Table = manifest.parse().ok()?;
+    let name = manifest.get("package")?.get("name")?.as_str()?;
+    Some(name.to_string())
+}
+
+fn dir_name(dir: &Path) -> String {
+    dir.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Set by the `tap shell-init` wrapper: `--print-cd` writes the directory to
+/// change into to this file instead of stdout, leaving stdout for normal output.
+const CD_FILE_ENV: &str = "TAP_CD_FILE";
+
+fn emit_cd_target(path: &Path, cd_file: Option<&Path>) -> Result<()> {
+    let target = fs::canonicalize(path).with_context(|| tr!("Failed to resolve directory"))?;
+    match cd_file {
+        Some(cd_file) => fs::write(cd_file, target.as_os_str().as_encoded_bytes())
+            .with_context(|| tr!("Failed to write cd target"))?,
+        None => say!("{}", target.display()),
+    }
+    Ok(())
+}
+
+pub fn shell_init_script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash | Shell::Zsh => {
+            r#"tap() {
+    local tap_cd_file tap_status
+    tap_cd_file="$(mktemp "${TMPDIR:-/tmp}/tap-cd.XXXXXX")" || return
+    TAP_CD_FILE="$tap_cd_file" command tap "$@"
+    tap_status=$?
+    if [ "$tap_status" -eq 0 ] && [ -s "$tap_cd_file" ]; then
+        cd -- "$(cat -- "$tap_cd_file")" || tap_status=$?
+    fi
+    rm -f -- "$tap_cd_file"
+    return "$tap_status"
+}
+"#
+        }
+        Shell::Fish => {
+            r#"function tap --wraps tap
+    set -l tap_cd_file (mktemp (set -q TMPDIR; and echo $TMPDIR; or echo /tmp)/tap-cd.XXXXXX); or return
+    TAP_CD_FILE=$tap_cd_file command tap $argv
+    set -l tap_status $status
+    if test $tap_status -eq 0; and test -s $tap_cd_file
+        cd (cat $tap_cd_file); or set tap_status $status
+    end
+    rm -f $tap_cd_file
+    return $tap_status
+end
+"#
+        }
+    }
+}
+
+/// Runs touching at least this many paths get filesystem checks up front.
+const LARGE_RUN_THRESHOLD: usize = 100;
+
+/// Below this share of free space a filesystem counts as nearly full.
+const LOW_SPACE_PERCENT: u64 = 5;
+
+struct FsInfo {
+    fs_type: Option<&'static str>,
+    read_only: bool,
+    available_bytes: u64,
+    total_bytes: u64,
+}
+
+impl FsInfo {
+    fn for_path(path: &Path) -> Result<Self> {
+        platform::filesystem_info(path)
+            .with_context(|| tr!("Failed to stat filesystem of {}", path.display()))
+    }
+}
+
+/// Fails when the filesystem `path` is on, or will be created on, hasn't
+/// room for `size` more bytes than `path` already takes. Where free space
+/// can't be read, such as on Windows, the write goes ahead.
+fn check_space_for(path: &Path, size: u64) -> Result<()> {
+    let current = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    let Some(existing) = path
+        .ancestors()
+        .map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                ancestor
+            }
+        })
+        .find(|ancestor| ancestor.exists())
+    else {
+        return Ok(());
+    };
+    let Ok(info) = FsInfo::for_path(existing) else {
+        return Ok(());
+    };
+    let needed = size.saturating_sub(current);
+    if needed > info.available_bytes {
+        anyhow::bail!(tr!(
+            "Not enough space for {} in {} ({} free)",
+            show_size(needed, false),
+            path.display(),
+            show_size(info.available_bytes, false)
+        ));
+    }
+    Ok(())
+}
+
+/// Checks each distinct filesystem the targets live on, using the nearest
+/// existing ancestor for paths that have not been created yet.
+fn filesystem_warnings(paths: &[PathBuf]) -> Vec<String> {
+    let mut seen_devices = HashSet::new();
+    let mut warnings = Vec::new();
+
+    for path in paths {
+        let Some(existing) = path
+            .ancestors()
+            .map(|ancestor| {
+                if ancestor.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    ancestor
+                }
+            })
+            .find(|ancestor| ancestor.exists())
+        else {
+            continue;
+        };
+        let Ok(metadata) = fs::metadata(existing) else {
+            continue;
+        };
+        if !seen_devices.insert(platform::device(&metadata)) {
+            continue;
+        }
+        if let Ok(info) = FsInfo::for_path(existing) {
+            warnings.extend(fs_info_warnings(existing, &info));
+        }
+    }
+
+    warnings
+}
+
+fn fs_info_warnings(path: &Path, info: &FsInfo) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(fs_type) = info.fs_type {
+        warnings.push(tr!(
+            "{} is on a network filesystem ({}); consider --throttle",
+            path.display(),
+            fs_type
+        ));
+    }
+    if info.read_only {
+        warnings.push(tr!("{} is on a read-only filesystem", path.display()));
+    }
+    if info.total_bytes > 0 && info.available_bytes * 100 / info.total_bytes < LOW_SPACE_PERCENT {
+        warnings.push(tr!(
+            "{} is on a nearly full filesystem ({}% free)",
+            path.display(),
+            info.available_bytes * 100 / info.total_bytes
+        ));
+    }
+    warnings
+}
+
+/// Applies a umask for the lifetime of the guard and restores the caller's
+/// umask when dropped. Windows has no umask, so there it only warns.
+struct UmaskGuard {
+    previous: Option<u32>,
+}
+
+impl UmaskGuard {
+    fn parse(mask: &str) -> Result<u32> {
+        u32::from_str_radix(mask, 8)
+            .ok()
+            .filter(|mask| *mask <= 0o777)
+            .with_context(|| tr!("Invalid umask value: {}", mask))
+    }
+
+    fn set(mask: &str) -> Result<Self> {
+        let mask = Self::parse(mask)?;
+        Ok(Self {
+            previous: platform::set_umask(mask),
+        })
+    }
+}
+
+impl Drop for UmaskGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            platform::set_umask(previous);
+        }
+    }
+}
+
+/// Restricts a listing to paths touched within the given windows before now.
+#[derive(Default)]
+pub struct TimeWindowFilter {
+    pub accessed_within: Option<Duration>,
+    pub modified_within: Option<Duration>,
+}
+
+impl TimeWindowFilter {
+    fn matches(&self, metadata: &fs::Metadata, now: SystemTime) -> Result<bool> {
+        let within = |time: SystemTime, window: Duration| {
+            // Times in the future (clock skew) count as recent
+            now.duration_since(time).map_or(true, |age| age <= window)
+        };
+        if let Some(window) = self.accessed_within {
+            if !within(metadata.accessed()?, window) {
+                return Ok(false);
+            }
+        }
+        if let Some(window) = self.modified_within {
+            if !within(metadata.modified()?, window) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+pub fn stat_paths(
+    paths: &[PathBuf],
+    filter: &TimeWindowFilter,
+    format: OutputFormat,
+    times: &TimeDisplay,
+    exact_sizes: bool,
+) -> Result<()> {
+    let now = SystemTime::now();
+    let mut records = Vec::new();
+    for path in paths {
+        let Ok(metadata) = fs::metadata(path) else {
+            say_err!("Does not exist: {}", path.display());
+            continue;
+        };
+        if filter.matches(&metadata, now)? {
+            let mut record = StatRecord::new(path, &metadata, times, exact_sizes)?;
+            // A stream has no streams of its own
+            if platform::data_stream(path).is_none() {
+                record.streams = platform::data_streams(path)
+                    .with_context(|| tr!("Failed to list data streams of {}", path.display()))?
+                    .into_iter()
+                    .map(|(name, size)| StreamRecord {
+                        name,
+                        size: show_size(size, exact_sizes),
+                    })
+                    .collect();
+            }
+            records.push(record);
+        }
+    }
+    print!("{}", render(&records, format));
+    Ok(())
+}
+
+/// The hex SHA-256 of a file's content.
+#[cfg(feature = "hash")]
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| tr!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| tr!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(not(feature = "hash"))]
+pub fn hash_file(path: &Path) -> Result<String> {
+    anyhow::bail!(tr!(
+        "Can't hash {}: tap was built without the hash feature",
+        path.display()
+    ))
+}
+
+/// A digest of a file's content that tells whether it changed: its
+/// SHA-256, or without the hash feature a 64-bit hash, which is enough to
+/// compare one file before and after.
+#[cfg(feature = "hash")]
+fn content_digest(path: &Path) -> Option<String> {
+    hash_file(path).ok()
+}
+
+#[cfg(not(feature = "hash"))]
+fn content_digest(path: &Path) -> Option<String> {
+    use std::hash::Hasher;
+
+    let mut file = fs::File::open(path).ok()?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).ok()?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+    }
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Groups regular files by content. Files are bucketed by size first so only
+/// potential duplicates get hashed; hard links to the same inode count once.
+pub fn find_duplicates(paths: &[PathBuf]) -> Result<Vec<DuplicateGroup>> {
+    let mut seen_inodes = HashSet::new();
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in paths {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            continue;
+        };
+        let first_link = match platform::file_id(&metadata) {
+            Some(id) => seen_inodes.insert(id),
+            None => true,
+        };
+        if metadata.is_file() && first_link {
+            by_size
+                .entry(metadata.len())
+                .or_default()
+                .push(path.clone());
+        }
+    }
+
+    let mut groups = Vec::new();
+    for (size, candidates) in by_size {
+        if candidates.len() < 2 {
+            continue;
+        }
+        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in candidates {
+            by_hash.entry(hash_file(&path)?).or_default().push(path);
+        }
+        for (hash, mut paths) in by_hash {
+            if paths.len() > 1 {
+                paths.sort();
+                groups.push(DuplicateGroup { hash, size, paths });
+            }
+        }
+    }
+    groups.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+    Ok(groups)
+}
+
+/// Keeps the first file of the group and atomically replaces the rest with links to it.
+pub fn dedupe_group(paths: &[PathBuf], mode: DedupeMode, verbose: bool) -> Result<()> {
+    let Some((original, duplicates)) = paths.split_first() else {
+        return Ok(());
+    };
+    let original =
+        fs::canonicalize(original).with_context(|| tr!("Failed to resolve original file"))?;
+    for duplicate in duplicates {
+        let temp = parent_dir(duplicate).join(format!(
+            ".{}.tap-dedupe",
+            duplicate.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        let _registered = temp_files::register(&temp);
+        match mode {
+            DedupeMode::Hardlink => fs::hard_link(&original, &temp),
+            DedupeMode::Symlink => platform::symlink(&original, &temp),
+        }
+        .with_context(|| tr!("Failed to link {}", duplicate.display()))?;
+        fs::rename(&temp, duplicate)
+            .with_context(|| tr!("Failed to replace {}", duplicate.display()))?;
+        if verbose {
+            say!("Linked {} -> {}", duplicate.display(), original.display());
+        }
+    }
+    Ok(())
+}
+
+/// Directory for tap's persistent state (`$XDG_STATE_HOME/tap`, falling back
+/// to `~/.local/state/tap`).
+fn state_dir() -> Result<PathBuf> {
+    Ok(xdg_base_dir("XDG_STATE_HOME", ".local/state", |var| {
+        std::env::var_os(var)
+    })?
+    .join("tap"))
+}
+
+/// Resolves an XDG base directory. Per the spec, only absolute values of the
+/// variable count; otherwise the default below `$HOME` (or `%USERPROFILE%` on
+/// Windows) is used.
+fn xdg_base_dir<F>(var: &str, default: &str, env: F) -> Result<PathBuf>
+where
+    F: Fn(&str) -> Option<std::ffi::OsString>,
+{
+    if let Some(dir) = env(var).map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        return Ok(dir);
+    }
+    let home = platform::HOME_VARS
+        .iter()
+        .find_map(|home| env(home))
+        .with_context(|| tr!("Cannot determine {}: HOME is not set", var))?;
+    Ok(PathBuf::from(home).join(default))
+}
+
+/// A named lock shared by all tap processes, held until dropped. Uses flock
+/// (LockFileEx on Windows), so a lock left behind by a killed process is
+/// released by the kernel; the holder's PID is recorded in the file for
+/// diagnostics only.
+struct ExclusiveLock {
+    _file: fs::File,
+}
+
+impl ExclusiveLock {
+    /// `wait` is `None` to fail immediately, or `Some(deadline)` to wait for
+    /// the lock until the optional deadline.
+    fn acquire(
+        lock_dir: &Path,
+        name: &str,
+        wait: Option<Option<Instant>>,
+        verbose: bool,
+    ) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            anyhow::bail!(tr!("Invalid lock name: {}", name));
+        }
+        fs::create_dir_all(lock_dir).with_context(|| tr!("Failed to create lock directory"))?;
+        let lock_path = lock_dir.join(format!("{}.lock", name));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .with_context(|| tr!("Failed to open lock file"))?;
+
+        let mut announced = false;
+        while let Err(err) = platform::lock(&file, false) {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err).with_context(|| tr!("Failed to lock"));
+            }
+            let holder = fs::read_to_string(&lock_path).unwrap_or_default();
+            let holder = holder.trim();
+            let Some(deadline) = wait else {
+                anyhow::bail!(tr!(
+                    "Lock '{}' is held by another tap run (pid {})",
+                    name,
+                    holder
+                ));
+            };
+            if deadline_passed(deadline) {
+                anyhow::bail!(tr!(
+                    "Timed out waiting for lock '{}' (held by pid {})",
+                    name,
+                    holder
+                ));
+            }
+            if !announced {
+                say!("Waiting for lock '{}' (held by pid {})", name, holder);
+                announced = true;
+            }
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+
+        file.set_len(0)
+            .with_context(|| tr!("Failed to write lock file"))?;
+        writeln!(file, "{}", std::process::id())
+            .with_context(|| tr!("Failed to write lock file"))?;
+        if verbose {
+            say!("Acquired lock: {}", name);
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+fn append_log_entry(path: &Path, entry: &str, op: &FileOp) -> Result<()> {
+    let items = StrftimeItems::new(&op.log_format).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        anyhow::bail!(tr!("Invalid log format: {}", op.log_format));
+    }
+    let line = format!(
+        "[{}] {}\n",
+        Utc::now().format_with_items(items.into_iter()),
+        entry
+    );
+
+    if let Some(limit) = &op.log_rotate {
+        let limit = parse_size(limit)?;
+        if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= limit) {
+            rotate_log(path, op.log_keep)?;
+            if op.verbose {
+                say!("Log rotated: {}", path.display());
+            }
+        }
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .with_mode(0o640)
+        .open(path)
+        .with_context(|| tr!("Failed to open log file"))?;
+    file.write_all(line.as_bytes())
+        .with_context(|| tr!("Failed to write log entry"))?;
+    if op.verbose {
+        say!("Log entry appended to: {}", path.display());
+    }
+    Ok(())
+}
+
+/// Shifts `log.1` to `log.2` and so on, dropping the oldest, then moves the
+/// current log to `log.1`.
+fn rotate_log(path: &Path, keep: u32) -> Result<()> {
+    let rotated = |index: u32| {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        return fs::remove_file(path).with_context(|| tr!("Failed to rotate log"));
+    }
+    for index in (1..keep).rev() {
+        let from = rotated(index);
+        if from.exists() {
+            fs::rename(&from, rotated(index + 1)).with_context(|| tr!("Failed to rotate log"))?;
+        }
+    }
+    fs::rename(path, rotated(1)).with_context(|| tr!("Failed to rotate log"))
+}
+
+/// Records an action in the system log selected with --log-target. Logging
+/// problems are reported but never fail the run.
+fn audit(op: &FileOp, action: &str, path: &Path) {
+    let Some(target) = op.log_target else {
+        return;
+    };
+    let message = format!("{}: {}", action, path.display());
+    let result = match target {
+        LogTarget::Syslog => send_to_syslog(&message),
+        LogTarget::Journald => send_to_journald(&message, action, path),
+    };
+    if let Err(e) = result {
+        say_err!(
+            "Warning: failed to write to {} log: {:#}",
+            target_name(target),
+            e
+        );
+    }
+}
+
+fn target_name(target: LogTarget) -> &'static str {
+    match target {
+        LogTarget::Syslog => "syslog",
+        LogTarget::Journald => "journald",
+    }
+}
+
+fn send_to_syslog(message: &str) -> Result<()> {
+    Ok(platform::syslog(message)?)
+}
+
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[cfg(unix)]
+fn send_to_journald(message: &str, action: &str, path: &Path) -> Result<()> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()
+        .with_context(|| tr!("Failed to create socket"))?;
+    socket
+        .send_to(&journald_entry(message, action, path), JOURNALD_SOCKET)
+        .with_context(|| tr!("Failed to send to journald"))?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn send_to_journald(_message: &str, _action: &str, _path: &Path) -> Result<()> {
+    anyhow::bail!(tr!("journald is not available on Windows"))
+}
+
+/// Encodes a journal entry in the native protocol, with the path and action
+/// as structured fields so they can be queried with `journalctl TAP_PATH=...`.
+#[cfg(unix)]
+fn journald_entry(message: &str, action: &str, path: &Path) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let fields: [(&str, &[u8]); 5] = [
+        ("MESSAGE", message.as_bytes()),
+        ("PRIORITY", b"6"),
+        ("SYSLOG_IDENTIFIER", b"tap"),
+        ("TAP_ACTION", action.as_bytes()),
+        ("TAP_PATH", path.as_os_str().as_bytes()),
+    ];
+    for (key, value) in fields {
+        entry.extend_from_slice(key.as_bytes());
+        if value.contains(&b'\n') {
+            // Values containing newlines use the length-prefixed binary form
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value);
+        entry.push(b'\n');
+    }
+    entry
+}
+
+/// Snapshot of a path's metadata (and content hash for regular files) used
+/// by audit reports.
+#[derive(Serialize, Deserialize)]
+struct PathState {
+    kind: String,
+    mode: String,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    modified: String,
+    accessed: String,
+}
+
+impl PathState {
+    /// Returns `None` when the path does not exist.
+    fn capture(path: &Path) -> Option<Self> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_file() {
+            "file"
+        } else if file_type.is_dir() {
+            "directory"
+        } else if file_type.is_symlink() {
+            "symlink"
+        } else {
+            "other"
+        };
+        let rfc3339 = |time: io::Result<SystemTime>| {
+            time.map(|time| DateTime::<Utc>::from(time).to_rfc3339())
+                .unwrap_or_default()
+        };
+        Some(Self {
+            kind: kind.to_string(),
+            mode: format!("{:04o}", platform::mode(&metadata)),
+            uid: platform::owner(&metadata).0,
+            gid: platform::owner(&metadata).1,
+            size: metadata.len(),
+            sha256: file_type.is_file().then(|| hash_file(path).ok()).flatten(),
+            modified: rfc3339(metadata.modified()),
+            accessed: rfc3339(metadata.accessed()),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct AuditEntry {
+    path: PathBuf,
+    before: Option<PathState>,
+    after: Option<PathState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct AuditReport {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditReport {
+    fn record(&mut self, path: &Path, before: Option<PathState>, result: &Result<()>) {
+        self.entries.push(AuditEntry {
+            path: path.to_path_buf(),
+            before,
+            after: PathState::capture(path),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        });
+    }
+
+    fn write(&self, destination: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .with_context(|| tr!("Failed to serialize audit report"))?;
+        fs::write(destination, json + "\n").with_context(|| tr!("Failed to write audit report"))
+    }
+}
+
+/// The path and any of its ancestors that don't exist yet, outermost first.
+fn missing_ancestors(path: &Path) -> Vec<PathBuf> {
+    let mut missing = path
+        .ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .take_while(|ancestor| fs::symlink_metadata(ancestor).is_err())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    missing.reverse();
+    missing
+}
+
+/// Every entry below `root` as a path relative to it, parents before children.
+fn walk_tree(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let dir = root.join(&relative);
+        let mut children = fs::read_dir(&dir)
+            .with_context(|| tr!("Failed to read directory {}", dir.display()))?
+            .map(|entry| entry.map(|entry| relative.join(entry.file_name())))
+            .collect::<io::Result<Vec<_>>>()
+            .with_context(|| tr!("Failed to read directory entry"))?;
+        children.sort();
+        for child in children.into_iter().rev() {
+            if fs::symlink_metadata(root.join(&child))?.is_dir() {
+                pending.push(child.clone());
+            }
+            entries.push(child);
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Creates a FIFO, reusing one that already exists at the path.
+pub fn create_fifo(path: &Path, verbose: bool) -> Result<()> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if platform::is_fifo(metadata.file_type()) {
+            return Ok(());
+        }
+        anyhow::bail!(tr!("{} exists and is not a FIFO", path.display()));
+    }
+    fs::create_dir_all(parent_dir(path))
+        .with_context(|| tr!("Failed to create parent directories"))?;
+    platform::make_fifo(path).with_context(|| tr!("Failed to create FIFO"))?;
+    if verbose {
+        say!("FIFO created: {}", path.display());
+    }
+    Ok(())
+}
+
+/// Creates a Unix socket file, reusing one that already exists at the path.
+/// Nothing listens on it afterwards, so it only holds the path, its mode and
+/// its owner for whatever binds there next.
+pub fn create_socket(path: &Path, verbose: bool) -> Result<()> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if platform::is_socket(metadata.file_type()) {
+            return Ok(());
+        }
+        anyhow::bail!(tr!("{} exists and is not a socket", path.display()));
+    }
+    fs::create_dir_all(parent_dir(path))
+        .with_context(|| tr!("Failed to create parent directories"))?;
+    platform::make_socket(path).with_context(|| tr!("Failed to create socket"))?;
+    if verbose {
+        say!("Socket created: {}", path.display());
+    }
+    Ok(())
+}
+
+/// Polls a non-blocking write open, which fails until some process has the
+/// FIFO open for reading.
+pub fn wait_for_fifo_reader(path: &Path, deadline: Option<Instant>, verbose: bool) -> Result<()> {
+    if verbose {
+        say!("Waiting for a reader on: {}", path.display());
+    }
+    loop {
+        match platform::open_fifo_writer(path) {
+            Ok(Some(_)) => {
+                if verbose {
+                    say!("Reader attached: {}", path.display());
+                }
+                return Ok(());
+            }
+            Ok(None) => {
+                if deadline_passed(deadline) {
+                    anyhow::bail!(tr!("Timed out waiting for a reader on {}", path.display()));
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e).with_context(|| tr!("Failed to open FIFO")),
+        }
+    }
+}
+
+/// Offset of each network filesystem's clock from ours, keyed by device and
+/// probed at most once per run.
+static SERVER_CLOCK_OFFSETS: Mutex<Vec<(u64, Duration, bool)>> = Mutex::new(Vec::new());
+
+/// "Now" as seen by the filesystem holding `path`. With `server_time` on a
+/// network mount this follows the server's clock, so freshly stamped files
+/// aren't considered to be in the future (or past) by other clients.
+fn current_time_for(path: &Path, server_time: bool) -> SystemTime {
+    let now = SystemTime::now();
+    if !server_time {
+        return now;
+    }
+    let dir = parent_dir(path);
+    let Ok(metadata) = fs::metadata(dir) else {
+        return now;
+    };
+    let device = platform::device(&metadata);
+
+    let mut offsets = SERVER_CLOCK_OFFSETS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let cached = offsets.iter().find(|(dev, _, _)| *dev == device).copied();
+    let (offset, ahead) = match cached {
+        Some((_, offset, ahead)) => (offset, ahead),
+        None => {
+            let is_network = platform::network_fs_type(dir).is_some();
+            let (offset, ahead) = if is_network {
+                probe_server_clock(dir).unwrap_or((Duration::ZERO, true))
+            } else {
+                (Duration::ZERO, true)
+            };
+            offsets.push((device, offset, ahead));
+            (offset, ahead)
+        }
+    };
+    if ahead {
+        now + offset
+    } else {
+        now - offset
+    }
+}
+
+/// Creates a probe file in `dir` and compares the mtime the server assigned
+/// it with our clock at the moment of creation. Returns the offset and
+/// whether the server is ahead.
+fn probe_server_clock(dir: &Path) -> Result<(Duration, bool)> {
+    let before = SystemTime::now();
+    let probe = tempfile::Builder::new()
+        .prefix(".tap-clock-probe")
+        .make_in(dir, create_registered)
+        .with_context(|| tr!("Failed to create clock probe file"))?;
+    let after = SystemTime::now();
+    let server = probe
+        .as_file()
+        .0
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| tr!("Failed to read clock probe file"))?;
+
+    let local = before + after.duration_since(before).unwrap_or_default() / 2;
+    Ok(match server.duration_since(local) {
+        Ok(ahead) => (ahead, true),
+        Err(behind) => (behind.duration(), false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    #[cfg(unix)]
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tempfile::{tempdir, NamedTempFile};
+
+    #[test]
+    fn test_expand_paths() -> Result<()> {
+        let dir = tempdir()?;
+        let file1 = dir.path().join("test1.txt");
+        let file2 = dir.path().join("test2.txt");
+        File::create(&file1)?;
+        File::create(&file2)?;
+
+        let paths = vec![dir.path().join("test*.txt").to_string_lossy().to_string()];
+        let expanded = expand_paths(&paths)?;
+
+        assert_eq!(expanded.len(), 2);
+        assert!(expanded.contains(&file1));
+        assert!(expanded.contains(&file2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_windows_device_names() {
+        for name in [
+            "nul",
+            "NUL.txt",
+            "con.tar.gz",
+            "aux ",
+            "Prn.",
+            "COM1",
+            "lpt9.log",
+            "conout$",
+        ] {
+            assert!(platform::is_reserved_name(name.as_ref()), "{name}");
+        }
+        for name in ["null", "console.txt", "com10", "lpt", "my-nul", ".con"] {
+            assert!(!platform::is_reserved_name(name.as_ref()), "{name}");
+        }
+        assert_eq!(
+            platform::reserved_component(Path::new("drivers/aux/aux.c")),
+            Some("aux".as_ref())
+        );
+        assert_eq!(platform::reserved_component(Path::new("src/main.rs")), None);
+        assert_eq!(
+            platform::reserved_component(Path::new(r"\\?\C:\src\nul")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_windows_glob_forms() {
+        let verbatim = GlobForm::for_windows(r"\\?\C:\logs\*.txt");
+        assert_eq!(verbatim.pattern, r"C:\logs\*.txt");
+        assert_eq!(
+            verbatim.respell(PathBuf::from(r"C:\logs\a.txt")),
+            PathBuf::from(r"\\?\C:\logs\a.txt")
+        );
+
+        let unc = GlobForm::for_windows(r"\\?\UNC\server\share\*.txt");
+        assert_eq!(unc.pattern, r"\\server\share\*.txt");
+        assert_eq!(
+            unc.respell(PathBuf::from(r"\\server\share\a.txt")),
+            PathBuf::from(r"\\?\UNC\server\share\a.txt")
+        );
+
+        let plain = GlobForm::for_windows("logs/*.txt");
+        assert_eq!(plain.pattern, "logs/*.txt");
+        assert_eq!(
+            plain.respell(PathBuf::from("logs/a.txt")),
+            PathBuf::from("logs/a.txt")
+        );
+    }
+
+    #[test]
+    fn test_attributes() -> Result<()> {
+        let attributes = Attributes::parse("+h,+r,-a")?;
+        assert_eq!(attributes.set, platform::HIDDEN | platform::READ_ONLY);
+        assert_eq!(attributes.clear, platform::ARCHIVE);
+        assert_eq!(
+            attributes.apply(platform::ARCHIVE | platform::SYSTEM),
+            platform::HIDDEN | platform::READ_ONLY | platform::SYSTEM
+        );
+        assert_eq!(Attributes::parse("+HS,-s")?.set, platform::HIDDEN);
+        for invalid in ["h", "+", "+x", "+h,,-a", ""] {
+            assert!(Attributes::parse(invalid).is_err(), "{invalid}");
+        }
+
+        let dir = tempdir()?;
+        let file = dir.path().join("a.txt");
+        let op = FileOp {
+            attrib: Some("+r".to_string()),
+            ..FileOp::default()
+        };
+        process_path(&file, &op)?;
+        let metadata = fs::metadata(&file)?;
+        assert!(metadata.permissions().readonly());
+        assert_eq!(
+            platform::attributes(&metadata) & platform::READ_ONLY,
+            platform::READ_ONLY
+        );
+        #[cfg(unix)]
+        assert_eq!(platform::mode(&metadata) & 0o222, 0);
+
+        set_attributes(&file, "-r", false, false)?;
+        assert!(!fs::metadata(&file)?.permissions().readonly());
+
+        #[cfg(windows)]
+        {
+            set_attributes(&file, "+h", false, false)?;
+            assert_eq!(
+                platform::attributes(&fs::metadata(&file)?) & platform::HIDDEN,
+                platform::HIDDEN
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_stream_paths() {
+        assert_eq!(
+            platform::data_stream(Path::new("notes.txt:author")),
+            Some("author")
+        );
+        assert_eq!(
+            platform::data_stream(Path::new("dir/notes.txt:author:$DATA")),
+            Some("author")
+        );
+        assert_eq!(platform::data_stream(Path::new("dir/notes.txt")), None);
+        assert_eq!(platform::data_stream(Path::new("notes.txt:")), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_data_streams() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("notes.txt");
+        let stream = dir.path().join("notes.txt:author");
+        fs::write(&file, "main")?;
+
+        let op = FileOp {
+            write: Some("first".to_string()),
+            ..FileOp::default()
+        };
+        process_path(&stream, &op)?;
+        // Rewriting goes in place rather than through a renamed temp file
+        let op = FileOp {
+            write: Some("second".to_string()),
+            ..op
+        };
+        process_path(&stream, &op)?;
+        assert_eq!(fs::read_to_string(&stream)?, "second");
+        assert_eq!(fs::read_to_string(&file)?, "main");
+        assert_eq!(platform::data_streams(&file)?, [("author".to_string(), 6)]);
+        assert_eq!(
+            expand_paths(&[file.display().to_string(), stream.display().to_string()])?.len(),
+            2
+        );
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_paths() -> Result<()> {
+        let dir = tempdir()?;
+        let op = FileOp::default();
+
+        // Past MAX_PATH without asking for it
+        let long = dir
+            .path()
+            .join("a".repeat(100))
+            .join("b".repeat(100))
+            .join(format!("{}.txt", "c".repeat(100)));
+        process_path(&long, &op)?;
+        assert!(long.is_file());
+
+        // Device names are refused, unless the path is verbatim
+        let device = dir.path().join("nul.txt");
+        assert!(process_path(&device, &op).is_err());
+        let verbatim = PathBuf::from(format!(r"\\?\{}", device.display()));
+        process_path(&verbatim, &op)?;
+        assert!(fs::metadata(&verbatim)?.is_file());
+
+        // Globs under a verbatim prefix match, and keep the prefix
+        let pattern = format!(r"\\?\{}\*.txt", dir.path().display());
+        assert_eq!(expand_paths(&[pattern])?, [verbatim]);
+
+        let drive = &dir.path().to_string_lossy()[..2];
+        let relative = GlobForm::for_windows(&format!("{drive}*.txt"));
+        assert!(relative.pattern.starts_with(&format!(r"{drive}\")));
+        assert!(relative.pattern.ends_with(r"\*.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_existence() -> Result<()> {
+        let dir = tempdir()?;
+        let existing_file = dir.path().join("existing.txt");
+        File::create(&existing_file)?;
+        let non_existing_file = dir.path().join("non_existing.txt");
+
+        check_existence(&existing_file, false)?;
+        check_existence(&non_existing_file, false)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_directory() -> Result<()> {
+        let dir = tempdir()?;
+        let new_dir = dir.path().join("new_dir");
+
+        create_directory(&new_dir, false)?;
+
+        assert!(new_dir.is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_or_update_file() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test.txt");
+
+        let op = FileOp {
+            dir: false,
+            chmod: None,
+            write: Some("Hello, World!".to_string()),
+            timestamp: None,
+            append: false,
+            verbose: false,
+            recursive: false,
+            template: None,
+            trim: false,
+            check: false,
+            ..Default::default()
+        };
+
+        create_or_update_file(&file_path, &op)?;
+
+        let content = fs::read_to_string(&file_path)?;
+        assert_eq!(content, "Hello, World!");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_to_file() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test.txt");
+        fs::write(&file_path, "Initial content\n")?;
+
+        let op = FileOp {
+            dir: false,
+            chmod: None,
+            write: Some("Appended content".to_string()),
+            timestamp: None,
+            append: true,
+            verbose: false,
+            recursive: false,
+            template: None,
+            trim: false,
+            check: false,
+            ..Default::default()
+        };
+
+        create_or_update_file(&file_path, &op)?;
+
+        let content = fs::read_to_string(&file_path)?;
+        assert_eq!(content, "Initial content\nAppended content");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim_whitespace() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test.txt");
+        fs::write(&file_path, "  Line with spaces  \nAnother line \t ")?;
+
+        let op = FileOp {
+            dir: false,
+            chmod: None,
+            write: None,
+            timestamp: None,
+            append: false,
+            verbose: false,
+            recursive: false,
+            template: None,
+            trim: true,
+            check: false,
+            ..Default::default()
+        };
+
+        create_or_update_file(&file_path, &op)?;
+
+        let content = fs::read_to_string(&file_path)?;
+        assert_eq!(content, "  Line with spaces\nAnother line");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_set_permissions() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let file_path = file.path();
+
+        set_permissions(file_path, "644", false, false)?;
+
+        let metadata = fs::metadata(file_path)?;
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o644);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_timestamp() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let file_path = file.path();
+
+        let time_str = "2023-05-01 12:00:00";
+        set_timestamp(file_path, time_str, TimeSelection::Both, None)?;
+
+        let metadata = fs::metadata(file_path)?;
+        let mtime = metadata.modified()?;
+        let expected_time = parse_timestamp(time_str)?;
+
+        assert_eq!(mtime, expected_time);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_timestamp() -> Result<()> {
+        let time_str = "2023-05-01 12:00:00";
+        let parsed_time = parse_timestamp(time_str)?;
+
+        let expected_time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1682942400);
+        assert_eq!(parsed_time, expected_time);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("test.txt");
+
+        let op = FileOp {
+            dir: false,
+            chmod: Some("644".to_string()),
+            write: Some("Test content".to_string()),
+            timestamp: Some("2023-05-01 12:00:00".to_string()),
+            append: false,
+            verbose: true,
+            recursive: false,
+            template: None,
+            trim: false,
+            check: false,
+            ..Default::default()
+        };
+
+        run(&op, &[file_path.to_string_lossy().to_string()])?;
+
+        assert!(file_path.exists());
+        let content = fs::read_to_string(&file_path)?;
+        assert_eq!(content, "Test content");
+
+        let metadata = fs::metadata(&file_path)?;
+        #[cfg(unix)]
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o644);
+
+        let mtime = metadata.modified()?;
+        let expected_time = parse_timestamp("2023-05-01 12:00:00")?;
+        assert_eq!(mtime, expected_time);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_throttle() -> Result<()> {
+        assert_eq!(
+            Throttle::parse("200/s")?.base_interval,
+            Duration::from_millis(5)
+        );
+        assert_eq!(
+            Throttle::parse("60/m")?.base_interval,
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            Throttle::parse("4")?.base_interval,
+            Duration::from_millis(250)
+        );
+        assert!(Throttle::parse("0/s").is_err());
+        assert!(Throttle::parse("10/week").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_throttle_backs_off_on_transient_errors() -> Result<()> {
+        let throttle = Throttle::parse("1000/s")?;
+        let mut calls = 0;
+        throttle.run(true, || {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::WouldBlock)).context("Failed to create file")
+            } else {
+                Ok(())
+            }
+        })?;
+        assert_eq!(calls, 3);
+        assert!(throttle.pace().interval > throttle.base_interval);
+
+        let result: Result<()> = throttle.run(true, || Err(anyhow::anyhow!("permanent failure")));
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_throttle_runs_unrepeatable_operations_once() -> Result<()> {
+        let throttle = Throttle::parse("1000/s")?;
+        let mut calls = 0;
+        let result: Result<()> = throttle.run(false, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::TimedOut)).context("Failed to append")
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        assert!(throttle.pace().interval > throttle.base_interval);
+
+        assert!(FileOp::default().repeatable());
+        let append = TapBuilder::new().write("line").append().build();
+        assert!(!append.repeatable());
+        let append_once = TapBuilder::new().write("line").append().once().build();
+        assert!(append_once.repeatable());
+        Ok(())
+    }
+
+    #[test]
+    fn test_throttle_batches() -> Result<()> {
+        let throttle = Throttle::parse("20/s")?.batch(5);
+        let started = Instant::now();
+        for _ in 0..5 {
+            throttle.run(true, || Ok(()))?;
+        }
+        // A batch goes through at once, and the next waits for five intervals
+        assert!(started.elapsed() < Duration::from_millis(100));
+        for _ in 0..5 {
+            throttle.run(true, || Ok(()))?;
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(240), "{:?}", elapsed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_throttle_is_shared_by_parallel_workers() -> Result<()> {
+        let throttle = Throttle::parse("100/s")?.burst(4);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..5 {
+                        throttle.run(true, || Ok(())).unwrap();
+                    }
+                });
+            }
+        });
+        // 20 paths at 100/s with the first 4 at once take 16 intervals; a
+        // throttle per worker would let them all through in 5
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(150), "{:?}", elapsed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("500ms")?, Duration::from_millis(500));
+        assert_eq!(parse_duration("30s")?, Duration::from_secs(30));
+        assert_eq!(parse_duration("45")?, Duration::from_secs(45));
+        assert_eq!(parse_duration("5m")?, Duration::from_secs(300));
+        assert_eq!(parse_duration("1.5h")?, Duration::from_secs(5400));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("10w").is_err());
+        assert!(parse_duration("99999999999999999999999").is_err());
+        assert_eq!(deadline_after(Duration::MAX), None);
+        assert_eq!(parse_wait_timeout("forever")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_directory() -> Result<()> {
+        let dir = tempdir()?;
+        let mount = dir.path().join("mount");
+        let expected = mount.clone();
+        let creator = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            fs::create_dir(&expected)
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        wait_for_directory(&mount, Some(deadline), false)?;
+        creator.join().unwrap()?;
+        assert!(mount.is_dir());
+
+        let missing = dir.path().join("never");
+        let deadline = Instant::now() + Duration::from_millis(150);
+        assert!(wait_for_directory(&missing, Some(deadline), false).is_err());
+
+        #[cfg(feature = "suggest")]
+        {
+            let typo = dir.path().join("muont");
+            let deadline = Instant::now() + Duration::from_millis(150);
+            let error = wait_for_directory(&typo, Some(deadline), false).unwrap_err();
+            assert!(
+                error
+                    .to_string()
+                    .ends_with(&format!("did you mean '{}'?", mount.display())),
+                "{error}"
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "suggest")]
+    #[test]
+    fn test_missing_paths_suggest_close_ones() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("src/bin"))?;
+        fs::write(dir.path().join("src/main.rs"), "")?;
+        fs::create_dir(dir.path().join("docs"))?;
+
+        let suggest = |path: &str| suggest_path(&dir.path().join(path));
+        assert_eq!(suggest("srd/main.rs"), Some(dir.path().join("src/main.rs")));
+        assert_eq!(suggest("src/mian.rs"), Some(dir.path().join("src/main.rs")));
+        assert_eq!(suggest("srd"), Some(dir.path().join("src")));
+        // Too far off, or close but with nothing under it
+        assert_eq!(suggest("lib/main.rs"), None);
+        assert_eq!(suggest("srd/lib.rs"), None);
+
+        let error = TapBuilder::new()
+            .no_touch()
+            .chmod("600")
+            .build()
+            .apply(&dir.path().join("srd/main.rs"))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Does not exist: {}, did you mean '{}'?",
+                dir.path().join("srd/main.rs").display(),
+                dir.path().join("src/main.rs").display()
+            )
+        );
+
+        // Parents are only left uncreated when asked
+        let op = TapBuilder::new().parents(false).build();
+        let error = op.apply(&dir.path().join("dcos/guide.md")).unwrap_err();
+        assert!(
+            error.to_string().ends_with(&format!(
+                "did you mean '{}'?",
+                dir.path().join("docs").display()
+            )),
+            "{error}"
+        );
+        assert!(!dir.path().join("dcos").exists());
+        assert!(plan_actions(&dir.path().join("dcos/guide.md"), &op, None).is_err());
+        op.apply(&dir.path().join("docs/guide.md"))?;
+        TapBuilder::new()
+            .parents(true)
+            .build()
+            .apply(&dir.path().join("dcos/guide.md"))?;
+        assert!(dir.path().join("dcos/guide.md").is_file());
+        Ok(())
+    }
+
+    #[cfg(feature = "suggest")]
+    #[test]
+    fn test_corrected_path_asks_before_using_the_suggestion() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("docs"))?;
+        let typo = dir.path().join("dcos/guide.md");
+        let op = TapBuilder::new().parents(false).interactive().build();
+
+        let mut asked = String::new();
+        let corrected = corrected_path(typo.clone(), &op, |question| {
+            asked = question.to_string();
+            true
+        });
+        assert_eq!(corrected, dir.path().join("docs/guide.md"));
+        assert!(asked.ends_with("instead? [y/N]"), "{asked}");
+        assert_eq!(corrected_path(typo.clone(), &op, |_| false), typo);
+
+        // Nothing to ask when the parent will be created, or exists
+        let creates = TapBuilder::new().interactive().build();
+        assert_eq!(corrected_path(typo.clone(), &creates, |_| panic!()), typo);
+        let exists = dir.path().join("docs/api.md");
+        assert_eq!(corrected_path(exists.clone(), &op, |_| panic!()), exists);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_and_clear_flag() -> Result<()> {
+        let dir = tempdir()?;
+        let flag = dir.path().join("stage").join("build.done");
+
+        set_flag(&flag, false)?;
+        assert!(flag.is_file());
+        set_flag(&flag, false)?;
+
+        clear_flag(&flag, false)?;
+        assert!(!flag.exists());
+        clear_flag(&flag, false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_for_flag() -> Result<()> {
+        let dir = tempdir()?;
+        let flag = dir.path().join("ready.done");
+        let setter_flag = flag.clone();
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            set_flag(&setter_flag, false)
+        });
+
+        wait_for_flag(&flag, Some(Duration::from_secs(5)), false)?;
+        setter.join().unwrap()?;
+
+        let missing = dir.path().join("never.done");
+        assert!(wait_for_flag(&missing, Some(Duration::from_millis(150)), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_pidfile() -> Result<()> {
+        let dir = tempdir()?;
+        let pidfile = dir.path().join("app.pid");
+
+        write_pidfile(&pidfile, 4242, false)?;
+        assert_eq!(fs::read_to_string(&pidfile)?, "4242\n");
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&pidfile)?.permissions().mode() & 0o777, 0o644);
+
+        // A live owner blocks, a dead one is treated as stale and replaced
+        let live = std::process::id();
+        fs::write(&pidfile, format!("{}\n", live))?;
+        assert!(write_pidfile(&pidfile, 4242, false).is_err());
+
+        let mut child = std::process::Command::new(std::env::current_exe()?)
+            .arg("--list")
+            .stdout(std::process::Stdio::null())
+            .spawn()?;
+        let dead = child.id();
+        child.wait()?;
+        fs::write(&pidfile, format!("{}\n", dead))?;
+        write_pidfile(&pidfile, 4242, false)?;
+        assert_eq!(fs::read_to_string(&pidfile)?, "4242\n");
+        // The takeover's lock file doesn't stay behind
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+
+        // A file that doesn't hold a PID, such as one still being written,
+        // is never taken over
+        for content in ["", "42x"] {
+            fs::write(&pidfile, content)?;
+            assert!(write_pidfile(&pidfile, 4242, false).is_err());
+            assert_eq!(fs::read_to_string(&pidfile)?, content);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_racing_pidfile_starters_get_one_winner() -> Result<()> {
+        let dir = tempdir()?;
+        let pidfile = dir.path().join("app.pid");
+        let live = std::process::id();
+        let race = || {
+            thread::scope(|scope| {
+                let starters: Vec<_> = (0..8)
+                    .map(|_| scope.spawn(|| write_pidfile(&pidfile, live, false)))
+                    .collect();
+                starters
+                    .into_iter()
+                    .map(|starter| starter.join().unwrap())
+                    .filter(Result::is_ok)
+                    .count()
+            })
+        };
+        assert_eq!(race(), 1);
+        assert_eq!(fs::read_to_string(&pidfile)?, format!("{}\n", live));
+
+        // The same when they all find a stale one to take over
+        let mut child = std::process::Command::new(std::env::current_exe()?)
+            .arg("--list")
+            .stdout(std::process::Stdio::null())
+            .spawn()?;
+        let dead = child.id();
+        child.wait()?;
+        fs::write(&pidfile, format!("{}\n", dead))?;
+        assert_eq!(race(), 1);
+        assert_eq!(fs::read_to_string(&pidfile)?, format!("{}\n", live));
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn test_cargo_workspace_resolution() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )?;
+        let member = dir.path().join("crates").join("core");
+        fs::create_dir_all(member.join("src"))?;
+        fs::write(
+            member.join("Cargo.toml"),
+            "[package]\nname = \"tap-core\"\n",
+        )?;
+
+        let workspace = Workspace::discover(&member.join("src"))?;
+        let expected = member.join("src/new.rs").to_string_lossy().into_owned();
+        assert_eq!(workspace.resolve("@core/src/new.rs")?, expected);
+        assert_eq!(workspace.resolve("@tap-core/src/new.rs")?, expected);
+        assert_eq!(workspace.resolve("plain.txt")?, "plain.txt");
+        assert!(workspace.resolve("@missing/file.rs").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tap_workspace_list() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("apps/web"))?;
+        fs::create_dir_all(dir.path().join("services/api"))?;
+        fs::write(
+            dir.path().join(".tap-workspace"),
+            "# members\napps/*\nbackend = services/api\n",
+        )?;
+
+        let workspace = Workspace::discover(dir.path())?;
+        assert_eq!(
+            workspace.resolve("@web/index.html")?,
+            dir.path().join("apps/web/index.html").to_string_lossy()
+        );
+        assert_eq!(
+            workspace.resolve("@backend/main.go")?,
+            dir.path().join("services/api/main.go").to_string_lossy()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_emit_cd_target() -> Result<()> {
+        let dir = tempdir()?;
+        let project = dir.path().join("project");
+        create_directory(&project, false)?;
+        let cd_file = dir.path().join("cd-target");
+
+        emit_cd_target(&project, Some(&cd_file))?;
+        assert_eq!(
+            PathBuf::from(fs::read_to_string(&cd_file)?),
+            fs::canonicalize(&project)?
+        );
+
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            assert!(shell_init_script(shell).contains(CD_FILE_ENV));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_filesystem_warnings() -> Result<()> {
+        let dir = tempdir()?;
+        let info = FsInfo::for_path(dir.path())?;
+        assert!(info.total_bytes > 0);
+        assert!(!info.read_only);
+
+        let nfs_full = FsInfo {
+            fs_type: Some("NFS"),
+            read_only: true,
+            available_bytes: 1,
+            total_bytes: 100,
+        };
+        let warnings = fs_info_warnings(dir.path(), &nfs_full);
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].contains("NFS"));
+
+        // Missing targets are checked through their nearest existing ancestor,
+        // and each filesystem is only reported once
+        let missing = dir.path().join("a/b/c.txt");
+        let sibling = dir.path().join("d.txt");
+        assert_eq!(
+            filesystem_warnings(std::slice::from_ref(&missing)).len(),
+            filesystem_warnings(&[missing, sibling]).len()
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_umask_guard() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("private.txt");
+        let original = unsafe { libc::umask(0o022) };
+
+        {
+            let _guard = UmaskGuard::set("027")?;
+            File::create(&file_path)?;
+        }
+        assert_eq!(
+            fs::metadata(&file_path)?.permissions().mode() & 0o777,
+            0o640
+        );
+        assert_eq!(unsafe { libc::umask(original) }, 0o022);
+
+        assert!(UmaskGuard::set("999").is_err());
+        assert!(UmaskGuard::set("1777").is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_no_touch_only_changes_permissions() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("script.sh");
+        fs::write(&file_path, "echo hi")?;
+        let old_time = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(&file_path, old_time)?;
+        let missing = dir.path().join("missing").join("file.txt");
+
+        let op = FileOp {
+            chmod: Some("755".to_string()),
+            no_touch: true,
+            ..Default::default()
+        };
+
+        process_path(&file_path, &op)?;
+        let metadata = fs::metadata(&file_path)?;
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&metadata),
+            old_time
+        );
+        assert_eq!(fs::read_to_string(&file_path)?, "echo hi");
+
+        assert!(process_path(&missing, &op).is_err());
+        assert!(!missing.parent().unwrap().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_times_only_never_creates_or_writes() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("readonly.txt");
+        fs::write(&file_path, "keep me")?;
+        #[cfg(unix)]
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o444))?;
+        let missing = dir.path().join("missing.txt");
+
+        let op = FileOp {
+            timestamp: Some("2023-05-01 12:00:00".to_string()),
+            times_only: true,
+            ..Default::default()
+        };
+
+        process_path(&file_path, &op)?;
+        let metadata = fs::metadata(&file_path)?;
+        assert_eq!(
+            metadata.modified()?,
+            parse_timestamp("2023-05-01 12:00:00")?
+        );
+        #[cfg(unix)]
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o444);
+        assert_eq!(fs::read_to_string(&file_path)?, "keep me");
+
+        assert!(process_path(&missing, &op).is_err());
+        assert!(!missing.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_window_filter() -> Result<()> {
+        let dir = tempdir()?;
+        let recent = dir.path().join("recent.txt");
+        let old = dir.path().join("old.txt");
+        File::create(&recent)?;
+        File::create(&old)?;
+        let two_hours_ago =
+            filetime::FileTime::from_system_time(SystemTime::now() - Duration::from_secs(2 * 3600));
+        filetime::set_file_times(&old, two_hours_ago, two_hours_ago)?;
+
+        let now = SystemTime::now();
+        let accessed = TimeWindowFilter {
+            accessed_within: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        assert!(accessed.matches(&fs::metadata(&recent)?, now)?);
+        assert!(!accessed.matches(&fs::metadata(&old)?, now)?);
+
+        let modified = TimeWindowFilter {
+            modified_within: Some(Duration::from_secs(3 * 3600)),
+            ..Default::default()
+        };
+        assert!(modified.matches(&fs::metadata(&old)?, now)?);
+        assert!(TimeWindowFilter::default().matches(&fs::metadata(&old)?, now)?);
+
+        let times = TimeDisplay::default();
+        let line = StatRecord::new(&recent, &fs::metadata(&recent)?, &times, false)?.text();
+        assert!(line.starts_with(&recent.display().to_string()));
+        assert!(line.contains("size 0"));
+        assert!(!line.contains("streams"));
+
+        let mut record = StatRecord::new(&recent, &fs::metadata(&recent)?, &times, false)?;
+        record.streams.push(StreamRecord {
+            name: "author".to_string(),
+            size: "6 B".to_string(),
+        });
+        assert!(record.text().ends_with("  streams author (6 B)"));
+        assert!(render_row(&record, OutputFormat::Json)
+            .contains(r#""streams":[{"name":"author","size":"6 B"}]"#));
+        Ok(())
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_find_and_dedupe_duplicates() -> Result<()> {
+        let dir = tempdir()?;
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        let c = dir.path().join("c.txt");
+        let d = dir.path().join("d.txt");
+        fs::write(&a, "same")?;
+        fs::write(&b, "same")?;
+        fs::write(&c, "diff")?;
+        fs::write(&d, "same")?;
+
+        let groups = find_duplicates(&[a.clone(), b.clone(), c.clone(), d.clone()])?;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].paths, vec![a.clone(), b.clone(), d.clone()]);
+        assert_eq!(groups[0].size, 4);
+        assert_eq!(groups[0].hash, hash_file(&a)?);
+
+        dedupe_group(&groups[0].paths, DedupeMode::Hardlink, false)?;
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&a)?.ino(), fs::metadata(&b)?.ino());
+        assert_eq!(fs::read_to_string(&d)?, "same");
+
+        // Once hard-linked, the files are no longer reported as duplicates
+        assert!(find_duplicates(&[a, b, c, d])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclusive_lock() -> Result<()> {
+        let dir = tempdir()?;
+        let lock = ExclusiveLock::acquire(dir.path(), "nightly", None, false)?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("nightly.lock"))?.trim(),
+            std::process::id().to_string()
+        );
+
+        assert!(ExclusiveLock::acquire(dir.path(), "nightly", None, false).is_err());
+        let deadline = Instant::now() + Duration::from_millis(150);
+        assert!(
+            ExclusiveLock::acquire(dir.path(), "nightly", Some(Some(deadline)), false).is_err()
+        );
+        ExclusiveLock::acquire(dir.path(), "hourly", None, false)?;
+
+        drop(lock);
+        ExclusiveLock::acquire(dir.path(), "nightly", None, false)?;
+        assert!(ExclusiveLock::acquire(dir.path(), "../escape", None, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_log_entry_and_rotate() -> Result<()> {
+        let dir = tempdir()?;
+        let log = dir.path().join("deploy.log");
+        let op = FileOp {
+            log_entry: Some("deploy finished".to_string()),
+            log_format: "%Y".to_string(),
+            log_keep: 2,
+            ..Default::default()
+        };
+        let year = Utc::now().format("%Y").to_string();
+
+        append_log_entry(&log, "deploy started", &op)?;
+        append_log_entry(&log, "deploy finished", &op)?;
+        assert_eq!(
+            fs::read_to_string(&log)?,
+            format!("[{0}] deploy started\n[{0}] deploy finished\n", year)
+        );
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&log)?.permissions().mode() & 0o777, 0o640);
+
+        // Every entry is over 10 bytes, so each further entry rotates first
+        let op = FileOp {
+            log_rotate: Some("10".to_string()),
+            ..op
+        };
+        append_log_entry(&log, "one", &op)?;
+        append_log_entry(&log, "two", &op)?;
+        append_log_entry(&log, "three", &op)?;
+        assert_eq!(fs::read_to_string(&log)?, format!("[{}] three\n", year));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("deploy.log.1"))?,
+            format!("[{}] two\n", year)
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("deploy.log.2"))?,
+            format!("[{}] one\n", year)
+        );
+        assert!(!dir.path().join("deploy.log.3").exists());
+
+        let bad_format = FileOp {
+            log_format: "%Q".to_string(),
+            ..op
+        };
+        assert!(append_log_entry(&log, "bad", &bad_format).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_journald_entry() {
+        let entry = journald_entry("created file: a.txt", "created file", Path::new("a.txt"));
+        let entry = String::from_utf8(entry).unwrap();
+        assert!(entry.starts_with("MESSAGE=created file: a.txt\n"));
+        assert!(entry.contains("SYSLOG_IDENTIFIER=tap\n"));
+        assert!(entry.ends_with("TAP_PATH=a.txt\n"));
+
+        let entry = journald_entry("odd", "created file", Path::new("line\nbreak"));
+        let mut expected = b"TAP_PATH\n".to_vec();
+        expected.extend_from_slice(&10u64.to_le_bytes());
+        expected.extend_from_slice(b"line\nbreak\n");
+        assert!(entry.ends_with(&expected));
+    }
+
+    #[cfg(unix)]
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_audit_report() -> Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        let created = dir.path().join("created.txt");
+        let report_path = dir.path().join("report.json");
+        fs::write(&existing, "old")?;
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o600))?;
+
+        let op = FileOp {
+            write: Some("new".to_string()),
+            chmod: Some("644".to_string()),
+            audit_report: Some(report_path.clone()),
+            ..Default::default()
+        };
+        let paths = [
+            existing.to_string_lossy().to_string(),
+            created.to_string_lossy().to_string(),
+        ];
+        run(&op, &paths)?;
+
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+        let entries = report["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["before"]["mode"], "0600");
+        assert_eq!(entries[0]["after"]["mode"], "0644");
+        assert_ne!(
+            entries[0]["before"]["sha256"],
+            entries[0]["after"]["sha256"]
+        );
+        assert!(entries[1]["before"].is_null());
+        assert_eq!(entries[1]["after"]["kind"], "file");
+        assert_eq!(entries[1]["after"]["size"], 3);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_with_reader() -> Result<()> {
+        use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+
+        let dir = tempdir()?;
+        let fifo = dir.path().join("pipes/events");
+        create_fifo(&fifo, false)?;
+        assert!(fs::symlink_metadata(&fifo)?.file_type().is_fifo());
+        create_fifo(&fifo, false)?;
+
+        let deadline = Instant::now() + Duration::from_millis(150);
+        assert!(wait_for_fifo_reader(&fifo, Some(deadline), false).is_err());
+
+        let reader_path = fifo.clone();
+        let reader = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let file = OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&reader_path);
+            thread::sleep(Duration::from_millis(300));
+            file.map(drop)
+        });
+        wait_for_fifo_reader(&fifo, Some(Instant::now() + Duration::from_secs(5)), false)?;
+        reader.join().unwrap()?;
+
+        let regular = dir.path().join("regular");
+        File::create(&regular)?;
+        assert!(create_fifo(&regular, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_timestamp_with_nanoseconds() -> Result<()> {
+        let base = parse_timestamp("2023-05-01 12:00:00")?;
+        assert_eq!(
+            parse_timestamp("2023-05-01 12:00:00.123456789")?,
+            base + Duration::from_nanos(123_456_789)
+        );
+        assert_eq!(
+            parse_timestamp("2023-05-01 12:00:00.5")?,
+            base + Duration::from_millis(500)
+        );
+        assert_eq!(
+            parse_timestamp("1969-12-31 23:59:59")?,
+            SystemTime::UNIX_EPOCH - Duration::from_secs(1)
+        );
+        assert!(parse_timestamp("2023-05-01 12:00:00.").is_err());
+
+        let file = NamedTempFile::new()?;
+        set_timestamp(
+            file.path(),
+            "2023-05-01 12:00:00.123456789",
+            TimeSelection::Both,
+            None,
+        )?;
+        assert_eq!(
+            fs::metadata(file.path())?.modified()?,
+            base + Duration::from_nanos(123_456_789)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_mtime_after() -> Result<()> {
+        let dir = tempdir()?;
+        let reference = dir.path().join("reference.o");
+        let target = dir.path().join("target.c");
+        File::create(&reference)?;
+        File::create(&target)?;
+        set_timestamp(
+            &reference,
+            "2023-05-01 12:00:00.5",
+            TimeSelection::Both,
+            None,
+        )?;
+
+        set_mtime_after(&target, &reference, false)?;
+        let reference_time = fs::metadata(&reference)?.modified()?;
+        let target_time = fs::metadata(&target)?.modified()?;
+        assert!(target_time > reference_time);
+        assert!(target_time.duration_since(reference_time)? <= Duration::from_secs(2));
+
+        assert!(set_mtime_after(&target, &dir.path().join("missing"), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_clock_probe() -> Result<()> {
+        let dir = tempdir()?;
+        // A local filesystem shares our clock, so the measured offset is tiny
+        let (offset, _) = probe_server_clock(dir.path())?;
+        assert!(offset < Duration::from_secs(1));
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+
+        let file_path = dir.path().join("file.txt");
+        let now = current_time_for(&file_path, true);
+        let drift = now
+            .duration_since(SystemTime::now())
+            .unwrap_or_else(|e| e.duration());
+        assert!(drift < Duration::from_secs(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_cache_follows_edits() -> Result<()> {
+        let dir = tempdir()?;
+        let template = dir.path().join("header.tpl");
+        fs::write(&template, "// generated\n")?;
+        let op = FileOp {
+            template: Some(template.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        create_or_update_file(&dir.path().join("a.rs"), &op)?;
+        fs::write(&template, "// edited between runs\n")?;
+        create_or_update_file(&dir.path().join("b.rs"), &op.clone())?;
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("b.rs"))?,
+            "// edited between runs\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "template-engine")]
+    #[test]
+    fn test_rendered_templates_are_reused_only_across_targets() -> Result<()> {
+        let dir = tempdir()?;
+        let template = dir.path().join("mod.tpl");
+        fs::write(&template, "{{ name }} {{ target.stem }}\n")?;
+        let shared = dir.path().join("shared.tpl");
+        fs::write(&shared, "{{ name | upper }}\n")?;
+        let op = TapBuilder::new().template_var("name", "x").build();
+        let with_template = |template: &Path| FileOp {
+            template: Some(template.to_string_lossy().to_string()),
+            ..op.clone()
+        };
+
+        for name in ["a.rs", "b.rs"] {
+            create_or_update_file(&dir.path().join(name), &with_template(&template))?;
+        }
+        assert_eq!(fs::read_to_string(dir.path().join("b.rs"))?, "x b\n");
+
+        create_or_update_file(&dir.path().join("c.rs"), &with_template(&shared))?;
+        let renamed = FileOp {
+            template_vars: vec![("name".to_string(), "y".to_string())],
+            ..with_template(&shared)
+        };
+        create_or_update_file(&dir.path().join("d.rs"), &renamed)?;
+        assert_eq!(fs::read_to_string(dir.path().join("c.rs"))?, "X\n");
+        assert_eq!(fs::read_to_string(dir.path().join("d.rs"))?, "Y\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tap_builder() -> Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("nested/greeting.txt");
+
+        let op = TapBuilder::new()
+            .write("Hello, World!")
+            .chmod("600")
+            .timestamp("2023-05-01 12:00:00")
+            .build();
+        op.apply(&file_path)?;
+
+        assert_eq!(fs::read_to_string(&file_path)?, "Hello, World!");
+        let metadata = fs::metadata(&file_path)?;
+        #[cfg(unix)]
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            metadata.modified()?,
+            parse_timestamp("2023-05-01 12:00:00")?
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "template-engine")]
+    #[test]
+    fn test_inline_templates_are_rendered() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("parser.rs");
+        TapBuilder::new()
+            .template_text("//! {{ target.stem | title }}\n")
+            .build()
+            .apply(&file)?;
+        assert_eq!(fs::read_to_string(&file)?, "//! Parser\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_templates() -> Result<()> {
+        let dir = tempdir()?;
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(templates.join("rust"))?;
+        fs::write(templates.join("rust-module.rs"), "//! Module\n")?;
+        fs::write(templates.join("rust/test.rs"), "#[test]\n")?;
+        assert_eq!(
+            list_templates(&templates)?,
+            [
+                PathBuf::from("rust/test.rs"),
+                PathBuf::from("rust-module.rs")
+            ]
+        );
+
+        let op = FileOp {
+            templates_dir: Some(templates.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_template(&op, "rust-module"),
+            templates.join("rust-module.rs")
+        );
+        assert_eq!(
+            resolve_template(&op, "rust/test"),
+            templates.join("rust/test.rs")
+        );
+        assert_eq!(resolve_template(&op, "missing"), PathBuf::from("missing"));
+        // An existing file is used as is
+        let literal = dir.path().join("local.tpl");
+        fs::write(&literal, "")?;
+        let literal = literal.to_string_lossy().into_owned();
+        assert_eq!(resolve_template(&op, &literal), PathBuf::from(&literal));
+        let url = "https://example.com/rust-module";
+        assert_eq!(resolve_template(&op, url), PathBuf::from(url));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_braces() -> Result<()> {
+        assert_eq!(
+            expand_braces("src/{lib,main,utils}.rs")?,
+            ["src/lib.rs", "src/main.rs", "src/utils.rs"]
+        );
+        assert_eq!(expand_braces("{a,b}/{x,y}")?, ["a/x", "a/y", "b/x", "b/y"]);
+        assert_eq!(expand_braces("a{b,c{d,e}}f")?, ["abf", "acdf", "acef"]);
+        assert_eq!(expand_braces("{a{b,c}}")?, ["{ab}", "{ac}"]);
+        assert_eq!(expand_braces("x{,.bak}")?, ["x", "x.bak"]);
+        assert_eq!(expand_braces("{single}")?, ["{single}"]);
+        assert_eq!(expand_braces("open{a,b")?, ["open{a,b"]);
+        assert_eq!(
+            expand_braces("file{1..3}.txt")?,
+            ["file1.txt", "file2.txt", "file3.txt"]
+        );
+        assert_eq!(
+            expand_braces("log-{008..010}")?,
+            ["log-008", "log-009", "log-010"]
+        );
+        assert_eq!(expand_braces("{3..1}")?, ["3", "2", "1"]);
+        assert_eq!(expand_braces("{0..10..5}")?, ["0", "5", "10"]);
+        assert_eq!(expand_braces("{-1..01}")?, ["-1", "00", "01"]);
+        assert_eq!(
+            expand_braces("part-{a..c}")?,
+            ["part-a", "part-b", "part-c"]
+        );
+        assert_eq!(expand_braces("{a..c}{1..2}")?.len(), 6);
+        assert_eq!(expand_braces("{1..b}")?, ["{1..b}"]);
+        assert_eq!(expand_braces("{aa..c}")?, ["{aa..c}"]);
+        let extremes = format!("{{{}..{}..{}}}", i64::MAX - 1, i64::MAX, i64::MIN);
+        assert_eq!(expand_braces(&extremes)?, [(i64::MAX - 1).to_string()]);
+        assert_eq!(expand_braces("{1..1000000}")?.len(), MAX_BRACE_EXPANSION);
+        assert!(expand_braces("{1..100000000}").is_err());
+        assert!(expand_braces(&format!("{{{}..{}}}", i64::MIN, i64::MAX)).is_err());
+        assert!(expand_braces("{1..1000}{1..1000}{1..2}").is_err());
+
+        let dir = tempdir()?;
+        let pattern = dir
+            .path()
+            .join("{one,two}.txt")
+            .to_string_lossy()
+            .into_owned();
+        let expanded = expand_paths(&[pattern])?;
+        assert_eq!(
+            expanded,
+            [dir.path().join("one.txt"), dir.path().join("two.txt")]
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_run_attempts_every_path() -> Result<()> {
+        let dir = tempdir()?;
+        let blocker = dir.path().join("blocker");
+        fs::write(&blocker, "")?;
+        let paths = ["a.txt", "blocker/x.txt", "b.txt", "blocker/y.txt", "c.txt"]
+            .map(|name| dir.path().join(name).to_string_lossy().into_owned());
+
+        let op = TapBuilder::new().write("hi").jobs(4).build();
+        let error = op.run(&paths).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to process 2 of 5 paths");
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            assert_eq!(fs::read_to_string(dir.path().join(name))?, "hi");
+        }
+
+        let ((), output) = capture_output(|| say!("Processing: {}", 1));
+        assert_eq!(output, "Processing: 1\n");
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_run_reports_in_path_order() -> Result<()> {
+        let dir = tempdir()?;
+        let names = (0..REORDER_WINDOW * 2 + 7)
+            .map(|i| dir.path().join(format!("{:04}.txt", i)))
+            .collect::<Vec<_>>();
+        let paths = names
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let list = dir.path().join("changed.txt");
+
+        TapBuilder::new()
+            .jobs(4)
+            .changed_list(&list)
+            .build()
+            .run(&paths)?;
+        let expected = names
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect::<String>();
+        assert_eq!(fs::read_to_string(&list)?, expected);
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_run_stops_when_output_fails() {
+        /// Output that takes `left` lines and then fails, like a pipe whose
+        /// reader has quit.
+        struct Closing {
+            left: usize,
+        }
+
+        impl Write for Closing {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.left == 0 {
+                    return Err(io::ErrorKind::BrokenPipe.into());
+                }
+                self.left -= 1;
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let total = REORDER_WINDOW * 4;
+        let paths = (0..total).map(|i| (PathBuf::from(format!("{:04}.txt", i)), None));
+        let processed = std::sync::atomic::AtomicUsize::new(0);
+        let process = |path: &Path, _: Option<&UserAccount>| {
+            processed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            say!("Processing: {}", path.display());
+            Ok(false)
+        };
+        let mut changed = Vec::new();
+        let error = process_in_parallel(
+            paths,
+            4,
+            process,
+            None,
+            &mut changed,
+            &mut Closing { left: 5 },
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Failed to write output");
+        assert!(processed.into_inner() < total);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_json_output_records_each_path() -> Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        fs::write(&existing, "")?;
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o644))?;
+        let created = dir.path().join("new.txt");
+        let paths = [&existing, &created].map(|path| path.to_string_lossy().into_owned());
+
+        let mut op = TapBuilder::new().chmod("600").verbose(true).build();
+        op.output = OutputFormat::Json;
+        let (result, output) = capture_output(|| op.run(&paths));
+        result?;
+        let records = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<Vec<serde_json::Value>>>()?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["action"], "updated");
+        assert_eq!(records[0]["mode_before"], "0644");
+        assert_eq!(records[0]["mode_after"], "0600");
+        assert_eq!(records[1]["action"], "created");
+        assert_eq!(records[1]["path"], created.to_string_lossy().as_ref());
+        assert!(records[1]["mtime_before"].is_null());
+        assert!(records[1]["error"].is_null());
+        // RFC 3339 unless a display format is given
+        let mtime = |record: &serde_json::Value| {
+            let time = record["mtime_after"].as_str().unwrap();
+            DateTime::parse_from_rfc3339(time).map(SystemTime::from)
+        };
+        assert_eq!(mtime(&records[1])?, fs::metadata(&created)?.modified()?);
+
+        let mut op = TapBuilder::new().timestamp("2023-05-01 12:00:00").build();
+        op.output = OutputFormat::Json;
+        op.time_format = Some("%d.%m.%Y".to_string());
+        let (result, output) = capture_output(|| op.run(&paths[..1]));
+        result?;
+        let record: serde_json::Value = serde_json::from_str(output.trim_end())?;
+        assert_eq!(record["mtime_after"], "01.05.2023");
+        Ok(())
+    }
+
+    #[test]
+    fn test_verbose_timestamp_shows_time_set() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let mut op = TapBuilder::new()
+            .timestamp("2023-05-01 12:00:00")
+            .verbose(true)
+            .build();
+        op.time_format = Some("%d.%m.%Y".to_string());
+        let (result, output) = capture_output(|| op.apply(file.path()));
+        result?;
+        assert!(
+            output.contains(&format!(
+                "Timestamp set to 01.05.2023 for: {}",
+                file.path().display()
+            )),
+            "{output}"
+        );
+
+        op.time_format = Some("%Q".to_string());
+        assert!(op
+            .run(&[file.path().to_string_lossy().into_owned()])
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlapping_patterns_process_each_file_once() -> Result<()> {
+        let dir = tempdir()?;
+        let log = dir.path().join("a.log");
+        fs::write(&log, "")?;
+        let paths = [
+            log.to_string_lossy().into_owned(),
+            dir.path().join("*.log").to_string_lossy().into_owned(),
+            dir.path()
+                .join(".")
+                .join("a.log")
+                .to_string_lossy()
+                .into_owned(),
+            dir.path().join("new{,}.log").to_string_lossy().into_owned(),
+        ];
+        assert_eq!(
+            expand_paths(&paths)?,
+            [log.clone(), dir.path().join("new.log")]
+        );
+
+        TapBuilder::new().write("x").append().build().run(&paths)?;
+        assert_eq!(fs::read_to_string(&log)?, "x");
+
+        let mut op = TapBuilder::new().write("x").append().build();
+        op.keep_duplicates = true;
+        op.run(&paths)?;
+        assert_eq!(fs::read_to_string(&log)?, "xxxx");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_plans_without_touching() -> Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("notes.txt");
+        fs::write(&existing, "four")?;
+        #[cfg(unix)]
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o644))?;
+        let new = dir.path().join("sub/new.txt");
+
+        let op = TapBuilder::new().write("hello").chmod("600").build();
+        assert_eq!(
+            plan_actions(&existing, &op, None)?,
+            [
+                "truncate 4 B of existing content",
+                "write 5 B",
+                "change mode 0644 -> 0600"
+            ]
+        );
+        assert_eq!(
+            plan_actions(&new, &op, None)?,
+            [
+                format!(
+                    "create parent directory {}",
+                    dir.path().join("sub").display()
+                ),
+                "create file".to_string(),
+                "write 5 B".to_string(),
+                "set mode 0600".to_string(),
+            ]
+        );
+
+        let mut op = op;
+        op.dry_run = true;
+        op.run(&[
+            existing.to_string_lossy().into_owned(),
+            new.to_string_lossy().into_owned(),
+        ])?;
+        assert_eq!(fs::read_to_string(&existing)?, "four");
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(&existing)?.permissions().mode() & 0o777, 0o644);
+        assert!(!dir.path().join("sub").exists());
+
+        let untouched = TapBuilder::new().chmod("644").build();
+        assert!(plan_actions(&existing, &untouched, None)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_touch_updates_times_without_opening() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("readonly.txt");
+        fs::write(&file, "keep")?;
+        let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let old = filetime::FileTime::from_system_time(past);
+        filetime::set_file_times(&file, old, old)?;
+        #[cfg(unix)]
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o444))?;
+
+        TapBuilder::new()
+            .build()
+            .run(&[file.to_string_lossy().into_owned()])?;
+        let metadata = fs::metadata(&file)?;
+        assert!(filetime::FileTime::from_last_modification_time(&metadata) > old);
+        assert!(filetime::FileTime::from_last_access_time(&metadata) > old);
+        assert_eq!(fs::read_to_string(&file)?, "keep");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reference_copies_both_times() -> Result<()> {
+        let dir = tempdir()?;
+        let reference = dir.path().join("reference");
+        fs::write(&reference, "")?;
+        let atime = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        let mtime = filetime::FileTime::from_unix_time(1_500_000_000, 500);
+        filetime::set_file_times(&reference, atime, mtime)?;
+
+        let target = dir.path().join("target.txt");
+        let op = TapBuilder::new().reference(&reference).build();
+        op.apply(&target)?;
+        let metadata = fs::metadata(&target)?;
+        assert_eq!(filetime::FileTime::from_last_access_time(&metadata), atime);
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&metadata),
+            mtime
+        );
+
+        let missing = TapBuilder::new().reference(dir.path().join("nope")).build();
+        let error = missing.apply(&target).unwrap_err();
+        assert!(format!("{:#}", error).contains("Reference file not found"));
+
+        // Nothing is created when the reference is missing
+        let new = dir.path().join("new/file.txt");
+        assert!(missing.apply(&new).is_err());
+        assert!(!dir.path().join("new").exists());
+        let after = FileOp {
+            after: Some(dir.path().join("nope")),
+            ..FileOp::default()
+        };
+        assert!(after.apply(&new).is_err());
+        assert!(!dir.path().join("new").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_timestamps() -> Result<()> {
+        use chrono::TimeZone;
+
+        let now = Local.with_ymd_and_hms(2024, 3, 10, 9, 30, 0).unwrap();
+        let at = |input: &str| relative_timestamp(input, now);
+        let offset =
+            |seconds: i64| Some(SystemTime::from(now + chrono::Duration::seconds(seconds)));
+        assert_eq!(at("now"), offset(0));
+        assert_eq!(at("+30m"), offset(30 * 60));
+        assert_eq!(at("-2h"), offset(-2 * 3600));
+        assert_eq!(at("2 hours ago"), offset(-2 * 3600));
+        assert_eq!(at("1 week ago"), offset(-7 * 86400));
+        assert_eq!(at("in 3 days"), offset(3 * 86400));
+        assert_eq!(at("Yesterday"), offset(-86400));
+        let yesterday = Local.with_ymd_and_hms(2024, 3, 9, 14, 0, 0).unwrap();
+        assert_eq!(at("yesterday 14:00"), Some(yesterday.into()));
+        let tomorrow = Local.with_ymd_and_hms(2024, 3, 11, 8, 15, 30).unwrap();
+        assert_eq!(at("tomorrow 08:15:30"), Some(tomorrow.into()));
+
+        for invalid in [
+            "",
+            "soon",
+            "2 fortnights ago",
+            "+2y",
+            "yesterday noon",
+            "in two days",
+        ] {
+            assert_eq!(at(invalid), None, "{}", invalid);
+        }
+        assert!(parse_timestamp("2 hours ago")? < SystemTime::now());
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_selection() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        let past = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let old = filetime::FileTime::from_system_time(past);
+        let new = parse_timestamp("2023-05-01 12:00:00")?;
+        let times = |path: &Path| -> Result<(SystemTime, SystemTime)> {
+            let metadata = fs::metadata(path)?;
+            Ok((metadata.accessed()?, metadata.modified()?))
+        };
+
+        filetime::set_file_times(file.path(), old, old)?;
+        set_timestamp(
+            file.path(),
+            "2023-05-01 12:00:00",
+            TimeSelection::Access,
+            None,
+        )?;
+        assert_eq!(times(file.path())?, (new, past));
+
+        filetime::set_file_times(file.path(), old, old)?;
+        set_timestamp(
+            file.path(),
+            "2023-05-01 12:00:00",
+            TimeSelection::Modification,
+            None,
+        )?;
+        assert_eq!(times(file.path())?, (past, new));
+
+        filetime::set_file_times(file.path(), old, old)?;
+        set_timestamp(
+            file.path(),
+            "2023-05-01 12:00:00",
+            TimeSelection::Both,
+            None,
+        )?;
+        assert_eq!(times(file.path())?, (new, new));
+
+        assert_eq!(TimeSelection::from_flags(true, true), TimeSelection::Both);
+        assert_eq!(
+            TimeSelection::from_flags(false, true),
+            TimeSelection::Modification
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_paths() {
+        let read = |input: &[u8], null| read_paths(input, null).collect::<Vec<_>>();
+        assert_eq!(
+            read(b"a.txt b.txt\n\n  c\td\n*.rs", false),
+            ["a.txt", "b.txt", "c", "d", "*.rs"].map(PathBuf::from)
+        );
+        assert_eq!(
+            read(b"with space.txt\0line\nbreak\0\0", true),
+            ["with space.txt", "line\nbreak"].map(PathBuf::from)
+        );
+        assert!(read(b" \n\t", false).is_empty());
+    }
+
+    #[test]
+    fn test_no_create_skips_missing_paths() -> Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        let missing = dir.path().join("missing/new.txt");
+        fs::write(&existing, "")?;
+        let paths = [&existing, &missing].map(|path| path.to_string_lossy().into_owned());
+
+        let op = TapBuilder::new()
+            .no_create()
+            .chmod("600")
+            .timestamp("2023-05-01 12:00:00")
+            .build();
+        op.run(&paths)?;
+
+        assert!(!dir.path().join("missing").exists());
+        let metadata = fs::metadata(&existing)?;
+        #[cfg(unix)]
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            metadata.modified()?,
+            parse_timestamp("2023-05-01 12:00:00")?
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "template-engine")]
+    #[test]
+    fn test_template_variables_are_rendered() -> Result<()> {
+        let dir = tempdir()?;
+        let template = dir.path().join("module.tpl");
+        fs::write(
+            &template,
+            "//! {{ name | title }}\npub struct {{ name | pascal }};\n",
+        )?;
+        let target = dir.path().join("out.rs");
+
+        TapBuilder::new()
+            .template(template.to_string_lossy())
+            .template_var("name", "user-account")
+            .build()
+            .apply(&target)?;
+        assert_eq!(
+            fs::read_to_string(&target)?,
+            "//! User Account\npub struct UserAccount;\n"
+        );
+
+        // Without the variable the braces aren't tap's to fill in
+        TapBuilder::new()
+            .template(template.to_string_lossy())
+            .build()
+            .apply(&target)?;
+        assert_eq!(
+            fs::read_to_string(&target)?,
+            "//! {{ name | title }}\npub struct {{ name | pascal }};\n"
+        );
+
+        // A failing expression leaves the file as it was
+        let failing = dir.path().join("failing.tpl");
+        fs::write(&failing, "{{ env(\"TAP_TEMPLATE_UNSET\") }}")?;
+        let failed = TapBuilder::new()
+            .template(failing.to_string_lossy())
+            .build()
+            .apply(&target);
+        assert!(failed.is_err());
+        assert!(fs::read_to_string(&target)?.contains("{{ name | title }}"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_guards() -> Result<()> {
+        let dir = tempdir()?;
+        let header = dir.path().join("new.h");
+        let guarded = |guard: HeaderGuard| TapBuilder::new().header_guard(guard);
+
+        guarded(HeaderGuard::Macro(Some("myproj".to_string())))
+            .write("int parse(void);\n")
+            .build()
+            .apply(&header)?;
+        let guard = template::include_guard(
+            &template::relative_path(&header).to_string_lossy(),
+            Some("myproj"),
+        );
+        assert!(guard.starts_with("MYPROJ_") && guard.ends_with("_NEW_H_"));
+        assert_eq!(
+            fs::read_to_string(&header)?,
+            format!(
+                "#ifndef {0}\n#define {0}\n\nint parse(void);\n\n#endif  // {0}\n",
+                guard
+            )
+        );
+
+        // An existing header without new content is only touched
+        guarded(HeaderGuard::PragmaOnce).build().apply(&header)?;
+        assert!(fs::read_to_string(&header)?.starts_with("#ifndef"));
+
+        let empty = dir.path().join("empty.hpp");
+        guarded(HeaderGuard::PragmaOnce).build().apply(&empty)?;
+        assert_eq!(fs::read_to_string(&empty)?, "#pragma once\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_owner_and_group() -> Result<()> {
+        assert_eq!(group_id("0")?, 0);
+        assert!(group_id("no-such-group-tap").is_err());
+        let dir = tempdir()?;
+        let tree = dir.path().join("tree");
+        fs::create_dir_all(tree.join("sub"))?;
+        fs::write(tree.join("sub/file"), "")?;
+
+        if unsafe { libc::geteuid() } != 0 {
+            // Giving files away needs root, so this only warns
+            let op = TapBuilder::new().owner("0").build();
+            return op.apply(&tree.join("sub/file"));
+        }
+        let op = TapBuilder::new()
+            .dir()
+            .owner("65534")
+            .group("65533")
+            .recursive()
+            .build();
+        op.apply(&tree)?;
+        for path in [&tree, &tree.join("sub"), &tree.join("sub/file")] {
+            let metadata = fs::symlink_metadata(path)?;
+            assert_eq!((metadata.uid(), metadata.gid()), (65534, 65533));
+        }
+
+        TapBuilder::new().group("0").build().apply(&tree)?;
+        let metadata = fs::symlink_metadata(&tree)?;
+        assert_eq!((metadata.uid(), metadata.gid()), (65534, 0));
+        assert_eq!(fs::symlink_metadata(tree.join("sub"))?.gid(), 65533);
+        Ok(())
+    }
+
+    #[cfg(feature = "template-engine")]
+    #[test]
+    fn test_append_once_per_target() -> Result<()> {
+        let dir = tempdir()?;
+        let template = dir.path().join("footer.tpl");
+        fs::write(
+            &template,
+            "Maintained by {{ owner }} ({{ target.parent | kebab }})\n",
+        )?;
+        let packages = ["alpha", "beta", "gamma"].map(|name| dir.path().join(name).join("README"));
+        for package in &packages {
+            fs::create_dir_all(package.parent().unwrap())?;
+            fs::write(package, "# Package")?;
+        }
+        let op = TapBuilder::new()
+            .template(template.to_string_lossy())
+            .template_var("owner", "infra")
+            .append()
+            .once()
+            .build();
+
+        op.apply(&packages[0])?;
+        let paths = packages
+            .each_ref()
+            .map(|path| path.to_string_lossy().into_owned());
+        let (result, output) = capture_output(|| op.run(&paths));
+        result?;
+        assert_eq!(output.lines().count(), 2, "{}", output);
+        assert!(!output.contains(&paths[0]));
+
+        let footer = |package: &Path| -> Result<String> { Ok(fs::read_to_string(package)?) };
+        let beta = footer(&packages[1])?;
+        assert!(beta.starts_with("# Package\nMaintained by infra ("));
+        assert!(beta.ends_with("beta)\n"));
+        assert_eq!(footer(&packages[0])?.matches("Maintained").count(), 1);
+
+        assert!(contains_lines(b"a\nb\nc", b"b\nc\n"));
+        assert!(!contains_lines(b"a\nbc\n", b"b"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_from() -> Result<()> {
+        let dir = tempdir()?;
+        let config = dir.path().join("app.conf");
+        let paths = [config.to_string_lossy().into_owned()];
+        let ensure = |status_from| {
+            TapBuilder::new()
+                .write("debug = false\n")
+                .append()
+                .once()
+                .status_from(status_from)
+                .build()
+        };
+
+        let error = ensure(StatusFrom::Changes).run(&paths).unwrap_err();
+        assert_eq!(error.to_string(), "Changed 1 path(s)");
+        ensure(StatusFrom::Changes).run(&paths)?;
+        ensure(StatusFrom::AnyError).run(&paths)?;
+
+        // Writing what is already there changes nothing, though setting a
+        // time does
+        let notes = [dir.path().join("notes.txt").to_string_lossy().into_owned()];
+        let write = |content: &str| {
+            TapBuilder::new()
+                .write(content)
+                .status_from(StatusFrom::Changes)
+                .build()
+                .run(&notes)
+        };
+        assert!(write("same\n").is_err());
+        write("same\n")?;
+        assert!(write("other\n").is_err());
+        let stamp = TapBuilder::new()
+            .timestamp("2020-01-01 00:00:00")
+            .status_from(StatusFrom::Changes)
+            .build();
+        assert!(stamp.run(&notes).is_err());
+        stamp.run(&notes)?;
+
+        // --dry-run counts what it would change
+        let planned = FileOp {
+            dry_run: true,
+            ..ensure(StatusFrom::Changes)
+        };
+        planned.run(&paths)?;
+        let missing = [dir.path().join("new.conf").to_string_lossy().into_owned()];
+        let (result, _) = capture_output(|| planned.run(&missing));
+        assert!(result.is_err());
+        assert!(!dir.path().join("new.conf").exists());
+
+        // Failures are still errors, unless the status comes from nothing
+        let blocked = [config.join("child").to_string_lossy().into_owned()];
+        assert!(ensure(StatusFrom::Changes).run(&blocked).is_err());
+        ensure(StatusFrom::None).run(&blocked)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_from_stdin() -> Result<()> {
+        let dir = tempdir()?;
+        let content = b"\x00\xffnot text\n";
+        *STDIN_CONTENT.lock().unwrap() = Some(Arc::from(&content[..]));
+
+        let files = [dir.path().join("a.bin"), dir.path().join("b.bin")];
+        let op = TapBuilder::new().stdin().build();
+        for file in &files {
+            op.apply(file)?;
+            assert_eq!(fs::read(file)?, content);
+        }
+
+        let once = TapBuilder::new().stdin().append().once().build();
+        let (result, _) = capture_output(|| once.apply(&files[0]));
+        result?;
+        assert_eq!(fs::read(&files[0])?, content);
+
+        let header = dir.path().join("blob.h");
+        let guarded = FileOp {
+            header_guard: Some(HeaderGuard::PragmaOnce),
+            ..op
+        };
+        assert!(guarded.apply(&header).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_list() -> Result<()> {
+        let dir = tempdir()?;
+        let list = dir.path().join("changed.txt");
+        let [kept, edited, added] = ["kept.gitignore", "edited.gitignore", "added.gitignore"]
+            .map(|name| dir.path().join(name));
+        fs::write(&kept, "target/\n")?;
+        fs::write(&edited, "*.o\n")?;
+        let paths = [&kept, &edited, &added].map(|path| path.to_string_lossy().into_owned());
+
+        let op = TapBuilder::new()
+            .write("target/")
+            .append()
+            .once()
+            .changed_list(&list)
+            .build();
+        let (result, _) = capture_output(|| op.run(&paths));
+        result?;
+        assert_eq!(
+            fs::read_to_string(&list)?,
+            format!("{}\n{}\n", edited.display(), added.display())
+        );
+
+        // Nothing left to change
+        op.run(&paths)?;
+        assert_eq!(fs::read_to_string(&list)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepend() -> Result<()> {
+        let dir = tempdir()?;
+        let changelog = dir.path().join("CHANGELOG.md");
+        fs::write(&changelog, "## 1.0.0\n")?;
+        TapBuilder::new()
+            .write("## 1.1.0")
+            .prepend()
+            .build()
+            .apply(&changelog)?;
+        assert_eq!(fs::read_to_string(&changelog)?, "## 1.1.0\n## 1.0.0\n");
+
+        // A header goes below the shebang, once
+        let script = dir.path().join("deploy.sh");
+        fs::write(&script, "#!/bin/sh\necho deploying\n")?;
+        let header = TapBuilder::new()
+            .write("# Copyright ACME\n")
+            .prepend()
+            .once()
+            .build();
+        for _ in 0..2 {
+            let (result, _) = capture_output(|| header.apply(&script));
+            result?;
+        }
+        assert_eq!(
+            fs::read_to_string(&script)?,
+            "#!/bin/sh\n# Copyright ACME\necho deploying\n"
+        );
+
+        // A shebang itself goes first
+        let shebang = TapBuilder::new()
+            .write("#!/usr/bin/env bash\n")
+            .prepend()
+            .build();
+        let bare = dir.path().join("bare.sh");
+        fs::write(&bare, "echo hi\n")?;
+        shebang.apply(&bare)?;
+        assert_eq!(fs::read_to_string(&bare)?, "#!/usr/bin/env bash\necho hi\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_content() -> Result<()> {
+        let dir = tempdir()?;
+        let module = dir.path().join("main.rs");
+        fs::write(&module, "use std::fs;\n\nfn main() {}")?;
+        let import = TapBuilder::new()
+            .write("use std::io;")
+            .insert_after("use std::fs")
+            .once()
+            .build();
+        import.apply(&module)?;
+        let (result, output) = capture_output(|| import.apply(&module));
+        result?;
+        assert_eq!(output, "");
+        assert_eq!(
+            fs::read_to_string(&module)?,
+            "use std::fs;\nuse std::io;\n\nfn main() {}"
+        );
+
+        let registry = dir.path().join("registry.txt");
+        fs::write(&registry, "alpha\ngamma\n")?;
+        TapBuilder::new()
+            .write("beta\n")
+            .at_line(2)
+            .build()
+            .apply(&registry)?;
+        // Just past the last line appends, on a line of its own
+        TapBuilder::new()
+            .write("omega")
+            .at_line(4)
+            .backup(".bak")
+            .build()
+            .apply(&registry)?;
+        assert_eq!(
+            fs::read_to_string(&registry)?,
+            "alpha\nbeta\ngamma\nomega\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("registry.txt.bak"))?,
+            "alpha\nbeta\ngamma\n"
+        );
+        let past_end = TapBuilder::new()
+            .write("x")
+            .at_line(99)
+            .build()
+            .apply(&registry);
+        assert!(format!("{:#}", past_end.unwrap_err()).contains("past the end"));
+
+        let missing = TapBuilder::new()
+            .write("x")
+            .insert_after("[section]")
+            .build()
+            .apply(&registry);
+        assert!(format!("{:#}", missing.unwrap_err()).contains("No line contains '[section]'"));
+        assert!(TapBuilder::new()
+            .write("x")
+            .at_line(0)
+            .build()
+            .apply(&registry)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup() -> Result<()> {
+        let dir = tempdir()?;
+        let config = dir.path().join("app.conf");
+        fs::write(&config, "port = 80\n")?;
+        TapBuilder::new()
+            .write("port = 8080\n")
+            .backup(".bak")
+            .build()
+            .apply(&config)?;
+        assert_eq!(fs::read_to_string(&config)?, "port = 8080\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("app.conf.bak"))?,
+            "port = 80\n"
+        );
+
+        let numbered = TapBuilder::new()
+            .write("port = 9090\n")
+            .backup("numbered")
+            .build();
+        numbered.apply(&config)?;
+        numbered.apply(&config)?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("app.conf.~1~"))?,
+            "port = 8080\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("app.conf.~2~"))?,
+            "port = 9090\n"
+        );
+
+        // Nothing to lose in an empty file
+        let empty = dir.path().join("empty.conf");
+        fs::write(&empty, "")?;
+        numbered.apply(&empty)?;
+        assert!(!dir.path().join("empty.conf.~1~").exists());
+
+        // Trimming rewrites the file too
+        let notes = dir.path().join("notes.txt");
+        fs::write(&notes, "one  \ntwo\t")?;
+        TapBuilder::new()
+            .trim()
+            .backup(".orig")
+            .build()
+            .apply(&notes)?;
+        assert_eq!(fs::read_to_string(&notes)?, "one\ntwo");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.txt.orig"))?,
+            "one  \ntwo\t"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_escape_controls() {
+        assert_eq!(escape_controls("a b\tc\nd"), "a b\tc\nd");
+        assert!(matches!(escape_controls("plain.txt"), Cow::Borrowed(_)));
+        assert_eq!(
+            escape_controls("\x1b[31mred\x1b[0m.txt"),
+            "\\u{1b}[31mred\\u{1b}[0m.txt"
+        );
+        assert_eq!(escape_controls("50%\rdone"), "50%\\u{d}done");
+        assert_eq!(escape_controls("caf\u{e9}"), "caf\u{e9}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link_to() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("real"))?;
+        fs::write(dir.path().join("real/file"), "content")?;
+        let link = dir.path().join("links/current");
+        let link_to = |target: &str, force: bool| {
+            let mut op = TapBuilder::new().link_to(target);
+            if force {
+                op = op.force();
+            }
+            op.build().run(&[link.to_string_lossy().into_owned()])
+        };
+
+        link_to("../real/file", false)?;
+        assert_eq!(fs::read_link(&link)?, Path::new("../real/file"));
+        assert_eq!(fs::read_to_string(&link)?, "content");
+        // The same link again is no change
+        assert!(!create_symlink(
+            &link,
+            Path::new("../real/file"),
+            false,
+            false
+        )?);
+
+        assert!(link_to("../real/other", false).is_err());
+        assert_eq!(fs::read_link(&link)?, Path::new("../real/file"));
+        link_to("../real/other", true)?;
+        assert_eq!(fs::read_link(&link)?, Path::new("../real/other"));
+        assert!(!dir.path().join("links/.current.tap-link").exists());
+
+        fs::remove_file(&link)?;
+        fs::write(&link, "regular")?;
+        assert!(link_to("../real/file", false).is_err());
+        link_to("../real/file", true)?;
+        assert_eq!(fs::read_link(&link)?, Path::new("../real/file"));
+
+        let directory = dir.path().join("real");
+        assert!(create_symlink(&directory, Path::new("elsewhere"), true, false).is_err());
+        assert!(directory.is_dir());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_link_to() -> Result<()> {
+        let dir = tempdir()?;
+        let target = dir.path().join("real");
+        let other = dir.path().join("other");
+        let link = dir.path().join("links/hard");
+        fs::write(&target, "content")?;
+        fs::write(&other, "other")?;
+        let hard_link_to = |target: &Path, force: bool| {
+            let mut op = TapBuilder::new().hard_link_to(target);
+            if force {
+                op = op.force();
+            }
+            op.build().run(&[link.to_string_lossy().into_owned()])
+        };
+
+        hard_link_to(&target, false)?;
+        let id = |path: &Path| fs::metadata(path).map(|metadata| platform::file_id(&metadata));
+        assert_eq!(id(&link)?, id(&target)?);
+        assert!(!create_hard_link(&link, &target, false, false)?);
+
+        assert!(hard_link_to(&other, false).is_err());
+        hard_link_to(&other, true)?;
+        assert_eq!(id(&link)?, id(&other)?);
+        assert_eq!(fs::read_to_string(&target)?, "content");
+
+        assert!(hard_link_to(&dir.path().join("missing"), true).is_err());
+        assert!(hard_link_to(&dir.path().join("links"), true).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_fifo_and_socket_modes() -> Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let dir = tempdir()?;
+        let fifo = dir.path().join("pipes/in");
+        let socket = dir.path().join("app.sock");
+        let run = |path: &Path, op: TapBuilder| {
+            op.chmod("600")
+                .build()
+                .run(&[path.to_string_lossy().into_owned()])
+        };
+
+        run(&fifo, TapBuilder::new().fifo())?;
+        run(&socket, TapBuilder::new().socket())?;
+        for (path, is_kind) in [
+            (&fifo, fs::FileType::is_fifo as fn(&fs::FileType) -> bool),
+            (&socket, fs::FileType::is_socket),
+        ] {
+            let metadata = fs::symlink_metadata(path)?;
+            assert!(is_kind(&metadata.file_type()));
+            assert_eq!(platform::mode(&metadata) & 0o777, 0o600);
+        }
+        // Existing ones are reused, anything else isn't replaced
+        run(&socket, TapBuilder::new().socket())?;
+        assert!(create_socket(&fifo, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shebang() -> Result<()> {
+        let dir = tempdir()?;
+        let script = dir.path().join("bin/run");
+        let run = |op: TapBuilder| op.build().run(&[script.to_string_lossy().into_owned()]);
+
+        run(TapBuilder::new().shebang("bash"))?;
+        assert_eq!(fs::read_to_string(&script)?, "#!/usr/bin/env bash\n");
+        #[cfg(unix)]
+        {
+            let mode = platform::mode(&fs::metadata(&script)?);
+            assert_eq!(mode & 0o111, (mode & 0o444) >> 2);
+            assert_ne!(mode & 0o100, 0);
+        }
+
+        // Content goes below the line, replacing a shebang of its own
+        run(TapBuilder::new()
+            .shebang("/bin/sh")
+            .write("#!/bin/bash\necho hi\n"))?;
+        assert_eq!(fs::read_to_string(&script)?, "#!/bin/sh\necho hi\n");
+
+        // An existing file keeps its content under the new line
+        fs::write(&script, "echo hi\n")?;
+        run(TapBuilder::new().shebang("python3"))?;
+        assert_eq!(
+            fs::read_to_string(&script)?,
+            "#!/usr/bin/env python3\necho hi\n"
+        );
+        let op = TapBuilder::new().shebang("python3").build();
+        assert_eq!(file_content(&script, &op)?, None);
+
+        assert!(run(TapBuilder::new().shebang(" ")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_size() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("fixture.bin");
+        let run = |op: TapBuilder| op.build().run(&[file.to_string_lossy().into_owned()]);
+
+        run(TapBuilder::new().size("64K"))?;
+        assert_eq!(fs::read(&file)?, vec![0; 65536]);
+        assert!(!resize_file(&file, 65536, false, false)?);
+
+        // Content first, then padded or cut to the size
+        run(TapBuilder::new().write("header").size("10"))?;
+        assert_eq!(fs::read(&file)?, b"header\0\0\0\0");
+        run(TapBuilder::new().write("header").size("3"))?;
+        assert_eq!(fs::read(&file)?, b"hea");
+
+        // What a cut loses is backed up first
+        run(TapBuilder::new().size("1").backup(".bak"))?;
+        assert_eq!(fs::read(&file)?, b"h");
+        assert_eq!(fs::read(dir.path().join("fixture.bin.bak"))?, b"hea");
+
+        run(TapBuilder::new().size("1G").sparse())?;
+        assert_eq!(fs::metadata(&file)?.len(), 1 << 30);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(fs::metadata(&file)?.blocks() * 512 < 1 << 20);
+        }
+
+        assert!(run(TapBuilder::new().size("ten")).is_err());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_punch_hole() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("fixture.bin");
+        fs::write(&file, vec![0xAB; 1 << 20])?;
+        TapBuilder::new()
+            .punch_hole("64K,128K")
+            .build()
+            .run(&[file.to_string_lossy().into_owned()])?;
+
+        let content = fs::read(&file)?;
+        assert_eq!(content.len(), 1 << 20);
+        assert!(content[..65536].iter().all(|&b| b == 0xAB));
+        assert!(content[65536..196608].iter().all(|&b| b == 0));
+        assert!(content[196608..].iter().all(|&b| b == 0xAB));
+
+        assert_eq!(parse_range("1M, 4K")?, (1 << 20, 4096));
+        assert!(parse_range("4K").is_err());
+        assert!(parse_range("4K,0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill() -> Result<()> {
+        let dir = tempdir()?;
+        let paths = ["a.bin", "b.bin"].map(|name| dir.path().join(name));
+        let run = |op: TapBuilder| {
+            op.build().run(
+                &paths
+                    .clone()
+                    .map(|path| path.to_string_lossy().into_owned()),
+            )
+        };
+
+        run(TapBuilder::new().fill("random:100K").seed(42))?;
+        let seeded = fs::read(&paths[0])?;
+        assert_eq!(seeded.len(), 102_400);
+        assert_eq!(fs::read(&paths[1])?, seeded);
+        run(TapBuilder::new().fill("random:100K"))?;
+        assert_ne!(fs::read(&paths[0])?, fs::read(&paths[1])?);
+        assert_ne!(fs::read(&paths[0])?, seeded);
+
+        run(TapBuilder::new().fill("pattern:0xFF:3"))?;
+        assert_eq!(fs::read(&paths[1])?, [0xFF; 3]);
+        assert!(run(TapBuilder::new().fill("random:lots")).is_err());
+        assert_eq!(fs::read(&paths[1])?, [0xFF; 3]);
+
+        // A huge size fails before anything is written
+        let error = run(TapBuilder::new().fill("random:99999999999999999")).unwrap_err();
+        assert!(format!("{error:#}").contains("Fill too large"), "{error:#}");
+        assert_eq!(fs::read(&paths[1])?, [0xFF; 3]);
+        #[cfg(unix)]
+        {
+            let error = check_space_for(&paths[0], u64::MAX).unwrap_err();
+            assert!(error.to_string().contains("Not enough space"), "{error}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lorem() -> Result<()> {
+        let dir = tempdir()?;
+        let doc = dir.path().join("docs/draft.md");
+        let run = |op: TapBuilder| op.build().run(&[doc.to_string_lossy().into_owned()]);
+
+        run(TapBuilder::new().lorem("paragraphs:2").seed(7))?;
+        let text = fs::read_to_string(&doc)?;
+        assert!(text.starts_with("Lorem ipsum dolor sit amet"));
+        assert_eq!(text.trim_end().split("\n\n").count(), 2);
+        run(TapBuilder::new().lorem("paragraphs:2").seed(7))?;
+        assert_eq!(fs::read_to_string(&doc)?, text);
+
+        // Placeholder text is content like any other
+        run(TapBuilder::new().lorem("words:5").append())?;
+        assert_eq!(
+            fs::read_to_string(&doc)?,
+            text + "Lorem ipsum dolor sit amet.\n"
+        );
+        assert!(run(TapBuilder::new().lorem("chapters:2")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_tokens() -> Result<()> {
+        let dir = tempdir()?;
+        let paths = ["a.json", "b.json"].map(|name| dir.path().join(name));
+        let args: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        let content = r#"{"id": "{uuid}", "tags": {}}"#;
+        let read = || -> Result<Vec<String>> {
+            paths
+                .iter()
+                .map(|path| Ok(fs::read_to_string(path)?))
+                .collect()
+        };
+
+        TapBuilder::new().write(content).build().run(&args)?;
+        let written = read()?;
+        assert_ne!(written[0], written[1]);
+        for text in &written {
+            assert!(text.starts_with(r#"{"id": ""#));
+            assert!(text.ends_with(r#"", "tags": {}}"#));
+            assert!(!text.contains("{uuid}"));
+        }
+
+        // A seed gives each file its own values, the same on every run
+        let seeded = || {
+            TapBuilder::new()
+                .write("{hex:8}")
+                .seed(3)
+                .build()
+                .run(&args)
+        };
+        seeded()?;
+        let written = read()?;
+        assert_ne!(written[0], written[1]);
+        seeded()?;
+        assert_eq!(read()?, written);
+
+        let bad = TapBuilder::new().write("{now:%Q}").build().run(&args);
+        assert!(bad.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_notify_url() -> Result<()> {
+        use std::net::TcpListener;
+
+        // Answers one request, handing back everything the client sent
+        fn serve(status: &'static str) -> Result<(String, thread::JoinHandle<String>)> {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let url = format!("http://{}/hooks/tap", listener.local_addr()?);
+            let server = thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8(body).unwrap());
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+                request
+            });
+            Ok((url, server))
+        }
+
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        fs::write(&existing, "hello\n")?;
+        let paths = ["existing.txt", "new.txt"]
+            .map(|name| dir.path().join(name).to_string_lossy().into_owned());
+
+        let (url, server) = serve("204 No Content")?;
+        let op = TapBuilder::new()
+            .write("hello")
+            .append()
+            .once()
+            .notify_url(url)
+            .build();
+        let (result, _) = capture_output(|| op.run(&paths));
+        result?;
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("POST /hooks/tap HTTP/1.1\r\n"),
+            "{}",
+            request
+        );
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        let summary: serde_json::Value = serde_json::from_str(body)?;
+        assert_eq!(summary["paths"], 2);
+        assert_eq!(summary["changed"], 1);
+        assert_eq!(summary["dry_run"], false);
+        assert!(summary.get("error").is_none());
+
+        // An unhappy webhook doesn't fail the run
+        let (url, server) = serve("500 Internal Server Error")?;
+        let op = TapBuilder::new().notify_url(url).build();
+        let (result, _) = capture_output(|| op.run(&paths[..1]));
+        result?;
+        server.join().unwrap();
+
+        let op = TapBuilder::new().notify_url("ftp://example.com").build();
+        assert!(op.run(&paths).is_err());
+        Ok(())
+    }
+}
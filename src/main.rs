@@ -1,54 +1,292 @@
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::os::unix::fs::PermissionsExt;
-use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
 
 use anyhow::{Context, Result};
-use chrono::NaiveDateTime;
-use clap::Parser;
-use glob::glob;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgGroup, ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand};
+use tap::i18n::{set_lang, Lang};
+use tap::sandbox::Sandbox;
+use tap::units::{parse_size, show_size};
+use tap::{
+    add_build_target, apply_manifest, clear_flag, create_fifo, deadline_after, dedupe_group,
+    default_templates_dir, expand_paths, find_duplicates, json_schema, list_templates,
+    parse_duration, plain, run_on_change, say, say_err, set_flag, set_permissions, set_plain,
+    shell_init_script, skel_sync, stat_paths, tr, update_dotenv, wait_for_fifo_reader,
+    wait_for_flag, write_conventions, ComparePolicy, Config, DedupeMode, FileOp, HeaderGuard,
+    Language, LogTarget, Manifest, NotifyTarget, OutputFormat, SchemaKind, Shell, Snapshot,
+    StatusFrom, TemplateCache, TestPairing, TimeDisplay, TimeSelection, TimeStyle,
+    TimeWindowFilter, UserAccount, XdgDirs, DEFAULT_LOG_FORMAT, DEFAULT_LOG_KEEP,
+};
+use tap::{serve, signature, temp_files, undo};
 
 #[derive(Parser)]
 #[command(name = "tap")]
 #[command(about = "A next-gen version of touch with extended capabilities", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
+#[command(
+    override_usage = "tap [OPTIONS] <PATHS>...\n       tap [RUN-WIDE OPTIONS] <COMMAND> [ARGS]..."
+)]
+#[command(group(ArgGroup::new("permissions").multiple(true).args(["chmod", "attrib"])))]
 struct Cli {
-    /// File(s) or directory to create or update (supports glob patterns)
-    #[arg(required = true)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// File(s) or directory to create or update (supports glob patterns).
+    /// A path named like a subcommand goes after `--`: `tap -- stat`
+    #[arg(required_unless_present = "xargs")]
     paths: Vec<String>,
 
+    /// Read the paths from stdin instead, like `xargs touch`, processing each
+    /// one as it arrives
+    #[arg(long, conflicts_with_all = ["paths", "each_user"])]
+    xargs: bool,
+
+    /// With --xargs, paths are separated by NUL bytes instead of whitespace
+    /// (for `find -print0` and `fd -0`)
+    #[arg(short = '0', long, requires = "xargs", conflicts_with = "paths")]
+    null: bool,
+
     /// Create a directory instead of a file
     #[arg(short, long)]
     dir: bool,
 
-    /// Set specific permissions (octal format, e.g., 644)
+    /// Create a symlink to TARGET instead of a file (a relative TARGET is
+    /// relative to the link's directory)
+    #[arg(
+        long,
+        value_name = "TARGET",
+        group = "link",
+        conflicts_with_all = ["dir", "write", "stdin", "timestamp", "append", "prepend", "at_line", "insert_after", "template", "trim", "pidfile", "log_entry", "no_touch", "times_only", "chmod", "attrib", "after", "reference"]
+    )]
+    link_to: Option<PathBuf>,
+
+    /// Create a hard link to the existing file TARGET instead of a file (it
+    /// has to be on the same filesystem)
+    #[arg(
+        long,
+        value_name = "TARGET",
+        group = "link",
+        conflicts_with_all = ["dir", "write", "stdin", "timestamp", "append", "prepend", "at_line", "insert_after", "template", "trim", "pidfile", "log_entry", "no_touch", "times_only", "chmod", "attrib", "after", "reference", "owner", "group"]
+    )]
+    hard_link_to: Option<PathBuf>,
+
+    /// Create a FIFO (named pipe) instead of a file
+    #[arg(
+        long,
+        conflicts_with_all = ["dir", "link", "write", "stdin", "append", "prepend", "at_line", "insert_after", "template", "trim", "pidfile", "log_entry"]
+    )]
+    fifo: bool,
+
+    /// Create a Unix socket file instead of a file (nothing listens on it)
+    #[arg(
+        long,
+        conflicts_with_all = ["dir", "link", "fifo", "write", "stdin", "append", "prepend", "at_line", "insert_after", "template", "trim", "pidfile", "log_entry"]
+    )]
+    socket: bool,
+
+    /// Replace an existing file or link with the one --link-to or
+    /// --hard-link-to makes
+    #[arg(long, requires = "link")]
+    force: bool,
+
+    /// Set specific permissions (octal, e.g., 644, or symbolic, e.g., u+x,go-w)
     #[arg(short, long)]
     chmod: Option<String>,
 
-    /// Add content to the file
-    #[arg(short, long)]
+    /// Set or clear Windows file attributes: r (read-only), h (hidden), s
+    /// (system) and a (archive), e.g. +h,+r,-a. Elsewhere only r applies
+    #[arg(long, value_name = "CHANGES", allow_hyphen_values = true)]
+    attrib: Option<String>,
+
+    /// Add content to the file (`-` reads it from standard input). {uuid},
+    /// {hex:N} and {now} or {now:FORMAT} in it are filled in for each file;
+    /// {{uuid}} and the like write the token itself
+    #[arg(short, long, group = "generated")]
     write: Option<String>,
 
-    /// Set access and modification times (format: YYYY-MM-DD HH:MM:SS)
-    #[arg(short, long)]
+    /// Write content read from standard input, which keeps it off the
+    /// command line and may be binary
+    #[arg(long, conflicts_with_all = ["write", "template", "trim", "dir", "xargs"])]
+    stdin: bool,
+
+    /// Set access and modification times (YYYY-MM-DD HH:MM:SS[.fraction], or
+    /// relative: now, +30m, -2h, "2 hours ago", "yesterday 14:00")
+    #[arg(short, long, allow_hyphen_values = true)]
     timestamp: Option<String>,
 
     /// Append content instead of overwriting
-    #[arg(short, long)]
+    #[arg(short, long, group = "placement")]
     append: bool,
 
+    /// Insert content at the top of the file (below a shebang line) instead
+    /// of overwriting
+    #[arg(long, group = "placement")]
+    prepend: bool,
+
+    /// Insert content so that it starts at line N (from 1) instead of
+    /// overwriting
+    #[arg(long, value_name = "N", group = "placement")]
+    at_line: Option<usize>,
+
+    /// Insert content after the first line containing TEXT instead of
+    /// overwriting
+    #[arg(long, value_name = "TEXT", group = "placement")]
+    insert_after: Option<String>,
+
+    /// With --append, --prepend, --at-line or --insert-after, skip files
+    /// that already contain the content, and list the ones changed
+    #[arg(long, requires = "placement")]
+    once: bool,
+
+    /// Before overwriting a non-empty file, copy it to NAME.bak, NAME plus
+    /// SUFFIX, or with `numbered` to the next NAME.~N~
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ".bak",
+        global = true
+    )]
+    backup: Option<String>,
+
+    /// Record the run so `tap undo` can revert it, even with `undo = false`
+    /// in the config
+    #[arg(long, global = true)]
+    undo: bool,
+
+    /// Don't record the run for `tap undo`
+    #[arg(long, global = true, conflicts_with = "undo")]
+    no_undo: bool,
+
     /// Enable verbose output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 
-    /// Apply chmod recursively (only works with directories)
+    /// Apply chmod, --attrib, --owner and --group recursively (only works
+    /// with directories)
     #[arg(short = 'R', long)]
     recursive: bool,
 
-    /// Use a template file for content
+    /// Use a template file for content, or a signed template at an http://
+    /// or https:// URL
     #[arg(long)]
     template: Option<String>,
 
+    /// Take content and mode from the config's [presets.NAME]
+    #[arg(long, value_name = "NAME", global = true)]
+    preset: Option<String>,
+
+    /// Create new files empty even when a [[rules]] entry in the config
+    /// matches their name
+    #[arg(long, global = true)]
+    no_rules: bool,
+
+    /// Set a variable for templates, used as `{{ NAME }}` (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_assignment, global = true)]
+    vars: Vec<(String, String)>,
+
+    /// Let templates run commands with `{{ shell("...") }}`
+    #[arg(long, global = true)]
+    allow_shell: bool,
+
+    /// Use templates fetched from URLs without checking their signature
+    #[arg(long, global = true)]
+    insecure: bool,
+
+    /// Run templates' shell() commands and `tap on-change` commands with an
+    /// empty environment but for PATH and, on Linux, without network access
+    #[arg(long, global = true)]
+    sandbox: bool,
+
+    /// With --sandbox, run the commands in DIR (default: the current one)
+    #[arg(long, value_name = "DIR", global = true, requires = "sandbox")]
+    sandbox_dir: Option<PathBuf>,
+
+    /// With --sandbox, keep these environment variables too
+    #[arg(
+        long,
+        value_name = "VAR",
+        value_delimiter = ',',
+        global = true,
+        requires = "sandbox"
+    )]
+    sandbox_env: Vec<String>,
+
+    /// With --sandbox, leave the commands their network access
+    #[arg(long, global = true, requires = "sandbox")]
+    sandbox_network: bool,
+
+    /// Wrap header content in an include guard derived from the path, e.g.
+    /// --guard=MYPROJ gives MYPROJ_SRC_FOO_H_ for src/foo.h
+    #[arg(long, value_name = "PREFIX", global = true, num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    guard: Option<String>,
+
+    /// Start header content with `#pragma once` instead
+    #[arg(long, global = true, conflicts_with = "guard")]
+    pragma_once: bool,
+
+    /// Start the file with a #! line for INTERPRETER (e.g. bash, run through
+    /// /usr/bin/env, or a full path like /bin/sh) and make it executable
+    #[arg(
+        long,
+        value_name = "INTERPRETER",
+        conflicts_with_all = ["dir", "link", "fifo", "socket", "append", "prepend", "at_line", "insert_after", "trim", "pidfile", "log_entry", "no_touch", "times_only", "guard", "pragma_once"]
+    )]
+    shebang: Option<String>,
+
+    /// Make the file exactly SIZE (e.g. 10M, 1.5GB), after any content is
+    /// written: cut short, or extended with zeros
+    #[arg(
+        long,
+        value_name = "SIZE",
+        conflicts_with_all = ["dir", "link", "fifo", "socket", "append", "prepend", "at_line", "insert_after", "trim", "pidfile", "log_entry", "no_touch", "times_only"]
+    )]
+    size: Option<String>,
+
+    /// With --size, extend the file with a hole instead of allocating zeros,
+    /// so it takes no disk space
+    #[arg(long, requires = "size")]
+    sparse: bool,
+
+    /// Write generated bytes instead of content: random:SIZE, zeros:SIZE or
+    /// pattern:BYTE:SIZE (e.g. random:1M, pattern:0xFF:4K)
+    #[arg(
+        long,
+        value_name = "GENERATOR",
+        group = "generated",
+        conflicts_with_all = ["dir", "link", "fifo", "socket", "write", "stdin", "template", "shebang", "size", "append", "prepend", "at_line", "insert_after", "trim", "pidfile", "log_entry", "no_touch", "times_only", "guard", "pragma_once"]
+    )]
+    fill: Option<String>,
+
+    /// Write placeholder prose as content: words:N, sentences:N or
+    /// paragraphs:N (e.g. paragraphs:3)
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        group = "generated",
+        conflicts_with_all = ["dir", "link", "fifo", "socket", "write", "stdin", "template", "trim", "pidfile", "log_entry", "no_touch", "times_only"]
+    )]
+    lorem: Option<String>,
+
+    /// Seed --fill, --lorem or the tokens in --write, so the same seed writes
+    /// the same content on every run. --fill and --lorem write the same into
+    /// every file; the tokens still differ from file to file
+    #[arg(long, value_name = "N", requires = "generated")]
+    seed: Option<u64>,
+
+    /// Free the disk space behind LEN bytes from OFFSET (e.g. 4K,1M), which
+    /// read as zeros afterwards (Linux only)
+    #[arg(
+        long,
+        value_name = "OFFSET,LEN",
+        conflicts_with_all = ["dir", "link", "fifo", "socket", "append", "prepend", "at_line", "insert_after", "trim", "pidfile", "log_entry", "no_touch", "times_only"]
+    )]
+    punch_hole: Option<String>,
+
     /// Remove trailing whitespace from each line
     #[arg(long)]
     trim: bool,
@@ -56,393 +294,1505 @@ struct Cli {
     /// Check if the file or directory exists (dry run)
     #[arg(long)]
     check: bool,
+
+    /// Print what would be created, written, chmod-ed and re-timestamped,
+    /// without changing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Limit how many paths are processed per second (e.g., 200/s, 600/m)
+    #[arg(long, value_name = "RATE", global = true)]
+    throttle: Option<String>,
+
+    /// With --throttle, process paths in groups of this many at the same
+    /// average rate
+    #[arg(long, value_name = "N", global = true, requires = "throttle")]
+    batch: Option<usize>,
+
+    /// Process a path once for every pattern that matches it, instead of once
+    #[arg(long, global = true)]
+    keep_duplicates: bool,
+
+    /// Process up to N paths at once (default: one per CPU for runs of more
+    /// than 100 paths)
+    #[arg(long, short = 'j', value_name = "N", global = true)]
+    jobs: Option<usize>,
+
+    /// Wait for the parent directory to appear and for busy targets to free up,
+    /// optionally giving up after TIMEOUT (e.g., --wait=30s)
+    #[arg(long, value_name = "TIMEOUT", global = true, num_args = 0..=1, require_equals = true, default_missing_value = "forever")]
+    wait: Option<String>,
+
+    /// Create missing parent directories, even with `parents = false` in the
+    /// config
+    #[arg(short, long, global = true)]
+    parents: bool,
+
+    /// Fail when a parent directory is missing instead of creating it,
+    /// suggesting an existing directory with a close name
+    #[arg(long, global = true, conflicts_with = "parents")]
+    no_parents: bool,
+
+    /// When a parent directory is missing and isn't created, ask whether to
+    /// use the close existing directory suggested instead
+    #[arg(long, global = true)]
+    interactive: bool,
+
+    /// Keep applying the operation until interrupted (Ctrl-C): again
+    /// whenever a matching path changes, or with --every on an interval
+    #[arg(long, global = true)]
+    watch: bool,
+
+    /// With --watch, apply the operation every DURATION (e.g., 30s) instead
+    /// of on changes
+    #[arg(long, value_name = "DURATION", global = true, requires = "watch")]
+    every: Option<String>,
+
+    /// Write a PID file (the calling process's PID unless --pid is given)
+    #[arg(long)]
+    pidfile: bool,
+
+    /// PID to record with --pidfile
+    #[arg(long, value_name = "PID", requires = "pidfile")]
+    pid: Option<u32>,
+
+    /// Resolve @member/... targets against the enclosing workspace
+    /// (a Cargo workspace or a .tap-workspace file)
+    #[arg(long, global = true)]
+    workspace: bool,
+
+    /// Print the created directory so the shell can cd into it (see `tap shell-init`)
+    #[arg(long, requires = "dir")]
+    print_cd: bool,
+
+    /// Skip the filesystem warnings shown before large or recursive runs
+    #[arg(long, global = true)]
+    no_fs_checks: bool,
+
+    /// Use this umask (octal, e.g., 027) while tap runs
+    #[arg(long, value_name = "MASK", global = true)]
+    umask: Option<String>,
+
+    /// Only change permissions: never create files or directories and never
+    /// update timestamps or content
+    #[arg(
+        long,
+        requires = "permissions",
+        conflicts_with_all = ["dir", "write", "timestamp", "append", "prepend", "at_line", "insert_after", "template", "trim", "pidfile", "wait"]
+    )]
+    no_touch: bool,
+
+    /// Only update timestamps of existing paths (to --timestamp or now),
+    /// without creating, opening, or writing anything
+    #[arg(
+        long,
+        conflicts_with_all = ["dir", "write", "chmod", "attrib", "append", "prepend", "at_line", "insert_after", "template", "trim", "pidfile", "wait", "no_touch"]
+    )]
+    times_only: bool,
+
+    /// Skip paths that don't exist instead of creating them, like `touch -c`
+    #[arg(long, global = true)]
+    no_create: bool,
+
+    /// Hold a named cross-process lock for the whole run, failing if another
+    /// tap run already holds it
+    #[arg(long, value_name = "NAME", global = true)]
+    exclusive: Option<String>,
+
+    /// With --exclusive, wait for the lock instead of failing, optionally
+    /// giving up after TIMEOUT (e.g., --exclusive-wait=5m)
+    #[arg(long, value_name = "TIMEOUT", requires = "exclusive", global = true, num_args = 0..=1, require_equals = true, default_missing_value = "forever")]
+    exclusive_wait: Option<String>,
+
+    /// Append a timestamped log line, e.g. `[2024-05-01T12:00:00Z] deploy finished`
+    #[arg(
+        long,
+        value_name = "MESSAGE",
+        conflicts_with_all = ["dir", "write", "append", "prepend", "at_line", "insert_after", "template", "trim", "pidfile", "no_touch", "times_only"]
+    )]
+    log_entry: Option<String>,
+
+    /// strftime format for --log-entry timestamps (UTC)
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        requires = "log_entry",
+        default_value = DEFAULT_LOG_FORMAT
+    )]
+    log_format: String,
+
+    /// Rotate the log before appending once it reaches this size (e.g., 10M)
+    #[arg(long, value_name = "SIZE", requires = "log_entry")]
+    log_rotate: Option<String>,
+
+    /// Number of rotated logs to keep
+    #[arg(
+        long,
+        value_name = "COUNT",
+        requires = "log_rotate",
+        default_value_t = DEFAULT_LOG_KEEP
+    )]
+    log_keep: u32,
+
+    /// Also record every change tap makes in the system log
+    #[arg(long, value_enum, value_name = "TARGET", global = true)]
+    log_target: Option<LogTarget>,
+
+    /// Write a JSON report of each path's state before and after the run
+    #[arg(long, value_name = "FILE", global = true)]
+    audit_report: Option<PathBuf>,
+
+    /// List the paths this run created or changed, one per line, in FILE
+    /// (`-` for standard output)
+    #[arg(long, value_name = "FILE", global = true)]
+    changed_list: Option<PathBuf>,
+
+    /// When the run finishes, announce its summary (desktop: a desktop
+    /// notification)
+    #[arg(long, value_enum, value_name = "TARGET", global = true)]
+    notify: Option<NotifyTarget>,
+
+    /// When the run finishes, POST its summary as JSON to this http:// or
+    /// https:// URL
+    #[arg(long, value_name = "URL", global = true)]
+    notify_url: Option<String>,
+
+    /// When running as root, hand everything tap creates to this user (name or UID)
+    #[arg(long, value_name = "USER", global = true)]
+    as_user: Option<String>,
+
+    /// Give the targets to this user (name or UID), whether tap created them
+    /// or not; everything inside too with -R
+    #[arg(long, value_name = "USER", global = true)]
+    owner: Option<String>,
+
+    /// Give the targets to this group (name or GID), whether tap created them
+    /// or not; everything inside too with -R
+    #[arg(long, value_name = "GROUP", global = true)]
+    group: Option<String>,
+
+    /// Treat the paths as relative to each user's home directory and apply
+    /// them for every regular user (UID >= 1000), owned by that user
+    #[arg(long, conflicts_with_all = ["as_user", "workspace"])]
+    each_user: bool,
+
+    /// With --each-user, only apply for these users
+    #[arg(
+        long,
+        value_name = "USER",
+        value_delimiter = ',',
+        requires = "each_user"
+    )]
+    users: Vec<String>,
+
+    /// Set the modification time to just after this file's, by the smallest
+    /// increment the filesystem can represent
+    #[arg(long, value_name = "FILE", conflicts_with = "timestamp")]
+    after: Option<PathBuf>,
+
+    /// Set access and modification times to this file's, like `touch -r`
+    #[arg(short, long, value_name = "FILE", conflicts_with_all = ["timestamp", "after"])]
+    reference: Option<PathBuf>,
+
+    /// Change only the access time, like `touch -a`
+    #[arg(long)]
+    atime: bool,
+
+    /// Change only the modification time, like `touch -m`
+    #[arg(long)]
+    mtime: bool,
+
+    /// On network filesystems, take "now" from the file server's clock
+    /// instead of the local one
+    #[arg(long, global = true)]
+    server_time: bool,
+
+    /// How to print results: messages, or one record per path (also for
+    /// --check and `tap stat`)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        global = true,
+        default_value = "text"
+    )]
+    output: OutputFormat,
+
+    /// strftime format for timestamps in output, in local time
+    /// (default "%Y-%m-%d %H:%M:%S")
+    #[arg(long, value_name = "FORMAT", global = true)]
+    time_format: Option<String>,
+
+    /// Show timestamps as formatted times or relative ones ("3 minutes ago")
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STYLE",
+        global = true,
+        default_value = "absolute",
+        conflicts_with = "time_format"
+    )]
+    time_style: TimeStyle,
+
+    /// Show sizes as exact byte counts instead of human-readable ones
+    #[arg(long, global = true)]
+    bytes: bool,
+
+    /// Language for messages (also TAP_LANG)
+    #[arg(long, value_enum, value_name = "LANG", global = true)]
+    lang: Option<Lang>,
+
+    /// Plain output for screen readers and dumb terminals: no colors, and
+    /// control characters in paths and messages escaped (also TAP_PLAIN,
+    /// or TERM=dumb)
+    #[arg(long, global = true)]
+    plain: bool,
+
+    /// What the exit status reports: any-error fails if a path fails,
+    /// changes also fails if anything changed (for CI checks, with or
+    /// without --dry-run), none always succeeds
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        global = true,
+        default_value = "any-error"
+    )]
+    status_from: StatusFrom,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    run(&cli)
+impl Cli {
+    fn file_op(&self) -> FileOp {
+        let (write, stdin) = content_args(&self.write, self.stdin);
+        FileOp {
+            dir: self.dir,
+            link_to: self.link_to.clone(),
+            hard_link_to: self.hard_link_to.clone(),
+            fifo: self.fifo,
+            socket: self.socket,
+            force: self.force,
+            chmod: self.chmod.clone(),
+            attrib: self.attrib.clone(),
+            write,
+            stdin,
+            copy_from: None,
+            timestamp: self.timestamp.clone(),
+            append: self.append,
+            prepend: self.prepend,
+            at_line: self.at_line,
+            insert_after: self.insert_after.clone(),
+            once: self.once,
+            backup: self.backup.clone(),
+            journal: journal_dir(self.undo),
+            verbose: self.verbose,
+            recursive: self.recursive,
+            template: self.template.clone(),
+            template_text: None,
+            template_vars: self.vars.clone(),
+            allow_shell: self.allow_shell,
+            sandbox: self.sandbox.then(|| Sandbox {
+                keep_env: self.sandbox_env.clone(),
+                dir: self.sandbox_dir.clone(),
+                network: self.sandbox_network,
+            }),
+            trusted_keys: Vec::new(),
+            insecure: self.insecure,
+            header_guard: match (&self.guard, self.pragma_once) {
+                (_, true) => Some(HeaderGuard::PragmaOnce),
+                (Some(prefix), false) => Some(HeaderGuard::Macro(
+                    Some(prefix.clone()).filter(|prefix| !prefix.is_empty()),
+                )),
+                (None, false) => None,
+            },
+            shebang: self.shebang.clone(),
+            size: self.size.clone(),
+            sparse: self.sparse,
+            punch_hole: self.punch_hole.clone(),
+            fill: self.fill.clone(),
+            lorem: self.lorem.clone(),
+            seed: self.seed,
+            trim: self.trim,
+            check: self.check,
+            throttle: self.throttle.clone(),
+            batch: self.batch,
+            dry_run: self.dry_run,
+            keep_duplicates: self.keep_duplicates,
+            xargs: self.xargs,
+            null: self.null,
+            jobs: self.jobs,
+            wait: self.wait.clone(),
+            parents: (self.parents || self.no_parents).then_some(self.parents),
+            interactive: self.interactive,
+            watch: self.watch,
+            every: self.every.clone(),
+            pidfile: self.pidfile,
+            pid: self.pid,
+            workspace: self.workspace,
+            print_cd: self.print_cd,
+            no_fs_checks: self.no_fs_checks,
+            umask: self.umask.clone(),
+            no_touch: self.no_touch,
+            times_only: self.times_only,
+            no_create: self.no_create,
+            exclusive: self.exclusive.clone(),
+            exclusive_wait: self.exclusive_wait.clone(),
+            log_entry: self.log_entry.clone(),
+            log_format: self.log_format.clone(),
+            log_rotate: self.log_rotate.clone(),
+            log_keep: self.log_keep,
+            log_target: self.log_target,
+            audit_report: self.audit_report.clone(),
+            changed_list: self.changed_list.clone(),
+            notify: self.notify,
+            notify_url: self.notify_url.clone(),
+            as_user: self.as_user.clone(),
+            owner: self.owner.clone(),
+            group: self.group.clone(),
+            each_user: self.each_user,
+            users: self.users.clone(),
+            after: self.after.clone(),
+            reference: self.reference.clone(),
+            times: TimeSelection::from_flags(self.atime, self.mtime),
+            server_time: self.server_time,
+            new_file_mode: None,
+            new_dir_mode: None,
+            templates_dir: None,
+            output: self.output,
+            time_format: self.time_format.clone(),
+            time_style: self.time_style,
+            bytes: self.bytes,
+            status_from: self.status_from,
+            rules: Vec::new(),
+            template_cache: TemplateCache::default(),
+        }
+    }
 }
 
-fn run(cli: &Cli) -> Result<()> {
-    let expanded_paths = expand_paths(&cli.paths)?;
+#[derive(Subcommand)]
+enum Command {
+    /// Create files or update their content and timestamps
+    File {
+        /// File(s) to create or update (supports glob patterns)
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Add content to the file (`-` reads it from standard input)
+        #[arg(short, long, group = "content")]
+        write: Option<String>,
+
+        /// Write content read from standard input
+        #[arg(long, group = "content")]
+        stdin: bool,
+
+        /// Append content instead of overwriting
+        #[arg(short, long, requires = "content")]
+        append: bool,
+
+        /// Insert content at the top of the file instead of overwriting
+        #[arg(long, requires = "content", conflicts_with = "append")]
+        prepend: bool,
+
+        /// Insert content so that it starts at line N (from 1)
+        #[arg(
+            long,
+            value_name = "N",
+            requires = "content",
+            conflicts_with_all = ["append", "prepend"]
+        )]
+        at_line: Option<usize>,
+
+        /// Insert content after the first line containing TEXT
+        #[arg(
+            long,
+            value_name = "TEXT",
+            requires = "content",
+            conflicts_with_all = ["append", "prepend", "at_line"]
+        )]
+        insert_after: Option<String>,
+
+        /// Remove trailing whitespace from each line
+        #[arg(long, conflicts_with = "content")]
+        trim: bool,
+
+        /// Set access and modification times (YYYY-MM-DD HH:MM:SS[.fraction], or
+        /// relative: now, +30m, -2h, "2 hours ago", "yesterday 14:00")
+        #[arg(short, long, allow_hyphen_values = true)]
+        timestamp: Option<String>,
+
+        /// Set specific permissions (octal, e.g., 644, or symbolic, e.g., u+x,go-w)
+        #[arg(short, long)]
+        chmod: Option<String>,
+    },
+    /// Create directories
+    Dir {
+        /// Directories to create (supports glob patterns)
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Set specific permissions (octal, e.g., 755, or symbolic, e.g., u+x,go-w)
+        #[arg(short, long)]
+        chmod: Option<String>,
+
+        /// Apply chmod to everything inside the directories as well
+        #[arg(short = 'R', long, requires = "chmod")]
+        recursive: bool,
+
+        /// Print the created directory so the shell can cd into it (see `tap shell-init`)
+        #[arg(long)]
+        print_cd: bool,
+    },
+    /// Create or overwrite files with a template's content
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Template {
+        #[command(subcommand)]
+        action: Option<TemplateAction>,
+
+        /// Template file, or the name of one in the templates directory
+        #[arg(required = true)]
+        template: Option<String>,
+
+        /// File(s) to write (supports glob patterns)
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Set specific permissions (octal, e.g., 644, or symbolic, e.g., u+x,go-w)
+        #[arg(short, long)]
+        chmod: Option<String>,
+    },
+    /// Report which paths exist without changing anything
+    Check {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Change permissions of existing paths without touching them otherwise
+    Chmod {
+        /// Permissions to set (octal, e.g., 644, or symbolic, e.g., u+x,go-w)
+        mode: String,
+
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Apply to everything inside directories as well
+        #[arg(short = 'R', long)]
+        recursive: bool,
+    },
+    /// Coordinate pipelines through sentinel files
+    Flag {
+        #[command(subcommand)]
+        action: FlagAction,
+    },
+    /// Print a shell function that lets `tap -d --print-cd` change directory
+    ShellInit {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Show metadata of matched paths, optionally filtered by recent access or modification
+    Stat {
+        /// File(s) or directories to inspect (supports glob patterns)
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Only list paths accessed within this long (e.g., 1h, 30m)
+        #[arg(long, value_name = "DURATION")]
+        accessed_within: Option<String>,
+
+        /// Only list paths modified within this long (e.g., 1h, 30m)
+        #[arg(long, value_name = "DURATION")]
+        modified_within: Option<String>,
+    },
+    /// Report groups of matched files with identical content
+    DedupeReport {
+        /// Files to compare (supports glob patterns)
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Replace duplicates with links to the first file of each group
+        #[arg(long, value_enum, value_name = "MODE")]
+        dedupe: Option<DedupeMode>,
+    },
+    /// Run a command whenever files matching the patterns change
+    OnChange {
+        /// Glob patterns to watch (e.g., 'src/**/*.rs')
+        #[arg(required = true)]
+        patterns: Vec<String>,
+
+        /// Wait for this long without further changes before running (e.g., 500ms)
+        #[arg(long, value_name = "DURATION", default_value = "200ms")]
+        debounce: String,
+
+        /// Command to run, given after `--`
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Create files from the skeleton directory that are missing from users' homes
+    SkelSync {
+        /// Only sync this user (default: every regular user)
+        #[arg(long, value_name = "NAME")]
+        user: Option<String>,
+
+        /// Skeleton directory to copy from
+        #[arg(long, value_name = "DIR", default_value = "/etc/skel")]
+        skel: PathBuf,
+
+        /// Only report what would be created
+        #[arg(long)]
+        check: bool,
+    },
+    /// Create an application's XDG config, cache, data, and state directories
+    XdgInit {
+        /// Application name (the base directories themselves if omitted)
+        app: Option<String>,
+
+        /// Print the resolved directories as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Create the conventional test file for a source file and print its path
+    TestFor {
+        /// Source file to pair a test with (Rust, Go, or Python)
+        source: PathBuf,
+    },
+    /// Create the files and directories described in a TOML or YAML manifest
+    Apply {
+        /// Manifest file (.toml, .yaml, or .yml)
+        manifest: PathBuf,
+
+        /// Allow entries that resolve outside the manifest's directory
+        #[arg(long)]
+        allow_outside_root: bool,
+
+        /// Remove the files and empty directories the manifest created on an
+        /// earlier run but no longer lists
+        #[arg(long)]
+        prune_orphans: bool,
+    },
+    /// Print a manifest describing an existing tree, which `tap apply`
+    /// recreates when it is saved in that tree
+    Export {
+        /// Directory to describe
+        dir: PathBuf,
+
+        /// Print TOML instead of YAML
+        #[arg(long)]
+        toml: bool,
+
+        /// Largest UTF-8 file (e.g. 4KiB) whose content goes in the manifest;
+        /// bigger and binary files are referenced as a `source` instead
+        #[arg(long, value_name = "SIZE", default_value = "4KiB")]
+        max_inline: String,
+    },
+    /// Answer JSON-RPC requests from an editor plugin, one per line, to
+    /// list and preview templates and create files
+    Serve {
+        /// Talk over standard input and output
+        #[arg(long, required = true)]
+        stdio: bool,
+    },
+    /// Print the JSON Schema of manifests or the config file, for editors
+    /// to complete and check them with
+    Schema {
+        /// Which file format to describe
+        #[arg(value_enum)]
+        kind: SchemaKind,
+    },
+    /// Add or update variables in a .env file (created with mode 600)
+    Env {
+        /// Environment file to edit
+        #[arg(default_value = ".env")]
+        file: PathBuf,
+
+        /// Set a variable (repeatable)
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_assignment)]
+        set: Vec<(String, String)>,
+
+        /// Don't replace variables that are already set
+        #[arg(long)]
+        unless_exists: bool,
+
+        /// Add variables missing from FILE.example, prompting for ones it leaves blank
+        #[arg(long)]
+        from_example: bool,
+    },
+    /// Add starter .gitignore, .editorconfig, and .gitattributes entries
+    Conventions {
+        /// Project directory
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Language bundle to use (detected from the project's build files if omitted)
+        #[arg(long, value_enum)]
+        language: Option<Language>,
+    },
+    /// Record the state of matched files and later report what changed
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+    /// Append an empty target to a Makefile or justfile
+    Target {
+        /// Name of the target or recipe
+        name: String,
+
+        /// Build file to add it to (created if missing)
+        #[arg(long = "in", value_name = "FILE", default_value = "Makefile")]
+        file: PathBuf,
+    },
+    /// Create a named pipe, optionally waiting until a reader opens it
+    Fifo {
+        path: PathBuf,
+
+        /// Set specific permissions (octal, e.g., 600, or symbolic, e.g., u+x,go-w)
+        #[arg(short, long)]
+        chmod: Option<String>,
+
+        /// Block until a process opens the FIFO for reading
+        #[arg(long)]
+        expect_reader: bool,
+
+        /// Give up waiting for a reader after this long (e.g., 5s)
+        #[arg(long, value_name = "DURATION", requires = "expect_reader")]
+        timeout: Option<String>,
+    },
+    /// Revert the last run: remove what it created and restore the content,
+    /// mode, owner and times of what it changed
+    Undo {
+        /// Also revert paths that changed again after the run
+        #[arg(long)]
+        force: bool,
+    },
+    /// Manage the public keys trusted to sign templates fetched from URLs
+    Key {
+        #[command(subcommand)]
+        action: KeyAction,
+    },
+    /// Look for temporary files left behind by tap runs that were killed
+    Doctor {
+        /// Remove them (every run also does this before it starts)
+        #[arg(long)]
+        cleanup: bool,
+    },
+}
 
-    for path in expanded_paths {
-        if cli.verbose {
-            println!("Processing: {}", path.display());
-        }
+#[derive(Subcommand)]
+enum TemplateAction {
+    /// List the templates in the templates directory
+    List,
+}
 
-        if cli.check {
-            check_existence(&path, cli.verbose)?;
-            continue;
-        }
+#[derive(Subcommand)]
+enum SnapshotAction {
+    /// Record mode, owner, size, hash, and times of the matched paths
+    Save {
+        name: String,
+
+        /// Paths to record (supports glob patterns)
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Report what was added, removed, or modified since the snapshot
+    Diff {
+        name: String,
+
+        /// Exit with an error when anything changed
+        #[arg(long)]
+        exit_code: bool,
+
+        /// Treat modification times this close together as unchanged (e.g., 2s on FAT)
+        #[arg(long, value_name = "DURATION")]
+        mtime_tolerance: Option<String>,
+    },
+}
 
-        // Ensure parent directories exist
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent).context("Failed to create parent directories")?;
-        }
+#[derive(Subcommand)]
+enum KeyAction {
+    /// Trust a minisign public key, given as itself or a minisign.pub file
+    Add { key: String },
+    /// Stop trusting a key
+    Remove { key: String },
+    /// List the keys trusted with `tap key add`
+    List,
+}
 
-        if cli.dir {
-            create_directory(&path, cli.verbose)?;
-        } else {
-            create_or_update_file(&path, cli)?;
-        }
+#[derive(Subcommand)]
+enum FlagAction {
+    /// Atomically create flag file(s)
+    Set {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Block until a flag file exists
+    Wait {
+        path: PathBuf,
+
+        /// Give up after this long (e.g., 60s, 5m)
+        #[arg(long, value_name = "DURATION")]
+        timeout: Option<String>,
+    },
+    /// Remove flag file(s), ignoring ones that are already gone
+    Clear {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
 
-        if let Some(chmod) = &cli.chmod {
-            set_permissions(&path, chmod, cli.recursive, cli.verbose)?;
+/// Subcommand names, for telling `tap FILE...` apart without building the
+/// clap command (checked against it in the tests)
+const SUBCOMMANDS: &[&str] = &[
+    "flag",
+    "shell-init",
+    "stat",
+    "dedupe-report",
+    "on-change",
+    "skel-sync",
+    "xdg-init",
+    "file",
+    "dir",
+    "template",
+    "check",
+    "chmod",
+    "test-for",
+    "fifo",
+    "env",
+    "conventions",
+    "snapshot",
+    "apply",
+    "export",
+    "serve",
+    "schema",
+    "target",
+    "undo",
+    "key",
+    "doctor",
+    "help",
+];
+
+fn main() -> ExitCode {
+    // Shell loops mostly call plain `tap FILE...`, which needs none of the
+    // argument parser; building it is most of tap's startup time
+    let args = std::env::args_os().skip(1).collect::<Vec<_>>();
+    let result = match plain_paths(&args) {
+        Some(paths) => run_paths(&paths),
+        None => {
+            let cli = parse_cli(&args);
+            if let Some(lang) = cli.lang {
+                set_lang(lang);
+            }
+            run(&cli)
         }
-
-        if let Some(timestamp) = &cli.timestamp {
-            set_timestamp(&path, timestamp, cli.verbose)?;
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            report_error(&error);
+            ExitCode::FAILURE
         }
     }
-
-    Ok(())
 }
 
-fn expand_paths(paths: &[String]) -> Result<Vec<PathBuf>> {
-    let mut expanded = Vec::new();
+/// Parses the command line, with clap's help and errors uncolored if the
+/// output is to be plain. `--plain` is looked for before parsing, since
+/// parsing can already print.
+fn parse_cli(args: &[OsString]) -> Cli {
+    if args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--plain")
+    {
+        set_plain(true);
+    }
+    let mut command = Cli::command();
+    if plain() {
+        command = command.color(ColorChoice::Never);
+    }
+    let args = std::iter::once(OsString::from("tap")).chain(args.iter().cloned());
+    try_parse_cli(command, args).unwrap_or_else(|error| error.exit())
+}
 
-    for path in paths {
-        match glob(path) {
-            Ok(entries) => {
-                let count = entries.count();
-                if count == 0 {
-                    // If no matches found, treat it as a new file/directory
-                    expanded.push(PathBuf::from(path));
-                } else {
-                    for entry in glob(path).expect("Failed to read glob pattern") {
-                        match entry {
-                            Ok(path) => expanded.push(path),
-                            Err(e) => println!("Error: {:?}", e),
-                        }
-                    }
-                }
-            }
-            Err(e) => println!("Invalid glob pattern '{}': {:?}", path, e),
+/// Parses `args`, the program name first. Run-wide options may come before
+/// a subcommand name (`tap -v stat a.txt`), but options of the flat form
+/// can't, since the subcommand would ignore them.
+fn try_parse_cli(
+    mut command: clap::Command,
+    args: impl IntoIterator<Item = OsString>,
+) -> Result<Cli, clap::Error> {
+    let matches = command.try_get_matches_from_mut(args)?;
+    let cli = Cli::from_arg_matches(&matches)?;
+    if let Some((name, _)) = matches.subcommand() {
+        let misplaced = command.get_arguments().find(|arg| {
+            !arg.is_global_set()
+                && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+        });
+        if let Some(arg) = misplaced {
+            let shown = match (arg.get_long(), arg.get_short()) {
+                (Some(long), _) => format!("--{long}"),
+                (None, Some(short)) => format!("-{short}"),
+                (None, None) => arg.get_id().to_string(),
+            };
+            let message = tr!(
+                "{} can't be used with tap {} (run-wide options can come before the subcommand, other options only without one)",
+                shown,
+                name
+            );
+            return Err(command.error(ErrorKind::ArgumentConflict, message));
         }
     }
-
-    Ok(expanded)
+    Ok(cli)
 }
 
-fn check_existence(path: &Path, verbose: bool) -> Result<()> {
-    if path.exists() {
-        if verbose {
-            println!("Exists: {}", path.display());
+/// Prints `error` and its causes the way returning it from `main` would,
+/// in the chosen language.
+fn report_error(error: &anyhow::Error) {
+    say_err!("Error: {}", error);
+    let causes = error.chain().skip(1).collect::<Vec<_>>();
+    if causes.is_empty() {
+        return;
+    }
+    eprintln!();
+    say_err!("Caused by:");
+    match &causes[..] {
+        [cause] => say_err!("    {}", cause),
+        causes => {
+            for (index, cause) in causes.iter().enumerate() {
+                say_err!("    {}: {}", index, cause);
+            }
         }
-    } else {
-        println!("Does not exist: {}", path.display());
     }
-    Ok(())
 }
 
-fn create_directory(path: &Path, verbose: bool) -> Result<()> {
-    fs::create_dir_all(path).context("Failed to create directory")?;
-    if verbose {
-        println!("Directory created: {}", path.display());
+/// The arguments as paths, when they are nothing but paths. After a leading
+/// `--` everything is a path, including names of subcommands.
+fn plain_paths(args: &[OsString]) -> Option<Vec<String>> {
+    let escaped = args.first().is_some_and(|arg| arg == "--");
+    let paths = args[usize::from(escaped)..]
+        .iter()
+        .map(|arg| arg.to_str().map(str::to_string))
+        .collect::<Option<Vec<_>>>()?;
+    let first = paths.first()?;
+    if !escaped
+        && (SUBCOMMANDS.contains(&first.as_str()) || paths.iter().any(|path| path.starts_with('-')))
+    {
+        return None;
     }
-    Ok(())
+    Some(paths)
 }
 
-fn create_or_update_file(path: &Path, cli: &Cli) -> Result<()> {
-    if cli.trim {
-        let content = fs::read_to_string(path).context("Failed to read file content")?;
-        let trimmed_content = content
-            .lines()
-            .map(|line| line.trim_end())
-            .collect::<Vec<_>>()
-            .join("\n");
-        fs::write(path, trimmed_content).context("Failed to write trimmed content to file")?;
-        if cli.verbose {
-            println!("Trailing whitespace removed from: {}", path.display());
-        }
-        return Ok(());
+/// What `run` does for a command line of only paths. Shell loops make
+/// hundreds of these calls, each costing about what `touch` does, so they
+/// are only journaled for `tap undo` when the config says `undo = true`;
+/// otherwise each would write a journal, and the few runs kept would be
+/// the loop's last calls anyway.
+fn run_paths(paths: &[String]) -> Result<()> {
+    let mut op = FileOp::default();
+    if let Some(config) = Config::load()? {
+        config.apply_to(&mut op);
+        op.journal = journal_dir(config.undo == Some(true));
     }
+    op.run(paths)
+}
 
-    let mut options = OpenOptions::new();
-    options.write(true).create(true);
+/// Where the run keeps its journal for `tap undo`, if it is recorded at
+/// all. Without a state directory to keep it in, the run goes ahead
+/// unrecorded.
+fn journal_dir(undo: bool) -> Option<PathBuf> {
+    if !undo {
+        return None;
+    }
+    undo::journal_dir().ok()
+}
 
-    if cli.append {
-        options.append(true);
-    } else if cli.write.is_some() || cli.template.is_some() {
-        options.truncate(true);
+/// `--write -` means the content comes from standard input, like `--stdin`.
+fn content_args(write: &Option<String>, stdin: bool) -> (Option<String>, bool) {
+    match write.as_deref() {
+        Some("-") => (None, true),
+        _ => (write.clone(), stdin),
     }
+}
 
-    let mut file = options
-        .open(path)
-        .context("Failed to create or open file")?;
-
-    if let Some(template) = &cli.template {
-        let content = fs::read_to_string(template).context("Failed to read template file")?;
-        file.write_all(content.as_bytes())
-            .context("Failed to write template content to file")?;
-        if cli.verbose {
-            println!(
-                "File created/updated with template content: {}",
-                path.display()
-            );
-        }
-    } else if let Some(content) = &cli.write {
-        file.write_all(content.as_bytes())
-            .context("Failed to write content to file")?;
-        if cli.verbose {
-            if cli.append {
-                println!("Content appended to file: {}", path.display());
-            } else {
-                println!("File created/updated with content: {}", path.display());
-            }
-        }
-    } else if cli.verbose {
-        let metadata = file.metadata().context("Failed to get file metadata")?;
-        if metadata.len() == 0 {
-            println!("File created: {}", path.display());
-        } else {
-            println!("File timestamp updated: {}", path.display());
-        }
+/// Config file defaults only fill in what the command line leaves unset;
+/// runs are journaled unless `no_undo` or the config's `undo` say not to,
+/// and `preset` picks one of the config's presets.
+fn with_config(mut base: FileOp, no_undo: bool, preset: Option<&str>) -> Result<FileOp> {
+    let config = Config::load()?;
+    if let Some(config) = &config {
+        config.apply_to(&mut base);
+    }
+    if base.journal.is_none() {
+        let undo = config.as_ref().and_then(|config| config.undo);
+        base.journal = journal_dir(!no_undo && undo.unwrap_or(true));
     }
+    if let Some(preset) = preset {
+        config
+            .unwrap_or_default()
+            .preset(preset)?
+            .apply_to(&mut base);
+    }
+    Ok(base)
+}
 
-    Ok(())
+fn parse_assignment(input: &str) -> Result<(String, String), String> {
+    input
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {}", input))
+}
+
+/// The file standard output is redirected into, if it is one, so that
+/// `tap export . > manifest.yaml` doesn't describe the manifest it writes.
+fn stdout_file() -> Option<std::fs::Metadata> {
+    #[cfg(unix)]
+    let handle = {
+        use std::os::fd::AsFd;
+        io::stdout().as_fd().try_clone_to_owned().ok()?
+    };
+    #[cfg(windows)]
+    let handle = {
+        use std::os::windows::io::AsHandle;
+        io::stdout().as_handle().try_clone_to_owned().ok()?
+    };
+    std::fs::File::from(handle)
+        .metadata()
+        .ok()
+        .filter(|metadata| metadata.is_file())
 }
-fn set_permissions(path: &Path, chmod: &str, recursive: bool, verbose: bool) -> Result<()> {
-    let permissions = u32::from_str_radix(chmod, 8).context("Invalid chmod value")?;
-    let permissions = fs::Permissions::from_mode(permissions);
-
-    if recursive && path.is_dir() {
-        for entry in fs::read_dir(path).context("Failed to read directory")? {
-            let entry = entry.context("Failed to read directory entry")?;
-            set_permissions(&entry.path(), chmod, recursive, verbose)?;
-        }
-    }
 
-    fs::set_permissions(path, permissions).context("Failed to set permissions")?;
-    if verbose {
-        println!("Permissions set to {} for: {}", chmod, path.display());
+/// Asks for a value on stderr/stdin, so answers can also be piped in.
+fn prompt_for(key: &str) -> Result<String> {
+    eprint!("{}: ", key);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        anyhow::bail!(tr!("No value given for {}", key));
     }
-    Ok(())
+    Ok(answer.trim_end_matches(['\r', '\n']).to_string())
 }
 
-fn set_timestamp(path: &Path, time_str: &str, verbose: bool) -> Result<()> {
-    let timestamp = parse_timestamp(time_str)?;
-    let file_time = filetime::FileTime::from_system_time(timestamp);
-    filetime::set_file_mtime(path, file_time).context("Failed to set timestamp")?;
-    if verbose {
-        println!("Timestamp set to {} for: {}", time_str, path.display());
+fn run(cli: &Cli) -> Result<()> {
+    let mut base = with_config(cli.file_op(), cli.no_undo, cli.preset.as_deref())?;
+    if cli.no_rules {
+        base.rules.clear();
+    }
+    match &cli.command {
+        Some(command) => run_command(command, &base),
+        None => base.run(&cli.paths),
     }
-    Ok(())
 }
 
-fn parse_timestamp(time_str: &str) -> Result<SystemTime> {
-    let dt = NaiveDateTime::parse_from_str(time_str, "%Y-%m-%d %H:%M:%S")
-        .context("Invalid timestamp format")?;
-    let timestamp =
-        SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(dt.and_utc().timestamp() as u64);
-    Ok(timestamp)
+/// `base` carries the run-wide options shared by every subcommand.
+fn run_command(command: &Command, base: &FileOp) -> Result<()> {
+    let verbose = base.verbose;
+    match command {
+        // The file-level subcommands are the flat options, grouped; the
+        // shared (global) options come from the top level
+        Command::File {
+            paths,
+            write,
+            stdin,
+            append,
+            prepend,
+            at_line,
+            insert_after,
+            trim,
+            timestamp,
+            chmod,
+        } => {
+            let (write, stdin) = content_args(write, *stdin);
+            FileOp {
+                write,
+                stdin,
+                append: *append,
+                prepend: *prepend,
+                at_line: *at_line,
+                insert_after: insert_after.clone(),
+                trim: *trim,
+                timestamp: timestamp.clone(),
+                chmod: chmod.clone(),
+                ..base.clone()
+            }
+            .run(paths)
+        }
+        Command::Dir {
+            paths,
+            chmod,
+            recursive,
+            print_cd,
+        } => FileOp {
+            dir: true,
+            chmod: chmod.clone(),
+            recursive: *recursive,
+            print_cd: *print_cd,
+            ..base.clone()
+        }
+        .run(paths),
+        Command::Template {
+            action: Some(TemplateAction::List),
+            ..
+        } => {
+            let dir = match &base.templates_dir {
+                Some(dir) => dir.clone(),
+                None => default_templates_dir()?,
+            };
+            for name in list_templates(&dir)? {
+                say!("{}", name.with_extension("").display());
+            }
+            Ok(())
+        }
+        Command::Template {
+            template,
+            paths,
+            chmod,
+            ..
+        } => FileOp {
+            template: template.clone(),
+            chmod: chmod.clone(),
+            ..base.clone()
+        }
+        .run(paths),
+        Command::Check { paths } => FileOp {
+            check: true,
+            ..base.clone()
+        }
+        .run(paths),
+        Command::Chmod {
+            mode,
+            paths,
+            recursive,
+        } => FileOp {
+            no_touch: true,
+            chmod: Some(mode.clone()),
+            recursive: *recursive,
+            ..base.clone()
+        }
+        .run(paths),
+        Command::Flag { action } => match action {
+            FlagAction::Set { paths } => paths.iter().try_for_each(|path| set_flag(path, verbose)),
+            FlagAction::Wait { path, timeout } => {
+                let timeout = timeout.as_deref().map(parse_duration).transpose()?;
+                wait_for_flag(path, timeout, verbose)
+            }
+            FlagAction::Clear { paths } => {
+                paths.iter().try_for_each(|path| clear_flag(path, verbose))
+            }
+        },
+        Command::ShellInit { shell } => {
+            print!("{}", shell_init_script(*shell));
+            Ok(())
+        }
+        Command::Stat {
+            paths,
+            accessed_within,
+            modified_within,
+        } => {
+            let filter = TimeWindowFilter {
+                accessed_within: accessed_within.as_deref().map(parse_duration).transpose()?,
+                modified_within: modified_within.as_deref().map(parse_duration).transpose()?,
+            };
+            let times = TimeDisplay::new(base.time_format.as_deref(), base.time_style)?;
+            stat_paths(
+                &expand_paths(paths)?,
+                &filter,
+                base.output,
+                &times,
+                base.bytes,
+            )
+        }
+        Command::DedupeReport { paths, dedupe } => {
+            let groups = find_duplicates(&expand_paths(paths)?)?;
+            for group in &groups {
+                say!(
+                    "Duplicate group ({} files, {} each, sha256 {}):",
+                    group.paths.len(),
+                    show_size(group.size, base.bytes),
+                    group.hash
+                );
+                for path in &group.paths {
+                    say!("  {}", path.display());
+                }
+                if let Some(mode) = dedupe {
+                    dedupe_group(&group.paths, *mode, verbose)?;
+                }
+            }
+            if groups.is_empty() && verbose {
+                say!("No duplicates found");
+            }
+            Ok(())
+        }
+        Command::OnChange {
+            patterns,
+            debounce,
+            command,
+        } => run_on_change(
+            patterns,
+            parse_duration(debounce)?,
+            command,
+            base.sandbox.as_ref(),
+            verbose,
+        ),
+        Command::SkelSync { user, skel, check } => {
+            let users = match user {
+                Some(user) => vec![UserAccount::lookup(user)?],
+                None => UserAccount::regular_users(),
+            };
+            let mut failures = 0;
+            for user in &users {
+                match skel_sync(skel, user, *check, verbose) {
+                    Ok(created) if !check => {
+                        say!("{}: created {} entries", user.name, created)
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        say_err!("Error for user {}: {:#}", user.name, e);
+                        failures += 1;
+                    }
+                }
+            }
+            if failures > 0 {
+                anyhow::bail!(tr!("Failed for {} of {} user(s)", failures, users.len()));
+            }
+            Ok(())
+        }
+        Command::XdgInit { app, json } => {
+            let dirs = XdgDirs::resolve(app.as_deref(), |var| std::env::var_os(var))?;
+            dirs.create(verbose)?;
+            if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&dirs)
+                        .with_context(|| tr!("Failed to serialize directories"))?
+                );
+            } else {
+                for (kind, dir) in dirs.entries() {
+                    say!("{}: {}", kind, dir.display());
+                }
+            }
+            Ok(())
+        }
+        Command::TestFor { source } => {
+            let pairing = TestPairing::for_source(source)?;
+            if !pairing.create(verbose)? && verbose {
+                say!("Tests already exist in: {}", pairing.path.display());
+            }
+            say!("{}", pairing.path.display());
+            Ok(())
+        }
+        Command::Apply {
+            manifest,
+            allow_outside_root,
+            prune_orphans,
+        } => apply_manifest(manifest, base, *allow_outside_root, *prune_orphans),
+        Command::Export {
+            dir,
+            toml,
+            max_inline,
+        } => {
+            let manifest = Manifest::export(dir, parse_size(max_inline)?, stdout_file().as_ref())?;
+            print!("{}", manifest.to_text(*toml)?);
+            Ok(())
+        }
+        Command::Serve { .. } => serve::serve(io::stdin().lock(), io::stdout().lock(), base),
+        Command::Schema { kind } => {
+            println!("{}", json_schema(*kind)?);
+            Ok(())
+        }
+        Command::Env {
+            file,
+            set,
+            unless_exists,
+            from_example,
+        } => {
+            let example = from_example.then(|| {
+                let mut name = file.file_name().unwrap_or_default().to_os_string();
+                name.push(".example");
+                file.with_file_name(name)
+            });
+            update_dotenv(
+                file,
+                set,
+                *unless_exists,
+                example.as_deref(),
+                prompt_for,
+                verbose,
+            )
+        }
+        Command::Conventions { dir, language } => {
+            let language = match language {
+                Some(language) => *language,
+                None => Language::detect(dir).with_context(|| {
+                    tr!(
+                        "Couldn't detect the language of {} (use --language)",
+                        dir.display()
+                    )
+                })?,
+            };
+            for path in write_conventions(dir, language, false)? {
+                say!("Updated {}", path.display());
+            }
+            Ok(())
+        }
+        Command::Snapshot { action } => match action {
+            SnapshotAction::Save { name, paths } => {
+                let snapshot = Snapshot::capture(paths)?;
+                snapshot.save(&Snapshot::path_for(name)?)?;
+                say!("Recorded {} paths in snapshot {}", snapshot.len(), name);
+                Ok(())
+            }
+            SnapshotAction::Diff {
+                name,
+                exit_code,
+                mtime_tolerance,
+            } => {
+                let policy = ComparePolicy {
+                    mtime_tolerance: mtime_tolerance
+                        .as_deref()
+                        .map(parse_duration)
+                        .transpose()?
+                        .unwrap_or_default(),
+                };
+                let drift = Snapshot::load(&Snapshot::path_for(name)?)?.diff(&policy)?;
+                for change in &drift {
+                    say!("{}", change);
+                }
+                if drift.is_empty() && verbose {
+                    say!("No changes since snapshot {}", name);
+                }
+                if *exit_code && !drift.is_empty() {
+                    anyhow::bail!(tr!(
+                        "{} path(s) changed since snapshot {}",
+                        drift.len(),
+                        name
+                    ));
+                }
+                Ok(())
+            }
+        },
+        Command::Target { name, file } => {
+            if !add_build_target(file, name, verbose)? {
+                say!("Target {} already exists in: {}", name, file.display());
+            }
+            Ok(())
+        }
+        Command::Fifo {
+            path,
+            chmod,
+            expect_reader,
+            timeout,
+        } => {
+            create_fifo(path, verbose)?;
+            if let Some(chmod) = chmod {
+                set_permissions(path, chmod, false, verbose)?;
+            }
+            if *expect_reader {
+                let deadline = timeout
+                    .as_deref()
+                    .map(parse_duration)
+                    .transpose()?
+                    .and_then(deadline_after);
+                wait_for_fifo_reader(path, deadline, verbose)?;
+            }
+            Ok(())
+        }
+        Command::Undo { force } => undo::undo(&undo::journal_dir()?, *force, base.dry_run, verbose),
+        Command::Key { action } => match action {
+            KeyAction::Add { key } => {
+                let (key, added) = signature::add_key(key)?;
+                if added {
+                    say!("Trusted key: {}", key);
+                } else {
+                    say!("Already trusted: {}", key);
+                }
+                Ok(())
+            }
+            KeyAction::Remove { key } => {
+                if !signature::remove_key(key)? {
+                    anyhow::bail!(tr!("Not a trusted key: {}", key));
+                }
+                if verbose {
+                    say!("Removed key: {}", key);
+                }
+                Ok(())
+            }
+            KeyAction::List => {
+                for key in signature::stored_keys()? {
+                    say!("{}", key);
+                }
+                Ok(())
+            }
+        },
+        Command::Doctor { cleanup } => {
+            let orphans = if *cleanup {
+                temp_files::remove_orphans(true)?
+            } else {
+                let orphans = temp_files::orphans()?;
+                for orphan in &orphans {
+                    say!("Orphaned temporary file: {}", orphan.display());
+                }
+                if !orphans.is_empty() {
+                    say!("Run tap doctor --cleanup to remove them");
+                }
+                orphans
+            };
+            if orphans.is_empty() {
+                say!("No orphaned temporary files");
+            }
+            Ok(())
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
-    use tempfile::{tempdir, NamedTempFile};
-
-    #[test]
-    fn test_expand_paths() -> Result<()> {
-        let dir = tempdir()?;
-        let file1 = dir.path().join("test1.txt");
-        let file2 = dir.path().join("test2.txt");
-        File::create(&file1)?;
-        File::create(&file2)?;
-
-        let paths = vec![dir.path().join("test*.txt").to_string_lossy().to_string()];
-        let expanded = expand_paths(&paths)?;
-
-        assert_eq!(expanded.len(), 2);
-        assert!(expanded.contains(&file1));
-        assert!(expanded.contains(&file2));
-
-        Ok(())
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        try_parse_cli(
+            Cli::command(),
+            std::iter::once("tap")
+                .chain(args.iter().copied())
+                .map(OsString::from),
+        )
     }
 
-    #[test]
-    fn test_check_existence() -> Result<()> {
-        let dir = tempdir()?;
-        let existing_file = dir.path().join("existing.txt");
-        File::create(&existing_file)?;
-        let non_existing_file = dir.path().join("non_existing.txt");
-
-        check_existence(&existing_file, false)?;
-        check_existence(&non_existing_file, false)?;
-
-        Ok(())
+    fn tap(args: &[&str]) -> Result<()> {
+        // Keep test runs out of the user's undo journal
+        run(&parse(&[args, &["--no-undo"]].concat())?)
     }
 
     #[test]
-    fn test_create_directory() -> Result<()> {
+    fn test_file_level_subcommands() -> Result<()> {
         let dir = tempdir()?;
-        let new_dir = dir.path().join("new_dir");
-
-        create_directory(&new_dir, false)?;
-
-        assert!(new_dir.is_dir());
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        tap(&["file", "-w", "hello", &path("a.txt")])?;
+        assert_eq!(fs::read_to_string(path("a.txt"))?, "hello");
+
+        tap(&["template", &path("a.txt"), &path("b.txt"), "--umask", "077"])?;
+        assert_eq!(fs::read_to_string(path("b.txt"))?, "hello");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(path("b.txt"))?.permissions().mode() & 0o777,
+            0o600
+        );
+
+        tap(&["dir", "--chmod", "700", &path("d")])?;
+        assert!(dir.path().join("d").is_dir());
+
+        tap(&["chmod", "640", &path("a.txt")])?;
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(path("a.txt"))?.permissions().mode() & 0o777,
+            0o640
+        );
+        assert!(tap(&["chmod", "640", &path("missing")]).is_err());
+
+        tap(&["check", &path("c.txt")])?;
+        assert!(!dir.path().join("c.txt").exists());
+
+        // The flat form still works, with its paths taken literally
+        tap(&[&path("file")])?;
+        assert!(dir.path().join("file").is_file());
         Ok(())
     }
 
     #[test]
-    fn test_create_or_update_file() -> Result<()> {
-        let dir = tempdir()?;
-        let file_path = dir.path().join("test.txt");
-
-        let cli = Cli {
-            paths: vec![file_path.to_string_lossy().to_string()],
-            dir: false,
-            chmod: None,
-            write: Some("Hello, World!".to_string()),
-            timestamp: None,
-            append: false,
-            verbose: false,
-            recursive: false,
-            template: None,
-            trim: false,
-            check: false,
-        };
-
-        create_or_update_file(&file_path, &cli)?;
-
-        let content = fs::read_to_string(&file_path)?;
-        assert_eq!(content, "Hello, World!");
-
+    fn test_plain_paths_fast_path_matches_parser() -> Result<()> {
+        let mut names = Cli::command()
+            .get_subcommands()
+            .map(|command| command.get_name().to_string())
+            .chain(["help".to_string()])
+            .collect::<Vec<_>>();
+        let mut known = SUBCOMMANDS
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        known.sort();
+        assert_eq!(names, known);
+
+        let args = ["a.txt", "src/{lib,main}.rs"].map(OsString::from);
+        assert!(plain_paths(&args).is_some());
+        let parsed = Cli::try_parse_from(["tap", "a.txt"])?.file_op();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", FileOp::default()));
+
+        for args in [
+            &["-v", "a.txt"][..],
+            &["stat", "a.txt"],
+            &["a.txt", "--dir"],
+            &["--"],
+            &[],
+        ] {
+            assert!(
+                plain_paths(&args.iter().map(OsString::from).collect::<Vec<_>>()).is_none(),
+                "{:?}",
+                args
+            );
+        }
         Ok(())
     }
 
     #[test]
-    fn test_append_to_file() -> Result<()> {
+    fn test_negative_relative_timestamps() -> Result<()> {
         let dir = tempdir()?;
-        let file_path = dir.path().join("test.txt");
-        fs::write(&file_path, "Initial content\n")?;
-
-        let cli = Cli {
-            paths: vec![file_path.to_string_lossy().to_string()],
-            dir: false,
-            chmod: None,
-            write: Some("Appended content".to_string()),
-            timestamp: None,
-            append: true,
-            verbose: false,
-            recursive: false,
-            template: None,
-            trim: false,
-            check: false,
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let hours_ago = |name: &str| -> Result<f64> {
+            let modified = fs::metadata(path(name))?.modified()?;
+            Ok(modified.elapsed()?.as_secs_f64() / 3600.0)
         };
-
-        create_or_update_file(&file_path, &cli)?;
-
-        let content = fs::read_to_string(&file_path)?;
-        assert_eq!(content, "Initial content\nAppended content");
-
+        tap(&["-t", "-2h", &path("a")])?;
+        assert!((hours_ago("a")? - 2.0).abs() < 0.1);
+        tap(&["file", "--timestamp", "-90m", &path("b")])?;
+        assert!((hours_ago("b")? - 1.5).abs() < 0.1);
         Ok(())
     }
 
     #[test]
-    fn test_trim_whitespace() -> Result<()> {
+    fn test_run_wide_options_before_subcommands() -> Result<()> {
         let dir = tempdir()?;
-        let file_path = dir.path().join("test.txt");
-        fs::write(&file_path, "  Line with spaces  \nAnother line \t ")?;
-
-        let cli = Cli {
-            paths: vec![file_path.to_string_lossy().to_string()],
-            dir: false,
-            chmod: None,
-            write: None,
-            timestamp: None,
-            append: false,
-            verbose: false,
-            recursive: false,
-            template: None,
-            trim: true,
-            check: false,
-        };
-
-        create_or_update_file(&file_path, &cli)?;
-
-        let content = fs::read_to_string(&file_path)?;
-        assert_eq!(content, "  Line with spaces\nAnother line");
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_set_permissions() -> Result<()> {
-        let file = NamedTempFile::new()?;
-        let file_path = file.path();
-
-        set_permissions(file_path, "644", false, false)?;
-
-        let metadata = fs::metadata(file_path)?;
-        assert_eq!(metadata.permissions().mode() & 0o777, 0o644);
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_set_timestamp() -> Result<()> {
-        let file = NamedTempFile::new()?;
-        let file_path = file.path();
-
-        let time_str = "2023-05-01 12:00:00";
-        set_timestamp(file_path, time_str, false)?;
-
-        let metadata = fs::metadata(file_path)?;
-        let mtime = metadata.modified()?;
-        let expected_time = parse_timestamp(time_str)?;
-
-        assert_eq!(mtime, expected_time);
-
-        Ok(())
-    }
-
-    #[test]
-    fn test_parse_timestamp() -> Result<()> {
-        let time_str = "2023-05-01 12:00:00";
-        let parsed_time = parse_timestamp(time_str)?;
-
-        let expected_time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1682942400);
-        assert_eq!(parsed_time, expected_time);
-
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        fs::write(path("x"), "")?;
+
+        let cli = parse(&["-v", "stat", &path("x")])?;
+        assert!(matches!(cli.command, Some(Command::Stat { .. })));
+        assert!(cli.verbose);
+        tap(&["-v", "stat", &path("x")])?;
+        assert!(!dir.path().join("stat").exists());
+
+        let cli = parse(&["--dry-run", "undo"])?;
+        assert!(matches!(cli.command, Some(Command::Undo { .. })));
+        assert!(cli.dry_run);
+
+        tap(&["--verbose", "chmod", "600", &path("x")])?;
+        assert!(!dir.path().join("chmod").exists());
+        #[cfg(unix)]
+        assert_eq!(fs::metadata(path("x"))?.permissions().mode() & 0o777, 0o600);
+
+        // Options of the flat form aren't silently dropped
+        let error = parse(&["-d", "stat", &path("x")]).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+
+        // Paths after the first, and after --, are still paths
+        let cli = parse(&[&path("a"), "stat"])?;
+        assert!(cli.command.is_none());
+        assert_eq!(cli.paths, [path("a"), "stat".to_string()]);
+        let cli = parse(&["-v", "--", "undo"])?;
+        assert!(cli.command.is_none());
         Ok(())
     }
 
     #[test]
-    fn test_run() -> Result<()> {
-        let dir = tempdir()?;
-        let file_path = dir.path().join("test.txt");
-
-        let cli = Cli {
-            paths: vec![file_path.to_string_lossy().to_string()],
-            dir: false,
-            chmod: Some("644".to_string()),
-            write: Some("Test content".to_string()),
-            timestamp: Some("2023-05-01 12:00:00".to_string()),
-            append: false,
-            verbose: true,
-            recursive: false,
-            template: None,
-            trim: false,
-            check: false,
-        };
-
-        run(&cli)?;
-
-        assert!(file_path.exists());
-        let content = fs::read_to_string(&file_path)?;
-        assert_eq!(content, "Test content");
-
-        let metadata = fs::metadata(&file_path)?;
-        assert_eq!(metadata.permissions().mode() & 0o777, 0o644);
-
-        let mtime = metadata.modified()?;
-        let expected_time = parse_timestamp("2023-05-01 12:00:00")?;
-        assert_eq!(mtime, expected_time);
-
+    fn test_paths_named_like_subcommands_after_double_dash() -> Result<()> {
+        for name in SUBCOMMANDS {
+            let args = ["--", name, "-x.txt"].map(OsString::from);
+            assert_eq!(
+                plain_paths(&args),
+                Some(vec![name.to_string(), "-x.txt".to_string()])
+            );
+            let cli = Cli::try_parse_from(["tap", "-v", "--", name])?;
+            assert!(cli.command.is_none(), "{}", name);
+            assert_eq!(cli.paths, [name.to_string()]);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,1218 @@
+//! Manifests for `tap apply` and `tap export`, the state file that
+//! remembers what each entry produced so re-applying only fixes drift, and
+//! the JSON Schemas `tap schema` prints.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    condition, content_digest, give_to_user, parent_dir, platform, render_template, same_file, say,
+    say_err, set_timestamp, tr, Condition, FileOp, UserAccount,
+};
+
+/// A version-controlled description of files and directories, realized by
+/// `tap apply`. Entry paths, templates and sources are relative to the
+/// manifest.
+///
+/// `include` names other manifests, relative to the one naming them, whose
+/// entries come first; an entry for a path an earlier one already has
+/// replaces it. Entry paths from an included manifest are still relative to
+/// the manifest being applied, but its templates and sources are relative
+/// to the included manifest.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Manifests to layer this one over, relative to this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// The file or directory, relative to the manifest
+    pub path: String,
+    /// A condition such as `os == 'linux'`; the entry is left out on
+    /// machines where it doesn't hold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    /// A directory rather than a file
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dir: bool,
+    /// Content, written as it is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// A template file the content is rendered from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// A file whose bytes are copied as they are, for content that is
+    /// binary or too big to give inline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Permissions (octal, e.g. `644`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// File attributes (`+h,+r`). On Windows they go with `mode`'s read-only
+    /// approximation, and on Unix only `r` applies, so an entry can give
+    /// each system what it has
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attrib: Option<String>,
+    /// User (name or UID) the entry should belong to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Access and modification times (`YYYY-MM-DD HH:MM:SS[.fraction]`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    /// The directory of the included manifest the entry comes from, which
+    /// its template and source are relative to
+    #[serde(skip)]
+    from: Option<PathBuf>,
+}
+
+/// Reads TOML `text` into a `T`, with errors at the line and column they
+/// point to.
+#[cfg(feature = "manifest")]
+pub(crate) fn from_toml<T: serde::de::DeserializeOwned>(text: &str) -> Result<T> {
+    toml::from_str(text).map_err(|error| toml_error(text, &error))
+}
+
+#[cfg(not(feature = "manifest"))]
+pub(crate) fn from_toml<T>(_text: &str) -> Result<T> {
+    anyhow::bail!(tr!(
+        "Can't read TOML: tap was built without the manifest feature"
+    ))
+}
+
+/// A TOML error in `text`, at the line and column it points to.
+#[cfg(feature = "manifest")]
+fn toml_error(text: &str, error: &toml::de::Error) -> anyhow::Error {
+    let position = error.span().map(|span| {
+        let before = &text[..span.start.min(text.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    });
+    format_error(error.message(), position)
+}
+
+/// Reads YAML `text` into a `T`, like `from_toml`.
+#[cfg(feature = "manifest")]
+fn from_yaml<T: serde::de::DeserializeOwned>(text: &str) -> Result<T> {
+    serde_yaml_ng::from_str(text).map_err(|error| yaml_error(&error))
+}
+
+#[cfg(not(feature = "manifest"))]
+fn from_yaml<T>(_text: &str) -> Result<T> {
+    anyhow::bail!(tr!(
+        "Can't read YAML: tap was built without the manifest feature"
+    ))
+}
+
+/// A YAML error, at the line and column it points to.
+#[cfg(feature = "manifest")]
+fn yaml_error(error: &serde_yaml_ng::Error) -> anyhow::Error {
+    let message = error.to_string();
+    let Some(location) = error.location() else {
+        return format_error(&message, None);
+    };
+    let at = format!(" at line {} column {}", location.line(), location.column());
+    format_error(
+        message.strip_suffix(&at).unwrap_or(&message),
+        Some((location.line(), location.column())),
+    )
+}
+
+/// `message` with the line and column, and serde's "unknown field `x`,
+/// expected one of ..." made into a suggestion of the key that was most
+/// likely meant.
+#[cfg(feature = "manifest")]
+fn format_error(message: &str, position: Option<(usize, usize)>) -> anyhow::Error {
+    let mut hint = None;
+    let mut message = message.trim_end().to_string();
+    if let Some((before, rest)) = message.split_once("unknown field `") {
+        if let Some((key, rest)) = rest.split_once('`') {
+            let expected: Vec<_> = rest.split('`').skip(1).step_by(2).collect();
+            #[cfg(not(feature = "suggest"))]
+            let closest: Option<(f64, &str)> = None;
+            #[cfg(feature = "suggest")]
+            let closest = expected
+                .iter()
+                .map(|candidate| (strsim::jaro_winkler(key, candidate), *candidate))
+                .filter(|(similarity, _)| *similarity >= 0.8)
+                .max_by(|a, b| a.0.total_cmp(&b.0));
+            hint = Some(match closest {
+                Some((_, candidate)) => tr!("did you mean '{}'?", candidate),
+                None if expected.is_empty() => tr!("no keys are allowed here"),
+                None => tr!("expected one of {}", expected.join(", ")),
+            });
+            message = format!("{}{}", before, tr!("unknown key '{}'", key));
+        }
+    }
+    if let Some((line, column)) = position {
+        message = tr!("{} at line {}, column {}", message, line, column);
+    }
+    if let Some(hint) = hint {
+        message = format!("{message}, {hint}");
+    }
+    anyhow::anyhow!(message)
+}
+
+/// The files being included, outermost first, so that a file including
+/// itself, directly or through others, is an error rather than endless
+/// recursion.
+#[derive(Default)]
+pub(crate) struct IncludeStack(Vec<(PathBuf, PathBuf)>);
+
+impl IncludeStack {
+    /// Where `include`, named in `path`, is.
+    pub(crate) fn resolve(path: &Path, include: &str) -> PathBuf {
+        path.parent().unwrap_or(Path::new("")).join(include)
+    }
+
+    pub(crate) fn push(&mut self, path: &Path) -> Result<()> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if let Some(start) = self.0.iter().position(|(seen, _)| *seen == canonical) {
+            let cycle: Vec<_> = self.0[start..]
+                .iter()
+                .map(|(_, shown)| shown.as_path())
+                .chain([path])
+                .map(|path| path.display().to_string())
+                .collect();
+            anyhow::bail!(tr!("Include cycle: {}", cycle.join(" -> ")));
+        }
+        self.0.push((canonical, path.to_path_buf()));
+        Ok(())
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.0.pop();
+    }
+}
+
+impl Manifest {
+    /// Reads a TOML (`.toml`) or YAML (`.yaml`, `.yml`) manifest, with the
+    /// entries of the manifests it includes.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_included(path, &mut IncludeStack::default())
+    }
+
+    fn load_included(path: &Path, stack: &mut IncludeStack) -> Result<Self> {
+        stack.push(path)?;
+        let own = Self::parse(path)?;
+        let mut manifest = Self {
+            include: Vec::new(),
+            entries: Vec::new(),
+        };
+        for include in &own.include {
+            let include = IncludeStack::resolve(path, include);
+            let included = Self::load_included(&include, stack)
+                .with_context(|| tr!("Included from {}", path.display()))?;
+            for mut entry in included.entries {
+                entry
+                    .from
+                    .get_or_insert_with(|| parent_dir(&include).to_path_buf());
+                manifest.add(entry);
+            }
+        }
+        for entry in own.entries {
+            manifest.add(entry);
+        }
+        stack.pop();
+        Ok(manifest)
+    }
+
+    /// Adds `entry`, in place of an earlier entry for the same path.
+    fn add(&mut self, entry: ManifestEntry) {
+        let key = manifest_key(&entry.path);
+        match self
+            .entries
+            .iter_mut()
+            .find(|earlier| manifest_key(&earlier.path) == key)
+        {
+            Some(earlier) => *earlier = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    fn parse(path: &Path) -> Result<Self> {
+        let text =
+            fs::read_to_string(path).with_context(|| tr!("Failed to read {}", path.display()))?;
+        let manifest: Self = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => {
+                from_toml(&text).with_context(|| tr!("Invalid manifest {}", path.display()))?
+            }
+            Some("yaml" | "yml") => {
+                from_yaml(&text).with_context(|| tr!("Invalid manifest {}", path.display()))?
+            }
+            _ => anyhow::bail!(tr!(
+                "Unknown manifest format (expected .toml or .yaml): {}",
+                path.display()
+            )),
+        };
+        manifest
+            .validate()
+            .with_context(|| tr!("Invalid manifest {}", path.display()))?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for entry in &self.entries {
+            if let Some(when) = &entry.when {
+                Condition::parse(when).with_context(|| tr!("Invalid entry {}", entry.path))?;
+            }
+            let sources = [&entry.content, &entry.template, &entry.source]
+                .iter()
+                .filter(|source| source.is_some())
+                .count();
+            if entry.dir && sources > 0 {
+                anyhow::bail!(tr!(
+                    "Directory entry {} can't have content, a template or a source",
+                    entry.path
+                ));
+            }
+            if sources > 1 {
+                anyhow::bail!(tr!(
+                    "Entry {} has more than one of content, template and source",
+                    entry.path
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Realizes every entry under `root`, on top of the options in `base`.
+    /// Applying the same manifest again only changes what has drifted:
+    /// entries still as `state` recorded them and as the manifest asks are
+    /// left alone, files whose content already matches are not rewritten,
+    /// and existing entries without content aren't touched.
+    ///
+    /// Entries, and the templates they are rendered from, must stay inside
+    /// `root` unless `allow_outside_root` is set. Entries whose `when`
+    /// doesn't hold here are skipped.
+    pub fn apply(
+        &self,
+        root: &Path,
+        base: &FileOp,
+        allow_outside_root: bool,
+        state: &ManifestState,
+    ) -> Result<()> {
+        for entry in &self.entries {
+            if !condition::holds(entry.when.as_deref())? {
+                if base.verbose {
+                    say!(
+                        "Skipped {} (when {})",
+                        entry.path,
+                        entry.when.as_deref().unwrap_or("")
+                    );
+                }
+                continue;
+            }
+            let from = entry.from.as_deref().unwrap_or(root);
+            let outside = std::iter::once((&entry.path, root))
+                .chain(entry.template.iter().map(|template| (template, from)))
+                .chain(entry.source.iter().map(|source| (source, from)))
+                .find(|(path, dir)| !allow_outside_root && !stays_inside(dir, Path::new(path)));
+            if let Some((outside, dir)) = outside {
+                anyhow::bail!(tr!(
+                    "Manifest entry {} is outside {} (use --allow-outside-root to allow it)",
+                    outside,
+                    dir.display()
+                ));
+            }
+            let target = root.join(&entry.path);
+            let template = entry.template.as_ref().map(|template| from.join(template));
+            let source = entry.source.as_ref().map(|source| from.join(source));
+            let desired = match (&entry.content, &template, &source) {
+                (Some(content), ..) => Some(content.clone().into_bytes()),
+                (None, Some(template), _) => {
+                    Some(render_template(template, &target, base)?.into_bytes())
+                }
+                (None, None, Some(source)) => Some(
+                    fs::read(source).with_context(|| tr!("Failed to read {}", source.display()))?,
+                ),
+                (None, None, None) => None,
+            };
+            let current = Produced::capture(&target);
+            let unchanged = match &desired {
+                Some(desired) => fs::read(&target).is_ok_and(|current| current == *desired),
+                None => current
+                    .as_ref()
+                    .is_some_and(|current| current.dir == entry.dir),
+            };
+            let mode_matches = entry.mode.as_deref().is_none_or(|mode| {
+                cfg!(unix)
+                    && current.as_ref().is_some_and(|current| {
+                        u32::from_str_radix(mode, 8).is_ok_and(|mode| mode == current.mode)
+                    })
+            });
+            let in_sync = unchanged
+                && mode_matches
+                && current.is_some()
+                && current.as_ref() == state.entries.get(&manifest_key(&entry.path))
+                && entry.attrib.is_none()
+                && entry.owner.is_none()
+                && entry.timestamp.is_none();
+            if in_sync {
+                if base.verbose {
+                    say!("No changes: {}", target.display());
+                }
+                continue;
+            }
+
+            let mut op = FileOp {
+                dir: entry.dir,
+                write: if unchanged {
+                    None
+                } else {
+                    entry.content.clone()
+                },
+                template: match template {
+                    Some(template) if !unchanged => Some(template.to_string_lossy().into_owned()),
+                    _ => None,
+                },
+                copy_from: source.filter(|_| !unchanged),
+                chmod: entry.mode.clone(),
+                attrib: entry.attrib.clone(),
+                timestamp: entry.timestamp.clone(),
+                as_user: entry.owner.clone(),
+                ..base.clone()
+            };
+            // A plain touch would bump the mtime of entries already in shape
+            if unchanged && !base.check && !base.dry_run {
+                op.no_touch = true;
+                // --no-touch only sets the mode; a timestamp still applies
+                if let Some(timestamp) = &entry.timestamp {
+                    let shown = base.timestamp_shown()?;
+                    set_timestamp(&target, timestamp, base.times, shown.as_ref())?;
+                }
+            }
+            op.apply(&target)
+                .with_context(|| tr!("Failed to apply {}", entry.path))?;
+
+            // --as-user only hands over what tap creates; a manifest also
+            // converges the owner of entries that already existed
+            if let (Some(owner), false) = (&entry.owner, base.check) {
+                let owner = UserAccount::lookup(owner)?;
+                let metadata = fs::symlink_metadata(&target)?;
+                if platform::owner(&metadata) != (owner.uid, owner.gid) {
+                    give_to_user(&[target], &owner, base.verbose)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes the tree under `root` as a manifest that `apply` recreates:
+    /// every directory and regular file, with its mode on Unix and its
+    /// attributes on Windows. UTF-8 files up to `max_inline` bytes have
+    /// their content inline; other files are referenced as their own
+    /// `source`, so that content is read from the tree beside the manifest.
+    /// Symlinks, special files, state files and the file `leave_out` (the
+    /// one the manifest is being written to) are skipped.
+    pub fn export(root: &Path, max_inline: u64, leave_out: Option<&fs::Metadata>) -> Result<Self> {
+        let mut manifest = Self {
+            include: Vec::new(),
+            entries: Vec::new(),
+        };
+        manifest.export_dir(root, Path::new(""), max_inline, leave_out)?;
+        Ok(manifest)
+    }
+
+    fn export_dir(
+        &mut self,
+        root: &Path,
+        relative: &Path,
+        max_inline: u64,
+        leave_out: Option<&fs::Metadata>,
+    ) -> Result<()> {
+        let dir = root.join(relative);
+        let mut names = fs::read_dir(&dir)
+            .with_context(|| tr!("Failed to read directory"))?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()
+            .with_context(|| tr!("Failed to read directory entry"))?;
+        names.sort();
+        for name in names {
+            let relative = relative.join(&name);
+            let path = root.join(&relative);
+            let metadata = fs::symlink_metadata(&path)
+                .with_context(|| tr!("Failed to read {}", path.display()))?;
+            let key = manifest_key(&relative.to_string_lossy());
+            let mut entry = ManifestEntry {
+                path: key.clone(),
+                mode: cfg!(unix).then(|| format!("{:o}", platform::mode(&metadata))),
+                attrib: windows_attrib(&metadata),
+                ..ManifestEntry::default()
+            };
+            if metadata.is_dir() {
+                entry.dir = true;
+                self.entries.push(entry);
+                self.export_dir(root, &relative, max_inline, leave_out)?;
+                continue;
+            }
+            if !metadata.is_file() {
+                say_err!(
+                    "Warning: Skipped {}, which is not a file or directory",
+                    path.display()
+                );
+                continue;
+            }
+            let state_file = name.to_string_lossy().ends_with(".tap-state");
+            if state_file || leave_out.is_some_and(|leave_out| same_file(leave_out, &metadata)) {
+                continue;
+            }
+            let inline = (metadata.len() <= max_inline)
+                .then(|| fs::read(&path))
+                .transpose()
+                .with_context(|| tr!("Failed to read {}", path.display()))?
+                .and_then(|content| String::from_utf8(content).ok());
+            match inline {
+                Some(content) => entry.content = Some(content),
+                None => entry.source = Some(key),
+            }
+            self.entries.push(entry);
+        }
+        Ok(())
+    }
+
+    /// The manifest as YAML, or with `toml` as TOML.
+    #[cfg(feature = "manifest")]
+    pub fn to_text(&self, toml: bool) -> Result<String> {
+        if toml {
+            toml::to_string(self).with_context(|| tr!("Failed to write the manifest"))
+        } else {
+            serde_yaml_ng::to_string(self).with_context(|| tr!("Failed to write the manifest"))
+        }
+    }
+
+    #[cfg(not(feature = "manifest"))]
+    pub fn to_text(&self, _toml: bool) -> Result<String> {
+        anyhow::bail!(tr!(
+            "Can't write the manifest: tap was built without the manifest feature"
+        ))
+    }
+
+    /// The paths of the entries that apply here, spelled the same way
+    /// however the manifest spells them, so `./a` and `a` are one path.
+    pub fn paths(&self) -> BTreeSet<String> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(condition::holds(entry.when.as_deref()), Ok(true)))
+            .map(|entry| manifest_key(&entry.path))
+            .collect()
+    }
+}
+
+/// The file formats `tap schema` describes.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SchemaKind {
+    /// Manifests, as `tap apply` reads them
+    Manifest,
+    /// The config file
+    Config,
+}
+
+/// The JSON Schema of a manifest or the config file, generated from the
+/// types they are read into, so that editors can complete and check them.
+#[cfg(feature = "schema")]
+pub fn json_schema(kind: SchemaKind) -> Result<String> {
+    let schema = match kind {
+        SchemaKind::Manifest => schemars::schema_for!(Manifest),
+        SchemaKind::Config => schemars::schema_for!(crate::Config),
+    };
+    serde_json::to_string_pretty(&schema).with_context(|| tr!("Failed to write the schema"))
+}
+
+#[cfg(not(feature = "schema"))]
+pub fn json_schema(_kind: SchemaKind) -> Result<String> {
+    anyhow::bail!(tr!(
+        "Can't describe the schema: tap was built without the schema feature"
+    ))
+}
+
+/// The read-only, hidden and system attributes a Windows file has, as
+/// `--attrib` would set them.
+fn windows_attrib(metadata: &fs::Metadata) -> Option<String> {
+    if !cfg!(windows) {
+        return None;
+    }
+    let attributes = platform::attributes(metadata);
+    let set: Vec<_> = [
+        (platform::READ_ONLY, "+r"),
+        (platform::HIDDEN, "+h"),
+        (platform::SYSTEM, "+s"),
+    ]
+    .into_iter()
+    .filter(|(attribute, _)| attributes & attribute != 0)
+    .map(|(_, change)| change)
+    .collect();
+    (!set.is_empty()).then(|| set.join(","))
+}
+
+fn manifest_key(path: &str) -> String {
+    Path::new(path)
+        .components()
+        .filter(|component| !matches!(component, std::path::Component::CurDir))
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// What a manifest last produced, kept beside it in `.NAME.tap-state` so the
+/// next `tap apply` can tell which entries drifted since, and which paths it
+/// no longer manages.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ManifestState {
+    /// What each entry left behind, by its path relative to the manifest's
+    /// directory
+    pub entries: BTreeMap<String, Produced>,
+}
+
+/// What applying an entry left at its path.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Produced {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dir: bool,
+    pub mode: u32,
+    pub owner: (u32, u32),
+    /// A digest of a file's content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+impl Produced {
+    /// What is at `path` now, if anything is.
+    fn capture(path: &Path) -> Option<Self> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        Some(Self {
+            dir: metadata.is_dir(),
+            mode: platform::mode(&metadata),
+            owner: platform::owner(&metadata),
+            content: metadata.is_file().then(|| content_digest(path)).flatten(),
+        })
+    }
+}
+
+impl ManifestState {
+    pub fn path_for(manifest: &Path) -> PathBuf {
+        let name = manifest.file_name().unwrap_or_default().to_string_lossy();
+        parent_dir(manifest).join(format!(".{}.tap-state", name))
+    }
+
+    /// Reads a state file; one that doesn't exist yet records nothing.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| tr!("Invalid state file {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| tr!("Failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = serde_json::to_string_pretty(self).expect("Failed to serialize state") + "\n";
+        fs::write(path, text).with_context(|| tr!("Failed to write {}", path.display()))
+    }
+}
+
+/// Applies the manifest at `path` to its directory, changing only entries
+/// that drifted from what its state file records, and compares the result
+/// with what it produced last time: paths it no
+/// longer has are reported as orphans, or removed with `prune_orphans`, and
+/// the state file is brought up to date. An orphan that is kept stays in
+/// the state, so it is reported again until it is pruned or removed by hand.
+/// A directory is only pruned once it is empty.
+pub fn apply_manifest(
+    path: &Path,
+    base: &FileOp,
+    allow_outside_root: bool,
+    prune_orphans: bool,
+) -> Result<()> {
+    let manifest = Manifest::load(path)?;
+    let root = parent_dir(path);
+    let state_path = ManifestState::path_for(path);
+    let mut state = ManifestState::load(&state_path)?;
+    manifest.apply(root, base, allow_outside_root, &state)?;
+
+    let paths = manifest.paths();
+    let mut entries: BTreeMap<_, _> = paths
+        .iter()
+        .filter_map(|path| Some((path.clone(), Produced::capture(&root.join(path))?)))
+        .collect();
+    let mut orphans: Vec<_> = state
+        .entries
+        .keys()
+        .filter(|orphan| !paths.contains(*orphan))
+        .cloned()
+        .collect();
+    // Deepest first, so a directory is emptied before it is pruned
+    orphans.sort_by_key(|orphan| std::cmp::Reverse(orphan.matches('/').count()));
+    let report_only = base.dry_run || base.check;
+    for orphan in orphans {
+        let target = root.join(&orphan);
+        let Ok(metadata) = fs::symlink_metadata(&target) else {
+            // Already gone, so there is nothing left to manage
+            continue;
+        };
+        if !prune_orphans {
+            say!("Orphaned (no longer in the manifest): {}", target.display());
+        } else if !allow_outside_root && !stays_inside(root, Path::new(&orphan)) {
+            say_err!(
+                "Warning: Kept orphan {}, which is outside {}",
+                target.display(),
+                root.display()
+            );
+        } else if report_only {
+            say!("Would remove orphan: {}", target.display());
+        } else {
+            let removed = if metadata.is_dir() {
+                fs::remove_dir(&target)
+            } else {
+                fs::remove_file(&target)
+            };
+            match removed {
+                Ok(()) => {
+                    say!("Removed orphan: {}", target.display());
+                    continue;
+                }
+                Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => say_err!(
+                    "Warning: Kept orphaned directory {}, which isn't empty",
+                    target.display()
+                ),
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| tr!("Failed to remove orphan {}", target.display()))
+                }
+            }
+        }
+        if let Some(produced) = state.entries.remove(&orphan) {
+            entries.insert(orphan, produced);
+        }
+    }
+    if !report_only {
+        ManifestState { entries }.save(&state_path)?;
+    }
+    Ok(())
+}
+
+/// Whether a relative path stays below `root` once joined to it: it has no
+/// `..` climbing out and isn't absolute, and no symlink already in the tree
+/// leads it elsewhere.
+fn stays_inside(root: &Path, path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+    let Ok(root) = root.canonicalize() else {
+        // Nothing of the tree exists yet, so nothing in it can be a symlink
+        return true;
+    };
+    // The deepest part that exists is where the rest will be created
+    root.join(path)
+        .ancestors()
+        .find_map(|ancestor| ancestor.canonicalize().ok())
+        .is_some_and(|real| real.starts_with(&root))
+}
+
+#[cfg(all(test, any(feature = "manifest", feature = "schema")))]
+mod tests {
+    use super::*;
+    #[cfg(all(unix, feature = "manifest"))]
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    #[cfg(feature = "manifest")]
+    use std::time::{Duration, SystemTime};
+    #[cfg(feature = "manifest")]
+    use tempfile::tempdir;
+
+    #[cfg(feature = "manifest")]
+    use crate::{parse_timestamp, Config};
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn test_apply_manifest() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        fs::write(root.join("header.tpl"), "// generated\n")?;
+        fs::write(
+            root.join("layout.toml"),
+            r#"
+[[entries]]
+path = "src"
+dir = true
+mode = "750"
+
+[[entries]]
+path = "src/main.rs"
+template = "header.tpl"
+
+[[entries]]
+path = "README.md"
+content = "hello\n"
+mode = "600"
+timestamp = "2023-05-01 12:00:00"
+"#,
+        )?;
+        let manifest = Manifest::load(&root.join("layout.toml"))?;
+        manifest.apply(root, &FileOp::default(), false, &ManifestState::default())?;
+
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(root.join("src"))?.permissions().mode() & 0o777,
+            0o750
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("src/main.rs"))?,
+            "// generated\n"
+        );
+        let readme = fs::metadata(root.join("README.md"))?;
+        #[cfg(unix)]
+        assert_eq!(readme.permissions().mode() & 0o777, 0o600);
+        assert_eq!(readme.modified()?, parse_timestamp("2023-05-01 12:00:00")?);
+
+        // Re-applying leaves matching files alone and repairs drift
+        let unchanged = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        filetime::set_file_mtime(root.join("src/main.rs"), unchanged.into())?;
+        fs::write(root.join("README.md"), "edited\n")?;
+        manifest.apply(root, &FileOp::default(), false, &ManifestState::default())?;
+        assert_eq!(
+            fs::metadata(root.join("src/main.rs"))?.modified()?,
+            unchanged
+        );
+        assert_eq!(fs::read_to_string(root.join("README.md"))?, "hello\n");
+
+        fs::write(
+            root.join("escape.yaml"),
+            "entries:\n  - path: ../outside.txt\n",
+        )?;
+        let escape = Manifest::load(&root.join("escape.yaml"))?;
+        assert!(escape
+            .apply(root, &FileOp::default(), false, &ManifestState::default())
+            .is_err());
+
+        // A symlink in the tree, or a template from outside it, escapes too
+        let outside = tempdir()?;
+        fs::write(outside.path().join("secret.tpl"), "secret\n")?;
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outside.path(), root.join("link"))?;
+            fs::write(
+                root.join("linked.toml"),
+                "[[entries]]\npath = \"link/x.txt\"\n",
+            )?;
+            let linked = Manifest::load(&root.join("linked.toml"))?;
+            assert!(linked
+                .apply(root, &FileOp::default(), false, &ManifestState::default())
+                .is_err());
+            assert!(!outside.path().join("x.txt").exists());
+        }
+        let template = Path::new("..")
+            .join(outside.path().file_name().unwrap())
+            .join("secret.tpl");
+        fs::write(
+            root.join("leak.toml"),
+            format!(
+                "[[entries]]\npath = \"leak.txt\"\ntemplate = \"{}\"\n",
+                template.display()
+            ),
+        )?;
+        let leak = Manifest::load(&root.join("leak.toml"))?;
+        assert!(leak
+            .apply(root, &FileOp::default(), false, &ManifestState::default())
+            .is_err());
+        assert!(!root.join("leak.txt").exists());
+        leak.apply(root, &FileOp::default(), true, &ManifestState::default())?;
+        assert_eq!(fs::read_to_string(root.join("leak.txt"))?, "secret\n");
+
+        fs::write(
+            root.join("typo.toml"),
+            "[[entries]]\npath = \"a\"\nchmmod = \"644\"\n",
+        )?;
+        assert!(Manifest::load(&root.join("typo.toml")).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn test_export_manifest() -> Result<()> {
+        let dir = tempdir()?;
+        let tree = dir.path().join("tree");
+        fs::create_dir_all(tree.join("src"))?;
+        fs::create_dir(tree.join("empty"))?;
+        fs::write(tree.join("src/main.rs"), "fn main() {}\n")?;
+        fs::write(tree.join("big.txt"), "x".repeat(100))?;
+        fs::write(tree.join("blob.bin"), [0xff, 0x00, 0xfe])?;
+        fs::write(tree.join(".old.yaml.tap-state"), "{}")?;
+        #[cfg(unix)]
+        fs::set_permissions(tree.join("src/main.rs"), fs::Permissions::from_mode(0o600))?;
+
+        let manifest = Manifest::export(&tree, 64, None)?;
+        let paths: Vec<_> = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            ["big.txt", "blob.bin", "empty", "src", "src/main.rs"]
+        );
+        let main = &manifest.entries[4];
+        assert_eq!(main.content.as_deref(), Some("fn main() {}\n"));
+        #[cfg(unix)]
+        assert_eq!(main.mode.as_deref(), Some("600"));
+        assert_eq!(manifest.entries[0].source.as_deref(), Some("big.txt"));
+        assert_eq!(manifest.entries[1].source.as_deref(), Some("blob.bin"));
+        assert!(manifest.entries[2].dir);
+
+        // Saved beside the files it references, it recreates the tree
+        let copy = dir.path().join("copy");
+        fs::create_dir(&copy)?;
+        for (name, toml) in [("layout.yaml", false), ("layout.toml", true)] {
+            fs::write(copy.join(name), manifest.to_text(toml)?)?;
+        }
+        fs::copy(tree.join("big.txt"), copy.join("big.txt"))?;
+        fs::copy(tree.join("blob.bin"), copy.join("blob.bin"))?;
+        for name in ["layout.yaml", "layout.toml"] {
+            Manifest::load(&copy.join(name))?.apply(
+                &copy,
+                &FileOp::default(),
+                false,
+                &ManifestState::default(),
+            )?;
+            assert_eq!(fs::read(copy.join("blob.bin"))?, [0xff, 0x00, 0xfe]);
+            assert!(copy.join("empty").is_dir());
+            assert_eq!(
+                fs::read_to_string(copy.join("src/main.rs"))?,
+                "fn main() {}\n"
+            );
+            #[cfg(unix)]
+            assert_eq!(
+                fs::metadata(copy.join("src/main.rs"))?.permissions().mode() & 0o777,
+                0o600
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn test_manifest_orphans() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        let manifest = root.join("layout.yaml");
+        let state = ManifestState::path_for(&manifest);
+        assert_eq!(state, root.join(".layout.yaml.tap-state"));
+        fs::write(
+            &manifest,
+            "entries:\n  - path: keep.txt\n  - path: old\n    dir: true\n  - path: ./old/a.txt\n  - path: gone.txt\n",
+        )?;
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        assert_eq!(
+            ManifestState::load(&state)?
+                .entries
+                .into_keys()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(["gone.txt", "keep.txt", "old", "old/a.txt"].map(String::from))
+        );
+
+        // Dropped entries are kept, and stay recorded, until they are pruned
+        fs::write(&manifest, "entries:\n  - path: keep.txt\n")?;
+        fs::remove_file(root.join("gone.txt"))?;
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        assert!(root.join("old/a.txt").exists());
+        assert_eq!(ManifestState::load(&state)?.entries.len(), 3);
+
+        let dry_run = FileOp {
+            dry_run: true,
+            ..FileOp::default()
+        };
+        apply_manifest(&manifest, &dry_run, false, true)?;
+        assert!(root.join("old/a.txt").exists());
+
+        // A directory holding files tap didn't make is kept
+        fs::write(root.join("old/mine.txt"), "")?;
+        apply_manifest(&manifest, &FileOp::default(), false, true)?;
+        assert!(!root.join("old/a.txt").exists());
+        assert!(root.join("old").is_dir());
+        assert_eq!(
+            ManifestState::load(&state)?
+                .entries
+                .into_keys()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(["keep.txt", "old"].map(String::from))
+        );
+        fs::remove_file(root.join("old/mine.txt"))?;
+        apply_manifest(&manifest, &FileOp::default(), false, true)?;
+        assert!(!root.join("old").exists());
+        assert_eq!(
+            ManifestState::load(&state)?
+                .entries
+                .into_keys()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(["keep.txt".to_string()])
+        );
+        Ok(())
+    }
+
+    #[cfg(all(unix, feature = "manifest"))]
+    #[test]
+    fn test_manifest_reapply_changes_only_drift() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        let manifest = root.join("layout.yaml");
+        fs::write(
+            &manifest,
+            "entries:\n  - path: e\n  - path: d\n    dir: true\n  - path: c.txt\n    content: hi\n    mode: '600'\n",
+        )?;
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        let past = filetime::FileTime::from_unix_time(1_577_836_800, 0);
+        for name in ["e", "c.txt"] {
+            filetime::set_file_mtime(root.join(name), past)?;
+        }
+
+        // Nothing drifted, so nothing is touched
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        for name in ["e", "c.txt"] {
+            let metadata = fs::metadata(root.join(name))?;
+            assert_eq!(
+                filetime::FileTime::from_last_modification_time(&metadata),
+                past
+            );
+        }
+
+        // Drifted content and modes are put back
+        fs::write(root.join("c.txt"), "changed")?;
+        fs::set_permissions(root.join("c.txt"), fs::Permissions::from_mode(0o644))?;
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        assert_eq!(fs::read_to_string(root.join("c.txt"))?, "hi");
+        assert_eq!(fs::metadata(root.join("c.txt"))?.mode() & 0o777, 0o600);
+        let state = ManifestState::load(&ManifestState::path_for(&manifest))?;
+        assert_eq!(
+            state.entries["c.txt"],
+            Produced::capture(&root.join("c.txt")).unwrap()
+        );
+        assert!(state.entries["d"].dir);
+
+        // And an entry removed by hand is made again
+        fs::remove_file(root.join("e"))?;
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        assert!(root.join("e").is_file());
+        Ok(())
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn test_conditional_entries() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        let manifest = root.join("layout.yaml");
+        fs::write(
+            &manifest,
+            format!(
+                "entries:\n  - path: here.txt\n    when: os == '{}'\n  - path: elsewhere.txt\n    when: os != '{0}'\n",
+                std::env::consts::OS
+            ),
+        )?;
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        assert!(root.join("here.txt").exists());
+        assert!(!root.join("elsewhere.txt").exists());
+        assert_eq!(
+            ManifestState::load(&ManifestState::path_for(&manifest))?
+                .entries
+                .into_keys()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from(["here.txt".to_string()])
+        );
+
+        fs::write(&manifest, "entries:\n  - path: a\n    when: os = 'linux'\n")?;
+        let error = format!("{:#}", Manifest::load(&manifest).unwrap_err());
+        assert!(error.contains("Invalid entry a"), "{error}");
+
+        let config_path = root.join("config.toml");
+        fs::write(
+            &config_path,
+            r##"
+[presets.never]
+content = "x"
+when = "os == 'none'"
+
+[[rules]]
+match = "*.sh"
+content = "#!/bin/sh\n"
+when = "env.TAP_TEST_UNSET_VARIABLE"
+"##,
+        )?;
+        let config = Config::load_from(&config_path)?;
+        let error = config.preset("never").unwrap_err().to_string();
+        assert!(error.contains("doesn't apply here"), "{error}");
+        let mut op = FileOp::default();
+        config.apply_to(&mut op);
+        let script = root.join("deploy.sh");
+        op.apply(&script)?;
+        assert_eq!(fs::read_to_string(&script)?, "");
+
+        fs::write(&config_path, "[presets.bad]\nwhen = \"os ==\"\n")?;
+        assert!(Config::load_from(&config_path).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn test_manifest_includes() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().join("project");
+        let org = dir.path().join("org");
+        fs::create_dir_all(&root)?;
+        fs::create_dir_all(&org)?;
+        fs::write(org.join("license.txt"), "MIT\n")?;
+        fs::write(
+            org.join("base.yaml"),
+            "entries:\n  - path: LICENSE\n    source: license.txt\n  - path: README.md\n    content: base\n",
+        )?;
+        let manifest = root.join("layout.toml");
+        fs::write(
+            &manifest,
+            "include = [\"../org/base.yaml\"]\n\n[[entries]]\npath = \"./README.md\"\ncontent = \"mine\"\n",
+        )?;
+        let loaded = Manifest::load(&manifest)?;
+        assert_eq!(
+            loaded
+                .entries
+                .iter()
+                .map(|e| e.path.as_str())
+                .collect::<Vec<_>>(),
+            ["LICENSE", "./README.md"]
+        );
+        apply_manifest(&manifest, &FileOp::default(), false, false)?;
+        assert_eq!(fs::read_to_string(root.join("LICENSE"))?, "MIT\n");
+        assert_eq!(fs::read_to_string(root.join("README.md"))?, "mine");
+
+        // An included manifest's sources stay inside its own directory
+        fs::write(
+            org.join("base.yaml"),
+            "entries:\n  - path: secret\n    source: ../project/layout.toml\n",
+        )?;
+        let error = format!(
+            "{:#}",
+            apply_manifest(&manifest, &FileOp::default(), false, false).unwrap_err()
+        );
+        assert!(error.contains("is outside"), "{error}");
+
+        fs::write(
+            org.join("base.yaml"),
+            "include: [\"../project/layout.toml\"]\n",
+        )?;
+        let error = format!("{:#}", Manifest::load(&manifest).unwrap_err());
+        assert!(error.contains("Include cycle"), "{error}");
+        assert!(error.contains("layout.toml -> "), "{error}");
+        Ok(())
+    }
+
+    #[cfg(feature = "manifest")]
+    #[test]
+    fn test_manifest_errors() -> Result<()> {
+        let dir = tempdir()?;
+        let yaml = dir.path().join("layout.yaml");
+        let toml = dir.path().join("layout.toml");
+        let config = dir.path().join("config.toml");
+        for (path, text, error) in [
+            (
+                &yaml,
+                "entries:\n  - path: x\n    tempalte: t\n",
+                "entries[0]: unknown key 'tempalte' at line 3, column 5, did you mean 'template'?",
+            ),
+            (
+                &toml,
+                "[[entries]]\npath = \"x\"\n  modes = \"644\"\n",
+                "unknown key 'modes' at line 3, column 3, did you mean 'mode'?",
+            ),
+            (
+                &yaml,
+                "entries:\n  - path: x\n    colour: red\n",
+                "unknown key 'colour' at line 3, column 5, expected one of path, when,",
+            ),
+            (
+                &yaml,
+                "entries:\n  - path: x\n    dir: maybe\n",
+                "expected a boolean at line 3, column 10",
+            ),
+            (&toml, "entries = [\n", "at line 1, column 12"),
+            (
+                &config,
+                "file_mod = \"644\"\n",
+                "unknown key 'file_mod' at line 1, column 1, did you mean 'file_mode'?",
+            ),
+        ] {
+            fs::write(path, text)?;
+            let result = if path == &config {
+                Config::load_from(path).map(|_| ())
+            } else {
+                Manifest::load(path).map(|_| ())
+            };
+            let message = format!("{:#}", result.unwrap_err());
+            assert!(message.contains(error), "{message}");
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "schema")]
+    #[test]
+    fn test_json_schema() -> Result<()> {
+        let manifest: serde_json::Value =
+            serde_json::from_str(&json_schema(SchemaKind::Manifest)?)?;
+        let entry = &manifest["$defs"]["ManifestEntry"];
+        assert_eq!(entry["additionalProperties"], false);
+        assert_eq!(entry["required"], serde_json::json!(["path"]));
+        let keys: BTreeSet<_> = entry["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        // Every key an entry can have, and none it can't, such as `from`
+        assert_eq!(
+            keys,
+            BTreeSet::from([
+                "path",
+                "when",
+                "dir",
+                "content",
+                "template",
+                "source",
+                "mode",
+                "attrib",
+                "owner",
+                "timestamp"
+            ])
+        );
+
+        let config: serde_json::Value = serde_json::from_str(&json_schema(SchemaKind::Config)?)?;
+        assert_eq!(
+            config["$defs"]["Rule"]["properties"]["match"]["type"],
+            "string"
+        );
+        assert_eq!(
+            config["properties"]["presets"]["additionalProperties"]["$ref"],
+            "#/$defs/Preset"
+        );
+        Ok(())
+    }
+}
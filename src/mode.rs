@@ -0,0 +1,204 @@
+//! File modes as `chmod` takes them: octal (`644`) or symbolic
+//! (`u+x,go-w`, `a=r`, `g=u`).
+
+use anyhow::{Context, Result};
+
+use crate::tr;
+
+/// A parsed `--chmod` value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mode {
+    /// Replaces the whole mode.
+    Octal(u32),
+    /// Changes the current mode, one clause after another.
+    Symbolic(Vec<Clause>),
+}
+
+/// One comma-separated part of a symbolic mode, such as `go-w` or `u=rw+x`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clause {
+    /// The bits the clause may touch, from `u`, `g`, `o` and `a`
+    who: u32,
+    actions: Vec<(char, Perms)>,
+}
+
+/// What an action adds, removes or sets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Perms {
+    /// From `r`, `w`, `x`, `s` and `t`, with whether `X` was given
+    Bits(u32, bool),
+    /// The permissions one class already has, as in `g=u`
+    Copy(u32),
+}
+
+const USER: u32 = 0o4700;
+const GROUP: u32 = 0o2070;
+const OTHER: u32 = 0o1007;
+
+impl Mode {
+    pub fn parse(input: &str) -> Result<Self> {
+        if !input.is_empty() && input.bytes().all(|b| b.is_ascii_digit()) {
+            let mode = u32::from_str_radix(input, 8)
+                .ok()
+                .filter(|mode| *mode <= 0o7777)
+                .with_context(|| tr!("Invalid chmod value: {}", input))?;
+            return Ok(Self::Octal(mode));
+        }
+        input
+            .split(',')
+            .map(|clause| {
+                Clause::parse(clause)
+                    .with_context(|| tr!("Invalid chmod clause '{}' in: {}", clause, input))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Self::Symbolic)
+    }
+
+    /// The mode a file with `current` permission bits ends up with. `X`
+    /// only adds execute permission to directories and to files that are
+    /// already executable by someone.
+    pub fn apply(&self, current: u32, is_dir: bool) -> u32 {
+        let clauses = match self {
+            Self::Octal(mode) => return *mode,
+            Self::Symbolic(clauses) => clauses,
+        };
+        let mut mode = current & 0o7777;
+        for clause in clauses {
+            for &(op, perms) in &clause.actions {
+                let bits = match perms {
+                    Perms::Bits(bits, conditional_x) => {
+                        let x = conditional_x && (is_dir || mode & 0o111 != 0);
+                        (bits | if x { 0o111 } else { 0 }) & clause.who
+                    }
+                    // Spread the source class's rwx over every class
+                    Perms::Copy(source) => {
+                        let rwx = match source {
+                            USER => mode >> 6,
+                            GROUP => mode >> 3,
+                            _ => mode,
+                        } & 0o7;
+                        (rwx << 6 | rwx << 3 | rwx) & clause.who
+                    }
+                };
+                mode = match op {
+                    '+' => mode | bits,
+                    '-' => mode & !bits,
+                    _ => mode & !clause.who | bits,
+                };
+            }
+        }
+        mode
+    }
+}
+
+impl Clause {
+    fn parse(input: &str) -> Result<Self> {
+        let split = input.find(['+', '-', '=']).unwrap_or(input.len());
+        let (classes, mut rest) = input.split_at(split);
+        let mut who = 0;
+        for class in classes.chars() {
+            who |= match class {
+                'u' => USER,
+                'g' => GROUP,
+                'o' => OTHER,
+                'a' => USER | GROUP | OTHER,
+                other => anyhow::bail!(tr!("'{}' is not one of u, g, o or a", other)),
+            };
+        }
+        // Like `chmod +x`, no class means all of them
+        if who == 0 {
+            who = USER | GROUP | OTHER;
+        }
+        if rest.is_empty() {
+            anyhow::bail!(tr!("Expected +, - or = after '{}'", classes));
+        }
+
+        let mut actions = Vec::new();
+        while let Some(op) = rest.chars().next() {
+            rest = &rest[1..];
+            let end = rest.find(['+', '-', '=']).unwrap_or(rest.len());
+            let (perms, next) = rest.split_at(end);
+            rest = next;
+            let perms = match perms {
+                "u" => Perms::Copy(USER),
+                "g" => Perms::Copy(GROUP),
+                "o" => Perms::Copy(OTHER),
+                perms => {
+                    let mut bits = 0;
+                    let mut conditional_x = false;
+                    for perm in perms.chars() {
+                        bits |= match perm {
+                            'r' => 0o444,
+                            'w' => 0o222,
+                            'x' => 0o111,
+                            's' => 0o6000,
+                            't' => 0o1000,
+                            'X' => {
+                                conditional_x = true;
+                                0
+                            }
+                            other => anyhow::bail!(tr!(
+                                "'{}' is not one of r, w, x, X, s, t (or a single u, g or o)",
+                                other
+                            )),
+                        };
+                    }
+                    Perms::Bits(bits, conditional_x)
+                }
+            };
+            actions.push((op, perms));
+        }
+        Ok(Self { who, actions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbolic_modes() -> Result<()> {
+        let cases: &[(&str, u32, bool, u32)] = &[
+            ("644", 0o777, false, 0o644),
+            ("u+x", 0o644, false, 0o744),
+            ("+x", 0o644, false, 0o755),
+            ("go-w", 0o777, false, 0o755),
+            ("a=r", 0o755, false, 0o444),
+            ("u=rw,go=r", 0o700, false, 0o644),
+            ("u+x,go-w", 0o666, false, 0o744),
+            ("o=", 0o777, false, 0o770),
+            ("g=u", 0o740, false, 0o770),
+            ("o=g-w", 0o770, false, 0o775),
+            ("a+X", 0o644, false, 0o644),
+            ("a+X", 0o644, true, 0o755),
+            ("a+X", 0o744, false, 0o755),
+            ("u+s,+t", 0o755, false, 0o5755),
+            ("g+s", 0o755, true, 0o2755),
+            ("u=rwx,g=rx,o=", 0o7777, false, 0o750),
+        ];
+        for (mode, current, is_dir, expected) in cases {
+            let applied = Mode::parse(mode)?.apply(*current, *is_dir);
+            assert_eq!(
+                applied, *expected,
+                "{} on {:o}: {:o}",
+                mode, current, applied
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_modes() {
+        for mode in [
+            "", "89", "17777", "u", "ug", "u+q", "z+x", "u+x,", "u+ug", ",",
+        ] {
+            assert!(Mode::parse(mode).is_err(), "{}", mode);
+        }
+        let error = format!("{:#}", Mode::parse("u+x,g+q").unwrap_err());
+        assert!(
+            error.contains("'g+q'") && error.contains("'q'"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Announcing that a run finished, on the desktop or to a webhook, for long
+//! jobs nobody is watching.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::platform;
+use crate::tr;
+
+/// What a run did, as posted to `--notify-url`.
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    /// Paths processed, including one that failed
+    pub paths: usize,
+    /// Paths created or changed, or under `dry_run` that would have been
+    pub changed: usize,
+    pub dry_run: bool,
+    pub seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RunSummary {
+    /// The summary as one line, such as "Processed 40 path(s), 3 changed, in 1.2s".
+    pub fn message(&self) -> String {
+        let seconds = format!("{:.1}", self.seconds);
+        let message = if self.dry_run {
+            tr!(
+                "Processed {} path(s), {} would change, in {}s",
+                self.paths,
+                self.changed,
+                seconds
+            )
+        } else {
+            tr!(
+                "Processed {} path(s), {} changed, in {}s",
+                self.paths,
+                self.changed,
+                seconds
+            )
+        };
+        match &self.error {
+            Some(error) => tr!("{}; failed: {}", message, error),
+            None => message,
+        }
+    }
+}
+
+pub fn desktop(summary: &RunSummary) -> Result<()> {
+    let title = if summary.error.is_some() {
+        tr!("tap failed")
+    } else {
+        tr!("tap finished")
+    };
+    platform::show_notification(&title, &summary.message())
+        .with_context(|| tr!("Failed to show desktop notification"))
+}
+
+/// Fails early on URLs `post` can't deliver to, so the run doesn't start.
+#[cfg(feature = "net")]
+pub fn check_url(url: &str) -> Result<()> {
+    if !crate::is_url(url) {
+        anyhow::bail!(tr!(
+            "--notify-url must be an http:// or https:// URL: {}",
+            url
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "net"))]
+pub fn check_url(_url: &str) -> Result<()> {
+    anyhow::bail!(tr!("--notify-url needs tap built with the net feature"))
+}
+
+/// POSTs the summary as JSON.
+#[cfg(feature = "net")]
+pub fn post(url: &str, summary: &RunSummary) -> Result<()> {
+    let body =
+        serde_json::to_vec(summary).with_context(|| tr!("Failed to serialize run summary"))?;
+    crate::http::post_json(url, &body).with_context(|| tr!("Failed to post run summary to {}", url))
+}
+
+#[cfg(not(feature = "net"))]
+pub fn post(url: &str, _summary: &RunSummary) -> Result<()> {
+    check_url(url)
+}
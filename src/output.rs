@@ -0,0 +1,448 @@
+//! How results are printed: as text, or one record per path in JSON, CSV
+//! or TSV, with timestamps shown as `--time-format` and `--time-style` say.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    capture_output, plain_text, platform, process_path_as, show_size, tr, FileOp, UserAccount,
+};
+
+/// How command results are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Csv,
+    Tsv,
+    /// One JSON object per line
+    Json,
+}
+
+/// One result of a command, printable as a line of text, a table row or a
+/// JSON object.
+pub trait Record: Serialize {
+    /// Column names, in the order `values` returns them
+    fn columns() -> &'static [&'static str];
+    fn values(&self) -> Vec<String>;
+    fn text(&self) -> String;
+}
+
+/// Renders records in `format`; tables start with a header row.
+pub fn render<R: Record>(records: &[R], format: OutputFormat) -> String {
+    std::iter::once(render_header::<R>(format))
+        .chain(records.iter().map(|record| render_row(record, format)))
+        .collect()
+}
+
+/// The header row of a table of `R`, or nothing for formats without one.
+pub fn render_header<R: Record>(format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text | OutputFormat::Json => String::new(),
+        OutputFormat::Csv | OutputFormat::Tsv => table_row(R::columns(), format),
+    }
+}
+
+/// One record as a line of `format`, including the newline.
+pub fn render_row<R: Record>(record: &R, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => plain_text(&record.text()).into_owned() + "\n",
+        // Records only hold strings, numbers and paths serialized as strings
+        OutputFormat::Json => {
+            serde_json::to_string(record).expect("Failed to serialize record") + "\n"
+        }
+        OutputFormat::Csv | OutputFormat::Tsv => table_row(&record.values(), format),
+    }
+}
+
+fn table_row(values: &[impl AsRef<str>], format: OutputFormat) -> String {
+    let separator = if format == OutputFormat::Tsv {
+        "\t"
+    } else {
+        ","
+    };
+    values
+        .iter()
+        .map(|value| table_field(value.as_ref(), format))
+        .collect::<Vec<_>>()
+        .join(separator)
+        + "\n"
+}
+
+fn serialize_path<S: serde::Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+fn serialize_mode<S: serde::Serializer>(mode: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:04o}", mode))
+}
+
+/// Quotes a CSV field (RFC 4180) or escapes a TSV one, whose fields can't
+/// contain tabs or newlines.
+fn table_field(value: &str, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Csv if value.contains([',', '"', '\n', '\r']) => {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        }
+        OutputFormat::Tsv => value
+            .replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r"),
+        _ => value.to_string(),
+    }
+}
+
+/// Default strftime format for timestamps shown to the user (local time)
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum TimeStyle {
+    /// Formatted with the time format
+    #[default]
+    Absolute,
+    /// Like "3 minutes ago"
+    Relative,
+}
+
+/// How timestamps are shown in output, as opposed to how `parse_timestamp`
+/// reads them.
+#[derive(Clone, Debug)]
+pub struct TimeDisplay {
+    format: String,
+    style: TimeStyle,
+}
+
+impl Default for TimeDisplay {
+    fn default() -> Self {
+        Self {
+            format: DEFAULT_TIME_FORMAT.to_string(),
+            style: TimeStyle::Absolute,
+        }
+    }
+}
+
+impl TimeDisplay {
+    pub fn new(format: Option<&str>, style: TimeStyle) -> Result<Self> {
+        let format = format.unwrap_or(DEFAULT_TIME_FORMAT);
+        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+            anyhow::bail!(tr!("Invalid time format: {}", format));
+        }
+        Ok(Self {
+            format: format.to_string(),
+            style,
+        })
+    }
+
+    pub fn show(&self, time: SystemTime) -> String {
+        self.show_at(time, SystemTime::now())
+    }
+
+    /// Like `show`, with relative times measured from `now`.
+    pub fn show_at(&self, time: SystemTime, now: SystemTime) -> String {
+        match self.style {
+            TimeStyle::Absolute => DateTime::<Local>::from(time)
+                .format(&self.format)
+                .to_string(),
+            TimeStyle::Relative => relative_time(time, now),
+        }
+    }
+}
+
+fn relative_time(time: SystemTime, now: SystemTime) -> String {
+    const UNITS: [(&str, u64); 6] = [
+        ("year", 365 * 86400),
+        ("month", 30 * 86400),
+        ("week", 7 * 86400),
+        ("day", 86400),
+        ("hour", 3600),
+        ("minute", 60),
+    ];
+    let (seconds, future) = match now.duration_since(time) {
+        Ok(elapsed) => (elapsed.as_secs(), false),
+        Err(error) => (error.duration().as_secs(), true),
+    };
+    if seconds == 0 {
+        return "just now".to_string();
+    }
+    let (unit, count) = UNITS
+        .iter()
+        .find(|(_, length)| seconds >= *length)
+        .map_or(("second", seconds), |(unit, length)| {
+            (*unit, seconds / length)
+        });
+    let plural = if count == 1 { "" } else { "s" };
+    if future {
+        format!("in {} {}{}", count, unit, plural)
+    } else {
+        format!("{} {}{} ago", count, unit, plural)
+    }
+}
+
+#[derive(Serialize)]
+pub struct StatRecord {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    #[serde(serialize_with = "serialize_mode")]
+    pub mode: u32,
+    /// Size, human-readable unless the record was made with `exact_sizes`
+    pub size: String,
+    /// Modification time, as shown by the `TimeDisplay` it was made with
+    pub modified: String,
+    pub accessed: String,
+    /// Alternate data streams, which only Windows files have
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub streams: Vec<StreamRecord>,
+}
+
+#[derive(Serialize)]
+pub struct StreamRecord {
+    pub name: String,
+    pub size: String,
+}
+
+impl StatRecord {
+    pub fn new(
+        path: &Path,
+        metadata: &fs::Metadata,
+        times: &TimeDisplay,
+        exact_sizes: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode: platform::mode(metadata),
+            size: show_size(metadata.len(), exact_sizes),
+            modified: times.show(metadata.modified()?),
+            accessed: times.show(metadata.accessed()?),
+            streams: Vec::new(),
+        })
+    }
+}
+
+impl Record for StatRecord {
+    fn columns() -> &'static [&'static str] {
+        &["path", "mode", "size", "modified", "accessed"]
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.path.display().to_string(),
+            format!("{:o}", self.mode),
+            self.size.clone(),
+            self.modified.clone(),
+            self.accessed.clone(),
+        ]
+    }
+
+    fn text(&self) -> String {
+        let mut text = format!(
+            "{}  mode {:o}  size {}  modified {}  accessed {}",
+            self.path.display(),
+            self.mode,
+            self.size,
+            self.modified,
+            self.accessed
+        );
+        if !self.streams.is_empty() {
+            let streams: Vec<_> = self
+                .streams
+                .iter()
+                .map(|stream| format!("{} ({})", stream.name, stream.size))
+                .collect();
+            text += &format!("  streams {}", streams.join(", "));
+        }
+        text
+    }
+}
+
+#[derive(Serialize)]
+pub struct CheckRecord {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub exists: bool,
+}
+
+impl Record for CheckRecord {
+    fn columns() -> &'static [&'static str] {
+        &["path", "exists"]
+    }
+
+    fn values(&self) -> Vec<String> {
+        vec![self.path.display().to_string(), self.exists.to_string()]
+    }
+
+    fn text(&self) -> String {
+        let state = if self.exists {
+            "Exists"
+        } else {
+            "Does not exist"
+        };
+        format!("{}: {}", state, self.path.display())
+    }
+}
+
+/// What a run did to one path, printed instead of the usual messages when
+/// `--output` selects a structured format.
+#[derive(Serialize)]
+pub struct PathRecord {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    /// `created`, `updated`, `unchanged` or `failed`
+    pub action: &'static str,
+    pub mode_before: Option<String>,
+    pub mode_after: Option<String>,
+    /// Modification times, in RFC 3339 unless `--time-format` or
+    /// `--time-style` says how to show them
+    pub mtime_before: Option<String>,
+    pub mtime_after: Option<String>,
+    pub error: Option<String>,
+}
+
+impl PathRecord {
+    /// Processes `path` with `op` for `owner` and records how its mode and
+    /// mtime changed.
+    pub(crate) fn process(
+        path: &Path,
+        op: &FileOp,
+        owner: Option<&UserAccount>,
+    ) -> (Self, Result<()>) {
+        let state = || {
+            fs::symlink_metadata(path).ok().map(|metadata| {
+                let modified = metadata.modified().ok();
+                (format!("{:04o}", platform::mode(&metadata)), modified)
+            })
+        };
+        let display = (op.time_format.is_some() || op.time_style != TimeStyle::Absolute)
+            .then(|| op.time_display().ok())
+            .flatten();
+        let show = |time: Option<SystemTime>| {
+            time.map(|time| match &display {
+                Some(display) => display.show(time),
+                None => DateTime::<Utc>::from(time).to_rfc3339(),
+            })
+        };
+        let before = state();
+        let (result, _) = capture_output(|| process_path_as(path, op, owner));
+        let after = state();
+
+        let action = match (&result, &before) {
+            (Err(_), _) => "failed",
+            (Ok(()), None) if after.is_some() => "created",
+            _ if before == after => "unchanged",
+            _ => "updated",
+        };
+        let (mode_before, mtime_before) = before.unzip();
+        let (mode_after, mtime_after) = after.unzip();
+        let record = Self {
+            path: path.to_path_buf(),
+            action,
+            mode_before,
+            mode_after,
+            mtime_before: show(mtime_before.flatten()),
+            mtime_after: show(mtime_after.flatten()),
+            error: result.as_ref().err().map(|error| format!("{:#}", error)),
+        };
+        (record, result)
+    }
+}
+
+impl Record for PathRecord {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "path",
+            "action",
+            "mode_before",
+            "mode_after",
+            "mtime_before",
+            "mtime_after",
+            "error",
+        ]
+    }
+
+    fn values(&self) -> Vec<String> {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        vec![
+            self.path.display().to_string(),
+            self.action.to_string(),
+            optional(&self.mode_before),
+            optional(&self.mode_after),
+            optional(&self.mtime_before),
+            optional(&self.mtime_after),
+            optional(&self.error),
+        ]
+    }
+
+    fn text(&self) -> String {
+        format!("{}: {}", self.action, self.path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::parse_timestamp;
+
+    #[test]
+    fn test_render_tables() {
+        let records = [
+            CheckRecord {
+                path: PathBuf::from("plain.txt"),
+                exists: true,
+            },
+            CheckRecord {
+                path: PathBuf::from("odd, \"name\"\tx"),
+                exists: false,
+            },
+        ];
+        assert_eq!(
+            render(&records, OutputFormat::Csv),
+            "path,exists\nplain.txt,true\n\"odd, \"\"name\"\"\tx\",false\n"
+        );
+        assert_eq!(
+            render(&records, OutputFormat::Tsv),
+            "path\texists\nplain.txt\ttrue\nodd, \"name\"\\tx\tfalse\n"
+        );
+        assert_eq!(
+            render(&records, OutputFormat::Text),
+            "Exists: plain.txt\nDoes not exist: odd, \"name\"\tx\n"
+        );
+    }
+
+    #[test]
+    fn test_time_display() -> Result<()> {
+        let now = SystemTime::now();
+        let relative = TimeDisplay::new(None, TimeStyle::Relative)?;
+        assert_eq!(relative.show_at(now, now), "just now");
+        assert_eq!(
+            relative.show_at(now - Duration::from_secs(180), now),
+            "3 minutes ago"
+        );
+        assert_eq!(
+            relative.show_at(now - Duration::from_secs(3600), now),
+            "1 hour ago"
+        );
+        assert_eq!(
+            relative.show_at(now + Duration::from_secs(2 * 86400), now),
+            "in 2 days"
+        );
+        assert_eq!(
+            relative.show_at(now - Duration::from_secs(5), now),
+            "5 seconds ago"
+        );
+
+        let custom = TimeDisplay::new(Some("%Y/%m/%d"), TimeStyle::Absolute)?;
+        let time = parse_timestamp("2023-05-01 12:00:00")?;
+        assert_eq!(custom.show(time), "2023/05/01");
+        assert!(TimeDisplay::new(Some("%Q"), TimeStyle::Absolute).is_err());
+        Ok(())
+    }
+}
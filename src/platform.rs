@@ -0,0 +1,96 @@
+//! The operations tap performs differently on Unix and Windows, behind one
+//! set of functions. Each system's side lives in `sys/`, and only the one
+//! being built for is compiled; the Unix side sticks to what POSIX and
+//! libc give every Unix, so static musl builds and ARM targets need no
+//! patches. What varies from one machine or filesystem to the next, such
+//! as whether flock works or a syslog daemon is listening, is checked when
+//! it is used rather than assumed from the target.
+//!
+//! Windows has no permission bits, only a read-only attribute: a file reads
+//! as `0o444` when it is set and `0o666` when it isn't (directories add the
+//! execute bits), and setting a mode only sets or clears the attribute from
+//! the owner's write bit. The other way round, Unix only has the read-only
+//! one of Windows' file attributes, as a file without write bits.
+
+use std::ffi::OsStr;
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+#[path = "sys/unix.rs"]
+mod sys;
+
+#[cfg(windows)]
+#[path = "sys/windows.rs"]
+mod sys;
+
+pub use sys::*;
+
+/// The file attributes `--attrib` changes, with their Windows values.
+pub const READ_ONLY: u32 = 0x1;
+pub const HIDDEN: u32 = 0x2;
+pub const SYSTEM: u32 = 0x4;
+pub const ARCHIVE: u32 = 0x20;
+
+/// Modes given when creating files and directories, which Windows ignores.
+pub trait ModeExt {
+    fn with_mode(&mut self, mode: u32) -> &mut Self;
+}
+
+/// Set by the first Ctrl-C (or, on Unix, SIGTERM) once `catch_interrupts`
+/// is in place.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether an interrupt was caught, asking tap to stop at the next safe point.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Whether Windows reads `name` as a device rather than a file, in any
+/// directory and with any extension: `nul.txt` is `NUL`. Trailing dots and
+/// spaces are dropped first, as Windows does.
+pub fn is_reserved_name(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    let name = name.trim_end_matches(['.', ' ']);
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$" => true,
+        _ => ["COM", "LPT"].iter().any(|device| {
+            upper.strip_prefix(device).is_some_and(|number| {
+                let mut chars = number.chars();
+                chars
+                    .next()
+                    .is_some_and(|c| "123456789\u{b9}\u{b2}\u{b3}".contains(c))
+                    && chars.next().is_none()
+            })
+        }),
+    }
+}
+
+/// The first part of `path` Windows would open as a device, unless the path
+/// is verbatim (`\\?\C:\...`), which Windows takes as written.
+pub fn reserved_component(path: &Path) -> Option<&OsStr> {
+    if is_verbatim(&path.to_string_lossy()) {
+        return None;
+    }
+    path.components().find_map(|component| match component {
+        Component::Normal(name) if is_reserved_name(name) => Some(name),
+        _ => None,
+    })
+}
+
+/// The alternate data stream a Windows `file.txt:stream` path names, without
+/// the `:$DATA` type it may be spelled with.
+pub fn data_stream(path: &Path) -> Option<&str> {
+    let (_, stream) = path.file_name()?.to_str()?.split_once(':')?;
+    let stream = stream.strip_suffix(":$DATA").unwrap_or(stream);
+    Some(stream).filter(|stream| !stream.is_empty())
+}
+
+/// Whether `path` starts with `\\?\`, which has Windows skip its parsing
+/// of the path: no `..`, no `/` as a separator, no device names and no length
+/// limit.
+pub fn is_verbatim(path: &str) -> bool {
+    path.starts_with(r"\\?\")
+}
@@ -0,0 +1,128 @@
+//! Limits on the commands tap runs on behalf of files it didn't write
+//! itself: `shell()` in templates and presets, which may come from a shared
+//! config, and `tap on-change` commands.
+//!
+//! A sandboxed command starts with an empty environment but for `PATH` (and
+//! `SystemRoot` on Windows, without which little runs there) and the
+//! variables it is told to keep, in a fixed working directory, and on Linux
+//! without network access.
+
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::platform;
+
+/// Variables every sandboxed command keeps.
+const KEPT_ENV: &[&str] = if cfg!(windows) {
+    &["PATH", "SystemRoot"]
+} else {
+    &["PATH"]
+};
+
+/// How a command is restricted, from `--sandbox` or a preset's `sandbox`
+/// table. An empty table restricts everything it can.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct Sandbox {
+    /// Environment variables to keep besides `PATH`
+    #[serde(default)]
+    pub keep_env: Vec<String>,
+    /// Directory the command runs in (default: the one tap started in)
+    pub dir: Option<PathBuf>,
+    /// Leave the command its network access
+    #[serde(default)]
+    pub network: bool,
+}
+
+impl Sandbox {
+    /// A command running `program` under these limits.
+    pub fn command(&self, program: impl AsRef<OsStr>) -> Command {
+        let mut command = Command::new(program);
+        command.env_clear();
+        let kept = KEPT_ENV
+            .iter()
+            .copied()
+            .chain(self.keep_env.iter().map(String::as_str));
+        for name in kept {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        if !self.network {
+            platform::isolate_network(&mut command);
+        }
+        command
+    }
+
+    /// `command` under `sandbox` if there is one, or as it is.
+    pub fn command_in(sandbox: Option<&Self>, program: impl AsRef<OsStr>) -> Command {
+        match sandbox {
+            Some(sandbox) => sandbox.command(program),
+            None => Command::new(program),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sandboxed_command() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        std::env::set_var("TAP_SANDBOX_KEPT", "kept");
+        std::env::set_var("TAP_SANDBOX_DROPPED", "dropped");
+        let sandbox = Sandbox {
+            keep_env: vec!["TAP_SANDBOX_KEPT".to_string()],
+            dir: Some(dir.path().to_path_buf()),
+            network: true,
+        };
+        let output = sandbox
+            .command("sh")
+            .args([
+                "-c",
+                "echo \"$TAP_SANDBOX_KEPT,$TAP_SANDBOX_DROPPED\"; pwd -P",
+            ])
+            .output()?;
+        assert!(output.status.success());
+        let dir = dir.path().canonicalize()?;
+        assert_eq!(
+            String::from_utf8(output.stdout)?,
+            format!("kept,\n{}\n", dir.display())
+        );
+
+        let output = Sandbox::command_in(None, "sh")
+            .args(["-c", "echo $TAP_SANDBOX_DROPPED"])
+            .output()?;
+        assert_eq!(String::from_utf8(output.stdout)?, "dropped\n");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sandbox_cuts_off_network() -> anyhow::Result<()> {
+        let interfaces = "tail -n +3 /proc/net/dev | cut -d: -f1 | tr -d ' '";
+        let output = match Sandbox::default()
+            .command("sh")
+            .args(["-c", interfaces])
+            .output()
+        {
+            Ok(output) => output,
+            // Namespaces can be turned off, and then nothing runs at all
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EPERM | libc::ENOSPC)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        assert_eq!(String::from_utf8(output.stdout)?, "lo\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+//! `tap test-for` and `tap target`: creating the conventional test file
+//! for a source file, and adding stub targets to Makefiles and justfiles.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{dir_name, parent_dir, replace_content, say, tr, FileOp};
+
+/// Where the tests for a source file belong and what to start them with.
+#[derive(Debug, PartialEq)]
+pub struct TestPairing {
+    pub path: PathBuf,
+    pub content: String,
+    /// The tests go at the end of an existing file (Rust unit tests)
+    /// rather than in a file of their own.
+    pub inline: bool,
+}
+
+impl TestPairing {
+    /// Derives the conventional test location for `source` from its language
+    /// and the layout of the project around it:
+    ///
+    /// - Rust: `tests/<name>.rs` when the crate has a `tests/` directory,
+    ///   otherwise a `#[cfg(test)]` module appended to the source file
+    /// - Go: `<name>_test.go` next to the source, in the same package
+    /// - Python: `tests/test_<name>.py` under the project root (the nearest
+    ///   directory with `pyproject.toml`, `setup.py` or `setup.cfg`)
+    pub fn for_source(source: &Path) -> Result<Self> {
+        let source = std::path::absolute(source)
+            .with_context(|| tr!("Failed to resolve {}", source.display()))?;
+        let name = match source.file_stem().and_then(|stem| stem.to_str()) {
+            Some("mod" | "__init__") => source.parent().map(dir_name).unwrap_or_default(),
+            Some(stem) => stem.to_string(),
+            None => anyhow::bail!(tr!("Not a source file: {}", source.display())),
+        };
+        let dir = parent_dir(&source);
+        match source.extension().and_then(|ext| ext.to_str()) {
+            Some("rs") => {
+                let root = project_root(dir, &["Cargo.toml"])
+                    .with_context(|| tr!("No Cargo.toml above {}", source.display()))?;
+                if source.starts_with(root.join("tests")) {
+                    anyhow::bail!(tr!("Already a test file: {}", source.display()));
+                }
+                if root.join("tests").is_dir() {
+                    Ok(Self {
+                        path: root.join("tests").join(format!("{}.rs", name)),
+                        content: format!("#[test]\nfn {}() {{}}\n", name.replace('-', "_")),
+                        inline: false,
+                    })
+                } else {
+                    Ok(Self {
+                        path: source.clone(),
+                        content: "\n#[cfg(test)]\nmod tests {\n    use super::*;\n\n    #[test]\n    fn it_works() {}\n}\n".to_string(),
+                        inline: true,
+                    })
+                }
+            }
+            Some("go") => {
+                if name.ends_with("_test") {
+                    anyhow::bail!(tr!("Already a test file: {}", source.display()));
+                }
+                let package = fs::read_to_string(&source)
+                    .ok()
+                    .and_then(|code| {
+                        code.lines()
+                            .find_map(|line| line.strip_prefix("package "))
+                            .map(|package| package.trim().to_string())
+                    })
+                    .unwrap_or_else(|| dir_name(dir));
+                let function = name
+                    .split('_')
+                    .map(|word| {
+                        let mut chars = word.chars();
+                        chars.next().map_or(String::new(), |first| {
+                            first.to_uppercase().chain(chars).collect()
+                        })
+                    })
+                    .collect::<String>();
+                Ok(Self {
+                    path: dir.join(format!("{}_test.go", name)),
+                    content: format!(
+                        "package {}\n\nimport \"testing\"\n\nfunc Test{}(t *testing.T) {{\n}}\n",
+                        package, function
+                    ),
+                    inline: false,
+                })
+            }
+            Some("py") => {
+                if name.starts_with("test_") {
+                    anyhow::bail!(tr!("Already a test file: {}", source.display()));
+                }
+                let root =
+                    project_root(dir, &["pyproject.toml", "setup.py", "setup.cfg"]).unwrap_or(dir);
+                let relative = source.strip_prefix(root).unwrap_or(&source);
+                let relative = relative.strip_prefix("src").unwrap_or(relative);
+                let mut module = relative
+                    .with_extension("")
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>();
+                if module.last().is_some_and(|last| last == "__init__") {
+                    module.pop();
+                }
+                Ok(Self {
+                    path: root.join("tests").join(format!("test_{}.py", name)),
+                    content: format!(
+                        "import {}\n\n\ndef test_{}():\n    pass\n",
+                        module.join("."),
+                        name
+                    ),
+                    inline: false,
+                })
+            }
+            _ => anyhow::bail!(tr!("Unsupported language: {}", source.display())),
+        }
+    }
+
+    /// Creates the test file, or appends the test module for inline tests.
+    /// Returns false when the tests already exist and nothing was written.
+    pub fn create(&self, verbose: bool) -> Result<bool> {
+        if self.inline {
+            let code = fs::read_to_string(&self.path)
+                .with_context(|| tr!("Failed to read {}", self.path.display()))?;
+            if code.contains("#[cfg(test)]") {
+                return Ok(false);
+            }
+            let mut file = OpenOptions::new()
+                .append(true)
+                .open(&self.path)
+                .with_context(|| tr!("Failed to open {}", self.path.display()))?;
+            file.write_all(self.content.as_bytes())
+                .with_context(|| tr!("Failed to write test module"))?;
+        } else {
+            fs::create_dir_all(parent_dir(&self.path))
+                .with_context(|| tr!("Failed to create parent directories"))?;
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&self.path);
+            let mut file = match file {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(false),
+                Err(e) => {
+                    return Err(e).with_context(|| tr!("Failed to create {}", self.path.display()))
+                }
+            };
+            file.write_all(self.content.as_bytes())
+                .with_context(|| tr!("Failed to write test file"))?;
+        }
+        if verbose {
+            say!("Test file created: {}", self.path.display());
+        }
+        Ok(true)
+    }
+}
+
+/// The nearest directory at or above `dir` containing one of `markers`.
+fn project_root<'a>(dir: &'a Path, markers: &[&str]) -> Option<&'a Path> {
+    dir.ancestors()
+        .find(|dir| markers.iter().any(|marker| dir.join(marker).exists()))
+}
+
+/// Build files `tap target` knows how to add a target to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildFile {
+    Make,
+    Just,
+}
+
+impl BuildFile {
+    /// Tells the format from the file name (`Makefile`, `GNUmakefile`,
+    /// `*.mk`, `justfile`, `.justfile`, `*.just`).
+    pub fn detect(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        match name.to_ascii_lowercase().as_str() {
+            "makefile" | "gnumakefile" => Ok(Self::Make),
+            "justfile" | ".justfile" => Ok(Self::Just),
+            lower if lower.ends_with(".mk") => Ok(Self::Make),
+            lower if lower.ends_with(".just") => Ok(Self::Just),
+            _ => anyhow::bail!(tr!("Not a Makefile or justfile: {}", path.display())),
+        }
+    }
+
+    /// What a new build file starts with.
+    fn header(self) -> &'static str {
+        match self {
+            Self::Make => "SHELL := /bin/sh\n.SUFFIXES:\n",
+            Self::Just => "default:\n    @just --list\n",
+        }
+    }
+
+    /// An empty rule for `name`. Make recipes must be indented with a tab.
+    fn stub(self, name: &str) -> String {
+        match self {
+            Self::Make => format!(".PHONY: {0}\n{0}:\n\t@echo \"TODO: {0}\"\n", name),
+            Self::Just => format!("{0}:\n    @echo \"TODO: {0}\"\n", name),
+        }
+    }
+
+    fn defines(self, content: &str, name: &str) -> bool {
+        content.lines().any(|line| {
+            let Some(rest) = line.strip_prefix(name) else {
+                return false;
+            };
+            match self {
+                Self::Make => rest.trim_start().starts_with(':'),
+                // just recipes may take parameters: `name arg='x':`
+                Self::Just => rest.starts_with(':') || rest.starts_with(' '),
+            }
+        })
+    }
+}
+
+/// Appends an empty target called `name` to a Makefile or justfile, creating
+/// the file with a header if it doesn't exist. Returns false when the target
+/// is already defined.
+pub fn add_build_target(path: &Path, name: &str, verbose: bool) -> Result<bool> {
+    let format = BuildFile::detect(path)?;
+    let valid = !name.is_empty()
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if !valid {
+        anyhow::bail!(tr!("Invalid target name: {}", name));
+    }
+
+    let mut content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => format.header().to_string(),
+        Err(e) => return Err(e).with_context(|| tr!("Failed to read {}", path.display())),
+    };
+    if format.defines(&content, name) {
+        return Ok(false);
+    }
+    if !content.is_empty() {
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        if !content.ends_with("\n\n") {
+            content.push('\n');
+        }
+    }
+    content.push_str(&format.stub(name));
+    replace_content(path, content.as_bytes(), &FileOp::default())
+        .with_context(|| tr!("Failed to write {}", path.display()))?;
+    if verbose {
+        say!("Target {} added to: {}", name, path.display());
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_test_pairing() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        fs::write(root.join("Cargo.toml"), "[package]\nname = \"demo\"\n")?;
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/parser.rs"), "pub fn parse() {}\n")?;
+
+        // Without tests/, Rust unit tests go inline, once
+        let pairing = TestPairing::for_source(&root.join("src/parser.rs"))?;
+        assert!(pairing.inline);
+        assert!(pairing.create(false)?);
+        assert!(!pairing.create(false)?);
+        let code = fs::read_to_string(root.join("src/parser.rs"))?;
+        assert_eq!(code.matches("#[cfg(test)]").count(), 1);
+
+        fs::create_dir(root.join("tests"))?;
+        let pairing = TestPairing::for_source(&root.join("src/parser.rs"))?;
+        assert_eq!(pairing.path, root.join("tests/parser.rs"));
+        assert!(pairing.create(false)?);
+        assert!(TestPairing::for_source(&root.join("tests/parser.rs")).is_err());
+
+        fs::write(root.join("src/http_client.go"), "package net\n")?;
+        let pairing = TestPairing::for_source(&root.join("src/http_client.go"))?;
+        assert_eq!(pairing.path, root.join("src/http_client_test.go"));
+        assert!(pairing.content.starts_with("package net\n"));
+        assert!(pairing
+            .content
+            .contains("func TestHttpClient(t *testing.T)"));
+
+        fs::write(root.join("pyproject.toml"), "")?;
+        fs::create_dir_all(root.join("src/app"))?;
+        let pairing = TestPairing::for_source(&root.join("src/app/models.py"))?;
+        assert_eq!(pairing.path, root.join("tests/test_models.py"));
+        assert!(pairing.content.starts_with("import app.models\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_build_target() -> Result<()> {
+        let dir = tempdir()?;
+        let makefile = dir.path().join("Makefile");
+        assert!(add_build_target(&makefile, "build", false)?);
+        assert!(add_build_target(&makefile, "test", false)?);
+        assert!(!add_build_target(&makefile, "build", false)?);
+        assert_eq!(
+            fs::read_to_string(&makefile)?,
+            "SHELL := /bin/sh\n.SUFFIXES:\n\n\
+             .PHONY: build\nbuild:\n\t@echo \"TODO: build\"\n\n\
+             .PHONY: test\ntest:\n\t@echo \"TODO: test\"\n"
+        );
+
+        let justfile = dir.path().join("justfile");
+        fs::write(&justfile, "serve port='8080':\n    ./serve {{port}}")?;
+        assert!(!add_build_target(&justfile, "serve", false)?);
+        assert!(add_build_target(&justfile, "lint", false)?);
+        assert!(fs::read_to_string(&justfile)?
+            .ends_with("{{port}}\n\nlint:\n    @echo \"TODO: lint\"\n"));
+
+        assert!(add_build_target(&dir.path().join("build.sh"), "x", false).is_err());
+        assert!(add_build_target(&makefile, "bad name", false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,333 @@
+//! `tap serve --stdio`: a long-running JSON-RPC 2.0 server for editor
+//! plugins, so creating a file or previewing a template doesn't start a
+//! process each time. Requests and responses are one JSON object per line;
+//! templates stay cached between requests and are read again once edited.
+//!
+//! Methods:
+//!
+//! - `initialize`: tap's version and the methods below
+//! - `templates/list`: the templates in the templates directory, as
+//!   `{"templates": [{"name", "path"}]}`
+//! - `templates/render`: `{"template" | "content", "target", "vars"}` to
+//!   `{"content"}`, without writing anything
+//! - `file/create`: `{"path", "template" | "content", "vars", "mode",
+//!   "dry_run", "overwrite"}` to `{"path", "actions", "messages"}`, where
+//!   `actions` is what `--dry-run` would list. An existing file is an error
+//!   unless `overwrite` is set
+//! - `shutdown`: answers, then stops the server
+//!
+//! Requests without an `id` are notifications and get no response.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    capture_output, default_templates_dir, list_templates, plan_actions, render_inline,
+    render_template, resolve_template, tr, FileOp, UserAccount,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// An operation that failed, such as a template that doesn't render
+const FAILED: i64 = -32000;
+
+const METHODS: &[&str] = &[
+    "initialize",
+    "templates/list",
+    "templates/render",
+    "file/create",
+    "shutdown",
+];
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RenderParams {
+    template: Option<String>,
+    content: Option<String>,
+    target: PathBuf,
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CreateParams {
+    path: PathBuf,
+    template: Option<String>,
+    content: Option<String>,
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+    mode: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+/// An error response's code and message.
+struct Failure(i64, String);
+
+impl From<anyhow::Error> for Failure {
+    fn from(error: anyhow::Error) -> Self {
+        Self(FAILED, format!("{error:#}"))
+    }
+}
+
+/// Answers the requests read from `input` on `output` until `shutdown` or
+/// the end of the input. Every request runs on top of the options in
+/// `base`, such as the templates directory and `--var`s.
+pub fn serve(input: impl BufRead, mut output: impl Write, base: &FileOp) -> Result<()> {
+    for line in input.lines() {
+        let line = line.with_context(|| tr!("Failed to read a request"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (response, shutdown) = answer(&line, base);
+        if let Some(response) = response {
+            writeln!(output, "{response}")
+                .and_then(|()| output.flush())
+                .with_context(|| tr!("Failed to write a response"))?;
+        }
+        if shutdown {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The response to one line, if it needs one, and whether it asked the
+/// server to stop.
+fn answer(line: &str, base: &FileOp) -> (Option<Value>, bool) {
+    let respond = |id: Value, outcome: Result<Value, Failure>| match outcome {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(Failure(code, message)) => {
+            json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+        }
+    };
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(error) => {
+            let failure = Failure(PARSE_ERROR, error.to_string());
+            return (Some(respond(Value::Null, Err(failure))), false);
+        }
+    };
+    let id = request.get("id").cloned();
+    let request = match serde_json::from_value::<Request>(request) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(_) => {
+            let failure = Failure(INVALID_REQUEST, tr!("Only JSON-RPC 2.0 is supported"));
+            return (Some(respond(id.unwrap_or_default(), Err(failure))), false);
+        }
+        Err(error) => {
+            let failure = Failure(INVALID_REQUEST, error.to_string());
+            return (Some(respond(id.unwrap_or_default(), Err(failure))), false);
+        }
+    };
+    let outcome = call(&request.method, request.params, base);
+    let shutdown = request.method == "shutdown";
+    (request.id.map(|id| respond(id, outcome)), shutdown)
+}
+
+fn call(method: &str, params: Value, base: &FileOp) -> Result<Value, Failure> {
+    match method {
+        "initialize" => Ok(json!({
+            "name": "tap",
+            "version": env!("CARGO_PKG_VERSION"),
+            "methods": METHODS,
+        })),
+        "templates/list" => Ok(list(base)?),
+        "templates/render" => Ok(render(parse_params(params)?, base)?),
+        "file/create" => Ok(create(parse_params(params)?, base)?),
+        "shutdown" => Ok(Value::Null),
+        _ => Err(Failure(METHOD_NOT_FOUND, tr!("Unknown method: {}", method))),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, Failure> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|error| Failure(INVALID_PARAMS, error.to_string()))
+}
+
+fn list(base: &FileOp) -> Result<Value> {
+    let dir = match &base.templates_dir {
+        Some(dir) => dir.clone(),
+        None => default_templates_dir()?,
+    };
+    let templates: Vec<_> = list_templates(&dir)?
+        .into_iter()
+        .map(|name| {
+            json!({
+                "name": name.with_extension("").to_string_lossy(),
+                "path": dir.join(&name).to_string_lossy(),
+            })
+        })
+        .collect();
+    Ok(json!({ "templates": templates }))
+}
+
+/// `base` with the request's variables, which win over `--var`s.
+fn with_vars(base: &FileOp, vars: BTreeMap<String, String>) -> FileOp {
+    let mut op = base.clone();
+    op.template_vars.extend(vars);
+    op
+}
+
+fn render(params: RenderParams, base: &FileOp) -> Result<Value> {
+    let op = with_vars(base, params.vars);
+    let target = &params.target;
+    let content = match (&params.template, &params.content) {
+        (Some(template), None) => render_template(&resolve_template(&op, template), target, &op)?,
+        (None, Some(content)) => render_inline(content, target, &op)?,
+        (Some(_), Some(_)) => anyhow::bail!(tr!("Give a template or content, not both")),
+        (None, None) => anyhow::bail!(tr!("Give a template or content")),
+    };
+    Ok(json!({ "content": content }))
+}
+
+fn create(params: CreateParams, base: &FileOp) -> Result<Value> {
+    if params.template.is_some() && params.content.is_some() {
+        anyhow::bail!(tr!("Give a template or content, not both"));
+    }
+    let path = &params.path;
+    if !params.overwrite && fs::symlink_metadata(path).is_ok() {
+        anyhow::bail!(tr!("{} already exists", path.display()));
+    }
+    let mut op = with_vars(base, params.vars);
+    op.template = params.template.or(op.template);
+    op.template_text = params.content.or(op.template_text);
+    op.chmod = params.mode.or(op.chmod);
+    op.dry_run |= params.dry_run;
+
+    let owner = op.as_user.as_deref().map(UserAccount::lookup).transpose()?;
+    let actions = plan_actions(path, &op, owner.as_ref())?;
+    let messages = if op.dry_run {
+        Vec::new()
+    } else {
+        let (result, messages) = capture_output(|| op.apply(path));
+        result?;
+        messages.lines().map(str::to_string).collect()
+    };
+    Ok(json!({
+        "path": Path::new(path).to_string_lossy(),
+        "actions": actions,
+        "messages": messages,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "template-engine")]
+    use tempfile::tempdir;
+
+    fn session(base: &FileOp, requests: &[Value]) -> Vec<Value> {
+        let input: String = requests
+            .iter()
+            .map(|request| format!("{request}\n"))
+            .collect();
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, base).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[cfg(feature = "template-engine")]
+    #[test]
+    fn test_serve() -> Result<()> {
+        let dir = tempdir()?;
+        let templates = dir.path().join("templates");
+        fs::create_dir_all(templates.join("rust"))?;
+        fs::write(templates.join("rust/module.rs"), "// {{ name }}\n")?;
+        let base = FileOp {
+            templates_dir: Some(templates.clone()),
+            ..FileOp::default()
+        };
+        let target = dir.path().join("src/lib.rs");
+        let responses = session(
+            &base,
+            &[
+                json!({"jsonrpc": "2.0", "id": 1, "method": "templates/list"}),
+                json!({"jsonrpc": "2.0", "id": 2, "method": "templates/render", "params": {
+                    "template": "rust/module", "target": target, "vars": {"name": "preview"}
+                }}),
+                json!({"jsonrpc": "2.0", "id": 3, "method": "file/create", "params": {
+                    "path": target, "template": "rust/module", "vars": {"name": "lib"}, "dry_run": true
+                }}),
+                json!({"jsonrpc": "2.0", "method": "file/create", "params": {
+                    "path": target, "template": "rust/module", "vars": {"name": "lib"}
+                }}),
+                json!({"jsonrpc": "2.0", "id": 4, "method": "file/create", "params": {
+                    "path": target, "content": "x"
+                }}),
+                json!({"jsonrpc": "2.0", "id": 5, "method": "shutdown"}),
+                json!({"jsonrpc": "2.0", "id": 6, "method": "initialize"}),
+            ],
+        );
+        // The notification gets no response, and nothing is read after
+        // shutdown
+        assert_eq!(responses.len(), 5);
+        assert_eq!(
+            responses[0]["result"]["templates"][0]["name"],
+            "rust/module"
+        );
+        assert_eq!(responses[1]["result"]["content"], "// preview\n");
+        let actions = responses[2]["result"]["actions"].as_array().unwrap();
+        assert!(actions.contains(&json!("create file")), "{actions:?}");
+        assert_eq!(fs::read_to_string(&target)?, "// lib\n");
+        assert!(responses[3]["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("already exists"));
+        assert_eq!(responses[4]["id"], 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_errors() {
+        let base = FileOp::default();
+        let responses = session(&base, &[]);
+        assert!(responses.is_empty());
+
+        let mut output = Vec::new();
+        let input = "not json\n{\"jsonrpc\": \"1.0\", \"id\": 1, \"method\": \"initialize\"}\n\
+            {\"jsonrpc\": \"2.0\", \"id\": 2, \"method\": \"nope\"}\n\
+            {\"jsonrpc\": \"2.0\", \"id\": 3, \"method\": \"templates/render\", \"params\": {\"target\": 1}}\n";
+        serve(input.as_bytes(), &mut output, &base).unwrap();
+        let codes: Vec<_> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["error"]["code"].clone())
+            .collect();
+        assert_eq!(
+            codes,
+            [
+                PARSE_ERROR,
+                INVALID_REQUEST,
+                METHOD_NOT_FOUND,
+                INVALID_PARAMS
+            ]
+            .map(|code| json!(code))
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Checking templates fetched from URLs against minisign signatures, and
+//! the public keys trusted to make them.
+//!
+//! A template at `URL` is signed in `URL.minisig`, as `minisign -S` writes
+//! it. Keys are trusted by listing them in the config's `trusted_keys`, or
+//! with `tap key add`, which keeps them one per line in `trusted-keys`
+//! beside the config file. Content without a good signature from one of
+//! them is refused, unless `--insecure` says to take it anyway.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+#[cfg(feature = "net")]
+use crate::{http, say_err};
+use crate::{tr, xdg_base_dir};
+
+/// The file `tap key` keeps trusted keys in.
+pub fn keys_file() -> Result<PathBuf> {
+    Ok(
+        xdg_base_dir("XDG_CONFIG_HOME", ".config", |var| std::env::var_os(var))?
+            .join("tap/trusted-keys"),
+    )
+}
+
+/// The keys `tap key add` has stored, in the order they were added. Blank
+/// lines and `#` comments are skipped.
+pub fn stored_keys() -> Result<Vec<String>> {
+    let path = keys_file()?;
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| tr!("Failed to read {}", path.display())),
+    }
+}
+
+fn store_keys(keys: &[String]) -> Result<()> {
+    let path = keys_file()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| tr!("Failed to create {}", dir.display()))?;
+    }
+    let text: String = keys.iter().map(|key| format!("{}\n", key)).collect();
+    fs::write(&path, text).with_context(|| tr!("Failed to write {}", path.display()))
+}
+
+/// The public key `key` gives: the base64 key itself, or the key in a
+/// `minisign.pub` file at that path.
+pub fn parse_key(key: &str) -> Result<String> {
+    let key = match fs::read_to_string(key) {
+        Ok(text) => text
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+            .unwrap_or_default()
+            .to_string(),
+        Err(_) => key.trim().to_string(),
+    };
+    check_key(&key)?;
+    Ok(key)
+}
+
+#[cfg(feature = "net")]
+fn check_key(key: &str) -> Result<()> {
+    minisign_verify::PublicKey::from_base64(key)
+        .map(drop)
+        .map_err(|error| anyhow::anyhow!(tr!("Invalid minisign public key {}: {}", key, error)))
+}
+
+#[cfg(not(feature = "net"))]
+fn check_key(_key: &str) -> Result<()> {
+    anyhow::bail!(tr!(
+        "Can't check signatures: tap was built without the net feature"
+    ))
+}
+
+/// Trusts `key` (see `parse_key`) from now on. Returns the key, and whether
+/// it wasn't trusted already.
+pub fn add_key(key: &str) -> Result<(String, bool)> {
+    let key = parse_key(key)?;
+    let mut keys = stored_keys()?;
+    if keys.contains(&key) {
+        return Ok((key, false));
+    }
+    keys.push(key.clone());
+    store_keys(&keys)?;
+    Ok((key, true))
+}
+
+/// Stops trusting `key`, returning whether it was trusted.
+pub fn remove_key(key: &str) -> Result<bool> {
+    let key = key.trim();
+    let mut keys = stored_keys()?;
+    let before = keys.len();
+    keys.retain(|stored| stored != key);
+    if keys.len() == before {
+        return Ok(false);
+    }
+    store_keys(&keys)?;
+    Ok(true)
+}
+
+/// Checks that `signature`, the text of a `.minisig` file, signs `content`
+/// with one of `keys`.
+#[cfg(feature = "net")]
+pub fn verify(content: &[u8], signature: &str, keys: &[String]) -> Result<()> {
+    let signature = minisign_verify::Signature::decode(signature)
+        .map_err(|error| anyhow::anyhow!(tr!("Invalid signature: {}", error)))?;
+    let mut last_error = None;
+    for key in keys {
+        let key = minisign_verify::PublicKey::from_base64(key).map_err(|error| {
+            anyhow::anyhow!(tr!("Invalid minisign public key {}: {}", key, error))
+        })?;
+        match key.verify(content, &signature, false) {
+            Ok(()) => return Ok(()),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    match last_error {
+        Some(minisign_verify::Error::UnexpectedKeyId) | None => {
+            anyhow::bail!(tr!("Not signed by a trusted key"))
+        }
+        Some(error) => anyhow::bail!(tr!("Bad signature: {}", error)),
+    }
+}
+
+/// Fetches `url`, refusing it unless `URL.minisig` signs it with one of
+/// `keys` or the stored keys. With `insecure`, it is taken unchecked.
+#[cfg(feature = "net")]
+pub fn fetch_signed(url: &str, keys: &[String], insecure: bool) -> Result<Vec<u8>> {
+    let content = http::get(url).with_context(|| tr!("Failed to fetch {}", url))?;
+    if insecure {
+        say_err!("Warning: Using {} without checking its signature", url);
+        return Ok(content);
+    }
+    let keys = [keys, &stored_keys()?].concat();
+    if keys.is_empty() {
+        anyhow::bail!(tr!(
+            "No trusted keys to check {} with (add one with tap key add, or pass --insecure)",
+            url
+        ));
+    }
+    let signature_url = format!("{}.minisig", url);
+    let signature = http::get(&signature_url)
+        .with_context(|| tr!("Failed to fetch the signature {}", signature_url))?;
+    let signature = String::from_utf8_lossy(&signature);
+    verify(&content, &signature, &keys).with_context(|| tr!("Refused {}", url))?;
+    Ok(content)
+}
+
+#[cfg(not(feature = "net"))]
+pub fn fetch_signed(url: &str, _keys: &[String], _insecure: bool) -> Result<Vec<u8>> {
+    anyhow::bail!(tr!(
+        "Can't fetch {}: tap was built without the net feature",
+        url
+    ))
+}
+
+#[cfg(all(test, feature = "net"))]
+mod tests {
+    use super::*;
+
+    // The example key and signature of the minisign-verify crate, which
+    // sign the four bytes "test"
+    const KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1633700835\tfile:test\tprehashed
+wLMDjy9FLAuxZ3q4NlEvkgtyhrr0gtTu6KC4KBJdITbbOeAi1zBIYo0v4iTgt8jJpIidRJnp94ABQkJAgAooBQ==";
+    // Valid, but not the key that made SIGNATURE
+    const OTHER_KEY: &str = "RWQAAAAAAAAAAASLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaV";
+
+    #[test]
+    fn test_verify() {
+        let key = [KEY.to_string()];
+        assert!(verify(b"test", SIGNATURE, &key).is_ok());
+        assert!(verify(b"tampered", SIGNATURE, &key).is_err());
+        assert!(verify(b"test", "not a signature", &key).is_err());
+        assert!(verify(b"test", SIGNATURE, &[]).is_err());
+
+        let other = [OTHER_KEY.to_string(), KEY.to_string()];
+        assert!(verify(b"test", SIGNATURE, &other).is_ok());
+        let error = verify(b"test", SIGNATURE, &other[..1]).unwrap_err();
+        assert_eq!(error.to_string(), "Not signed by a trusted key");
+    }
+
+    #[test]
+    fn test_parse_key() -> Result<()> {
+        assert_eq!(parse_key(&format!(" {} ", KEY))?, KEY);
+        assert!(parse_key("not a key").is_err());
+
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("minisign.pub");
+        fs::write(
+            &file,
+            format!("untrusted comment: minisign public key\n{}\n", KEY),
+        )?;
+        assert_eq!(parse_key(&file.to_string_lossy())?, KEY);
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+//! `tap snapshot`: recording the state of matched paths and reporting
+//! what changed since.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{expand_paths, parent_dir, state_dir, tr, PathState};
+
+/// Metadata and hashes of the paths matched by some patterns at one point in
+/// time, for finding out later what changed.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    patterns: Vec<String>,
+    entries: std::collections::BTreeMap<PathBuf, PathState>,
+}
+
+/// How strictly recorded state is compared with what is on disk now.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ComparePolicy {
+    /// Modification times this close together count as equal, for
+    /// filesystems that store them coarsely (FAT keeps 2s, some NFS servers
+    /// 1s).
+    pub mtime_tolerance: Duration,
+}
+
+impl ComparePolicy {
+    pub fn same_time(&self, a: SystemTime, b: SystemTime) -> bool {
+        let difference = a
+            .duration_since(b)
+            .unwrap_or_else(|earlier| earlier.duration());
+        difference <= self.mtime_tolerance
+    }
+
+    /// Compares RFC 3339 timestamps as recorded in snapshots and reports.
+    fn same_recorded_time(&self, a: &str, b: &str) -> bool {
+        let parse = |time: &str| DateTime::parse_from_rfc3339(time).map(SystemTime::from);
+        match (parse(a), parse(b)) {
+            (Ok(a), Ok(b)) => self.same_time(a, b),
+            _ => a == b,
+        }
+    }
+}
+
+/// One difference between a snapshot and the current state.
+#[derive(Debug, PartialEq)]
+pub enum Drift {
+    Added(PathBuf),
+    Removed(PathBuf),
+    /// The path and what changed about it
+    Modified(PathBuf, Vec<String>),
+}
+
+impl std::fmt::Display for Drift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added(path) => write!(f, "added     {}", path.display()),
+            Self::Removed(path) => write!(f, "removed   {}", path.display()),
+            Self::Modified(path, changes) => {
+                write!(f, "modified  {} ({})", path.display(), changes.join(", "))
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    /// Where the snapshot called `name` is kept.
+    pub fn path_for(name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            anyhow::bail!(tr!("Invalid snapshot name: {}", name));
+        }
+        Ok(state_dir()?
+            .join("snapshots")
+            .join(format!("{}.json", name)))
+    }
+
+    /// Records the paths matching `patterns`. Relative patterns are made
+    /// absolute so the snapshot can be compared from any directory.
+    pub fn capture(patterns: &[String]) -> Result<Self> {
+        let cwd =
+            std::env::current_dir().with_context(|| tr!("Failed to get current directory"))?;
+        let patterns = patterns
+            .iter()
+            .map(|pattern| cwd.join(pattern).to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let entries = Self::matches(&patterns)?;
+        Ok(Self { patterns, entries })
+    }
+
+    fn matches(patterns: &[String]) -> Result<std::collections::BTreeMap<PathBuf, PathState>> {
+        Ok(expand_paths(patterns)?
+            .into_iter()
+            .filter_map(|path| PathState::capture(&path).map(|state| (path, state)))
+            .collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn save(&self, destination: &Path) -> Result<()> {
+        fs::create_dir_all(parent_dir(destination))
+            .with_context(|| tr!("Failed to create snapshot directory"))?;
+        let json = serde_json::to_string_pretty(self)
+            .with_context(|| tr!("Failed to serialize snapshot"))?;
+        fs::write(destination, json + "\n").with_context(|| tr!("Failed to write snapshot"))
+    }
+
+    pub fn load(source: &Path) -> Result<Self> {
+        let json = fs::read_to_string(source)
+            .with_context(|| tr!("Failed to read snapshot {}", source.display()))?;
+        serde_json::from_str(&json).with_context(|| tr!("Invalid snapshot {}", source.display()))
+    }
+
+    /// Re-reads the snapshot's patterns and reports what was added, removed,
+    /// or modified (kind, content, mode, owner, modification time). Access
+    /// times are left out: merely reading a file changes them.
+    pub fn diff(&self, policy: &ComparePolicy) -> Result<Vec<Drift>> {
+        let current = Self::matches(&self.patterns)?;
+        let mut drift = Vec::new();
+        for (path, before) in &self.entries {
+            let Some(after) = current.get(path) else {
+                drift.push(Drift::Removed(path.clone()));
+                continue;
+            };
+            let mut changes = Vec::new();
+            if before.kind != after.kind {
+                changes.push(format!("{} -> {}", before.kind, after.kind));
+            } else if before.sha256 != after.sha256 || before.size != after.size {
+                changes.push("content".to_string());
+            }
+            if before.mode != after.mode {
+                changes.push(format!("mode {} -> {}", before.mode, after.mode));
+            }
+            if (before.uid, before.gid) != (after.uid, after.gid) {
+                changes.push(format!(
+                    "owner {}:{} -> {}:{}",
+                    before.uid, before.gid, after.uid, after.gid
+                ));
+            }
+            if !policy.same_recorded_time(&before.modified, &after.modified) {
+                changes.push("mtime".to_string());
+            }
+            if !changes.is_empty() {
+                drift.push(Drift::Modified(path.clone(), changes));
+            }
+        }
+        drift.extend(
+            current
+                .keys()
+                .filter(|path| !self.entries.contains_key(*path))
+                .map(|path| Drift::Added(path.clone())),
+        );
+        Ok(drift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(all(unix, feature = "hash"))]
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_snapshot_diff() -> Result<()> {
+        let dir = tempdir()?;
+        let kept = dir.path().join("kept.conf");
+        let edited = dir.path().join("edited.conf");
+        let removed = dir.path().join("removed.conf");
+        for path in [&kept, &edited, &removed] {
+            fs::write(path, "original")?;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o644))?;
+        }
+        let pattern = dir.path().join("*.conf").to_string_lossy().into_owned();
+        let snapshot = Snapshot::capture(&[pattern])?;
+        let saved = dir.path().join("state/snapshots/install.json");
+        snapshot.save(&saved)?;
+        assert_eq!(Snapshot::load(&saved)?.len(), 3);
+
+        fs::write(&edited, "changed!")?;
+        fs::set_permissions(&edited, fs::Permissions::from_mode(0o600))?;
+        fs::remove_file(&removed)?;
+        fs::write(dir.path().join("added.conf"), "")?;
+
+        let drift = Snapshot::load(&saved)?.diff(&ComparePolicy::default())?;
+        assert_eq!(drift.len(), 3);
+        assert!(drift.contains(&Drift::Removed(removed)));
+        assert!(drift.contains(&Drift::Added(dir.path().join("added.conf"))));
+        let Some(Drift::Modified(path, changes)) = drift
+            .iter()
+            .find(|drift| matches!(drift, Drift::Modified(..)))
+        else {
+            panic!("edited.conf not reported: {:?}", drift);
+        };
+        assert_eq!(path, &edited);
+        assert_eq!(changes[0], "content");
+        assert_eq!(changes[1], "mode 0644 -> 0600");
+        assert!(Snapshot::path_for("../escape").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_policy_mtime_tolerance() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("fat.txt");
+        fs::write(&file, "")?;
+        let recorded = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        filetime::set_file_mtime(&file, recorded.into())?;
+        let pattern = file.to_string_lossy().into_owned();
+        let snapshot = Snapshot::capture(&[pattern])?;
+
+        // A FAT-style round to the next even second
+        filetime::set_file_mtime(&file, (recorded + Duration::from_millis(1500)).into())?;
+        assert_eq!(snapshot.diff(&ComparePolicy::default())?.len(), 1);
+        let lenient = ComparePolicy {
+            mtime_tolerance: Duration::from_secs(2),
+        };
+        assert!(snapshot.diff(&lenient)?.is_empty());
+        assert!(!lenient.same_time(recorded, recorded + Duration::from_secs(3)));
+        Ok(())
+    }
+}
@@ -0,0 +1,619 @@
+//! The Unix side of `platform`, written against what POSIX and libc give
+//! every Unix alike (glibc or musl, any architecture), so static and cross
+//! builds need nothing extra. Linux-only calls are marked as such.
+
+use std::ffi::{CStr, CString};
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use super::{ModeExt, INTERRUPTED, READ_ONLY};
+use crate::{say_err, tr, FsInfo, UserAccount};
+
+/// The environment variables naming the user's home directory, in the order
+/// they are tried.
+pub const HOME_VARS: &[&str] = &["HOME"];
+
+impl ModeExt for OpenOptions {
+    fn with_mode(&mut self, mode: u32) -> &mut Self {
+        self.mode(mode)
+    }
+}
+
+impl ModeExt for DirBuilder {
+    fn with_mode(&mut self, mode: u32) -> &mut Self {
+        self.mode(mode)
+    }
+}
+
+/// The permission bits of a file, including setuid, setgid and sticky.
+pub fn mode(metadata: &fs::Metadata) -> u32 {
+    metadata.mode() & 0o7777
+}
+
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// A file reads as read-only when no one can write it.
+pub fn attributes(metadata: &fs::Metadata) -> u32 {
+    if mode(metadata) & 0o222 == 0 {
+        READ_ONLY
+    } else {
+        0
+    }
+}
+
+/// Setting read-only clears every write bit and clearing it sets the
+/// owner's; the other attributes have no Unix equivalent.
+pub fn set_attributes(path: &Path, set: u32, clear: u32) -> io::Result<()> {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if (set | clear) & !READ_ONLY != 0 && !WARNED.swap(true, Ordering::Relaxed) {
+        say_err!("Warning: Unix only has the read-only attribute; hidden, system and archive are ignored");
+    }
+    let current = mode(&fs::metadata(path)?);
+    let new = if set & READ_ONLY != 0 {
+        current & !0o222
+    } else if clear & READ_ONLY != 0 {
+        current | 0o200
+    } else {
+        current
+    };
+    if new == current {
+        return Ok(());
+    }
+    set_mode(path, new)
+}
+
+/// The user and group IDs owning a file.
+pub fn owner(metadata: &fs::Metadata) -> (u32, u32) {
+    (metadata.uid(), metadata.gid())
+}
+
+/// Changes the owner of `path` itself, not of a symlink's target.
+pub fn lchown(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    std::os::unix::fs::lchown(path, uid, gid)
+}
+
+/// Whether this process may give files to `uid`: as root, or when they are
+/// its own already.
+pub fn can_give_to(uid: u32) -> bool {
+    let euid = unsafe { libc::geteuid() };
+    euid == 0 || euid == uid
+}
+
+/// Held while the effective user is switched, which applies to the whole
+/// process, so two switches never overlap.
+static USER_SWITCH: Mutex<()> = Mutex::new(());
+
+/// Runs `f` as `uid` and `gid`, with no other groups, so what it creates is
+/// owned by that user and it can only write where they can: a symlink they
+/// plant in their home can't lead root's writes elsewhere. Only root
+/// switches; anyone else runs `f` as they are. The switch applies to every
+/// thread, so all writes made for another user go through here.
+pub fn as_user<T>(uid: u32, gid: u32, f: impl FnOnce() -> T) -> io::Result<T> {
+    let _switch = USER_SWITCH.lock().unwrap_or_else(|e| e.into_inner());
+    let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    if euid != 0 || uid == 0 {
+        return Ok(f());
+    }
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    if unsafe { libc::getgroups(count, groups.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let restore = Restore { egid, groups };
+    unsafe {
+        if libc::setgroups(1, &gid) != 0 || libc::setegid(gid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::seteuid(uid) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let result = f();
+    drop(restore);
+    Ok(result)
+}
+
+/// Switches back to root when dropped, even if the switched code panics.
+struct Restore {
+    egid: libc::gid_t,
+    groups: Vec<libc::gid_t>,
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        // Carrying on as the wrong user would be worse than stopping
+        let restored = unsafe {
+            (libc::geteuid() == 0 || libc::seteuid(0) == 0)
+                && libc::setegid(self.egid) == 0
+                && libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) == 0
+        };
+        if !restored {
+            std::process::abort();
+        }
+    }
+}
+
+/// The device a file lives on.
+pub fn device(metadata: &fs::Metadata) -> u64 {
+    metadata.dev()
+}
+
+/// The device and inode of a file, the same for every hard link to it.
+pub fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// How many hard links a file has.
+pub fn link_count(metadata: &fs::Metadata) -> u64 {
+    metadata.nlink()
+}
+
+/// A path from raw bytes, which Unix paths are already.
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+pub fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+/// Takes an exclusive lock on the whole file, released when it is closed.
+/// Without `wait`, fails with `WouldBlock` while someone else holds it.
+/// Where the filesystem or kernel has no flock (some NFS and FUSE mounts,
+/// and small kernels built without it), a POSIX record lock is taken
+/// instead.
+pub fn lock(file: &File, wait: bool) -> io::Result<()> {
+    let operation = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    let unsupported = [libc::ENOLCK, libc::ENOSYS, libc::EOPNOTSUPP, libc::ENOTSUP];
+    match err.raw_os_error() {
+        Some(code) if unsupported.contains(&code) => lock_record(file, wait),
+        _ => Err(err),
+    }
+}
+
+/// An fcntl write lock on the whole file. These belong to the process
+/// rather than the open file, which makes no difference to tap, as it opens
+/// each lock file once.
+fn lock_record(file: &File, wait: bool) -> io::Result<()> {
+    let mut whole: libc::flock = unsafe { std::mem::zeroed() };
+    whole.l_type = libc::F_WRLCK as _;
+    whole.l_whence = libc::SEEK_SET as _;
+    let command = if wait { libc::F_SETLKW } else { libc::F_SETLK };
+    if unsafe { libc::fcntl(file.as_raw_fd(), command, &whole) } != 0 {
+        let err = io::Error::last_os_error();
+        if matches!(err.raw_os_error(), Some(libc::EACCES | libc::EAGAIN)) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// The process that started tap.
+pub fn parent_pid() -> Option<u32> {
+    Some(std::os::unix::process::parent_id())
+}
+
+pub fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // Signal 0 performs the permission and existence checks without sending anything
+    let rc = unsafe { libc::kill(pid, 0) };
+    rc == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Turns SIGINT and SIGTERM into a request to stop, seen through
+/// `interrupted`, so that work in progress can finish. A second one exits
+/// straight away.
+pub fn catch_interrupts() -> io::Result<()> {
+    extern "C" fn on_signal(_signal: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            unsafe { libc::_exit(130) };
+        }
+    }
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(signal, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Errors from files another process is using, which clear up on their own.
+pub fn is_busy(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EBUSY) | Some(libc::ETXTBSY))
+}
+
+/// Sets the process umask, returning the one it replaces.
+pub fn set_umask(mask: u32) -> Option<u32> {
+    Some(unsafe { libc::umask(mask as libc::mode_t) } as u32)
+}
+
+/// Looks up a user by name.
+pub fn user_by_name(name: &str) -> io::Result<Option<UserAccount>> {
+    let name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, tr!("Invalid user name")))?;
+    lookup_user(|passwd, buffer, result| unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            result,
+        )
+    })
+}
+
+/// Looks up a user by UID.
+pub fn user_by_uid(uid: u32) -> io::Result<Option<UserAccount>> {
+    lookup_user(|passwd, buffer, result| unsafe {
+        libc::getpwuid_r(uid, passwd, buffer.as_mut_ptr(), buffer.len(), result)
+    })
+}
+
+fn lookup_user(
+    getpw: impl FnOnce(&mut libc::passwd, &mut [libc::c_char], &mut *mut libc::passwd) -> libc::c_int,
+) -> io::Result<Option<UserAccount>> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let rc = getpw(&mut passwd, &mut buffer, &mut result);
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    if result.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(unsafe { account(&passwd) }))
+}
+
+/// Every entry in the user database.
+pub fn users() -> Vec<UserAccount> {
+    let mut users = Vec::new();
+    unsafe {
+        libc::setpwent();
+        loop {
+            let passwd = libc::getpwent();
+            if passwd.is_null() {
+                break;
+            }
+            users.push(account(&*passwd));
+        }
+        libc::endpwent();
+    }
+    users
+}
+
+/// # Safety
+/// `passwd` must come from a successful getpw* call whose buffers are still alive.
+unsafe fn account(passwd: &libc::passwd) -> UserAccount {
+    let text = |ptr: *const libc::c_char| CStr::from_ptr(ptr).to_string_lossy().into_owned();
+    UserAccount {
+        name: text(passwd.pw_name),
+        uid: passwd.pw_uid,
+        gid: passwd.pw_gid,
+        home: PathBuf::from(text(passwd.pw_dir)),
+    }
+}
+
+/// Looks up a group's GID by name.
+pub fn group_by_name(name: &str) -> io::Result<Option<u32>> {
+    let name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, tr!("Invalid group name")))?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    Ok((!result.is_null()).then_some(entry.gr_gid))
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, tr!("Path contains a NUL byte")))
+}
+
+/// The space and flags of the filesystem holding `path`.
+pub fn filesystem_info(path: &Path) -> io::Result<FsInfo> {
+    let c_path = c_path(path)?;
+    let mut vfs: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut vfs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(FsInfo {
+        fs_type: network_fs_type(path),
+        read_only: vfs.f_flag & libc::ST_RDONLY != 0,
+        available_bytes: vfs.f_bavail as u64 * vfs.f_frsize as u64,
+        total_bytes: vfs.f_blocks as u64 * vfs.f_frsize as u64,
+    })
+}
+
+/// Unix files hold one stream of data, so there are no others to list.
+pub fn data_streams(_path: &Path) -> io::Result<Vec<(String, u64)>> {
+    Ok(Vec::new())
+}
+
+/// Identifies network and userspace filesystems, which are slow and often
+/// rate-limited for bulk metadata operations.
+#[cfg(target_os = "linux")]
+pub fn network_fs_type(path: &Path) -> Option<&'static str> {
+    let c_path = c_path(path).ok()?;
+    let mut fs: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut fs) } != 0 {
+        return None;
+    }
+    match fs.f_type as u32 {
+        0x6969 => Some("NFS"),
+        0x517B => Some("SMB"),
+        0xFF53_4D42 => Some("CIFS"),
+        0xFE53_4D42 => Some("SMB2"),
+        0x0102_1997 => Some("9P"),
+        0x6573_5546 => Some("FUSE"),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn network_fs_type(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+/// Where the local syslog daemon listens, when one does.
+#[cfg(target_os = "macos")]
+const SYSLOG_SOCKET: &str = "/var/run/syslog";
+
+#[cfg(not(target_os = "macos"))]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Logs `message` to syslog. The C library drops messages silently when no
+/// daemon is listening, as on many small systems, so that is checked first.
+pub fn syslog(message: &str) -> io::Result<()> {
+    if fs::symlink_metadata(SYSLOG_SOCKET).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            tr!("no syslog daemon is listening on {}", SYSLOG_SOCKET),
+        ));
+    }
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    unsafe {
+        libc::openlog(c"tap".as_ptr(), libc::LOG_PID, libc::LOG_USER);
+        libc::syslog(libc::LOG_INFO, c"%s".as_ptr(), message.as_ptr());
+    }
+    Ok(())
+}
+
+pub fn is_fifo(file_type: fs::FileType) -> bool {
+    file_type.is_fifo()
+}
+
+pub fn make_fifo(path: &Path) -> io::Result<()> {
+    if unsafe { libc::mkfifo(c_path(path)?.as_ptr(), 0o666) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn is_socket(file_type: fs::FileType) -> bool {
+    file_type.is_socket()
+}
+
+/// Binds a Unix socket at `path` and closes it again, leaving the socket
+/// file behind.
+pub fn make_socket(path: &Path) -> io::Result<()> {
+    std::os::unix::net::UnixListener::bind(path).map(drop)
+}
+
+/// Allocates disk space for `len` bytes from `offset`, which read as zeros,
+/// growing the file if they go past its end. Fails with `Unsupported` where
+/// the system or filesystem can't do that without writing them.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub fn allocate(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    let result = unsafe {
+        libc::posix_fallocate(file.as_raw_fd(), offset as libc::off_t, len as libc::off_t)
+    };
+    match result {
+        0 => Ok(()),
+        libc::EOPNOTSUPP | libc::EINVAL => Err(io::ErrorKind::Unsupported.into()),
+        code => Err(io::Error::from_raw_os_error(code)),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+pub fn allocate(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Frees the disk space behind `len` bytes from `offset`, which read as
+/// zeros afterwards; the file keeps its size. Linux-only.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn punch_hole(file: &File, offset: u64, len: u64) -> io::Result<()> {
+    let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+    let result = unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            mode,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn punch_hole(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        tr!("punching holes is only supported on Linux"),
+    ))
+}
+
+/// Opens a FIFO for writing without waiting, which gives `None` while no
+/// process has it open for reading.
+pub fn open_fifo_writer(path: &Path) -> io::Result<Option<File>> {
+    let result = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path);
+    match result {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Shows a desktop notification through Notification Center.
+#[cfg(target_os = "macos")]
+pub fn show_notification(title: &str, body: &str) -> io::Result<()> {
+    // Passing the text as arguments saves quoting it into the script
+    run_notifier(std::process::Command::new("osascript").args([
+        "-e",
+        "on run argv",
+        "-e",
+        "display notification (item 2 of argv) with title (item 1 of argv)",
+        "-e",
+        "end run",
+        title,
+        body,
+    ]))
+}
+
+/// Shows a desktop notification through `notify-send`.
+#[cfg(not(target_os = "macos"))]
+pub fn show_notification(title: &str, body: &str) -> io::Result<()> {
+    run_notifier(std::process::Command::new("notify-send").args(["--app-name=tap", title, body]))
+}
+
+fn run_notifier(command: &mut std::process::Command) -> io::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command.status().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => io::Error::new(err.kind(), tr!("{} not found", program)),
+        _ => err,
+    })?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} {}", program, status)));
+    }
+    Ok(())
+}
+
+/// Starts `command` in a network namespace of its own, which has nothing
+/// but a loopback interface that is down. Without root, a user namespace
+/// makes that possible, mapping only tap's own user and group into it so
+/// files keep their owners. Where neither is allowed, `command` fails to
+/// start rather than running with the network.
+#[cfg(target_os = "linux")]
+pub fn isolate_network(command: &mut std::process::Command) {
+    use std::os::unix::process::CommandExt;
+
+    // Written after fork, where nothing may be allocated
+    let uid_map = format!("{0} {0} 1", unsafe { libc::geteuid() });
+    let gid_map = format!("{0} {0} 1", unsafe { libc::getegid() });
+    let isolate = move || {
+        if unsafe { libc::unshare(libc::CLONE_NEWNET) } == 0 {
+            return Ok(());
+        }
+        if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        write_proc_file(c"/proc/self/setgroups", b"deny")?;
+        write_proc_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+        write_proc_file(c"/proc/self/gid_map", gid_map.as_bytes())
+    };
+    unsafe { command.pre_exec(isolate) };
+}
+
+/// Elsewhere there is no way to take the network from one process, so
+/// commands keep it.
+#[cfg(not(target_os = "linux"))]
+pub fn isolate_network(_command: &mut std::process::Command) {}
+
+#[cfg(target_os = "linux")]
+fn write_proc_file(path: &CStr, content: &[u8]) -> io::Result<()> {
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let written = unsafe { libc::write(fd, content.as_ptr().cast(), content.len()) };
+    let result = if written == content.len() as isize {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    };
+    unsafe { libc::close(fd) };
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_record() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("held.lock");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        lock_record(&file, false)?;
+        // Record locks belong to the process, so taking it again succeeds,
+        // and a child process is kept out
+        lock_record(&file, true)?;
+        let child = std::process::Command::new(std::env::current_exe()?)
+            .args(["--exact", "platform::sys::tests::test_lock_record_held"])
+            .arg("--ignored")
+            .env("TAP_HELD_LOCK", &path)
+            .output()?;
+        assert!(child.status.success());
+        assert!(String::from_utf8_lossy(&child.stdout).contains("1 passed"));
+        Ok(())
+    }
+
+    /// Run by `test_lock_record` in a child process.
+    #[test]
+    #[ignore]
+    fn test_lock_record_held() -> io::Result<()> {
+        let Some(path) = std::env::var_os("TAP_HELD_LOCK") else {
+            return Ok(());
+        };
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let err = lock_record(&file, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        Ok(())
+    }
+}
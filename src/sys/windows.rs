@@ -0,0 +1,373 @@
+//! The Windows side of `platform`. What Windows has no equivalent for
+//! (owners, groups, umasks, FIFOs, syslog) fails with `Unsupported`, or
+//! reads as the closest thing that does no harm.
+
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{ModeExt, ARCHIVE, HIDDEN, INTERRUPTED, READ_ONLY, SYSTEM};
+use crate::{say_err, tr, FsInfo, UserAccount};
+
+/// The environment variables naming the user's home directory, in the order
+/// they are tried.
+pub const HOME_VARS: &[&str] = &["HOME", "USERPROFILE"];
+
+fn unsupported(what: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, what)
+}
+
+impl ModeExt for OpenOptions {
+    fn with_mode(&mut self, _mode: u32) -> &mut Self {
+        self
+    }
+}
+
+impl ModeExt for DirBuilder {
+    fn with_mode(&mut self, _mode: u32) -> &mut Self {
+        self
+    }
+}
+
+pub fn mode(metadata: &fs::Metadata) -> u32 {
+    let mode = if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o666
+    };
+    if metadata.is_dir() {
+        mode | 0o111
+    } else {
+        mode
+    }
+}
+
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if mode & 0o7577 != 0o444 && !WARNED.swap(true, Ordering::Relaxed) {
+        say_err!("Warning: Windows only has a read-only attribute; modes other than 444 and 644 are approximated by the owner's write bit");
+    }
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+pub fn attributes(metadata: &fs::Metadata) -> u32 {
+    use std::os::windows::fs::MetadataExt;
+
+    metadata.file_attributes() & (READ_ONLY | HIDDEN | SYSTEM | ARCHIVE)
+}
+
+pub fn set_attributes(path: &Path, set: u32, clear: u32) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::{
+        SetFileAttributesW, FILE_ATTRIBUTE_NORMAL, FILE_ATTRIBUTE_NOT_CONTENT_INDEXED,
+        FILE_ATTRIBUTE_OFFLINE, FILE_ATTRIBUTE_TEMPORARY,
+    };
+
+    // Only these can be set; the rest, such as being a directory, describe
+    // the file
+    let settable = READ_ONLY
+        | HIDDEN
+        | SYSTEM
+        | ARCHIVE
+        | FILE_ATTRIBUTE_NOT_CONTENT_INDEXED
+        | FILE_ATTRIBUTE_OFFLINE
+        | FILE_ATTRIBUTE_TEMPORARY;
+    let current = fs::metadata(path)?.file_attributes();
+    let new = (current | set) & !clear & settable;
+    if new == current & settable {
+        return Ok(());
+    }
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let new = if new == 0 { FILE_ATTRIBUTE_NORMAL } else { new };
+    if unsafe { SetFileAttributesW(wide.as_ptr(), new) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Windows files have no user and group IDs, and read as owned by 0:0.
+pub fn owner(_metadata: &fs::Metadata) -> (u32, u32) {
+    (0, 0)
+}
+
+pub fn lchown(_path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+    Err(unsupported(tr!("file owners are not supported on Windows")))
+}
+
+pub fn can_give_to(_uid: u32) -> bool {
+    false
+}
+
+/// There are no user IDs to switch to, and no files are given to other
+/// users, so `f` runs as it is.
+pub fn as_user<T>(_uid: u32, _gid: u32, f: impl FnOnce() -> T) -> io::Result<T> {
+    Ok(f())
+}
+
+/// Files on different volumes can't be told apart, so all read as 0.
+pub fn device(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+pub fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Without file IDs to compare, every file reads as having one link.
+pub fn link_count(_metadata: &fs::Metadata) -> u64 {
+    1
+}
+
+/// A path from raw bytes, read as UTF-8.
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Windows tells file and directory symlinks apart, so `original` must
+/// exist to pick one.
+pub fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    let target = match link.parent() {
+        Some(parent) if original.is_relative() => parent.join(original),
+        _ => original.to_path_buf(),
+    };
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(original, link)
+    } else {
+        std::os::windows::fs::symlink_file(original, link)
+    }
+}
+
+/// Takes an exclusive lock on the whole file, released when it is closed.
+/// Without `wait`, fails with `WouldBlock` while someone else holds it.
+pub fn lock(file: &File, wait: bool) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+
+    let mut flags = LOCKFILE_EXCLUSIVE_LOCK;
+    if !wait {
+        flags |= LOCKFILE_FAIL_IMMEDIATELY;
+    }
+    let mut overlapped = unsafe { std::mem::zeroed() };
+    let locked = unsafe {
+        LockFileEx(
+            file.as_raw_handle(),
+            flags,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    if locked == 0 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+pub fn parent_pid() -> Option<u32> {
+    None
+}
+
+pub fn process_alive(pid: u32) -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, ERROR_ACCESS_DENIED, STILL_ACTIVE};
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if process.is_null() {
+        return io::Error::last_os_error().raw_os_error() == Some(ERROR_ACCESS_DENIED as i32);
+    }
+    let mut code = 0;
+    let queried = unsafe { GetExitCodeProcess(process, &mut code) };
+    unsafe { CloseHandle(process) };
+    queried != 0 && code == STILL_ACTIVE as u32
+}
+
+/// Turns Ctrl-C into a request to stop, seen through `interrupted`, so that
+/// work in progress can finish. A second one exits straight away.
+pub fn catch_interrupts() -> io::Result<()> {
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    unsafe extern "system" fn on_ctrl(_event: u32) -> i32 {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            // Not handled, so Windows ends the process
+            return 0;
+        }
+        1
+    }
+
+    if unsafe { SetConsoleCtrlHandler(Some(on_ctrl), 1) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Errors from files another process is using, which clear up on their own.
+pub fn is_busy(err: &io::Error) -> bool {
+    use windows_sys::Win32::Foundation::{ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION};
+
+    matches!(
+        err.raw_os_error(),
+        Some(code) if code == ERROR_SHARING_VIOLATION as i32 || code == ERROR_LOCK_VIOLATION as i32
+    )
+}
+
+/// Windows has no umask, so this only warns.
+pub fn set_umask(_mask: u32) -> Option<u32> {
+    say_err!("Warning: --umask has no effect on Windows");
+    None
+}
+
+pub fn user_by_name(name: &str) -> io::Result<Option<UserAccount>> {
+    Err(unsupported(tr!(
+        "User accounts are not supported on Windows: {}",
+        name
+    )))
+}
+
+pub fn user_by_uid(uid: u32) -> io::Result<Option<UserAccount>> {
+    user_by_name(&uid.to_string())
+}
+
+/// There are no users to list on Windows.
+pub fn users() -> Vec<UserAccount> {
+    Vec::new()
+}
+
+pub fn group_by_name(name: &str) -> io::Result<Option<u32>> {
+    Err(unsupported(tr!(
+        "Groups are not supported on Windows: {}",
+        name
+    )))
+}
+
+pub fn filesystem_info(path: &Path) -> io::Result<FsInfo> {
+    Err(unsupported(tr!(
+        "Filesystem checks are not supported on Windows: {}",
+        path.display()
+    )))
+}
+
+/// The names and sizes of the alternate data streams of `path`, leaving out
+/// its main one.
+pub fn data_streams(path: &Path) -> io::Result<Vec<(String, u64)>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{ERROR_HANDLE_EOF, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut found = WIN32_FIND_STREAM_DATA::default();
+    let search = unsafe {
+        FindFirstStreamW(
+            wide.as_ptr(),
+            FindStreamInfoStandard,
+            (&mut found as *mut WIN32_FIND_STREAM_DATA).cast(),
+            0,
+        )
+    };
+    if search == INVALID_HANDLE_VALUE {
+        let err = io::Error::last_os_error();
+        // Directories and files on filesystems without streams have none
+        if err.raw_os_error() == Some(ERROR_HANDLE_EOF as i32) {
+            return Ok(Vec::new());
+        }
+        return Err(err);
+    }
+    let mut streams = Vec::new();
+    loop {
+        // Names read as `:name:$DATA`, and the main stream as `::$DATA`
+        let len = found
+            .cStreamName
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(found.cStreamName.len());
+        let name = String::from_utf16_lossy(&found.cStreamName[..len]);
+        let name = name.trim_start_matches(':');
+        let name = name.strip_suffix(":$DATA").unwrap_or(name);
+        if !name.is_empty() && name != "$DATA" {
+            streams.push((name.to_string(), found.StreamSize as u64));
+        }
+        let next =
+            unsafe { FindNextStreamW(search, (&mut found as *mut WIN32_FIND_STREAM_DATA).cast()) };
+        if next == 0 {
+            break;
+        }
+    }
+    let err = io::Error::last_os_error();
+    unsafe { FindClose(search) };
+    if err.raw_os_error() != Some(ERROR_HANDLE_EOF as i32) {
+        return Err(err);
+    }
+    Ok(streams)
+}
+
+pub fn network_fs_type(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+pub fn syslog(_message: &str) -> io::Result<()> {
+    Err(unsupported(tr!("syslog is not available on Windows")))
+}
+
+pub fn is_fifo(_file_type: fs::FileType) -> bool {
+    false
+}
+
+pub fn make_fifo(path: &Path) -> io::Result<()> {
+    Err(unsupported(tr!(
+        "FIFOs are not supported on Windows: {}",
+        path.display()
+    )))
+}
+
+pub fn is_socket(_file_type: fs::FileType) -> bool {
+    false
+}
+
+pub fn make_socket(path: &Path) -> io::Result<()> {
+    Err(unsupported(tr!(
+        "Unix sockets are not supported on Windows: {}",
+        path.display()
+    )))
+}
+
+/// Space can't be allocated without writing it, short of a privilege tap
+/// doesn't ask for.
+pub fn allocate(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+pub fn punch_hole(_file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(unsupported(tr!(
+        "punching holes is only supported on Linux"
+    )))
+}
+
+pub fn open_fifo_writer(path: &Path) -> io::Result<Option<File>> {
+    make_fifo(path).map(|()| None)
+}
+
+pub fn show_notification(_title: &str, _body: &str) -> io::Result<()> {
+    Err(unsupported(tr!(
+        "desktop notifications are not supported on Windows"
+    )))
+}
+
+/// Windows has no way to take the network from one process, so commands
+/// keep it.
+pub fn isolate_network(_command: &mut std::process::Command) {}
@@ -0,0 +1,258 @@
+//! A record of the temporary files tap creates, so that ones left behind by
+//! a killed run can be found and removed later.
+//!
+//! Each process that creates temporary files keeps a registry of them in
+//! `$XDG_STATE_HOME/tap/temp`, locked for as long as the process lives. A
+//! line `+PATH` is appended before a temporary file is created and `-PATH`
+//! once it has been renamed into place or removed, so a registry that can
+//! be locked belongs to a process that is gone, and the paths it lists
+//! without a matching `-` are orphans. Every run removes them before it
+//! starts, as does `tap doctor --cleanup`.
+
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::{platform, say, state_dir, tr};
+
+/// This process's registry, opened when it first registers a file and
+/// removed again once nothing is registered.
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+struct Registry {
+    file: File,
+    path: PathBuf,
+    registered: usize,
+}
+
+impl Registry {
+    /// Creates and locks a new registry in `dir`.
+    fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let path = dir.join(format!("{}-{}.list", std::process::id(), nanos));
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(&path)?;
+        platform::lock(&file, false)?;
+        Ok(Self {
+            file,
+            path,
+            registered: 0,
+        })
+    }
+
+    fn record(&mut self, sign: char, path: &str) -> io::Result<()> {
+        self.file
+            .write_all(format!("{}{}\n", sign, path).as_bytes())
+    }
+}
+
+/// A temporary file in the registry, taken out of it again when dropped.
+/// Drop it only after the file has been renamed into place or removed.
+pub(crate) struct Registered {
+    path: Option<String>,
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(open) = registry.as_mut() else {
+            return;
+        };
+        let _ = open.record('-', path);
+        open.registered -= 1;
+        if open.registered == 0 {
+            // Removed while still locked, so no other run mistakes it for
+            // an orphaned registry in between
+            let _ = fs::remove_file(&open.path);
+            *registry = None;
+        }
+    }
+}
+
+/// Adds `path` to the registry before the temporary file is created there.
+/// Registering is best effort: if the registry can't be written, or the path
+/// can't be written to it (it isn't UTF-8 or holds a newline), the file is
+/// created all the same, just without protection against a crash.
+pub(crate) fn register(path: &Path) -> Registered {
+    let unregistered = Registered { path: None };
+    let Ok(path) = std::path::absolute(path) else {
+        return unregistered;
+    };
+    let Some(path) = path.to_str().filter(|path| !path.contains('\n')) else {
+        return unregistered;
+    };
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    if registry.is_none() {
+        *registry = registry_dir()
+            .ok()
+            .and_then(|dir| Registry::open(&dir).ok());
+    }
+    let Some(open) = registry.as_mut() else {
+        return unregistered;
+    };
+    if open.record('+', path).is_err() {
+        return unregistered;
+    }
+    open.registered += 1;
+    Registered {
+        path: Some(path.to_string()),
+    }
+}
+
+fn registry_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("temp"))
+}
+
+/// Temporary files left behind by tap runs that are no longer running.
+pub fn orphans() -> Result<Vec<PathBuf>> {
+    orphans_in(&registry_dir()?, false)
+}
+
+/// Removes the temporary files left behind by tap runs that are no longer
+/// running, returning the ones that were there to remove.
+pub fn remove_orphans(verbose: bool) -> Result<Vec<PathBuf>> {
+    orphans_in(&registry_dir()?, true).inspect(|removed| {
+        if verbose {
+            for path in removed {
+                say!("Removed orphaned temporary file: {}", path.display());
+            }
+        }
+    })
+}
+
+/// The orphans listed by the unlocked registries in `dir`, which with
+/// `remove` are deleted along with the registries.
+fn orphans_in(dir: &Path, remove: bool) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| tr!("Failed to read {}", dir.display()));
+        }
+    };
+    let mut orphans = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| tr!("Failed to read {}", dir.display()))?
+            .path();
+        if path.extension() != Some(OsStr::new("list")) {
+            continue;
+        }
+        let Ok(mut file) = OpenOptions::new().read(true).write(true).open(&path) else {
+            continue;
+        };
+        // Held by a live run, or just removed by its run or another cleanup
+        if platform::lock(&file, false).is_err() {
+            continue;
+        }
+        // Read through the locked handle, as Windows locks keep out others
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .with_context(|| tr!("Failed to read {}", path.display()))?;
+        for orphan in leftovers(&contents) {
+            let orphan = PathBuf::from(orphan);
+            if fs::symlink_metadata(&orphan).is_err() {
+                continue;
+            }
+            if remove {
+                match fs::remove_file(&orphan) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                        return Err(e)
+                            .with_context(|| tr!("Failed to remove {}", orphan.display()));
+                    }
+                    _ => {}
+                }
+            }
+            orphans.push(orphan);
+        }
+        if remove {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| tr!("Failed to remove {}", path.display()));
+                }
+                _ => {}
+            }
+        }
+    }
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// The paths a registry lists as created but not yet released.
+fn leftovers(contents: &str) -> Vec<&str> {
+    let mut created = Vec::new();
+    for line in contents.lines() {
+        match line.split_at_checked(1) {
+            Some(("+", path)) => created.push(path),
+            Some(("-", path)) => {
+                if let Some(index) = created.iter().rposition(|created| *created == path) {
+                    created.remove(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    created
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_orphans() -> Result<()> {
+        let dir = tempdir()?;
+        let registries = dir.path().join("temp");
+        let kept = dir.path().join(".a.tmp");
+        let renamed = dir.path().join(".b.tmp");
+        let held = dir.path().join(".c.tmp");
+        for path in [&kept, &renamed, &held] {
+            fs::write(path, "")?;
+        }
+
+        // A run killed before renaming one of its files into place; closing
+        // the registry releases its lock the way the process exiting would
+        let mut dead = Registry::open(&registries)?;
+        dead.record('+', kept.to_str().unwrap())?;
+        dead.record('+', renamed.to_str().unwrap())?;
+        dead.record('-', renamed.to_str().unwrap())?;
+        dead.record('+', &dir.path().join(".gone.tmp").to_string_lossy())?;
+        let dead_path = dead.path.clone();
+        drop(dead);
+
+        let mut live = Registry::open(&registries)?;
+        live.record('+', held.to_str().unwrap())?;
+
+        assert_eq!(orphans_in(&registries, false)?, vec![kept.clone()]);
+        assert!(kept.exists() && dead_path.exists());
+        assert_eq!(orphans_in(&registries, true)?, vec![kept.clone()]);
+        assert!(!kept.exists() && !dead_path.exists());
+        assert!(renamed.exists() && held.exists() && live.path.exists());
+        assert!(orphans_in(&registries, true)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_leftovers() {
+        assert_eq!(leftovers("+/a\n+/b\n-/a\n+/c\n"), vec!["/b", "/c"]);
+        assert_eq!(leftovers("+/a\n-/a\n+/a\n"), vec!["/a"]);
+        assert!(leftovers("").is_empty());
+    }
+}
@@ -0,0 +1,47 @@
+//! The `{{ ... }}` expressions in templates: variables, string literals,
+//! functions like `env("USER")` and filters like `{{ name | kebab }}`.
+//!
+//! A filter is a function whose first argument comes through the pipe, so
+//! `{{ name | pad(8, "-") }}` is `{{ pad(name, 8, "-") }}`. Every value is a
+//! string; numbers are strings that `format` and `pad` happen to accept.
+//!
+//! Templates see the variables they are rendered with and the environment,
+//! and nothing else: they can't read files, and `shell(...)` only runs a
+//! command once the caller opts in with [`Context::allow_shell`].
+//!
+//! Rendering needs the `template-engine` feature; the helpers for paths and
+//! include guards, which header guards use too, are always built.
+
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "template-engine")]
+mod engine;
+
+#[cfg(feature = "template-engine")]
+pub use engine::{render, Context};
+
+/// `path` relative to the working directory if it is inside it, and as it
+/// is otherwise.
+pub fn relative_path(path: &Path) -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    path.strip_prefix(&cwd).unwrap_or(path).to_path_buf()
+}
+
+/// An include guard macro for the header at `path`: `src/foo-bar.h` with
+/// prefix `myproj` is `MYPROJ_SRC_FOO_BAR_H_`.
+pub fn include_guard(path: &str, prefix: Option<&str>) -> String {
+    let mut guard = String::new();
+    for part in [prefix.unwrap_or(""), path] {
+        for c in part.chars() {
+            if c.is_ascii_alphanumeric() {
+                guard.push(c.to_ascii_uppercase());
+            } else if !guard.is_empty() && !guard.ends_with('_') {
+                guard.push('_');
+            }
+        }
+        if !guard.is_empty() && !guard.ends_with('_') {
+            guard.push('_');
+        }
+    }
+    guard
+}
@@ -0,0 +1,693 @@
+//! The engine that fills in templates' `{{ ... }}` expressions, built with
+//! the `template-engine` feature.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+
+use super::{include_guard, relative_path};
+use crate::sandbox::Sandbox;
+use crate::tr;
+
+/// What a template can see while it is rendered.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    vars: HashMap<String, String>,
+    allow_shell: bool,
+    sandbox: Option<Sandbox>,
+    used_target: Cell<bool>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn vars(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.vars.extend(vars);
+        self
+    }
+
+    /// Describes the file being written, as `target.name` (`parser.rs`),
+    /// `target.stem` (`parser`), `target.ext` (`rs`), `target.parent`
+    /// (`src`) and `target.relpath` (`src/parser.rs`). Paths are relative
+    /// to the working directory when they are inside it.
+    pub fn target(self, target: &Path) -> Self {
+        let relpath = relative_path(target);
+        let relpath = relpath.as_path();
+        let part = |part: Option<&std::ffi::OsStr>| {
+            part.map(|part| part.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let parent = match relpath.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => ".".to_string(),
+            parent => part(parent.map(Path::as_os_str)),
+        };
+        self.var("target.name", part(relpath.file_name()))
+            .var("target.stem", part(relpath.file_stem()))
+            .var("target.ext", part(relpath.extension()))
+            .var("target.parent", parent)
+            .var("target.relpath", relpath.to_string_lossy())
+    }
+
+    /// Lets `shell("...")` run commands with `sh -c`.
+    pub fn allow_shell(mut self, allow: bool) -> Self {
+        self.allow_shell = allow;
+        self
+    }
+
+    /// Runs `shell("...")` commands under these limits.
+    pub fn sandbox(mut self, sandbox: Option<Sandbox>) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Whether a render so far looked at `target.*` or ran a command, so its
+    /// output can't be reused for another target.
+    pub fn used_target(&self) -> bool {
+        self.used_target.get()
+    }
+}
+
+/// The functions templates can call, with the arguments they take counting
+/// a piped-in value, and how to call them.
+const FUNCTIONS: &[(&str, usize, usize, &str)] = &[
+    ("lower", 1, 1, "value | lower"),
+    ("upper", 1, 1, "value | upper"),
+    ("title", 1, 1, "value | title"),
+    ("kebab", 1, 1, "value | kebab"),
+    ("snake", 1, 1, "value | snake"),
+    ("camel", 1, 1, "value | camel"),
+    ("pascal", 1, 1, "value | pascal"),
+    ("slug", 1, 1, "value | slug"),
+    ("trim", 1, 1, "value | trim"),
+    ("pad", 2, 3, "value | pad(width[, fill])"),
+    ("replace", 3, 3, "value | replace(from, to)"),
+    ("default", 2, 2, "value | default(fallback)"),
+    ("format", 1, usize::MAX, "\"%03d\" | format(values...)"),
+    ("guard", 1, 2, "path | guard([prefix])"),
+    ("env", 1, 2, "env(name[, fallback])"),
+    ("shell", 1, 1, "shell(command)"),
+];
+
+/// The widest `pad` or `format` may make a value, so a typo in a width
+/// can't exhaust memory.
+const MAX_WIDTH: usize = 10_000;
+
+/// Renders every `{{ expression }}` in `template`. Text outside them is kept
+/// as it is, and so are braces that aren't one of tap's expressions: ones
+/// that don't parse, or name a variable or function tap doesn't know, as
+/// in a template written for Handlebars or Jinja. A literal `{{` can also
+/// be written as `{{ "{{" }}`.
+pub fn render(template: &str, context: &Context) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let inside = &rest[start + 2..];
+        let Some(end) = closing_braces(inside) else {
+            rest = &rest[start..];
+            break;
+        };
+        let expression = &inside[..end];
+        if parse(expression, context, false).is_err() {
+            output.push_str(&rest[start..start + 2]);
+            rest = inside;
+            continue;
+        }
+        let value = parse(expression, context, true)
+            .with_context(|| tr!("Failed to render {{{{{}}}}}", expression))?;
+        output.push_str(&value);
+        rest = &inside[end + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Where the `}}` closing an expression starts, skipping ones inside strings.
+fn closing_braces(inside: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in inside.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '}' if !in_string && inside[index..].starts_with("}}") => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Str(String),
+    Name(String),
+    Pipe,
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '|' => tokens.push(Token::Pipe),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(c @ ('"' | '\\')) => value.push(c),
+                            Some(c) => anyhow::bail!(tr!("Unknown escape \\{} in string", c)),
+                            None => anyhow::bail!(tr!("Unterminated string")),
+                        },
+                        Some(c) => value.push(c),
+                        None => anyhow::bail!(tr!("Unterminated string")),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            // Numbers are plain strings; names may be dotted (`target.stem`)
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut value = c.to_string();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit() || **c == '.') {
+                    value.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut name = c.to_string();
+                while let Some(&c) = chars
+                    .peek()
+                    .filter(|c| c.is_alphanumeric() || **c == '_' || **c == '.')
+                {
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => anyhow::bail!(tr!("Unexpected '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Evaluates `expression`, or with `run` false only checks that it is one
+/// of tap's, without calling anything.
+fn parse(expression: &str, context: &Context, run: bool) -> Result<String> {
+    let tokens = tokenize(expression)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        context,
+        run,
+    };
+    let value = parser.expression()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        anyhow::bail!(tr!(
+            "Unexpected {} after the expression",
+            format!("{:?}", token)
+        ));
+    }
+    Ok(value)
+}
+
+/// Evaluates as it parses; expressions are short and run once per target.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    context: &'a Context,
+    run: bool,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// `term ( | name[(args)] )*`
+    fn expression(&mut self) -> Result<String> {
+        let mut value = self.term()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.position += 1;
+            let Some(Token::Name(name)) = self.next().cloned() else {
+                anyhow::bail!(tr!("Expected a filter name after |"));
+            };
+            let mut args = vec![value];
+            if self.peek() == Some(&Token::Open) {
+                args.extend(self.arguments()?);
+            }
+            value = self.call(&name, &args)?;
+        }
+        Ok(value)
+    }
+
+    fn call(&self, name: &str, args: &[String]) -> Result<String> {
+        if self.run {
+            return call(name, args, self.context);
+        }
+        if !FUNCTIONS.iter().any(|(function, ..)| *function == name) {
+            anyhow::bail!(tr!("Unknown function '{}'", name));
+        }
+        Ok(String::new())
+    }
+
+    /// A string, a number, a variable, or a function call.
+    fn term(&mut self) -> Result<String> {
+        match self.next().cloned() {
+            Some(Token::Str(value)) => Ok(value),
+            Some(Token::Name(name)) if self.peek() == Some(&Token::Open) => {
+                let args = self.arguments()?;
+                self.call(&name, &args)
+            }
+            Some(Token::Name(name)) => {
+                if name.starts_with("target.") {
+                    self.context.used_target.set(true);
+                }
+                self.context
+                    .vars
+                    .get(&name)
+                    .cloned()
+                    .with_context(|| tr!("Unknown variable '{}'", name))
+            }
+            Some(token) => anyhow::bail!(tr!("Unexpected {}", format!("{:?}", token))),
+            None => anyhow::bail!(tr!("Empty expression")),
+        }
+    }
+
+    /// `( expression, ... )`
+    fn arguments(&mut self) -> Result<Vec<String>> {
+        self.position += 1;
+        let mut args = Vec::new();
+        if self.peek() == Some(&Token::Close) {
+            self.position += 1;
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expression()?);
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(Token::Close) => return Ok(args),
+                _ => anyhow::bail!(tr!("Expected , or ) in argument list")),
+            }
+        }
+    }
+}
+
+fn call(name: &str, args: &[String], context: &Context) -> Result<String> {
+    let &(_, min, max, usage) = FUNCTIONS
+        .iter()
+        .find(|(function, ..)| *function == name)
+        .with_context(|| tr!("Unknown function '{}'", name))?;
+    if args.len() < min || args.len() > max {
+        anyhow::bail!(tr!("Wrong number of arguments for {}: use {}", name, usage));
+    }
+    let words = || words(&args[0]);
+    Ok(match name {
+        "lower" => args[0].to_lowercase(),
+        "upper" => args[0].to_uppercase(),
+        "title" => words()
+            .iter()
+            .map(|w| capitalize(w))
+            .collect::<Vec<_>>()
+            .join(" "),
+        "kebab" => words().join("-").to_lowercase(),
+        "snake" => words().join("_").to_lowercase(),
+        "camel" => words()
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                if i == 0 {
+                    w.to_lowercase()
+                } else {
+                    capitalize(w)
+                }
+            })
+            .collect(),
+        "pascal" => words().iter().map(|w| capitalize(w)).collect(),
+        "slug" => args[0]
+            .to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-"),
+        "trim" => args[0].trim().to_string(),
+        "pad" => {
+            let width: usize = args[1]
+                .parse()
+                .ok()
+                .filter(|width| *width <= MAX_WIDTH)
+                .with_context(|| {
+                    tr!("Invalid width for pad (at most {}): {}", MAX_WIDTH, args[1])
+                })?;
+            let fill = match args.get(2).map(|fill| fill.chars().collect::<Vec<_>>()) {
+                None => ' ',
+                Some(fill) if fill.len() == 1 => fill[0],
+                Some(_) => anyhow::bail!(tr!("pad's fill must be a single character")),
+            };
+            let padding = width.saturating_sub(args[0].chars().count());
+            fill.to_string().repeat(padding) + &args[0]
+        }
+        "replace" => args[0].replace(&args[1], &args[2]),
+        "default" if args[0].is_empty() => args[1].clone(),
+        "default" => args[0].clone(),
+        "format" => format(&args[0], &args[1..])?,
+        "guard" => include_guard(&args[0], args.get(1).map(String::as_str)),
+        "env" => match (std::env::var(&args[0]), args.get(1)) {
+            (Ok(value), _) => value,
+            (Err(_), Some(fallback)) => fallback.clone(),
+            (Err(_), None) => anyhow::bail!(tr!("Environment variable {} is not set", args[0])),
+        },
+        "shell" => shell(&args[0], context)?,
+        _ => unreachable!("{} is listed in FUNCTIONS", name),
+    })
+}
+
+/// Splits on anything that isn't a letter or digit and at case changes, so
+/// `HTTPServer_config` is `HTTP`, `Server`, `config`.
+fn words(input: &str) -> Vec<String> {
+    let chars = input.chars().collect::<Vec<_>>();
+    let mut words = Vec::new();
+    let mut current = String::new();
+    for (index, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        let previous = index.checked_sub(1).map(|index| chars[index]);
+        let next = chars.get(index + 1);
+        let boundary = c.is_uppercase()
+            && previous.is_some_and(|previous| {
+                previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || previous.is_uppercase() && next.is_some_and(|next| next.is_lowercase())
+            });
+        if boundary && !current.is_empty() {
+            words.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// printf-style formatting: `%s`, `%d`, `%x`, `%X`, `%o`, `%f` and `%%`, with
+/// `-` and `0` flags, a width and a precision (`%-8s`, `%03d`, `%.2f`).
+fn format(format: &str, values: &[String]) -> Result<String> {
+    let mut output = String::new();
+    let mut values = values.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            output.push('%');
+            continue;
+        }
+        let (mut left, mut zero) = (false, false);
+        while let Some(flag) = chars.next_if(|c| *c == '-' || *c == '0') {
+            left |= flag == '-';
+            zero |= flag == '0';
+        }
+        let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            digits.parse::<usize>().ok()
+        };
+        let width = number(&mut chars).unwrap_or(0);
+        if width > MAX_WIDTH {
+            anyhow::bail!(tr!("Width in format \"{}\" is over {}", format, MAX_WIDTH));
+        }
+        let precision = chars
+            .next_if_eq(&'.')
+            .map(|_| number(&mut chars).unwrap_or(0));
+        let conversion = chars
+            .next()
+            .with_context(|| tr!("Incomplete % directive in format"))?;
+        let value = values
+            .next()
+            .with_context(|| tr!("Not enough values for format \"{}\"", format))?;
+        let integer = || {
+            value
+                .trim()
+                .parse::<i64>()
+                .with_context(|| tr!("%{} needs a whole number, got '{}'", conversion, value))
+        };
+        let body = match conversion {
+            's' => match precision {
+                Some(precision) => value.chars().take(precision).collect(),
+                None => value.clone(),
+            },
+            'd' | 'i' => integer()?.to_string(),
+            'x' => format!("{:x}", integer()?),
+            'X' => format!("{:X}", integer()?),
+            'o' => format!("{:o}", integer()?),
+            'f' => {
+                let number = value
+                    .trim()
+                    .parse::<f64>()
+                    .with_context(|| tr!("%f needs a number, got '{}'", value))?;
+                format!("{:.*}", precision.unwrap_or(6), number)
+            }
+            other => anyhow::bail!(tr!("Unsupported directive %{} in format", other)),
+        };
+        let padding = width.saturating_sub(body.chars().count());
+        if left {
+            output.push_str(&body);
+            output.push_str(&" ".repeat(padding));
+        } else if zero && conversion != 's' {
+            // Zeros go after the sign: -007
+            let (sign, digits) = body.split_at(usize::from(body.starts_with('-')));
+            output.push_str(sign);
+            output.push_str(&"0".repeat(padding));
+            output.push_str(digits);
+        } else {
+            output.push_str(&" ".repeat(padding));
+            output.push_str(&body);
+        }
+    }
+    if values.next().is_some() {
+        anyhow::bail!(tr!("More values than directives in format \"{}\"", format));
+    }
+    Ok(output)
+}
+
+fn shell(command: &str, context: &Context) -> Result<String> {
+    context.used_target.set(true);
+    if !context.allow_shell {
+        anyhow::bail!(tr!(
+            "shell() is disabled; pass --allow-shell to let templates run commands"
+        ));
+    }
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let output = Sandbox::command_in(context.sandbox.as_ref(), shell)
+        .arg(flag)
+        .arg(command)
+        .output()
+        .with_context(|| tr!("Failed to run: {}", command))?;
+    if !output.status.success() {
+        anyhow::bail!(tr!(
+            "Command failed ({}): {}: {}",
+            output.status,
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end_matches('\n')
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_with(template: &str, context: &Context) -> String {
+        render(template, context).unwrap()
+    }
+
+    #[test]
+    fn test_render_filters() {
+        let context = Context::new()
+            .var("name", "HTTPServer config")
+            .var("n", "7");
+        let cases: &[(&str, &str)] = &[
+            ("plain text", "plain text"),
+            ("{{ name }}", "HTTPServer config"),
+            ("{{name|kebab}}", "http-server-config"),
+            ("{{ name | snake }}", "http_server_config"),
+            ("{{ name | camel }}", "httpServerConfig"),
+            ("{{ name | pascal }}", "HttpServerConfig"),
+            ("{{ name | title }}", "Http Server Config"),
+            ("{{ \"Hello, World!\" | slug }}", "hello-world"),
+            (
+                "{{ name | upper | replace(\" \", \"_\") }}",
+                "HTTPSERVER_CONFIG",
+            ),
+            ("{{ \"%03d\" | format(n) }}", "007"),
+            (
+                "{{ format(\"%-4s|%x|%.2f|%%\", \"ab\", 255, 1.005) }}",
+                "ab  |ff|1.00|%",
+            ),
+            ("{{ format(\"%04d\", \"-7\") }}", "-007"),
+            ("{{ n | pad(3, \"0\") }}-{{ n | pad(2) }}", "007- 7"),
+            ("{{ \"\" | default(\"none\") }}", "none"),
+            (
+                "{{ env(\"TAP_TEMPLATE_UNSET\", \"fallback\") }}",
+                "fallback",
+            ),
+            ("{{ \"{{\" }} and }}", "{{ and }}"),
+            ("{{ \"a}}b\" }}", "a}}b"),
+            ("{{ \"src/foo-bar.h\" | guard }}", "SRC_FOO_BAR_H_"),
+            (
+                "{{ \"./io/x.hpp\" | guard(\"myproj\") }}",
+                "MYPROJ_IO_X_HPP_",
+            ),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(render_with(template, &context), *expected, "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let context = Context::new().var("n", "x");
+        for template in [
+            "{{ n | pad }}",
+            "{{ n | pad(99999999) }}",
+            "{{ \"%d\" | format(n) }}",
+            "{{ \"%s %s\" | format(n) }}",
+            "{{ format(\"%99999999s\", n) }}",
+            "{{ env(\"TAP_TEMPLATE_UNSET\") }}",
+        ] {
+            assert!(render(template, &context).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_foreign_braces_are_kept() {
+        let context = Context::new().var("n", "x");
+        for template in [
+            "{{ missing }}",
+            "{{ n | nope }}",
+            "{{#each items}}{{this}}{{/each}}",
+            "{{> header }}",
+            "{{ user.name | e }}",
+            "{{ \"unterminated }}",
+            "{{ n ",
+            "{{ n n }}",
+            "{{ shell(\"echo hi\") | nope }}",
+        ] {
+            assert_eq!(render_with(template, &context), template);
+        }
+        assert_eq!(render_with("{{ {{ n }} }}", &context), "{{ x }}");
+    }
+
+    #[test]
+    fn test_unclosed_braces_after_text() {
+        let context = Context::new().var("n", "x");
+        for template in ["let s = \"{{\";", "}}{{", "{{ n }} and {{ n"] {
+            let expected = template.replacen("{{ n }}", "x", 1);
+            assert_eq!(render_with(template, &context), expected);
+        }
+    }
+
+    #[test]
+    fn test_target_variables() {
+        let template = "{{ target.stem | upper }}_{{ target.ext | upper }} {{ target.parent }} {{ target.name }}";
+        let cwd = std::env::current_dir().unwrap();
+        let cases: &[(&Path, &str)] = &[
+            (Path::new("src/parser.h"), "PARSER_H src parser.h"),
+            (&cwd.join("include/io/file.h"), "FILE_H include/io file.h"),
+            (Path::new("Makefile"), "MAKEFILE_ . Makefile"),
+        ];
+        for (target, expected) in cases {
+            let context = Context::new().target(target);
+            assert_eq!(
+                render_with(template, &context),
+                *expected,
+                "{}",
+                target.display()
+            );
+        }
+        let context = Context::new().target(&cwd.join("src/lib.rs"));
+        assert_eq!(render_with("{{ target.relpath }}", &context), "src/lib.rs");
+        assert!(context.used_target());
+
+        let context = Context::new().target(Path::new("a.rs")).var("n", "x");
+        render_with("{{ n | upper }}", &context);
+        assert!(!context.used_target());
+    }
+
+    #[test]
+    fn test_shell_needs_opt_in() {
+        let template = "{{ shell(\"echo hello\") | upper }}";
+        assert!(render(template, &Context::new()).is_err());
+        let context = Context::new().allow_shell(true);
+        assert_eq!(render_with(template, &context), "HELLO");
+        assert!(render("{{ shell(\"exit 3\") }}", &context).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_sandbox() {
+        let template = "{{ shell(\"echo ${HOME:-none}\") }}";
+        let sandbox = Sandbox {
+            dir: Some("/".into()),
+            network: true,
+            ..Sandbox::default()
+        };
+        let context = Context::new().allow_shell(true).sandbox(Some(sandbox));
+        assert_eq!(render_with(template, &context), "none");
+        assert_eq!(render_with("{{ shell(\"pwd\") }}", &context), "/");
+    }
+}
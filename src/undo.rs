@@ -0,0 +1,643 @@
+//! The undo journal: what each run changed, so that `tap undo` can put it
+//! back.
+//!
+//! A run keeps its journal in a directory of its own under the journal
+//! directory (`$XDG_STATE_HOME/tap/undo`). `journal.jsonl` gets a line for
+//! every path the run changed, written as soon as the path is done, with
+//! what the path was like before: missing (and which parents were missing
+//! with it), or its mode, owner and times, and either its length before an
+//! append or a copy, in `content/`, of what a write replaced. Only the last
+//! few runs are kept.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+use crate::units::{parse_size, show_size};
+use crate::{missing_ancestors, platform, say, say_err, state_dir, tr, FileOp, Fingerprint};
+
+/// Runs kept for `tap undo`; older journals are removed as new ones are
+/// written.
+const KEEP_RUNS: usize = 10;
+
+/// Content a run copies aside for undo, 64 MiB. Paths whose content a write
+/// replaces past that are changed without a journal entry, so a large
+/// `--write` run over a glob doesn't write everything twice.
+const COPY_LIMIT: u64 = 64 << 20;
+
+const ENTRIES: &str = "journal.jsonl";
+
+/// Where runs keep their journals by default.
+pub fn journal_dir() -> Result<PathBuf> {
+    Ok(state_dir()?.join("undo"))
+}
+
+/// A changed path, as recorded in the journal.
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    path: PathBuf,
+    /// The path before the run, or `None` if the run created it
+    before: Option<Previous>,
+    /// Parent directories the run created for the path, outermost first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    created_parents: Vec<PathBuf>,
+    /// The path after the run, to tell whether it changed again since
+    after: Option<Stamp>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Previous {
+    /// Where the path pointed, if it was a symlink
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<PathBuf>,
+    /// Which file it was, to tell when the run replaced it with a hard link
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_id: Option<(u64, u64)>,
+    mode: u32,
+    owner: (u32, u32),
+    accessed: Option<SystemTime>,
+    modified: Option<SystemTime>,
+    content: Option<Content>,
+}
+
+/// How to get back the content a run changed.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Content {
+    /// Cut the file back to this length, undoing an append
+    Length(u64),
+    /// Copy back this file from the journal's `content/` directory
+    Copy(String),
+}
+
+/// What is compared to tell whether a path changed after the run. A
+/// directory's length and times change with its entries, so only its
+/// existence counts.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Stamp {
+    is_dir: bool,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl Stamp {
+    fn capture(path: &Path) -> Option<Self> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let is_dir = metadata.is_dir();
+        Some(Self {
+            is_dir,
+            len: if is_dir { 0 } else { metadata.len() },
+            modified: metadata.modified().ok().filter(|_| !is_dir),
+        })
+    }
+}
+
+/// The journal of a run in progress. Nothing is written until the first
+/// path needs it, so a run that changes nothing leaves no journal behind.
+pub(crate) struct Journal {
+    root: PathBuf,
+    dir: PathBuf,
+    entries: Mutex<Option<File>>,
+    copies: AtomicUsize,
+    /// Bytes copied aside so far, up to `COPY_LIMIT`
+    copied: AtomicU64,
+    over_limit: AtomicBool,
+}
+
+/// What `Journal::before` found out about a path before processing it.
+pub(crate) struct Before {
+    path: PathBuf,
+    fingerprint: Option<Fingerprint>,
+    previous: Option<Previous>,
+    created_parents: Vec<PathBuf>,
+}
+
+impl Journal {
+    /// A journal for a new run, in its own directory under `root`.
+    pub(crate) fn start(root: &Path) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Self {
+            root: root.to_path_buf(),
+            dir: root.join(format!("{:020}-{}", nanos, std::process::id())),
+            entries: Mutex::new(None),
+            copies: AtomicUsize::new(0),
+            copied: AtomicU64::new(0),
+            over_limit: AtomicBool::new(false),
+        }
+    }
+
+    /// Captures what processing `path` with `op` may change, copying aside
+    /// content that a write would replace. `None` once the run has copied
+    /// all it may (see `COPY_LIMIT`), when the path goes unjournaled.
+    pub(crate) fn before(&self, path: &Path, op: &FileOp) -> Result<Option<Before>> {
+        let absolute = std::path::absolute(path)
+            .with_context(|| tr!("Failed to resolve {}", path.display()))?;
+        let fingerprint = Fingerprint::capture(&absolute, op);
+        let metadata = fs::symlink_metadata(&absolute).ok();
+        let Some(metadata) = metadata else {
+            return Ok(Some(Before {
+                created_parents: absolute.parent().map(missing_ancestors).unwrap_or_default(),
+                path: absolute,
+                fingerprint,
+                previous: None,
+            }));
+        };
+        let content = match content_change(op) {
+            _ if !metadata.is_file() => None,
+            Some(ContentChange::Append) => Some(Content::Length(metadata.len())),
+            Some(ContentChange::Resize(size)) if size >= metadata.len() => {
+                Some(Content::Length(metadata.len()))
+            }
+            Some(ContentChange::Replace | ContentChange::Resize(_)) => {
+                match self.copy(&absolute, metadata.len())? {
+                    Some(name) => Some(Content::Copy(name)),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+        Ok(Some(Before {
+            previous: Some(Previous {
+                link: fs::read_link(&absolute).ok(),
+                file_id: platform::file_id(&metadata),
+                mode: platform::mode(&metadata),
+                owner: platform::owner(&metadata),
+                accessed: metadata.accessed().ok(),
+                modified: metadata.modified().ok(),
+                content,
+            }),
+            path: absolute,
+            fingerprint,
+            created_parents: Vec::new(),
+        }))
+    }
+
+    /// Copies `path`, `len` bytes long, into the journal, returning the name
+    /// of the copy, or `None` if that would take the run past `COPY_LIMIT`.
+    fn copy(&self, path: &Path, len: u64) -> Result<Option<String>> {
+        let copied = self.copied.fetch_add(len, Ordering::Relaxed);
+        if copied.saturating_add(len) > COPY_LIMIT {
+            self.copied.fetch_sub(len, Ordering::Relaxed);
+            if !self.over_limit.swap(true, Ordering::Relaxed) {
+                say_err!(
+                    "Warning: Saved {} of replaced content for undo; tap undo won't restore the rest of this run's writes",
+                    show_size(COPY_LIMIT, false)
+                );
+            }
+            return Ok(None);
+        }
+        let name = self.copies.fetch_add(1, Ordering::Relaxed).to_string();
+        let content_dir = self.dir.join("content");
+        let result = fs::create_dir_all(&content_dir)
+            .and_then(|()| fs::copy(path, content_dir.join(&name)))
+            .with_context(|| tr!("Failed to save {} for undo", path.display()));
+        if result.is_err() {
+            self.copied.fetch_sub(len, Ordering::Relaxed);
+        }
+        result.map(|_| Some(name))
+    }
+
+    /// Adds the path to the journal if processing changed it. Failing to is
+    /// a warning, since the change itself has already been made.
+    pub(crate) fn record(&self, before: Before, op: &FileOp) {
+        if Fingerprint::capture(&before.path, op) == before.fingerprint {
+            if let Some(Content::Copy(name)) = before.previous.and_then(|p| p.content) {
+                let _ = fs::remove_file(self.dir.join("content").join(name));
+            }
+            return;
+        }
+        let entry = Entry {
+            after: Stamp::capture(&before.path),
+            path: before.path,
+            before: before.previous,
+            created_parents: before.created_parents,
+        };
+        if let Err(error) = self.append(&entry) {
+            say_err!(
+                "Warning: {} can't be undone: {:#}",
+                entry.path.display(),
+                error
+            );
+        }
+    }
+
+    fn append(&self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.is_none() {
+            fs::create_dir_all(&self.dir)
+                .with_context(|| tr!("Failed to create {}", self.dir.display()))?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(ENTRIES))
+                .with_context(|| tr!("Failed to write the undo journal"))?;
+            *entries = Some(file);
+        }
+        let file = entries.as_mut().expect("opened above");
+        file.write_all(line.as_bytes())
+            .with_context(|| tr!("Failed to write the undo journal"))
+    }
+
+    /// Ends the run: removes the journal directory if nothing was recorded
+    /// in it, and otherwise the journals of all but the last few runs.
+    pub(crate) fn finish(self) {
+        let recorded = self
+            .entries
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some();
+        if !recorded {
+            let _ = fs::remove_dir_all(&self.dir);
+            return;
+        }
+        let mut runs = runs(&self.root);
+        if runs.len() > KEEP_RUNS {
+            runs.truncate(runs.len() - KEEP_RUNS);
+            for run in runs {
+                let _ = fs::remove_dir_all(run);
+            }
+        }
+    }
+}
+
+enum ContentChange {
+    Append,
+    Replace,
+    /// Set to this length, which only needs a copy if it cuts the file short
+    Resize(u64),
+}
+
+/// How processing with `op` changes an existing file's content.
+fn content_change(op: &FileOp) -> Option<ContentChange> {
+    if op.dir || op.no_touch || op.times_only || op.check {
+        None
+    } else if op.link_to.is_some() || op.hard_link_to.is_some() {
+        Some(ContentChange::Replace)
+    } else if op.log_entry.is_some() && op.log_rotate.is_none() {
+        Some(ContentChange::Append)
+    } else if op.log_entry.is_some()
+        || op.pidfile
+        || op.trim
+        || op.punch_hole.is_some()
+        || op.fill.is_some()
+    {
+        Some(ContentChange::Replace)
+    } else if op.write.is_none()
+        && op.template.is_none()
+        && !op.stdin
+        && op.lorem.is_none()
+        && op.shebang.is_none()
+    {
+        let size = op.size.as_deref().and_then(|size| parse_size(size).ok());
+        size.map(ContentChange::Resize)
+    } else if op.append {
+        Some(ContentChange::Append)
+    } else {
+        Some(ContentChange::Replace)
+    }
+}
+
+/// The journal directories under `root`, oldest first.
+fn runs(root: &Path) -> Vec<PathBuf> {
+    let mut runs = fs::read_dir(root)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.join(ENTRIES).is_file())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    runs.sort();
+    runs
+}
+
+/// Reverts the last run journaled under `root`. Paths that changed again
+/// since are left alone unless `force` is set, and stay in the journal for
+/// another try; with `dry_run`, only says what would be reverted.
+pub fn undo(root: &Path, force: bool, dry_run: bool, verbose: bool) -> Result<()> {
+    let Some(run) = runs(root).pop() else {
+        anyhow::bail!(tr!("Nothing to undo"));
+    };
+    let journal = run.join(ENTRIES);
+    let file = File::open(&journal)
+        .with_context(|| tr!("Failed to read the undo journal {}", journal.display()))?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line =
+            line.with_context(|| tr!("Failed to read the undo journal {}", journal.display()))?;
+        // A run killed mid-line leaves an incomplete last entry
+        if let Ok(entry) = serde_json::from_str::<Entry>(&line) {
+            entries.push(entry);
+        }
+    }
+
+    let mut kept = Vec::new();
+    let mut undone = 0;
+    let mut parents = Vec::new();
+    for entry in entries.into_iter().rev() {
+        if !force && Stamp::capture(&entry.path) != entry.after {
+            say_err!(
+                "Warning: {} changed after the run, so it was left alone (undo with --force to revert it anyway)",
+                entry.path.display()
+            );
+            kept.push(entry);
+            continue;
+        }
+        let result = if dry_run {
+            match entry.before {
+                Some(_) => say!("Would restore: {}", entry.path.display()),
+                None => say!("Would remove: {}", entry.path.display()),
+            }
+            Ok(())
+        } else {
+            revert(&entry, &run, verbose)
+        };
+        if let Err(error) = result {
+            say_err!("Error: {}: {:#}", entry.path.display(), error);
+            kept.push(entry);
+            continue;
+        }
+        undone += 1;
+        parents.extend(entry.created_parents);
+    }
+    // Parents go last, innermost first, as several paths can share them
+    parents.sort_by_key(|parent| std::cmp::Reverse(parent.components().count()));
+    parents.dedup();
+    for parent in parents {
+        if dry_run {
+            say!("Would remove: {}", parent.display());
+        } else if fs::remove_dir(&parent).is_ok() && verbose {
+            say!("Removed: {}", parent.display());
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+
+    if kept.is_empty() {
+        fs::remove_dir_all(&run).with_context(|| tr!("Failed to remove {}", run.display()))?;
+    } else {
+        kept.reverse();
+        let lines = kept
+            .iter()
+            .map(|entry| serde_json::to_string(entry).map(|line| line + "\n"))
+            .collect::<Result<String, _>>()?;
+        fs::write(&journal, lines).with_context(|| tr!("Failed to write the undo journal"))?;
+    }
+    say!("Undid {} path(s)", undone);
+    if !kept.is_empty() {
+        anyhow::bail!(tr!("{} path(s) were not undone", kept.len()));
+    }
+    Ok(())
+}
+
+/// Puts one path back the way the journal says it was.
+fn revert(entry: &Entry, run: &Path, verbose: bool) -> Result<()> {
+    let path = &entry.path;
+    let Some(previous) = &entry.before else {
+        let removed = match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => fs::remove_dir(path),
+            Ok(_) => fs::remove_file(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => Err(e),
+        };
+        removed.with_context(|| tr!("Failed to remove {}", path.display()))?;
+        if verbose {
+            say!("Removed: {}", path.display());
+        }
+        return Ok(());
+    };
+
+    // A link the run put in place of the path goes first, so that what was
+    // there is restored in its place rather than through the link: a
+    // symlink, or another file's hard link
+    let current = fs::symlink_metadata(path).ok();
+    let link = fs::read_link(path).ok();
+    let hard_linked = current.as_ref().is_some_and(|metadata| {
+        previous.file_id.is_some()
+            && platform::file_id(metadata) != previous.file_id
+            && platform::link_count(metadata) > 1
+    });
+    let replaced = current.is_some() && (link != previous.link || hard_linked);
+    if replaced {
+        fs::remove_file(path).with_context(|| tr!("Failed to remove {}", path.display()))?;
+    }
+    if let Some(link) = &previous.link {
+        if replaced || current.is_none() {
+            platform::symlink(link, path)
+                .with_context(|| tr!("Failed to create symlink {}", path.display()))?;
+        }
+        if verbose {
+            say!("Restored: {}", path.display());
+        }
+        return Ok(());
+    }
+
+    match &previous.content {
+        Some(Content::Length(len)) => OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(*len))
+            .map(|_| ()),
+        Some(Content::Copy(name)) => fs::copy(run.join("content").join(name), path).map(|_| ()),
+        None => Ok(()),
+    }
+    .with_context(|| tr!("Failed to restore the content of {}", path.display()))?;
+
+    let metadata =
+        fs::symlink_metadata(path).with_context(|| tr!("Failed to read {}", path.display()))?;
+    if platform::mode(&metadata) != previous.mode {
+        platform::set_mode(path, previous.mode)
+            .with_context(|| tr!("Failed to set permissions"))?;
+    }
+    if platform::owner(&metadata) != previous.owner {
+        let (uid, gid) = previous.owner;
+        platform::lchown(path, Some(uid), Some(gid))
+            .with_context(|| tr!("Failed to change owner of {}", path.display()))?;
+    }
+    if let (Some(accessed), Some(modified)) = (previous.accessed, previous.modified) {
+        filetime::set_file_times(
+            path,
+            FileTime::from_system_time(accessed),
+            FileTime::from_system_time(modified),
+        )
+        .with_context(|| tr!("Failed to set timestamp"))?;
+    }
+    if verbose {
+        say!("Restored: {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use crate::TapBuilder;
+
+    #[test]
+    fn test_undo() -> Result<()> {
+        let dir = tempdir()?;
+        let journals = dir.path().join("undo");
+        let existing = dir.path().join("existing.txt");
+        let log = dir.path().join("log.txt");
+        let edited = dir.path().join("edited.txt");
+        let created = dir.path().join("new/nested/file.txt");
+        fs::write(&existing, "original")?;
+        fs::write(&log, "first\n")?;
+        fs::write(&edited, "before")?;
+        let old = FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_times(&existing, old, old)?;
+
+        let run = |paths: &[&Path], op: TapBuilder| {
+            let paths = paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            op.journal(&journals).build().run(&paths)
+        };
+        run(
+            &[&existing, &created, &edited],
+            TapBuilder::new().write("replaced"),
+        )?;
+        run(&[&log], TapBuilder::new().write("second\n").append())?;
+        assert_eq!(runs(&journals).len(), 2);
+
+        undo(&journals, false, false, false)?;
+        assert_eq!(fs::read_to_string(&log)?, "first\n");
+
+        // Edited after the run, so left for --force
+        fs::write(&edited, "edited again")?;
+        assert!(undo(&journals, false, false, false).is_err());
+        assert_eq!(fs::read_to_string(&existing)?, "original");
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&existing)?);
+        assert_eq!(mtime, old);
+        assert!(!dir.path().join("new").exists());
+        assert_eq!(fs::read_to_string(&edited)?, "edited again");
+
+        undo(&journals, true, false, false)?;
+        assert_eq!(fs::read_to_string(&edited)?, "before");
+        assert!(runs(&journals).is_empty());
+        assert!(undo(&journals, false, false, false).is_err());
+
+        // Nothing changed, so nothing to journal
+        let mode = platform::mode(&fs::metadata(&existing)?);
+        run(
+            &[&existing],
+            TapBuilder::new().chmod(format!("{:o}", mode)).no_touch(),
+        )?;
+        assert!(runs(&journals).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unjournaled_writes_go_ahead() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("file.txt");
+        let big = dir.path().join("big.bin");
+        fs::write(&file, "before")?;
+        fs::File::create(&big)?.set_len(COPY_LIMIT + 1)?;
+        let write = |journals: &Path, path: &Path| {
+            TapBuilder::new()
+                .write("after")
+                .journal(journals)
+                .build()
+                .run(&[path.to_string_lossy().into_owned()])
+        };
+
+        // No journal directory can be made under a file
+        let blocked = dir.path().join("blocked");
+        fs::write(&blocked, "")?;
+        write(&blocked.join("undo"), &file)?;
+        assert_eq!(fs::read_to_string(&file)?, "after");
+
+        // Too big to copy aside
+        let journals = dir.path().join("undo");
+        write(&journals, &big)?;
+        assert_eq!(fs::read_to_string(&big)?, "after");
+        assert!(runs(&journals).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_undo_resize() -> Result<()> {
+        let dir = tempdir()?;
+        let journals = dir.path().join("undo");
+        let file = dir.path().join("data.bin");
+        fs::write(&file, "content")?;
+        let paths = [file.to_string_lossy().into_owned()];
+        let resize = |size: &str| {
+            TapBuilder::new()
+                .size(size)
+                .journal(&journals)
+                .build()
+                .run(&paths)
+        };
+
+        // Growing only needs the old length to undo, cutting a copy
+        resize("1M")?;
+        resize("3")?;
+        assert_eq!(fs::read(&file)?, b"con");
+        undo(&journals, false, false, false)?;
+        assert_eq!(fs::metadata(&file)?.len(), 1 << 20);
+        undo(&journals, false, false, false)?;
+        assert_eq!(fs::read_to_string(&file)?, "content");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_link() -> Result<()> {
+        let dir = tempdir()?;
+        let journals = dir.path().join("undo");
+        let file = dir.path().join("file.txt");
+        let link = dir.path().join("link");
+        fs::write(&file, "original")?;
+        platform::symlink(Path::new("elsewhere"), &link)?;
+
+        let paths = [&file, &link].map(|path| path.to_string_lossy().into_owned());
+        TapBuilder::new()
+            .link_to("target")
+            .force()
+            .journal(&journals)
+            .build()
+            .run(&paths)?;
+        assert_eq!(fs::read_link(&file)?, Path::new("target"));
+
+        undo(&journals, false, false, false)?;
+        assert!(fs::symlink_metadata(&file)?.is_file());
+        assert_eq!(fs::read_to_string(&file)?, "original");
+        assert_eq!(fs::read_link(&link)?, Path::new("elsewhere"));
+
+        // Restoring the file must not write through to the one it was
+        // hard linked to
+        let target = dir.path().join("target");
+        fs::write(&target, "target")?;
+        TapBuilder::new()
+            .hard_link_to(&target)
+            .force()
+            .journal(&journals)
+            .build()
+            .run(&paths)?;
+        assert_eq!(fs::read_to_string(&file)?, "target");
+        undo(&journals, false, false, false)?;
+        assert_eq!(fs::read_to_string(&file)?, "original");
+        assert_eq!(fs::read_to_string(&target)?, "target");
+        assert_eq!(fs::read_link(&link)?, Path::new("elsewhere"));
+        Ok(())
+    }
+}
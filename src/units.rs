@@ -0,0 +1,151 @@
+//! Sizes as users write them (`512`, `64K`, `1.5MB`, `2GiB`) and as tap
+//! prints them.
+
+use anyhow::{Context, Result};
+
+use crate::tr;
+
+/// Binary units from KiB up, as used by `format_size`.
+const BINARY_UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Parses a size in bytes. A bare letter or an `iB` suffix means a binary
+/// multiple (`64K` and `64KiB` are both 65536) and a `B` suffix an SI one
+/// (`1.5MB` is 1,500,000). Fractions are rounded to the nearest byte.
+pub fn parse_size(input: &str) -> Result<u64> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (value, unit) = input.split_at(split);
+    let multiplier = multiplier(unit.trim())
+        .with_context(|| tr!("Invalid size unit '{}' in: {}", unit.trim(), input))?;
+
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if whole.is_empty() && fraction.is_empty() || fraction.contains('.') {
+        anyhow::bail!(tr!("Invalid size: {}", input));
+    }
+    let digits = |part: &str| match part {
+        "" => Ok(0),
+        part => part
+            .parse::<u128>()
+            .with_context(|| tr!("Invalid size: {}", input)),
+    };
+    // Exact arithmetic, so `0.5K` is 512 and not 511.99…
+    let scale = 10u128
+        .checked_pow(fraction.len() as u32)
+        .with_context(|| tr!("Invalid size: {}", input))?;
+    let bytes = digits(whole)? * multiplier + (digits(fraction)? * multiplier + scale / 2) / scale;
+    u64::try_from(bytes).with_context(|| tr!("Size too large: {}", input))
+}
+
+fn multiplier(unit: &str) -> Option<u128> {
+    let unit = unit.to_ascii_uppercase();
+    if unit.is_empty() || unit == "B" {
+        return Some(1);
+    }
+    let (prefix, suffix) = unit.split_at(1);
+    let power = "KMGTPE".find(prefix)? as u32 + 1;
+    match suffix {
+        "" | "IB" => Some(1u128 << (10 * power)),
+        "B" => Some(1000u128.pow(power)),
+        _ => None,
+    }
+}
+
+/// Formats a size for people: `512 B`, `1.5 KiB`, `10 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // Compare after rounding, so 1023.96 KiB shows as 1 MiB
+    while (value * 10.0).round() / 10.0 >= 1024.0 && unit < BINARY_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    let shown = format!("{:.1}", value);
+    format!(
+        "{} {}",
+        shown.strip_suffix(".0").unwrap_or(&shown),
+        BINARY_UNITS[unit]
+    )
+}
+
+/// Formats a size as `format_size` does, or as a plain byte count if `exact`.
+pub fn show_size(bytes: u64, exact: bool) -> String {
+    if exact {
+        bytes.to_string()
+    } else {
+        format_size(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("512")?, 512);
+        assert_eq!(parse_size("64K")?, 65536);
+        assert_eq!(parse_size("10M")?, 10 * 1024 * 1024);
+        assert_eq!(parse_size("1gib")?, 1 << 30);
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("5Q").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_size_units() -> Result<()> {
+        let cases: &[(&str, u64)] = &[
+            ("0", 0),
+            ("7B", 7),
+            ("7 b", 7),
+            ("1KiB", 1024),
+            ("1KB", 1000),
+            ("1kb", 1000),
+            ("1.5MB", 1_500_000),
+            ("1.5M", 1_572_864),
+            ("1.5MiB", 1_572_864),
+            ("2G", 2 << 30),
+            ("2GB", 2_000_000_000),
+            ("3T", 3 << 40),
+            ("3TB", 3_000_000_000_000),
+            ("1P", 1 << 50),
+            ("1EiB", 1 << 60),
+            ("0.5K", 512),
+            ("1.0001K", 1024),
+            (".5K", 512),
+            ("2.", 2),
+            (" 10 MiB ", 10 << 20),
+            ("15E", 15 << 60),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_size(input)?, *expected, "{}", input);
+        }
+        for input in ["", ".", "1.2.3", "K", "-1", "1KiBs", "1Ki", "16E", "1e3"] {
+            assert!(parse_size(input).is_err(), "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_size() {
+        let cases: &[(u64, &str)] = &[
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1 KiB"),
+            (1536, "1.5 KiB"),
+            (10 << 20, "10 MiB"),
+            ((1 << 20) - 1, "1 MiB"),
+            (3 << 40, "3 TiB"),
+            (u64::MAX, "16 EiB"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(format_size(*bytes), *expected, "{}", bytes);
+        }
+        assert_eq!(show_size(1536, true), "1536");
+        assert_eq!(show_size(1536, false), "1.5 KiB");
+    }
+}
@@ -0,0 +1,416 @@
+//! Running as other users: `--as-user` targets in each user's home,
+//! `tap skel-sync` and the XDG directories of `tap xdg-init`.
+
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::platform::{self, ModeExt};
+use crate::{expand_paths, say, say_err, tr, walk_tree, xdg_base_dir};
+
+/// An entry from the user database.
+pub struct UserAccount {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+impl UserAccount {
+    /// Looks up a user by name, or by UID when given a number.
+    pub fn lookup(user: &str) -> Result<Self> {
+        match user.parse() {
+            Ok(uid) => platform::user_by_uid(uid),
+            Err(_) => platform::user_by_name(user),
+        }
+        .with_context(|| tr!("Failed to look up user {}", user))?
+        .with_context(|| tr!("Unknown user: {}", user))
+    }
+
+    /// All users with a login UID (>= 1000, excluding nobody) and an existing
+    /// home. There are none on Windows.
+    pub fn regular_users() -> Vec<Self> {
+        platform::users()
+            .into_iter()
+            .filter(|user| user.uid >= 1000 && user.uid != 65534 && user.home.is_dir())
+            .collect()
+    }
+}
+
+/// Sets the owner and group of entries tap just created, which it creates
+/// as `owner` (see `platform::as_user`), so new entries get the user's group
+/// rather than one inherited from their directory.
+pub(crate) fn give_to_user(created: &[PathBuf], owner: &UserAccount, verbose: bool) -> Result<()> {
+    if !platform::can_give_to(owner.uid) {
+        anyhow::bail!(tr!("--as-user {} requires running as root", owner.name));
+    }
+    for path in created
+        .iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+    {
+        platform::lchown(path, Some(owner.uid), Some(owner.gid))
+            .with_context(|| tr!("Failed to change owner of {}", path.display()))?;
+        if verbose {
+            say!("Owner set to {} for: {}", owner.name, path.display());
+        }
+    }
+    Ok(())
+}
+
+/// The requested paths under each user's home directory, each with the
+/// user to create it as. A user whose paths can't be expanded is reported
+/// and added to `failed`, without stopping the others.
+pub(crate) fn user_targets<'a>(
+    paths: &[String],
+    users: &'a [UserAccount],
+    failed: &mut BTreeSet<&'a str>,
+) -> Result<Vec<(PathBuf, Option<&'a UserAccount>)>> {
+    if let Some(absolute) = paths.iter().find(|path| Path::new(path).is_absolute()) {
+        anyhow::bail!(tr!(
+            "--each-user paths must be relative to the home directory: {}",
+            absolute
+        ));
+    }
+
+    let mut targets = Vec::new();
+    for user in users {
+        let patterns = paths
+            .iter()
+            .map(|path| user.home.join(path).to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        match expand_paths(&patterns) {
+            Ok(paths) => targets.extend(paths.into_iter().map(|path| (path, Some(user)))),
+            Err(e) => {
+                say_err!("Error for user {}: {:#}", user.name, e);
+                failed.insert(user.name.as_str());
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// Copies skeleton entries missing from the user's home, keeping their modes
+/// and giving them to the user. Existing entries are never touched. Returns
+/// how many entries were (or, with `check`, would be) created.
+pub fn skel_sync(skel: &Path, user: &UserAccount, check: bool, verbose: bool) -> Result<usize> {
+    let mut created = 0;
+    for relative in walk_tree(skel)? {
+        let source = skel.join(&relative);
+        let target = user.home.join(&relative);
+        if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        }
+        created += 1;
+        if check {
+            say!("{}: would create {}", user.name, target.display());
+            continue;
+        }
+
+        // Read as the caller, since skeleton files may be private to root,
+        // and created as the user
+        let metadata = fs::symlink_metadata(&source)?;
+        let link = match metadata.file_type().is_symlink() {
+            true => Some(fs::read_link(&source)?),
+            false => None,
+        };
+        let content = match metadata.is_dir() || link.is_some() {
+            true => Vec::new(),
+            false => {
+                fs::read(&source).with_context(|| tr!("Failed to copy {}", source.display()))?
+            }
+        };
+        platform::as_user(user.uid, user.gid, || -> Result<()> {
+            if metadata.is_dir() {
+                fs::create_dir(&target)
+                    .with_context(|| tr!("Failed to create {}", target.display()))?;
+            } else if let Some(link) = &link {
+                platform::symlink(link, &target)
+                    .with_context(|| tr!("Failed to create {}", target.display()))?;
+            } else {
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&target)
+                    .and_then(|mut file| file.write_all(&content))
+                    .with_context(|| tr!("Failed to copy {}", source.display()))?;
+            }
+            if link.is_none() {
+                fs::set_permissions(&target, metadata.permissions())
+                    .with_context(|| tr!("Failed to set permissions on {}", target.display()))?;
+            }
+            give_to_user(std::slice::from_ref(&target), user, false)
+        })
+        .with_context(|| tr!("Failed to switch to user {}", user.name))??;
+        if verbose {
+            say!("{}: created {}", user.name, target.display());
+        }
+    }
+    Ok(created)
+}
+
+#[derive(Serialize)]
+pub struct XdgDirs {
+    config: PathBuf,
+    cache: PathBuf,
+    data: PathBuf,
+    state: PathBuf,
+}
+
+impl XdgDirs {
+    pub fn resolve<F>(app: Option<&str>, env: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<std::ffi::OsString>,
+    {
+        if let Some(app) = app {
+            if app.is_empty() || app.contains(['/', '\\']) || app == "." || app == ".." {
+                anyhow::bail!(tr!("Invalid application name: {}", app));
+            }
+        }
+        let dir = |var: &str, default: &str| -> Result<PathBuf> {
+            let base = xdg_base_dir(var, default, &env)?;
+            Ok(app.map_or(base.clone(), |app| base.join(app)))
+        };
+        Ok(Self {
+            config: dir("XDG_CONFIG_HOME", ".config")?,
+            cache: dir("XDG_CACHE_HOME", ".cache")?,
+            data: dir("XDG_DATA_HOME", ".local/share")?,
+            state: dir("XDG_STATE_HOME", ".local/state")?,
+        })
+    }
+
+    pub fn entries(&self) -> [(&'static str, &Path); 4] {
+        [
+            ("config", &self.config),
+            ("cache", &self.cache),
+            ("data", &self.data),
+            ("state", &self.state),
+        ]
+    }
+
+    /// Creates the directories private to the user (0700), as the XDG spec
+    /// expects for per-user data. Existing directories keep their modes.
+    pub fn create(&self, verbose: bool) -> Result<()> {
+        for (_, dir) in self.entries() {
+            if dir.is_dir() {
+                continue;
+            }
+            fs::DirBuilder::new()
+                .recursive(true)
+                .with_mode(0o700)
+                .create(dir)
+                .with_context(|| tr!("Failed to create {}", dir.display()))?;
+            if verbose {
+                say!("Directory created: {}", dir.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use tempfile::tempdir;
+
+    use crate::{capture_output, process_path, run_for, FileOp};
+
+    #[test]
+    fn test_as_user_owns_created_entries() -> Result<()> {
+        let root = UserAccount::lookup("0")?;
+        assert_eq!(root.uid, 0);
+        assert!(UserAccount::lookup("no-such-user-tap").is_err());
+        if unsafe { libc::geteuid() } != 0 {
+            return Ok(());
+        }
+
+        // Switching user applies to the whole process, so it happens in a
+        // child rather than alongside the other tests
+        let child = std::process::Command::new(std::env::current_exe()?)
+            .args(["--exact", "users::tests::test_as_user_in_child"])
+            .arg("--ignored")
+            .env("TAP_AS_USER_TEST", "1")
+            .output()?;
+        let stdout = String::from_utf8_lossy(&child.stdout);
+        assert!(child.status.success(), "{}", stdout);
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+        Ok(())
+    }
+
+    /// Run as root by `test_as_user_owns_created_entries` in a child process.
+    #[test]
+    #[ignore]
+    fn test_as_user_in_child() -> Result<()> {
+        if std::env::var_os("TAP_AS_USER_TEST").is_none() {
+            return Ok(());
+        }
+        let dir = tempdir()?;
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755))?;
+        let nobody = UserAccount::lookup("65534")?;
+        let home = dir.path().join("home");
+        fs::create_dir(&home)?;
+        platform::lchown(&home, Some(nobody.uid), Some(nobody.gid))?;
+        let file_path = home.join("config/app.toml");
+        let op = FileOp {
+            as_user: Some("65534".to_string()),
+            ..Default::default()
+        };
+        process_path(&file_path, &op)?;
+
+        for created in [home.join("config"), file_path] {
+            let metadata = fs::metadata(&created)?;
+            assert_eq!((metadata.uid(), metadata.gid()), (nobody.uid, nobody.gid));
+        }
+        assert_eq!(fs::metadata(dir.path())?.uid(), 0);
+
+        // A symlink the user plants can't lead the write outside their home
+        let outside = dir.path().join("etc");
+        fs::create_dir(&outside)?;
+        std::os::unix::fs::symlink(&outside, home.join(".config"))?;
+        assert!(process_path(&home.join(".config/app/app.toml"), &op).is_err());
+        assert!(!outside.join("app").exists());
+        assert_eq!(unsafe { libc::geteuid() }, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_for_each_user() -> Result<()> {
+        let dir = tempdir()?;
+        let alice_home = dir.path().join("alice");
+        let bob_home = dir.path().join("bob");
+        fs::create_dir(&alice_home)?;
+        fs::create_dir(&bob_home)?;
+        // bob's .config is a file, so creating inside it fails for bob only
+        fs::write(bob_home.join(".config"), "")?;
+        let users = [
+            UserAccount {
+                name: "alice".to_string(),
+                uid: unsafe { libc::geteuid() },
+                gid: unsafe { libc::getegid() },
+                home: alice_home.clone(),
+            },
+            UserAccount {
+                name: "bob".to_string(),
+                uid: unsafe { libc::geteuid() },
+                gid: unsafe { libc::getegid() },
+                home: bob_home,
+            },
+        ];
+
+        let paths = [".config/app/config.toml".to_string()];
+        let op = FileOp {
+            each_user: true,
+            check: true,
+            ..Default::default()
+        };
+        run_for(&op, &paths, &users)?;
+        assert!(!alice_home.join(".config").exists());
+
+        let target = alice_home.join(".config/app/config.toml");
+        let dry_run = FileOp {
+            check: false,
+            dry_run: true,
+            ..op.clone()
+        };
+        let (result, output) = capture_output(|| run_for(&dry_run, &paths, &users));
+        result?;
+        assert!(
+            output.contains(&format!("Would create file: {}", target.display())),
+            "{output}"
+        );
+        assert!(!alice_home.join(".config").exists());
+
+        // Each user's paths go through the rest of the run like any others
+        let list = dir.path().join("changed.txt");
+        let op = FileOp {
+            check: false,
+            changed_list: Some(list.clone()),
+            ..op
+        };
+        let result = run_for(&op, &paths, &users);
+        assert!(result.unwrap_err().to_string().contains("1 of 2"));
+        assert!(target.is_file());
+        assert_eq!(
+            fs::read_to_string(&list)?,
+            format!("{}\n", target.display())
+        );
+
+        let absolute = ["/etc/passwd".to_string()];
+        assert!(run_for(&op, &absolute, &users).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_skel_sync() -> Result<()> {
+        let dir = tempdir()?;
+        let skel = dir.path().join("skel");
+        let home = dir.path().join("home");
+        fs::create_dir_all(skel.join(".config/app"))?;
+        fs::create_dir(&home)?;
+        fs::write(skel.join(".bashrc"), "skel bashrc")?;
+        fs::write(skel.join(".config/app/secret"), "token")?;
+        fs::set_permissions(
+            skel.join(".config/app/secret"),
+            fs::Permissions::from_mode(0o600),
+        )?;
+        fs::write(home.join(".bashrc"), "custom bashrc")?;
+
+        let user = UserAccount {
+            name: "alice".to_string(),
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getegid() },
+            home: home.clone(),
+        };
+
+        assert_eq!(skel_sync(&skel, &user, true, false)?, 3);
+        assert!(!home.join(".config").exists());
+
+        assert_eq!(skel_sync(&skel, &user, false, false)?, 3);
+        assert_eq!(fs::read_to_string(home.join(".bashrc"))?, "custom bashrc");
+        assert_eq!(
+            fs::read_to_string(home.join(".config/app/secret"))?,
+            "token"
+        );
+        assert_eq!(
+            fs::metadata(home.join(".config/app/secret"))?
+                .permissions()
+                .mode()
+                & 0o777,
+            0o600
+        );
+        assert_eq!(skel_sync(&skel, &user, false, false)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xdg_dirs() -> Result<()> {
+        let dir = tempdir()?;
+        let home = dir.path().to_path_buf();
+        let cache = dir.path().join("custom-cache");
+        let env = |var: &str| match var {
+            "HOME" => Some(home.clone().into_os_string()),
+            "XDG_CACHE_HOME" => Some(cache.clone().into_os_string()),
+            // Relative values are ignored per the spec
+            "XDG_DATA_HOME" => Some("relative/data".into()),
+            _ => None,
+        };
+
+        let dirs = XdgDirs::resolve(Some("myapp"), env)?;
+        assert_eq!(dirs.config, home.join(".config/myapp"));
+        assert_eq!(dirs.cache, cache.join("myapp"));
+        assert_eq!(dirs.data, home.join(".local/share/myapp"));
+        assert_eq!(dirs.state, home.join(".local/state/myapp"));
+
+        dirs.create(false)?;
+        assert_eq!(
+            fs::metadata(&dirs.state)?.permissions().mode() & 0o777,
+            0o700
+        );
+        assert!(XdgDirs::resolve(Some("../evil"), env).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+//! Watching paths for changes, for `--watch` and `tap on-change`: through
+//! filesystem events with the watch feature, or by checking every so often
+//! with `--every`.
+
+#[cfg(feature = "watch")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "watch")]
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+#[cfg(feature = "watch")]
+use glob::Pattern;
+#[cfg(feature = "watch")]
+use notify::{RecursiveMode, Watcher};
+
+#[cfg(feature = "watch")]
+use crate::expand_braces;
+use crate::{deadline_after, parse_duration, platform, run, say, say_err, tr, FileOp, Sandbox};
+
+#[cfg(feature = "watch")]
+pub fn run_on_change(
+    patterns: &[String],
+    debounce: Duration,
+    command: &[String],
+    sandbox: Option<&Sandbox>,
+    verbose: bool,
+) -> Result<()> {
+    let changes = Changes::watch(patterns, verbose)?;
+    let rx = &changes.events;
+
+    loop {
+        let event = rx.recv().with_context(|| tr!("File watcher stopped"))?;
+        if !event.is_ok_and(|event| changes.is_relevant(&event)) {
+            continue;
+        }
+        // Let a burst of changes (editor saves, checkouts) settle before running
+        while rx.recv_timeout(debounce).is_ok() {}
+
+        if verbose {
+            say!("Running: {}", command.join(" "));
+        }
+        match Sandbox::command_in(sandbox, &command[0])
+            .args(&command[1..])
+            .status()
+        {
+            Ok(status) if !status.success() && verbose => {
+                say!("Command exited with {}", status)
+            }
+            Ok(_) => {}
+            Err(e) => say_err!("Failed to run {}: {}", command[0], e),
+        }
+        // Changes made while the command ran (often by the command itself) don't retrigger it
+        while rx.try_recv().is_ok() {}
+    }
+}
+
+#[cfg(not(feature = "watch"))]
+pub fn run_on_change(
+    _patterns: &[String],
+    _debounce: Duration,
+    _command: &[String],
+    _sandbox: Option<&Sandbox>,
+    _verbose: bool,
+) -> Result<()> {
+    anyhow::bail!(tr!(
+        "Can't watch for changes: tap was built without the watch feature"
+    ))
+}
+
+/// How often `--watch` checks for an interrupt while it waits.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long `--watch` lets a burst of changes settle before the next pass.
+#[cfg(feature = "watch")]
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+/// How often files are scanned for changes where the system can't report
+/// them (kernels built without inotify, or out of watches).
+#[cfg(feature = "watch")]
+const WATCH_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Applies `op` to `paths` until interrupted, as `--watch` asks: again after
+/// each change to a matching path, or every `op.every`. A failing first
+/// pass ends the watch; later failures are reported and the watch goes on.
+/// An interrupt lets the pass in progress finish.
+pub(crate) fn watch(op: &FileOp, paths: &[String]) -> Result<()> {
+    if op.xargs {
+        anyhow::bail!(tr!("--watch can't read the paths from standard input"));
+    }
+    if op.log_entry.is_some() {
+        anyhow::bail!(tr!(
+            "--watch can't add --log-entry lines, as every pass would add one"
+        ));
+    }
+    if (op.append || op.inserts()) && !op.once {
+        anyhow::bail!(tr!(
+            "--watch needs --once to add content, or every pass would add it again"
+        ));
+    }
+    let trigger = match &op.every {
+        Some(every) => Trigger::Every(parse_duration(every)?),
+        #[cfg(feature = "watch")]
+        None => Trigger::Changes(Changes::watch(paths, op.verbose)?),
+        #[cfg(not(feature = "watch"))]
+        None => anyhow::bail!(tr!(
+            "--watch needs --every: tap was built without the watch feature"
+        )),
+    };
+    platform::catch_interrupts().with_context(|| tr!("Failed to catch interrupts"))?;
+
+    let first = FileOp {
+        watch: false,
+        every: None,
+        ..op.clone()
+    };
+    run(&first, paths)?;
+    // Only the first pass is recorded for `tap undo` and announced, so that
+    // neither fills up with one entry per pass
+    let again = FileOp {
+        journal: None,
+        notify: None,
+        notify_url: None,
+        ..first
+    };
+    loop {
+        let woken = match &trigger {
+            Trigger::Every(every) => sleep_unless_interrupted(*every),
+            #[cfg(feature = "watch")]
+            Trigger::Changes(changes) => {
+                // The pass's own changes don't start another one
+                changes.settle();
+                changes.wait()?
+            }
+        };
+        if !woken {
+            break;
+        }
+        if let Err(error) = run(&again, paths) {
+            say_err!("Error: {:#}", error);
+        }
+    }
+    if op.verbose {
+        say!("Stopped watching");
+    }
+    Ok(())
+}
+
+/// What starts each pass of a watch after the first.
+enum Trigger {
+    Every(Duration),
+    #[cfg(feature = "watch")]
+    Changes(Changes),
+}
+
+/// Sleeps for `interval`, returning false if interrupted first.
+fn sleep_unless_interrupted(interval: Duration) -> bool {
+    let deadline = deadline_after(interval);
+    while !platform::interrupted() {
+        let left = deadline.map_or(WATCH_POLL_INTERVAL, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        });
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(WATCH_POLL_INTERVAL));
+    }
+    false
+}
+
+/// Changes to the paths matching a set of glob patterns, as reported by a
+/// filesystem watcher on the directories they could be in.
+#[cfg(feature = "watch")]
+struct Changes {
+    _watcher: Box<dyn Watcher>,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    matchers: Vec<Pattern>,
+    /// How long to wait for the events of one burst of changes
+    settle: Duration,
+}
+
+#[cfg(feature = "watch")]
+impl Changes {
+    fn watch(patterns: &[String], verbose: bool) -> Result<Self> {
+        let cwd =
+            std::env::current_dir().with_context(|| tr!("Failed to get current directory"))?;
+        let mut matchers = Vec::new();
+        let mut roots = Vec::new();
+        for pattern in patterns {
+            for expanded in expand_braces(pattern)? {
+                let absolute = cwd.join(&expanded);
+                let absolute = absolute.to_string_lossy();
+                matchers.push(
+                    Pattern::new(&absolute)
+                        .with_context(|| tr!("Invalid glob pattern: {}", pattern))?,
+                );
+                roots.push(watch_root(Path::new(absolute.as_ref())));
+            }
+        }
+        roots.sort();
+        roots.dedup();
+
+        let (tx, events) = mpsc::channel();
+        let native = notify::recommended_watcher(tx.clone())
+            .with_context(|| tr!("Failed to start file watcher"))
+            .and_then(|mut watcher| watch_roots(&mut watcher, &roots).map(|()| watcher));
+        let (watcher, settle): (Box<dyn Watcher>, _) = match native {
+            Ok(watcher) => (Box::new(watcher), WATCH_DEBOUNCE),
+            Err(error) => {
+                say_err!(
+                    "Warning: {:#}; checking for changes every {}s instead",
+                    error,
+                    WATCH_SCAN_INTERVAL.as_secs()
+                );
+                let config = notify::Config::default().with_poll_interval(WATCH_SCAN_INTERVAL);
+                let mut watcher = notify::PollWatcher::new(tx, config)
+                    .with_context(|| tr!("Failed to start file watcher"))?;
+                watch_roots(&mut watcher, &roots)?;
+                // A scan only sees changes made since the last one
+                (Box::new(watcher), WATCH_SCAN_INTERVAL * 2)
+            }
+        };
+        if verbose {
+            for root in &roots {
+                say!("Watching: {}", root.display());
+            }
+        }
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            matchers,
+            settle,
+        })
+    }
+
+    fn is_relevant(&self, event: &notify::Event) -> bool {
+        !event.kind.is_access()
+            && event.paths.iter().any(|path| {
+                self.matchers
+                    .iter()
+                    .any(|matcher| matcher.matches_path(path))
+            })
+    }
+
+    /// Waits for a matching path to change and the burst of changes it came
+    /// in to settle, returning false if interrupted first.
+    fn wait(&self) -> Result<bool> {
+        while !platform::interrupted() {
+            match self.events.recv_timeout(WATCH_POLL_INTERVAL) {
+                Ok(Ok(event)) if self.is_relevant(&event) => {
+                    self.settle();
+                    return Ok(true);
+                }
+                Ok(_) | Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!(tr!("File watcher stopped"));
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Drops events until none have come for a moment.
+    fn settle(&self) {
+        while self.events.recv_timeout(self.settle).is_ok() {}
+    }
+}
+
+#[cfg(feature = "watch")]
+fn watch_roots(watcher: &mut dyn Watcher, roots: &[PathBuf]) -> Result<()> {
+    for root in roots {
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| tr!("Failed to watch {}", root.display()))?;
+    }
+    Ok(())
+}
+
+/// The deepest directory of a glob pattern that contains no wildcards, which
+/// is what needs watching to see every possible match.
+#[cfg(feature = "watch")]
+fn watch_root(pattern: &Path) -> PathBuf {
+    let mut root = PathBuf::new();
+    for component in pattern.components() {
+        let text = component.as_os_str().to_string_lossy();
+        if text.contains(['*', '?', '[', '{']) {
+            break;
+        }
+        root.push(component);
+    }
+    while !root.is_dir() {
+        if !root.pop() {
+            return PathBuf::from(".");
+        }
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "watch")]
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[cfg(feature = "watch")]
+    use crate::create_directory;
+    use crate::TapBuilder;
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_watch_root() -> Result<()> {
+        let dir = tempdir()?;
+        let src = dir.path().join("src");
+        create_directory(&src.join("nested"), false)?;
+
+        assert_eq!(watch_root(&src.join("**/*.rs")), src);
+        assert_eq!(watch_root(&src.join("nested/*.rs")), src.join("nested"));
+        assert_eq!(watch_root(&src.join("main.rs")), src);
+        assert_eq!(watch_root(&dir.path().join("missing/*.txt")), dir.path());
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch_refuses_repeated_additions() -> Result<()> {
+        let dir = tempdir()?;
+        let log = dir.path().join("app.log");
+        let paths = [log.to_string_lossy().into_owned()];
+        let every = Some(Duration::from_millis(10));
+        let appending = TapBuilder::new().write("x").append().watch(every).build();
+        let error = appending.run(&paths).unwrap_err();
+        assert!(error.to_string().contains("--once"));
+        let mut logging = TapBuilder::new().watch(every).build();
+        logging.log_entry = Some("started".to_string());
+        assert!(logging.run(&paths).is_err());
+        assert!(!log.exists());
+        Ok(())
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_changes_match_expanded_patterns() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir(dir.path().join("src"))?;
+        let pattern = dir.path().join("src/*.{rs,toml}");
+        let changes = Changes::watch(&[pattern.to_string_lossy().into_owned()], false)?;
+        let event = |path: &str| {
+            notify::Event::new(notify::EventKind::Modify(notify::event::ModifyKind::Any))
+                .add_path(dir.path().join(path))
+        };
+        assert!(changes.is_relevant(&event("src/main.rs")));
+        assert!(changes.is_relevant(&event("src/Cargo.toml")));
+        assert!(!changes.is_relevant(&event("src/notes.md")));
+        assert!(!changes.is_relevant(&event("main.rs")));
+        Ok(())
+    }
+}